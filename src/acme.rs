@@ -0,0 +1,70 @@
+//! Automatic certificates via ACME (ie Let's Encrypt)
+//!
+//! Certificates are requested with the TLS-ALPN-01 challenge, so only the
+//! HTTPS port needs to be reachable. Certificates and the account key are
+//! stored in the cache dir, a restart uses the cached certificate and renewal
+//! happens in the background, well before it expires. Without a configured
+//! cache dir, a private directory of the current user is used, see
+//! [`default_cache_dir`].
+
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use futures_util::StreamExt;
+use rustls_acme::caches::DirCache;
+use rustls_acme::AcmeConfig;
+use rustls_acme::EventOk;
+use rustls_acme::ResolvesServerCertAcme;
+
+use crate::config::Config;
+use crate::utils::user_cache_dir;
+
+/// Name of the directory in the user cache dir used for the ACME state
+const DEFAULT_CACHE_DIR_NAME: &str = "acme";
+
+/// Start managing the certificates of the configured domains, `None` when no
+/// domains are configured
+///
+/// The returned resolver serves the current certificate, and answers the
+/// challenges of the ACME server
+pub fn acme_resolver(config: &Config) -> Option<Arc<ResolvesServerCertAcme>> {
+    if config.acme_domain.is_empty() {
+        return None;
+    }
+
+    let mut acme = AcmeConfig::new(&config.acme_domain)
+        .cache_option(config.acme_cache.clone().map(DirCache::new))
+        .directory_lets_encrypt(!config.acme_staging);
+
+    if let Some(email) = &config.acme_email {
+        acme = acme.contact_push(format!("mailto:{email}"));
+    }
+
+    let mut state = acme.state();
+    let resolver = state.resolver();
+
+    // the state drives ordering and renewing certificates, it never ends
+    tokio::spawn(async move {
+        while let Some(event) = state.next().await {
+            match event {
+                Ok(EventOk::DeployedCachedCert) => tracing::info!("Using cached certificate"),
+                Ok(EventOk::DeployedNewCert) => tracing::info!("Using new certificate"),
+                Ok(EventOk::CertCacheStore | EventOk::AccountCacheStore) => {
+                    tracing::debug!("Stored ACME state in the cache dir");
+                }
+                Err(err) => tracing::warn!("Could not get a certificate: {err}"),
+            }
+        }
+    });
+
+    Some(resolver)
+}
+
+/// Default directory to store the ACME state in, ie `~/.cache/srvr/acme`
+///
+/// It holds the private keys of the account and certificates, only the current
+/// user has access to it. Ordering a new certificate on every start would soon
+/// run into the rate limits of Let's Encrypt
+pub fn default_cache_dir() -> std::io::Result<PathBuf> {
+    user_cache_dir(DEFAULT_CACHE_DIR_NAME)
+}
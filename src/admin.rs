@@ -0,0 +1,304 @@
+//! Admin API
+//!
+//! Only available when an admin token is configured, every request should
+//! authenticate with an `authorization: Bearer <token>` header. All routes
+//! live under [`ADMIN_PREFIX`], so they can not clash with served files.
+//!
+//! With `--releases-dir`, serving can be switched to another release (a
+//! directory in the releases dir) without a restart, and switched back just as
+//! fast. The switch is not persisted, a restart serves the base dir again.
+//!
+//! Virtual files override paths with in-memory content: `PUT` the content to
+//! `/_srvr/virtual/<path>` and `DELETE` it again, or `DELETE /_srvr/virtual`
+//! to remove them all. The content type is taken from the request, or guessed
+//! from the path.
+//!
+//! Counters of the server, ie requests refused because of
+//! `--max-concurrent-requests`, are at `/_srvr/metrics`.
+
+use std::path::Path;
+use std::path::PathBuf;
+
+use axum::body::Bytes;
+use axum::extract::OriginalUri;
+use axum::extract::Request;
+use axum::extract::State;
+use axum::http::header::CONTENT_TYPE;
+use axum::http::header::WWW_AUTHENTICATE;
+use axum::http::HeaderMap;
+use axum::http::HeaderValue;
+use axum::http::StatusCode;
+use axum::middleware::from_fn_with_state;
+use axum::middleware::Next;
+use axum::response::IntoResponse;
+use axum::response::Response;
+use axum::routing::get;
+use axum::routing::put;
+use axum::Json;
+use axum::Router;
+use axum_extra::headers::authorization::Bearer;
+use axum_extra::headers::Authorization;
+use axum_extra::headers::HeaderMapExt;
+use serde::Deserialize;
+use serde::Serialize;
+
+use crate::app::ServerState;
+use crate::audit::Rejection;
+use crate::file_cache::content_type;
+use crate::normalize::normalize_path;
+
+/// Prefix of all admin routes
+pub const ADMIN_PREFIX: &str = "/_srvr";
+
+/// Routes of the admin API, `None` when no admin token is configured
+pub fn admin_router(state: &ServerState) -> Option<Router<ServerState>> {
+    state.config.admin_token.as_ref()?;
+
+    let mut router = Router::new()
+        .route("/connections", get(connections))
+        .route("/metrics", get(metrics))
+        .route(
+            "/virtual",
+            get(list_virtual_files).delete(clear_virtual_files),
+        )
+        .route(
+            "/virtual/*path",
+            put(put_virtual_file).delete(delete_virtual_file),
+        );
+
+    if state.config.releases_dir.is_some() {
+        router = router.route("/release", get(current_release).post(switch_release));
+    }
+
+    let router = router.layer(from_fn_with_state(state.clone(), authenticate));
+
+    Some(Router::new().nest(ADMIN_PREFIX, router))
+}
+
+/// Compare two tokens, without leaking where they differ via timing
+pub fn tokens_match(expected: &str, given: &str) -> bool {
+    expected.len() == given.len()
+        && expected
+            .bytes()
+            .zip(given.bytes())
+            .fold(0, |difference, (a, b)| difference | (a ^ b))
+            == 0
+}
+
+/// Middleware that only lets requests with the admin token through
+async fn authenticate(State(state): State<ServerState>, request: Request, next: Next) -> Response {
+    let authorized = match (
+        &state.config.admin_token,
+        request.headers().typed_get::<Authorization<Bearer>>(),
+    ) {
+        (Some(expected), Some(Authorization(bearer))) => tokens_match(expected, bearer.token()),
+        _ => false,
+    };
+
+    if !authorized {
+        tracing::warn!("Unauthorized admin request to {}", request.uri());
+        return Rejection::Unauthorized.response((
+            StatusCode::UNAUTHORIZED,
+            [(WWW_AUTHENTICATE, HeaderValue::from_static("Bearer"))],
+        ));
+    }
+
+    next.run(request).await
+}
+
+/// List the active connections
+async fn connections(State(state): State<ServerState>) -> Response {
+    Json(state.connections.list()).into_response()
+}
+
+/// Show the counters of the server
+async fn metrics(State(state): State<ServerState>) -> Response {
+    Json(state.metrics.snapshot()).into_response()
+}
+
+#[derive(Debug, thiserror::Error)]
+enum ReleaseError {
+    #[error("Could not open release \"{0}\": {1}")]
+    InvalidDir(PathBuf, std::io::Error),
+
+    #[error("The release \"{0}\" is not a directory in the releases dir")]
+    OutsideReleasesDir(PathBuf),
+}
+
+/// Body of a release switch
+#[derive(Debug, Deserialize)]
+struct SwitchRelease {
+    /// Directory of the release, relative to the releases dir
+    dir: PathBuf,
+}
+
+/// The directory being served, and the one before it when switching
+#[derive(Debug, Serialize)]
+struct ReleaseInfo {
+    dir: PathBuf,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    previous: Option<PathBuf>,
+}
+
+/// Body of a failed admin request
+#[derive(Debug, Serialize)]
+struct AdminError {
+    error: String,
+}
+
+/// Show the directory being served
+async fn current_release(State(state): State<ServerState>) -> Response {
+    Json(ReleaseInfo {
+        dir: state.release().base_dir.clone(),
+        previous: None,
+    })
+    .into_response()
+}
+
+/// Switch to serving another release
+async fn switch_release(
+    State(state): State<ServerState>,
+    Json(switch): Json<SwitchRelease>,
+) -> Response {
+    let Some(releases_dir) = &state.config.releases_dir else {
+        return StatusCode::NOT_FOUND.into_response();
+    };
+
+    let dir = match resolve_release(releases_dir, &switch.dir) {
+        Ok(dir) => dir,
+        Err(err) => {
+            tracing::warn!("Refusing to switch release: {err}");
+
+            let body = AdminError {
+                error: err.to_string(),
+            };
+            return (StatusCode::BAD_REQUEST, Json(body)).into_response();
+        }
+    };
+
+    let previous = state.switch_release(dir.clone()).await;
+    tracing::info!("Switched release from {:?} to {dir:?}", previous.base_dir);
+
+    Json(ReleaseInfo {
+        dir,
+        previous: Some(previous.base_dir.clone()),
+    })
+    .into_response()
+}
+
+/// List the virtual files
+async fn list_virtual_files(State(state): State<ServerState>) -> Response {
+    Json(state.virtual_files.list()).into_response()
+}
+
+/// Remove all virtual files
+async fn clear_virtual_files(State(state): State<ServerState>) -> Response {
+    let removed = state.virtual_files.clear();
+    tracing::info!("Removed {removed} virtual file(s)");
+
+    StatusCode::NO_CONTENT.into_response()
+}
+
+/// Add or replace a virtual file
+async fn put_virtual_file(
+    State(state): State<ServerState>,
+    OriginalUri(uri): OriginalUri,
+    headers: HeaderMap,
+    content: Bytes,
+) -> Response {
+    let Some(path) = virtual_path(uri.path()) else {
+        let body = AdminError {
+            error: format!("Invalid virtual file path \"{}\"", uri.path()),
+        };
+        return (StatusCode::BAD_REQUEST, Json(body)).into_response();
+    };
+
+    let content_type = headers
+        .get(CONTENT_TYPE)
+        .cloned()
+        .unwrap_or_else(|| content_type(Path::new(&path)));
+
+    tracing::info!("Serving virtual file {path} ({} bytes)", content.len());
+    state.virtual_files.insert(path, content_type, content);
+
+    StatusCode::NO_CONTENT.into_response()
+}
+
+/// Remove a virtual file, the file system is used for its path again
+async fn delete_virtual_file(
+    State(state): State<ServerState>,
+    OriginalUri(uri): OriginalUri,
+) -> Response {
+    let removed = virtual_path(uri.path()).is_some_and(|path| state.virtual_files.remove(&path));
+
+    if !removed {
+        return StatusCode::NOT_FOUND.into_response();
+    }
+
+    tracing::info!("Removed virtual file {}", uri.path());
+
+    StatusCode::NO_CONTENT.into_response()
+}
+
+/// Path a virtual file is served on, from the path of its admin route
+///
+/// The path should be normalized already, requests are matched after normalization
+fn virtual_path(admin_path: &str) -> Option<String> {
+    let path = admin_path
+        .strip_prefix(ADMIN_PREFIX)?
+        .strip_prefix("/virtual")?;
+
+    let is_valid = path.len() > 1
+        && normalize_path(path).as_deref() == Some(path)
+        && !path.starts_with(ADMIN_PREFIX);
+
+    is_valid.then(|| path.to_string())
+}
+
+/// Resolve the release to a directory in the releases dir
+fn resolve_release(releases_dir: &Path, dir: &Path) -> Result<PathBuf, ReleaseError> {
+    let releases_dir = releases_dir
+        .canonicalize()
+        .map_err(|err| ReleaseError::InvalidDir(releases_dir.to_path_buf(), err))?;
+
+    // canonical paths resolve `..` and symlinks, so escaping is detected
+    let release = releases_dir
+        .join(dir)
+        .canonicalize()
+        .map_err(|err| ReleaseError::InvalidDir(dir.to_path_buf(), err))?;
+
+    if release == releases_dir || !release.starts_with(&releases_dir) || !release.is_dir() {
+        return Err(ReleaseError::OutsideReleasesDir(dir.to_path_buf()));
+    }
+
+    Ok(release)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_tokens_match() {
+        assert!(tokens_match("secret", "secret"));
+        assert!(!tokens_match("secret", "secreT"));
+        assert!(!tokens_match("secret", "secret2"));
+        assert!(!tokens_match("secret", ""));
+    }
+
+    #[test]
+    fn test_virtual_path() {
+        assert_eq!(
+            virtual_path("/_srvr/virtual/banner.json").as_deref(),
+            Some("/banner.json")
+        );
+        assert_eq!(
+            virtual_path("/_srvr/virtual/docs/").as_deref(),
+            Some("/docs/")
+        );
+        assert_eq!(virtual_path("/_srvr/virtual/"), None);
+        assert_eq!(virtual_path("/_srvr/virtual/a//b"), None);
+        assert_eq!(virtual_path("/_srvr/virtual/../secret"), None);
+        assert_eq!(virtual_path("/_srvr/virtual/_srvr/connections"), None);
+    }
+}
@@ -0,0 +1,179 @@
+//! Audit log of denied and rejected requests
+//!
+//! With `--audit-log <file>`, every request that is refused for a security
+//! reason gets a JSON line in the file, separate from the access log: the
+//! time, client address, method, path, status and the reason. The reasons are
+//! path traversal attempts, failed authentication, requests that are not
+//! allowed, hotlinks, banned clients, rate limited clients and requests shed
+//! because of the concurrency limit. The file is appended to, it is never
+//! truncated.
+
+use std::fs::File;
+use std::fs::OpenOptions;
+use std::io;
+use std::io::Write;
+use std::net::IpAddr;
+use std::path::Path;
+use std::sync::Mutex;
+use std::sync::PoisonError;
+use std::time::SystemTime;
+
+use axum::extract::Request;
+use axum::extract::State;
+use axum::middleware::Next;
+use axum::response::IntoResponse;
+use axum::response::Response;
+use humantime::format_rfc3339_millis;
+use serde::Serialize;
+
+use crate::app::ServerState;
+use crate::forwarded::client_address;
+
+/// Security reason a request was refused for
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Rejection {
+    /// The path tried to go above the base dir
+    Traversal,
+
+    /// Missing or invalid credentials
+    Unauthorized,
+
+    /// Authenticated, but not allowed
+    Forbidden,
+
+    /// Embedded by a page of another site
+    Hotlink,
+
+    /// The client is banned for its failed requests
+    Banned,
+
+    /// The client is over the rate limit
+    RateLimited,
+
+    /// Too many requests at the same time
+    Overloaded,
+}
+
+impl Rejection {
+    /// Mark the response as a rejection, for the audit log
+    pub fn response(self, response: impl IntoResponse) -> Response {
+        let mut response = response.into_response();
+        response.extensions_mut().insert(self);
+
+        response
+    }
+}
+
+/// Line of the audit log
+#[derive(Debug, Serialize)]
+struct AuditEntry<'a> {
+    time: String,
+    client: Option<IpAddr>,
+    method: &'a str,
+    path: &'a str,
+    status: u16,
+    reason: Rejection,
+}
+
+/// The audit log file
+pub struct AuditLog {
+    file: Mutex<File>,
+}
+
+impl AuditLog {
+    /// Open the file to append to, it is created when missing
+    pub fn open(path: &Path) -> io::Result<Self> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+
+        Ok(Self {
+            file: Mutex::new(file),
+        })
+    }
+
+    /// Append an entry
+    fn write(&self, entry: &AuditEntry<'_>) {
+        let Ok(mut line) = serde_json::to_vec(entry) else {
+            return;
+        };
+        line.push(b'\n');
+
+        // a single write, so the lines never interleave
+        let mut file = self.file.lock().unwrap_or_else(PoisonError::into_inner);
+
+        if let Err(err) = file.write_all(&line) {
+            tracing::error!("Could not write to the audit log: {err}");
+        }
+    }
+}
+
+/// Middleware that writes the rejected requests to the audit log
+pub async fn audit(State(state): State<ServerState>, request: Request, next: Next) -> Response {
+    let Some(audit_log) = &state.audit_log else {
+        return next.run(request).await;
+    };
+
+    let client = client_address(&state, &request);
+    let method = request.method().clone();
+    let path = request.uri().path().to_string();
+
+    let response = next.run(request).await;
+
+    if let Some(reason) = response.extensions().get::<Rejection>() {
+        audit_log.write(&AuditEntry {
+            time: format_rfc3339_millis(SystemTime::now()).to_string(),
+            client,
+            method: method.as_str(),
+            path: &path,
+            status: response.status().as_u16(),
+            reason: *reason,
+        });
+    }
+
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::read_to_string;
+    use std::fs::remove_file;
+    use std::process;
+
+    use axum::http::StatusCode;
+
+    use super::*;
+
+    #[test]
+    fn test_write() {
+        let path = std::env::temp_dir().join(format!("srvr-audit-{}.log", process::id()));
+        let audit_log = AuditLog::open(&path).expect("A writable audit log");
+
+        let response = Rejection::RateLimited.response(StatusCode::TOO_MANY_REQUESTS);
+        let reason = *response
+            .extensions()
+            .get::<Rejection>()
+            .expect("A rejection");
+
+        for path in ["/.env", "/wp-admin"] {
+            audit_log.write(&AuditEntry {
+                time: String::from("2024-01-01T00:00:00.000Z"),
+                client: Some(IpAddr::from([192, 168, 1, 1])),
+                method: "GET",
+                path,
+                status: 429,
+                reason,
+            });
+        }
+
+        let content = read_to_string(&path).expect("A readable audit log");
+        remove_file(&path).ok();
+
+        assert_eq!(
+            content.lines().next(),
+            Some(
+                r#"{"time":"2024-01-01T00:00:00.000Z","client":"192.168.1.1","method":"GET","path":"/.env","status":429,"reason":"rate_limited"}"#
+            )
+        );
+        assert_eq!(content.lines().count(), 2);
+    }
+}
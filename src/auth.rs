@@ -0,0 +1,692 @@
+//! Authentication of every request
+//!
+//! With `--auth <user:password>` or `--auth-file <.htpasswd>`, every request
+//! needs the HTTP Basic credentials of one of the users. The htpasswd file can
+//! have bcrypt (`htpasswd -B`), MD5 (`htpasswd -m`, the default) and
+//! SHA-crypt entries.
+//!
+//! With `--token <token>` or `--token-file <file>`, a request can authenticate
+//! with an `authorization: Bearer <token>` header instead. The token file has
+//! the SHA-256 of a token per line, in hex, ie the output of
+//! `printf %s <token> | sha256sum`, so it does not contain the tokens
+//! themselves.
+//!
+//! A JSON Web Token is accepted as bearer token as well, see [`jwt`].
+//!
+//! With the `ldap` feature, `--ldap-url` validates the credentials with the
+//! LDAP server, see [`ldap`].
+//!
+//! With `--auth-rules` only some paths need authentication, or only some users
+//! can access them, see [`rules`].
+//!
+//! Behind a proxy that authenticates users, like oauth2-proxy or Authelia,
+//! `--auth-header <header>` accepts requests from a trusted proxy with the user
+//! in that header.
+//!
+//! With `--signing-secret` a URL signed by `srvr sign` is let through until it
+//! expires, see [`signed_url`].
+//!
+//! Both files are read again when they change. The admin API never gets here,
+//! it has a token of its own; other paths in its prefix are authenticated as
+//! usual.
+
+use std::fmt::Write;
+use std::net::IpAddr;
+use std::net::SocketAddr;
+use std::str::FromStr;
+
+use axum::extract::ConnectInfo;
+use axum::extract::Request;
+use axum::extract::State;
+use axum::http::header::WWW_AUTHENTICATE;
+use axum::http::HeaderMap;
+use axum::http::HeaderValue;
+use axum::http::StatusCode;
+use axum::middleware::Next;
+use axum::response::Response;
+use axum_extra::headers::authorization::Basic;
+use axum_extra::headers::authorization::Bearer;
+use axum_extra::headers::Authorization;
+use axum_extra::headers::HeaderMapExt;
+use md5::Digest;
+use md5::Md5;
+use percent_encoding::percent_decode_str;
+use sha2::Sha256;
+use tracing::Span;
+
+use crate::admin::tokens_match;
+use crate::app::ServerState;
+use crate::audit::Rejection;
+use crate::auth::rules::AuthRules;
+use crate::auth::rules::Requirement;
+use crate::auth::signed_url::is_signed;
+use crate::forwarded::is_trusted_proxy;
+use crate::normalize::normalize_path;
+
+pub mod jwt;
+#[cfg(feature = "ldap")]
+pub mod ldap;
+pub mod rules;
+pub mod signed_url;
+
+/// Prefix of the salt and hash of an Apache MD5 password
+const APR1_MAGIC: &str = "$apr1$";
+
+/// Prefixes of the password hashes that can be verified
+const SUPPORTED_HASHES: &[&str] = &[APR1_MAGIC, "$1$", "$2a$", "$2b$", "$2y$", "$5$", "$6$"];
+
+#[derive(Debug, thiserror::Error)]
+#[error("Expected credentials in the form of \"<user>:<password>\"")]
+pub struct CredentialsError;
+
+#[derive(Debug, thiserror::Error)]
+pub enum HtpasswdError {
+    #[error("Line {0}: expected \"<user>:<hash>\"")]
+    InvalidFormat(usize),
+
+    #[error("Line {0}: unsupported password hash, use bcrypt, MD5 or SHA-crypt")]
+    UnsupportedHash(usize),
+}
+
+#[derive(Debug, thiserror::Error)]
+#[error("Line {0}: expected the SHA-256 of a token, in hex")]
+pub struct TokenHashesError(usize);
+
+/// A user and its password, from the command line
+#[derive(Clone, Debug)]
+pub struct Credentials {
+    username: String,
+    password: String,
+}
+
+impl FromStr for Credentials {
+    type Err = CredentialsError;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let (username, password) = value.split_once(':').ok_or(CredentialsError)?;
+
+        if username.is_empty() {
+            return Err(CredentialsError);
+        }
+
+        Ok(Self {
+            username: username.to_string(),
+            password: password.to_string(),
+        })
+    }
+}
+
+/// Users of an htpasswd file, with their password hashes
+#[derive(Debug, Default)]
+pub struct Htpasswd {
+    users: Vec<(String, String)>,
+}
+
+impl Htpasswd {
+    /// Check the password of the user, slow on purpose for most hashes
+    pub fn verify(&self, username: &str, password: &str) -> bool {
+        self.users
+            .iter()
+            .find(|(user, _)| user == username)
+            .is_some_and(|(_, hash)| verify_hash(password, hash))
+    }
+}
+
+impl FromStr for Htpasswd {
+    type Err = HtpasswdError;
+
+    fn from_str(content: &str) -> Result<Self, Self::Err> {
+        let mut users = vec![];
+
+        for (index, line) in content.lines().enumerate() {
+            let line_number = index + 1;
+            let line = line.trim();
+
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let (username, hash) = line
+                .split_once(':')
+                .filter(|(username, _)| !username.is_empty())
+                .ok_or(HtpasswdError::InvalidFormat(line_number))?;
+
+            if !SUPPORTED_HASHES
+                .iter()
+                .any(|prefix| hash.starts_with(prefix))
+            {
+                return Err(HtpasswdError::UnsupportedHash(line_number));
+            }
+
+            users.push((username.to_string(), hash.to_string()));
+        }
+
+        Ok(Self { users })
+    }
+}
+
+/// SHA-256 hashes of the tokens in a token file
+#[derive(Debug, Default)]
+pub struct TokenHashes {
+    hashes: Vec<String>,
+}
+
+impl TokenHashes {
+    /// Check if the token is one of the hashed tokens
+    pub fn contains(&self, token: &str) -> bool {
+        let hash = sha256_hex(token);
+
+        // no short-circuit, every hash takes as long to compare
+        self.hashes
+            .iter()
+            .fold(false, |found, known| tokens_match(known, &hash) | found)
+    }
+}
+
+impl FromStr for TokenHashes {
+    type Err = TokenHashesError;
+
+    fn from_str(content: &str) -> Result<Self, Self::Err> {
+        let mut hashes = vec![];
+
+        for (index, line) in content.lines().enumerate() {
+            let line = line.trim();
+
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            // `sha256sum` adds the name of the file, or `-` for its input
+            let hash = line
+                .split_whitespace()
+                .next()
+                .unwrap_or_default()
+                .to_ascii_lowercase();
+
+            if hash.len() != 64 || !hash.bytes().all(|byte| byte.is_ascii_hexdigit()) {
+                return Err(TokenHashesError(index + 1));
+            }
+
+            hashes.push(hash);
+        }
+
+        Ok(Self { hashes })
+    }
+}
+
+/// SHA-256 of the value, in lowercase hex
+fn sha256_hex(value: &str) -> String {
+    hex(&Sha256::digest(value))
+}
+
+/// The bytes in lowercase hex
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().fold(String::new(), |mut hex, byte| {
+        let _ = write!(hex, "{byte:02x}");
+        hex
+    })
+}
+
+/// Check a password against its hash, by the format of the hash
+fn verify_hash(password: &str, hash: &str) -> bool {
+    match hash.strip_prefix(APR1_MAGIC) {
+        Some(salt_and_hash) => {
+            let salt = salt_and_hash
+                .split_once('$')
+                .map_or(salt_and_hash, |(salt, _)| salt);
+
+            tokens_match(hash, &apr1_crypt(password, salt))
+        }
+        None => pwhash::unix::verify(password, hash),
+    }
+}
+
+/// Hash a password with the Apache variant of MD5-crypt, only its magic
+/// differs from `$1$`
+fn apr1_crypt(password: &str, salt: &str) -> String {
+    let password = password.as_bytes();
+    let salt = &salt.as_bytes()[..salt.len().min(8)];
+
+    let alternate = Md5::new()
+        .chain_update(password)
+        .chain_update(salt)
+        .chain_update(password)
+        .finalize();
+
+    let mut context = Md5::new()
+        .chain_update(password)
+        .chain_update(APR1_MAGIC)
+        .chain_update(salt);
+
+    for chunk in password.chunks(16) {
+        context.update(&alternate[..chunk.len()]);
+    }
+
+    let mut length = password.len();
+    while length > 0 {
+        if length & 1 == 1 {
+            context.update([0]);
+        } else {
+            context.update(&password[..1]);
+        }
+        length >>= 1;
+    }
+
+    let mut digest = context.finalize();
+
+    for round in 0..1000 {
+        let mut context = Md5::new();
+
+        if round & 1 == 1 {
+            context.update(password);
+        } else {
+            context.update(digest);
+        }
+        if round % 3 != 0 {
+            context.update(salt);
+        }
+        if round % 7 != 0 {
+            context.update(password);
+        }
+        if round & 1 == 1 {
+            context.update(digest);
+        } else {
+            context.update(password);
+        }
+
+        digest = context.finalize();
+    }
+
+    let groups = [(0, 6, 12), (1, 7, 13), (2, 8, 14), (3, 9, 15), (4, 10, 5)];
+    let mut encoded = groups
+        .iter()
+        .map(|&(a, b, c)| {
+            let value =
+                (u32::from(digest[a]) << 16) | (u32::from(digest[b]) << 8) | u32::from(digest[c]);
+            crypt_base64(value, 4)
+        })
+        .collect::<String>();
+    encoded.push_str(&crypt_base64(u32::from(digest[11]), 2));
+
+    format!("{APR1_MAGIC}{}${encoded}", String::from_utf8_lossy(salt))
+}
+
+/// The lowest bits of the value in the base64 alphabet of crypt, least
+/// significant first
+fn crypt_base64(mut value: u32, length: usize) -> String {
+    const ALPHABET: &[u8] = b"./0123456789ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz";
+
+    (0..length)
+        .map(|_| {
+            let char = ALPHABET[(value & 0x3f) as usize] as char;
+            value >>= 6;
+            char
+        })
+        .collect()
+}
+
+/// Check the credentials against the users of the command line, the htpasswd
+/// file and the LDAP server
+async fn is_user(state: &ServerState, basic: &Basic) -> bool {
+    let is_user = state.config.auth.iter().any(|credentials| {
+        credentials.username == basic.username()
+            && tokens_match(&credentials.password, basic.password())
+    });
+
+    if is_user {
+        return true;
+    }
+
+    if let Some(auth_file) = &state.config.auth_file {
+        // an invalid file lets no one in
+        if let Some(htpasswd) = state.file_cache.parsed::<Htpasswd>(auth_file).await {
+            let username = basic.username().to_string();
+            let password = basic.password().to_string();
+
+            // hashes are slow to verify on purpose, keep them off the runtime
+            let is_user =
+                tokio::task::spawn_blocking(move || htpasswd.verify(&username, &password))
+                    .await
+                    .unwrap_or(false);
+
+            if is_user {
+                return true;
+            }
+        }
+    }
+
+    #[cfg(feature = "ldap")]
+    if let Some(ldap) = &state.ldap {
+        return ldap.verify(basic.username(), basic.password()).await;
+    }
+
+    false
+}
+
+/// Check the token against the tokens of the command line and the token file,
+/// or validate it as a JSON Web Token, with its subject as the user
+async fn check_token(state: &ServerState, bearer: &Bearer) -> (bool, Option<String>) {
+    let is_token = state
+        .config
+        .token
+        .iter()
+        .any(|token| tokens_match(token, bearer.token()));
+
+    if is_token {
+        return (true, None);
+    }
+
+    if let Some(token_file) = &state.config.token_file {
+        // an invalid file lets no one in
+        let is_hashed_token = state
+            .file_cache
+            .parsed::<TokenHashes>(token_file)
+            .await
+            .is_some_and(|hashes| hashes.contains(bearer.token()));
+
+        if is_hashed_token {
+            return (true, None);
+        }
+    }
+
+    let Some(jwt) = &state.jwt else {
+        return (false, None);
+    };
+
+    match jwt.validate(bearer.token()) {
+        Ok(subject) => (true, subject),
+        Err(err) => {
+            tracing::debug!("Invalid JSON Web Token: {err}");
+            (false, None)
+        }
+    }
+}
+
+/// Challenges of the configured schemes, for an unauthorized response
+fn challenges(state: &ServerState) -> Vec<HeaderValue> {
+    let mut challenges = vec![];
+
+    if state.config.has_basic_auth() {
+        let realm = state
+            .config
+            .auth_realm
+            .replace('\\', "\\\\")
+            .replace('"', "\\\"");
+
+        challenges.push(
+            HeaderValue::from_str(&format!("Basic realm=\"{realm}\", charset=\"UTF-8\""))
+                .unwrap_or_else(|_| HeaderValue::from_static("Basic")),
+        );
+    }
+
+    if !state.config.token.is_empty() || state.config.token_file.is_some() || state.jwt.is_some() {
+        challenges.push(HeaderValue::from_static("Bearer"));
+    }
+
+    challenges
+}
+
+/// User authenticated by a trusted proxy, from the configured header
+fn forwarded_user(
+    state: &ServerState,
+    headers: &HeaderMap,
+    peer: Option<IpAddr>,
+) -> Option<String> {
+    let header = state.config.auth_header.as_ref()?;
+
+    if !is_trusted_proxy(&state.config.trusted_proxy, peer) {
+        return None;
+    }
+
+    headers
+        .get(header)?
+        .to_str()
+        .ok()
+        .map(str::trim)
+        .filter(|user| !user.is_empty())
+        .map(String::from)
+}
+
+/// Who can access the served path, everyone needs to authenticate without
+/// rules, or when the rules file is invalid
+async fn requirement(state: &ServerState, path: Option<&str>) -> Requirement {
+    let (Some(auth_rules), Some(path)) = (&state.config.auth_rules, path) else {
+        return Requirement::Authenticated;
+    };
+
+    state
+        .file_cache
+        .parsed::<AuthRules>(auth_rules)
+        .await
+        .map_or(Requirement::Authenticated, |rules| {
+            rules.requirement(path).clone()
+        })
+}
+
+/// Check the credentials of the request, with the user when known
+async fn check_request(
+    state: &ServerState,
+    headers: &HeaderMap,
+    peer: Option<IpAddr>,
+) -> (bool, Option<String>) {
+    if let Some(user) = forwarded_user(state, headers, peer) {
+        (true, Some(user))
+    } else if let Some(Authorization(basic)) = headers.typed_get::<Authorization<Basic>>() {
+        (
+            is_user(state, &basic).await,
+            Some(basic.username().to_string()),
+        )
+    } else if let Some(Authorization(bearer)) = headers.typed_get::<Authorization<Bearer>>() {
+        check_token(state, &bearer).await
+    } else {
+        (false, None)
+    }
+}
+
+/// What [`authenticate`] knows about a request, to authorize the other paths it
+/// is served, ie the target of a rewrite rule
+#[derive(Clone, Debug)]
+pub struct Access {
+    peer: Option<IpAddr>,
+
+    /// The user of the accepted credentials, `None` when they were not checked
+    accepted: Option<Accepted>,
+}
+
+#[derive(Clone, Debug)]
+struct Accepted {
+    user: Option<String>,
+}
+
+/// Middleware that only lets authenticated requests through, and only the
+/// allowed users with per-path rules, or requests with a signed URL, the user
+/// is recorded in the trace span when known
+pub async fn authenticate(
+    State(state): State<ServerState>,
+    mut request: Request,
+    next: Next,
+) -> Response {
+    let peer = request
+        .extensions()
+        .get::<ConnectInfo<SocketAddr>>()
+        .map(|ConnectInfo(address)| address.ip());
+
+    request.extensions_mut().insert(Access {
+        peer,
+        accepted: None,
+    });
+
+    // the rules and signatures are for the path as it is served
+    let path = normalize_path(&percent_decode_str(request.uri().path()).decode_utf8_lossy());
+
+    if let (Some(secret), Some(path)) = (&state.config.signing_secret, &path) {
+        if is_signed(secret, path, request.uri().query()) {
+            return next.run(request).await;
+        }
+    }
+
+    let requirement = requirement(&state, path.as_deref()).await;
+
+    if requirement == Requirement::Public {
+        return next.run(request).await;
+    }
+
+    let (authorized, user) = check_request(&state, request.headers(), peer).await;
+
+    if let Some(user) = &user {
+        Span::current().record("user", user.as_str());
+    }
+
+    if !authorized {
+        return unauthorized(&state);
+    }
+
+    if !requirement.allows(user.as_deref()) {
+        tracing::debug!("Not allowed by the authorization rules");
+        return Rejection::Forbidden.response(StatusCode::FORBIDDEN);
+    }
+
+    request.extensions_mut().insert(Access {
+        peer,
+        accepted: Some(Accepted { user }),
+    });
+
+    next.run(request).await
+}
+
+/// Check if the request can be served another path than it asked for, ie the
+/// target of a rewrite rule, the rejection when it can not
+///
+/// The rules and signature of the requested path say nothing about the other
+/// path, a public path can not be rewritten to a protected one
+pub async fn authorize_path(
+    state: &ServerState,
+    access: Option<&Access>,
+    headers: &HeaderMap,
+    path: &str,
+) -> Option<Response> {
+    if !state.config.has_auth() {
+        return None;
+    }
+
+    let requirement = requirement(state, Some(path)).await;
+
+    if requirement == Requirement::Public {
+        return None;
+    }
+
+    let user = if let Some(accepted) = access.and_then(|access| access.accepted.as_ref()) {
+        accepted.user.clone()
+    } else {
+        // the credentials were not checked for the requested path, ie a public one
+        let peer = access.and_then(|access| access.peer);
+        let (authorized, user) = check_request(state, headers, peer).await;
+
+        if !authorized {
+            return Some(unauthorized(state));
+        }
+
+        user
+    };
+
+    if !requirement.allows(user.as_deref()) {
+        tracing::debug!("Not allowed by the authorization rules of {path}");
+        return Some(Rejection::Forbidden.response(StatusCode::FORBIDDEN));
+    }
+
+    None
+}
+
+/// Response for a request without valid credentials, with the ways to
+/// authenticate
+fn unauthorized(state: &ServerState) -> Response {
+    let challenges = challenges(state);
+
+    // without a challenge, only the proxy can authenticate requests
+    if challenges.is_empty() {
+        return Rejection::Unauthorized.response(StatusCode::FORBIDDEN);
+    }
+
+    let mut response = Rejection::Unauthorized.response(StatusCode::UNAUTHORIZED);
+
+    for challenge in challenges {
+        response.headers_mut().append(WWW_AUTHENTICATE, challenge);
+    }
+
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_credentials() {
+        assert!("admin".parse::<Credentials>().is_err());
+        assert!(":secret".parse::<Credentials>().is_err());
+
+        let credentials = "admin:se:cret"
+            .parse::<Credentials>()
+            .expect("Valid credentials");
+        assert_eq!(credentials.username, "admin");
+        assert_eq!(credentials.password, "se:cret");
+    }
+
+    #[test]
+    fn test_parse_htpasswd() {
+        assert!(matches!(
+            "admin".parse::<Htpasswd>(),
+            Err(HtpasswdError::InvalidFormat(1))
+        ));
+        assert!(matches!(
+            "# users\nadmin:{SHA}5en6G6MezRroT3XKqkdPOmY/BfQ=".parse::<Htpasswd>(),
+            Err(HtpasswdError::UnsupportedHash(2))
+        ));
+        assert!(matches!(
+            "admin:secret".parse::<Htpasswd>(),
+            Err(HtpasswdError::UnsupportedHash(1))
+        ));
+    }
+
+    #[test]
+    fn test_apr1_crypt() {
+        assert_eq!(
+            apr1_crypt("secret", "abcdefgh"),
+            "$apr1$abcdefgh$h9FWgUz3n9YxylKLlR5SQ/"
+        );
+    }
+
+    #[test]
+    fn test_verify() {
+        let htpasswd = "apache:$apr1$abcdefgh$h9FWgUz3n9YxylKLlR5SQ/\n\
+                        md5:$1$abcdefgh$cHJi5PXp/ki/ktXzqlk6I1\n\
+                        bcrypt:$2y$05$H7xXmlkzdTWvBA4FOGArMuTUzmfzNbWzI.w9nlHHdsXTpJlJbwObe\n"
+            .parse::<Htpasswd>()
+            .expect("A valid htpasswd file");
+
+        assert!(htpasswd.verify("apache", "secret"));
+        assert!(!htpasswd.verify("apache", "Secret"));
+        assert!(htpasswd.verify("md5", "secret"));
+        assert!(!htpasswd.verify("md5", ""));
+        assert!(htpasswd.verify("bcrypt", "password"));
+        assert!(!htpasswd.verify("bcrypt", "secret"));
+        assert!(!htpasswd.verify("unknown", "secret"));
+    }
+
+    #[test]
+    fn test_token_hashes() {
+        assert!(matches!(
+            "# tokens\nsecret".parse::<TokenHashes>(),
+            Err(TokenHashesError(2))
+        ));
+
+        // `printf %s secret | sha256sum`
+        let hashes = "2BB80D537B1DA3E38BD30361AA855686BDE0EACD7162FEF6A25FE97BF527A25B  -\n"
+            .parse::<TokenHashes>()
+            .expect("A valid token file");
+
+        assert!(hashes.contains("secret"));
+        assert!(!hashes.contains("Secret"));
+        assert!(!hashes.contains(""));
+    }
+}
@@ -0,0 +1,290 @@
+//! JSON Web Tokens as bearer tokens
+//!
+//! A token is accepted when it is signed with the HMAC secret of `--jwt-secret`
+//! or one of the keys at `--jwks-url`, and it has not expired. With
+//! `--jwt-audience` and `--jwt-issuer` its `aud` and `iss` claims should match
+//! as well. The keys are fetched in the background, on startup and then every
+//! `--jwks-refresh`, the previous keys are kept when fetching fails.
+
+use std::sync::Arc;
+use std::sync::PoisonError;
+use std::sync::RwLock;
+use std::time::Duration;
+
+use axum::body::Body;
+use axum::http::StatusCode;
+use axum::http::Uri;
+use hyper_rustls::HttpsConnectorBuilder;
+use hyper_util::client::legacy::Client;
+use hyper_util::rt::TokioExecutor;
+use jsonwebtoken::decode;
+use jsonwebtoken::decode_header;
+use jsonwebtoken::jwk::JwkSet;
+use jsonwebtoken::Algorithm;
+use jsonwebtoken::DecodingKey;
+use jsonwebtoken::Header;
+use jsonwebtoken::Validation;
+use serde::Deserialize;
+
+use crate::app::ServerState;
+use crate::config::Config;
+
+/// Maximum size of a key set, they are small
+const MAX_JWKS_SIZE: usize = 1024 * 1024;
+
+/// Time to wait before fetching the keys again after a failure, unless the
+/// refresh interval is shorter
+const JWKS_RETRY_DELAY: Duration = Duration::from_secs(30);
+
+#[derive(Debug, thiserror::Error)]
+pub enum JwtError {
+    #[error("No key to verify the token with")]
+    UnknownKey,
+
+    #[error(transparent)]
+    Invalid(#[from] jsonwebtoken::errors::Error),
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum JwksError {
+    #[error(transparent)]
+    Request(#[from] hyper_util::client::legacy::Error),
+
+    #[error("Unexpected status {0}")]
+    Status(StatusCode),
+
+    #[error(transparent)]
+    Body(#[from] axum::Error),
+
+    #[error("Invalid key set: {0}")]
+    Json(#[from] serde_json::Error),
+}
+
+/// Claims of a token used besides the validated ones
+#[derive(Deserialize)]
+struct Claims {
+    sub: Option<String>,
+}
+
+/// Validates the JSON Web Tokens of requests
+pub struct JwtValidator {
+    secret: Option<DecodingKey>,
+    keys: RwLock<JwkSet>,
+    audience: Vec<String>,
+    issuer: Vec<String>,
+}
+
+impl JwtValidator {
+    /// Validator for the configured secret and JWKS URL, `None` when neither is
+    /// configured
+    pub fn from_config(config: &Config) -> Option<Self> {
+        if config.jwt_secret.is_none() && config.jwks_url.is_none() {
+            return None;
+        }
+
+        Some(Self {
+            secret: config
+                .jwt_secret
+                .as_ref()
+                .map(|secret| DecodingKey::from_secret(secret.as_bytes())),
+            keys: RwLock::new(JwkSet { keys: vec![] }),
+            audience: config.jwt_audience.clone(),
+            issuer: config.jwt_issuer.clone(),
+        })
+    }
+
+    /// Key to verify the token with, the secret for HMAC, otherwise the key
+    /// with its key ID, or the only key when the token has none
+    fn decoding_key(&self, header: &Header) -> Result<DecodingKey, JwtError> {
+        if matches!(
+            header.alg,
+            Algorithm::HS256 | Algorithm::HS384 | Algorithm::HS512
+        ) {
+            return self.secret.clone().ok_or(JwtError::UnknownKey);
+        }
+
+        let keys = self.keys.read().unwrap_or_else(PoisonError::into_inner);
+        let jwk = match &header.kid {
+            Some(kid) => keys.find(kid),
+            None if keys.keys.len() == 1 => keys.keys.first(),
+            None => None,
+        }
+        .ok_or(JwtError::UnknownKey)?;
+
+        Ok(DecodingKey::from_jwk(jwk)?)
+    }
+
+    /// Check the signature of the token, and its `exp`, `aud` and `iss`
+    /// claims, with its subject when it has one
+    pub fn validate(&self, token: &str) -> Result<Option<String>, JwtError> {
+        let header = decode_header(token)?;
+        let key = self.decoding_key(&header)?;
+
+        let mut validation = Validation::new(header.alg);
+
+        if self.audience.is_empty() {
+            validation.validate_aud = false;
+        } else {
+            validation.set_audience(&self.audience);
+            validation.required_spec_claims.insert(String::from("aud"));
+        }
+
+        if !self.issuer.is_empty() {
+            validation.set_issuer(&self.issuer);
+            validation.required_spec_claims.insert(String::from("iss"));
+        }
+
+        let claims = decode::<Claims>(token, &key, &validation)?.claims;
+
+        Ok(claims.sub)
+    }
+}
+
+/// Fetch the key set at the URL
+async fn fetch_keys(url: &Uri) -> Result<JwkSet, JwksError> {
+    let connector = HttpsConnectorBuilder::new()
+        .with_webpki_roots()
+        .https_or_http()
+        .enable_http1()
+        .build();
+    let client = Client::builder(TokioExecutor::new()).build::<_, Body>(connector);
+
+    let response = client.get(url.clone()).await?;
+
+    if !response.status().is_success() {
+        return Err(JwksError::Status(response.status()));
+    }
+
+    let body = axum::body::to_bytes(Body::new(response.into_body()), MAX_JWKS_SIZE).await?;
+
+    Ok(serde_json::from_slice(&body)?)
+}
+
+/// Keep the keys of the JWKS URL up to date in the background, if configured
+pub fn spawn_jwks_refresh(state: &ServerState) {
+    let (Some(validator), Some(url)) = (&state.jwt, &state.config.jwks_url) else {
+        return;
+    };
+
+    let validator = Arc::clone(validator);
+    let url = url.clone();
+    let interval = state.config.jwks_refresh;
+
+    tokio::spawn(async move {
+        loop {
+            let delay = match fetch_keys(&url).await {
+                Ok(keys) => {
+                    tracing::debug!("Fetched {} key(s) from {url}", keys.keys.len());
+                    *validator
+                        .keys
+                        .write()
+                        .unwrap_or_else(PoisonError::into_inner) = keys;
+                    interval
+                }
+                Err(err) => {
+                    tracing::warn!("Could not fetch the keys from {url}: {err}");
+                    interval.min(JWKS_RETRY_DELAY)
+                }
+            };
+
+            tokio::time::sleep(delay).await;
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::SystemTime;
+    use std::time::UNIX_EPOCH;
+
+    use jsonwebtoken::encode;
+    use jsonwebtoken::EncodingKey;
+    use serde_json::json;
+    use serde_json::Value;
+
+    use super::*;
+
+    fn validator(audience: &[&str], issuer: &[&str]) -> JwtValidator {
+        JwtValidator {
+            secret: Some(DecodingKey::from_secret(b"secret")),
+            keys: RwLock::new(JwkSet { keys: vec![] }),
+            audience: audience.iter().map(ToString::to_string).collect(),
+            issuer: issuer.iter().map(ToString::to_string).collect(),
+        }
+    }
+
+    fn token(claims: &Value, secret: &[u8]) -> String {
+        encode(
+            &Header::default(),
+            claims,
+            &EncodingKey::from_secret(secret),
+        )
+        .expect("A valid token")
+    }
+
+    fn now() -> u64 {
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .expect("A time after the epoch")
+            .as_secs()
+    }
+
+    #[test]
+    fn test_validate() {
+        let validator = validator(&[], &[]);
+
+        let valid = token(
+            &json!({ "exp": now() + 60, "aud": "app", "sub": "alice" }),
+            b"secret",
+        );
+        assert_eq!(
+            validator.validate(&valid).expect("A valid token"),
+            Some(String::from("alice"))
+        );
+
+        let expired = token(&json!({ "exp": now() - 3600 }), b"secret");
+        assert!(validator.validate(&expired).is_err());
+
+        let without_exp = token(&json!({ "sub": "user" }), b"secret");
+        assert!(validator.validate(&without_exp).is_err());
+
+        let other_secret = token(&json!({ "exp": now() + 60 }), b"other");
+        assert!(validator.validate(&other_secret).is_err());
+
+        assert!(validator.validate("not-a-token").is_err());
+    }
+
+    #[test]
+    fn test_validate_claims() {
+        let validator = validator(&["app"], &["https://id.example.com"]);
+
+        let valid = token(
+            &json!({ "exp": now() + 60, "aud": "app", "iss": "https://id.example.com" }),
+            b"secret",
+        );
+        assert!(validator.validate(&valid).is_ok());
+
+        let other_audience = token(
+            &json!({ "exp": now() + 60, "aud": "api", "iss": "https://id.example.com" }),
+            b"secret",
+        );
+        assert!(validator.validate(&other_audience).is_err());
+
+        let without_issuer = token(&json!({ "exp": now() + 60, "aud": "app" }), b"secret");
+        assert!(validator.validate(&without_issuer).is_err());
+    }
+
+    #[test]
+    fn test_unknown_key() {
+        let validator = validator(&[], &[]);
+        let header = Header {
+            kid: Some(String::from("key-1")),
+            ..Header::new(Algorithm::RS256)
+        };
+
+        assert!(matches!(
+            validator.decoding_key(&header),
+            Err(JwtError::UnknownKey)
+        ));
+    }
+}
@@ -0,0 +1,157 @@
+//! HTTP Basic credentials validated with an LDAP bind
+//!
+//! With `--ldap-url` the credentials of a request are checked by binding to
+//! the LDAP server as `<attribute>=<user>,<base DN>`, ie
+//! `uid=alice,ou=people,dc=example,dc=com`. Successful binds are remembered for
+//! `--ldap-cache-ttl`, so not every request needs a round trip to the server.
+
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::sync::PoisonError;
+use std::time::Duration;
+use std::time::Instant;
+
+use ldap3::dn_escape;
+use ldap3::LdapConnAsync;
+use ldap3::LdapConnSettings;
+use ldap3::LdapError;
+
+use crate::config::Config;
+
+use super::sha256_hex;
+
+/// Time to wait for a connection to the LDAP server
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Result code of a bind with the wrong credentials
+const INVALID_CREDENTIALS: u32 = 49;
+
+/// Validates credentials against an LDAP server
+pub struct LdapAuthenticator {
+    url: String,
+    base_dn: String,
+    user_attribute: String,
+    cache_ttl: Duration,
+
+    /// Time of the last successful bind, by user and the hash of the password
+    binds: Mutex<HashMap<(String, String), Instant>>,
+}
+
+impl LdapAuthenticator {
+    /// Authenticator for the configured server, `None` when none is configured
+    pub fn from_config(config: &Config) -> Option<Self> {
+        Some(Self {
+            url: config.ldap_url.clone()?,
+            base_dn: config.ldap_base_dn.clone()?,
+            user_attribute: config.ldap_user_attribute.clone(),
+            cache_ttl: config.ldap_cache_ttl,
+            binds: Mutex::new(HashMap::new()),
+        })
+    }
+
+    /// Distinguished name to bind as for the user
+    fn bind_dn(&self, username: &str) -> String {
+        format!(
+            "{}={},{}",
+            self.user_attribute,
+            dn_escape(username),
+            self.base_dn
+        )
+    }
+
+    /// Check the password of the user, with a bind unless a recent one
+    /// succeeded
+    pub async fn verify(&self, username: &str, password: &str) -> bool {
+        // a bind without a password is anonymous, it succeeds for anyone
+        if username.is_empty() || password.is_empty() {
+            return false;
+        }
+
+        let key = (username.to_string(), sha256_hex(password));
+
+        {
+            let binds = self.binds.lock().unwrap_or_else(PoisonError::into_inner);
+
+            if binds
+                .get(&key)
+                .is_some_and(|bound| bound.elapsed() < self.cache_ttl)
+            {
+                return true;
+            }
+        }
+
+        match self.bind(username, password).await {
+            Ok(true) => {
+                let mut binds = self.binds.lock().unwrap_or_else(PoisonError::into_inner);
+                binds.retain(|_, bound| bound.elapsed() < self.cache_ttl);
+                binds.insert(key, Instant::now());
+
+                true
+            }
+            Ok(false) => false,
+            Err(err) => {
+                tracing::warn!("Could not bind to {}: {err}", self.url);
+                false
+            }
+        }
+    }
+
+    /// Bind as the user, `false` when the credentials are wrong
+    async fn bind(&self, username: &str, password: &str) -> Result<bool, LdapError> {
+        let settings = LdapConnSettings::new().set_conn_timeout(CONNECT_TIMEOUT);
+        let (connection, mut ldap) = LdapConnAsync::with_settings(settings, &self.url).await?;
+        ldap3::drive!(connection);
+
+        let result = ldap.simple_bind(&self.bind_dn(username), password).await?;
+        ldap.unbind().await.ok();
+
+        match result.rc {
+            0 => Ok(true),
+            INVALID_CREDENTIALS => Ok(false),
+            _ => result.success().map(|_| false),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn authenticator() -> LdapAuthenticator {
+        LdapAuthenticator {
+            url: String::from("ldap://127.0.0.1:1"),
+            base_dn: String::from("ou=people,dc=example,dc=com"),
+            user_attribute: String::from("uid"),
+            cache_ttl: Duration::from_secs(60),
+            binds: Mutex::new(HashMap::new()),
+        }
+    }
+
+    #[test]
+    fn test_bind_dn() {
+        let authenticator = authenticator();
+
+        assert_eq!(
+            authenticator.bind_dn("alice"),
+            "uid=alice,ou=people,dc=example,dc=com"
+        );
+        assert_eq!(
+            authenticator.bind_dn("eve,ou=admins"),
+            "uid=eve\\2cou\\3dadmins,ou=people,dc=example,dc=com"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_verify_cached() {
+        let authenticator = authenticator();
+
+        authenticator.binds.lock().expect("A valid lock").insert(
+            (String::from("alice"), sha256_hex("secret")),
+            Instant::now(),
+        );
+
+        assert!(authenticator.verify("alice", "secret").await);
+        assert!(!authenticator.verify("alice", "").await);
+        assert!(!authenticator.verify("alice", "wrong").await);
+    }
+}
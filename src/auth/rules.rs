@@ -0,0 +1,232 @@
+//! Authorization rules per path
+//!
+//! With `--auth-rules <file>` only the paths with a rule need authentication,
+//! the first rule that matches the path wins and paths without one are public.
+//! A rule is a glob pattern followed by who can access it:
+//!
+//! ```text
+//! # groups of users
+//! @editors alice bob
+//!
+//! /admin/**   alice
+//! /drafts/**  @editors carol
+//! /private/** *
+//! /private/press-kit/** public
+//! ```
+//!
+//! `*` lets any authenticated request through, including bearer tokens without
+//! a user, `public` any request. A pattern ending with `/**` matches the
+//! directory itself as well, ie `/admin`.
+//!
+//! The target of a rewrite in the `_redirects` file is checked as well, a
+//! public path can not be rewritten to a protected file.
+
+use std::str::FromStr;
+
+use crate::utils::wildcard_match;
+
+#[derive(Debug, thiserror::Error)]
+pub enum AuthRulesError {
+    #[error("Line {0}: expected a path and who can access it, ie `/admin/** alice @editors`")]
+    InvalidRule(usize),
+
+    #[error("Line {0}: expected a group and its users, ie `@editors alice bob`")]
+    InvalidGroup(usize),
+
+    #[error("Line {0}: unknown group \"{1}\"")]
+    UnknownGroup(usize, String),
+}
+
+/// Who can access the paths of a rule
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Requirement {
+    /// Anyone, without authentication
+    Public,
+
+    /// Any authenticated request
+    Authenticated,
+
+    /// Only these users, groups are expanded to their users
+    Users(Vec<String>),
+}
+
+impl Requirement {
+    /// Check if an authenticated request, with the user when known, is allowed
+    pub fn allows(&self, user: Option<&str>) -> bool {
+        match self {
+            Self::Public | Self::Authenticated => true,
+            Self::Users(users) => user.is_some_and(|user| users.iter().any(|known| known == user)),
+        }
+    }
+}
+
+/// Rule for the paths matching a pattern
+#[derive(Clone, Debug)]
+struct AuthRule {
+    pattern: String,
+    requirement: Requirement,
+}
+
+impl AuthRule {
+    /// Check if the path matches the pattern
+    fn matches(&self, path: &str) -> bool {
+        wildcard_match(&self.pattern, path)
+            || self
+                .pattern
+                .strip_suffix("/**")
+                .is_some_and(|directory| directory == path.trim_end_matches('/'))
+    }
+}
+
+/// All rules of the rules file, in order
+#[derive(Debug, Default)]
+pub struct AuthRules {
+    rules: Vec<AuthRule>,
+}
+
+impl AuthRules {
+    /// Who can access the path, the requirement of the first matching rule
+    pub fn requirement(&self, path: &str) -> &Requirement {
+        self.rules
+            .iter()
+            .find(|rule| rule.matches(path))
+            .map_or(&Requirement::Public, |rule| &rule.requirement)
+    }
+}
+
+impl FromStr for AuthRules {
+    type Err = AuthRulesError;
+
+    fn from_str(content: &str) -> Result<Self, Self::Err> {
+        let lines = content
+            .lines()
+            .enumerate()
+            .map(|(index, line)| (index + 1, line.split_whitespace().collect::<Vec<_>>()))
+            .filter(|(_, words)| words.first().is_some_and(|word| !word.starts_with('#')))
+            .collect::<Vec<_>>();
+
+        // groups first, so they can be defined anywhere in the file
+        let mut groups = vec![];
+
+        for (line_number, words) in &lines {
+            let Some(group) = words[0].strip_prefix('@') else {
+                continue;
+            };
+
+            if group.is_empty() || words.len() < 2 {
+                return Err(AuthRulesError::InvalidGroup(*line_number));
+            }
+
+            groups.push((group, &words[1..]));
+        }
+
+        let mut rules = vec![];
+
+        for (line_number, words) in &lines {
+            if words[0].starts_with('@') {
+                continue;
+            }
+
+            let line_number = *line_number;
+
+            let (pattern, who) = match words.as_slice() {
+                [pattern, who @ ..] if pattern.starts_with('/') && !who.is_empty() => {
+                    (*pattern, who)
+                }
+                _ => return Err(AuthRulesError::InvalidRule(line_number)),
+            };
+
+            let requirement = match who {
+                ["public"] => Requirement::Public,
+                ["*"] => Requirement::Authenticated,
+                who => {
+                    let mut users = vec![];
+
+                    for user in who {
+                        let Some(group) = user.strip_prefix('@') else {
+                            users.push((*user).to_string());
+                            continue;
+                        };
+
+                        let Some((_, members)) = groups.iter().find(|(name, _)| *name == group)
+                        else {
+                            return Err(AuthRulesError::UnknownGroup(
+                                line_number,
+                                group.to_string(),
+                            ));
+                        };
+
+                        users.extend(members.iter().map(ToString::to_string));
+                    }
+
+                    Requirement::Users(users)
+                }
+            };
+
+            rules.push(AuthRule {
+                pattern: pattern.to_string(),
+                requirement,
+            });
+        }
+
+        Ok(Self { rules })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        assert!(matches!(
+            "admin/** alice".parse::<AuthRules>(),
+            Err(AuthRulesError::InvalidRule(1))
+        ));
+        assert!(matches!(
+            "# no one\n/admin/**".parse::<AuthRules>(),
+            Err(AuthRulesError::InvalidRule(2))
+        ));
+        assert!(matches!(
+            "@editors".parse::<AuthRules>(),
+            Err(AuthRulesError::InvalidGroup(1))
+        ));
+        assert!(matches!(
+            "/drafts/** @editors".parse::<AuthRules>(),
+            Err(AuthRulesError::UnknownGroup(1, _))
+        ));
+    }
+
+    #[test]
+    fn test_requirement() {
+        let rules = "/admin/**   alice\n\
+                     /drafts/**  @editors carol\n\
+                     /private/press-kit/** public\n\
+                     /private/** *\n\
+                     \n\
+                     @editors alice bob\n"
+            .parse::<AuthRules>()
+            .expect("Valid rules");
+
+        let admin = rules.requirement("/admin/users.html");
+        assert_eq!(admin, &Requirement::Users(vec![String::from("alice")]));
+        assert_eq!(rules.requirement("/admin"), admin);
+        assert_eq!(rules.requirement("/admin/"), admin);
+        assert_eq!(rules.requirement("/administration"), &Requirement::Public);
+
+        assert!(rules.requirement("/drafts/post.html").allows(Some("bob")));
+        assert!(rules.requirement("/drafts/post.html").allows(Some("carol")));
+        assert!(!rules.requirement("/drafts/post.html").allows(Some("dave")));
+        assert!(!rules.requirement("/drafts/post.html").allows(None));
+
+        assert_eq!(
+            rules.requirement("/private/press-kit/logo.png"),
+            &Requirement::Public
+        );
+        assert_eq!(
+            rules.requirement("/private/report.pdf"),
+            &Requirement::Authenticated
+        );
+        assert_eq!(rules.requirement("/index.html"), &Requirement::Public);
+    }
+}
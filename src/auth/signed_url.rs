@@ -0,0 +1,132 @@
+//! Signed URLs that expire
+//!
+//! With `--signing-secret`, a URL with a valid signature is let through until
+//! it expires, without other credentials, ie to share a single download. The
+//! signature is an HMAC-SHA256 of the path and the expiry time, added as the
+//! `expires` (a Unix timestamp) and `sig` query parameters. Other query
+//! parameters are not signed. `srvr sign <path> --ttl 1h` prints a signed URL.
+
+use std::time::Duration;
+use std::time::SystemTime;
+use std::time::UNIX_EPOCH;
+
+use anyhow::Context;
+use clap::Args;
+use hmac::Hmac;
+use hmac::Mac;
+use percent_encoding::utf8_percent_encode;
+use sha2::Sha256;
+
+use crate::config::Config;
+use crate::normalize::normalize_path;
+use crate::utils::PATH_SEGMENT;
+
+use super::hex;
+use super::tokens_match;
+
+/// Options of the URL signer
+#[derive(Args, Clone, Debug)]
+pub struct SignConfig {
+    /// The path to sign, ie `/downloads/report.pdf`
+    path: String,
+
+    /// How long the URL stays valid
+    #[arg(long, value_name = "DURATION", value_parser = humantime::parse_duration, default_value = "1h")]
+    ttl: Duration,
+}
+
+/// Seconds since the Unix epoch
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |since_epoch| since_epoch.as_secs())
+}
+
+/// Signature of the path until the expiry time, in hex
+fn signature(secret: &str, path: &str, expires: u64) -> String {
+    // any key length is valid for HMAC
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret.as_bytes())
+        .unwrap_or_else(|_| unreachable!("HMAC accepts keys of any length"));
+    mac.update(format!("{path}\n{expires}").as_bytes());
+
+    hex(&mac.finalize().into_bytes())
+}
+
+/// Check the `expires` and `sig` query parameters for the normalized path
+pub fn is_signed(secret: &str, path: &str, query: Option<&str>) -> bool {
+    let mut expires = None;
+    let mut sig = None;
+
+    for (name, value) in form_urlencoded::parse(query.unwrap_or_default().as_bytes()) {
+        match &*name {
+            "expires" => expires = value.parse::<u64>().ok(),
+            "sig" => sig = Some(value),
+            _ => {}
+        }
+    }
+
+    let (Some(expires), Some(sig)) = (expires, sig) else {
+        return false;
+    };
+
+    expires > now() && tokens_match(&signature(secret, path, expires), &sig)
+}
+
+/// Print a signed URL for the path
+pub fn sign(config: &Config, sign_config: &SignConfig) -> anyhow::Result<()> {
+    let secret = config
+        .signing_secret
+        .as_ref()
+        .context("Signing needs a secret, via --signing-secret")?;
+
+    let path = normalize_path(&format!("/{}", sign_config.path.trim_start_matches('/')))
+        .context("The path can not go above the base dir")?;
+    let expires = now() + sign_config.ttl.as_secs();
+
+    let url_path = path
+        .split('/')
+        .map(|segment| utf8_percent_encode(segment, PATH_SEGMENT).to_string())
+        .collect::<Vec<_>>()
+        .join("/");
+
+    println!(
+        "{url_path}?expires={expires}&sig={}",
+        signature(secret, &path, expires)
+    );
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn query(secret: &str, path: &str, expires: u64) -> String {
+        format!("expires={expires}&sig={}", signature(secret, path, expires))
+    }
+
+    #[test]
+    fn test_is_signed() {
+        let expires = now() + 60;
+        let signed = query("secret", "/report.pdf", expires);
+
+        assert!(is_signed("secret", "/report.pdf", Some(&signed)));
+        assert!(is_signed(
+            "secret",
+            "/report.pdf",
+            Some(&format!("download=1&{signed}"))
+        ));
+
+        assert!(!is_signed("other", "/report.pdf", Some(&signed)));
+        assert!(!is_signed("secret", "/other.pdf", Some(&signed)));
+        assert!(!is_signed(
+            "secret",
+            "/report.pdf",
+            Some(&signed.replace(&expires.to_string(), &(expires + 1).to_string()))
+        ));
+        assert!(!is_signed("secret", "/report.pdf", None));
+
+        let outdated = query("secret", "/report.pdf", now() - 1);
+        assert!(!is_signed("secret", "/report.pdf", Some(&outdated)));
+    }
+}
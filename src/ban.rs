@@ -0,0 +1,209 @@
+//! Temporary bans of clients probing for files
+//!
+//! With `--ban-after 20`, a client address with 20 responses that are a 404 or
+//! a 403 within `--ban-window` is banned for `--ban-duration`: its requests get
+//! a 403 without being handled. Scanners looking for `/wp-admin` or `.env`
+//! trip it quickly, regular visitors do not. Behind a trusted proxy, the client
+//! address is taken from `x-forwarded-for`.
+
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::sync::PoisonError;
+use std::time::Duration;
+use std::time::Instant;
+
+use axum::extract::Request;
+use axum::extract::State;
+use axum::http::StatusCode;
+use axum::middleware::Next;
+use axum::response::Response;
+use humantime::format_duration;
+
+use crate::app::ServerState;
+use crate::audit::Rejection;
+use crate::config::Config;
+use crate::forwarded::client_address;
+
+/// Time between forgetting the clients that are no longer of interest
+const CLEANUP_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Failed requests of a client in the current window, and its ban
+#[derive(Clone, Copy, Debug)]
+struct Offender {
+    window_start: Instant,
+    failures: u32,
+    banned_until: Option<Instant>,
+}
+
+/// Clients with failed requests, and the banned ones
+pub struct Bans {
+    after: u32,
+    window: Duration,
+    duration: Duration,
+    offenders: Mutex<HashMap<IpAddr, Offender>>,
+}
+
+impl Bans {
+    /// Bans as configured, `None` when disabled
+    pub fn from_config(config: &Config) -> Option<Self> {
+        Some(Self {
+            after: config.ban_after?,
+            window: config.ban_window,
+            duration: config.ban_duration,
+            offenders: Mutex::default(),
+        })
+    }
+
+    /// Check if the client is banned
+    fn is_banned(&self, client: IpAddr, now: Instant) -> bool {
+        self.offenders
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .get(&client)
+            .and_then(|offender| offender.banned_until)
+            .is_some_and(|banned_until| now < banned_until)
+    }
+
+    /// Count a failed request of the client, `true` when it is banned because
+    /// of it
+    fn add_failure(&self, client: IpAddr, now: Instant) -> bool {
+        let mut offenders = self
+            .offenders
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+
+        let offender = offenders.entry(client).or_insert(Offender {
+            window_start: now,
+            failures: 0,
+            banned_until: None,
+        });
+
+        if now.saturating_duration_since(offender.window_start) >= self.window {
+            offender.window_start = now;
+            offender.failures = 0;
+        }
+
+        offender.failures += 1;
+
+        if offender.failures < self.after {
+            return false;
+        }
+
+        offender.window_start = now;
+        offender.failures = 0;
+        offender.banned_until = Some(now + self.duration);
+
+        true
+    }
+
+    /// Forget the clients without recent failures or a ban
+    fn cleanup(&self, now: Instant) {
+        self.offenders
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .retain(|_, offender| {
+                now.saturating_duration_since(offender.window_start) < self.window
+                    || offender
+                        .banned_until
+                        .is_some_and(|banned_until| now < banned_until)
+            });
+    }
+}
+
+/// Periodically forget the clients that are no longer of interest in the
+/// background, if bans are enabled
+pub fn spawn_ban_cleanup(state: &ServerState) {
+    let Some(bans) = &state.bans else {
+        return;
+    };
+
+    let bans = Arc::clone(bans);
+
+    tokio::spawn(async move {
+        loop {
+            tokio::time::sleep(CLEANUP_INTERVAL).await;
+            bans.cleanup(Instant::now());
+        }
+    });
+}
+
+/// Middleware that refuses the requests of banned clients, and bans the ones
+/// with too many failed requests
+pub async fn ban(State(state): State<ServerState>, request: Request, next: Next) -> Response {
+    let (Some(bans), Some(client)) = (&state.bans, client_address(&state, &request)) else {
+        return next.run(request).await;
+    };
+
+    if bans.is_banned(client, Instant::now()) {
+        return Rejection::Banned.response(StatusCode::FORBIDDEN);
+    }
+
+    let response = next.run(request).await;
+
+    if matches!(
+        response.status(),
+        StatusCode::NOT_FOUND | StatusCode::FORBIDDEN
+    ) && bans.add_failure(client, Instant::now())
+    {
+        tracing::warn!(
+            "Banned {client} for {} after {} failed requests",
+            format_duration(bans.duration),
+            bans.after
+        );
+    }
+
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn bans() -> Bans {
+        Bans {
+            after: 3,
+            window: Duration::from_secs(60),
+            duration: Duration::from_secs(600),
+            offenders: Mutex::default(),
+        }
+    }
+
+    #[test]
+    fn test_ban() {
+        let bans = bans();
+        let client = IpAddr::from([192, 168, 1, 1]);
+        let now = Instant::now();
+
+        assert!(!bans.add_failure(client, now));
+        assert!(!bans.add_failure(client, now));
+        assert!(!bans.is_banned(client, now));
+
+        assert!(bans.add_failure(client, now));
+        assert!(bans.is_banned(client, now));
+        assert!(!bans.is_banned(IpAddr::from([192, 168, 1, 2]), now));
+
+        let later = now + Duration::from_secs(600);
+        assert!(!bans.is_banned(client, later));
+
+        bans.cleanup(later);
+        assert!(bans.offenders.lock().expect("A valid lock").is_empty());
+    }
+
+    #[test]
+    fn test_window() {
+        let bans = bans();
+        let client = IpAddr::from([192, 168, 1, 1]);
+        let now = Instant::now();
+
+        assert!(!bans.add_failure(client, now));
+        assert!(!bans.add_failure(client, now));
+
+        // a new window starts counting again
+        let later = now + Duration::from_secs(60);
+        assert!(!bans.add_failure(client, later));
+        assert!(!bans.add_failure(client, later));
+        assert!(!bans.is_banned(client, later));
+    }
+}
@@ -0,0 +1,273 @@
+//! Built-in load generator
+//!
+//! Either a running srvr is benchmarked via `--url`, or the config is served
+//! in-process through the same router, which also reports how the file cache
+//! behaved during the run.
+
+use std::collections::BTreeMap;
+use std::sync::atomic::AtomicUsize;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use std::time::Duration;
+use std::time::Instant;
+
+use axum::body::Body;
+use axum::http::header::ACCEPT_ENCODING;
+use axum::http::HeaderValue;
+use axum::http::Request;
+use axum::http::StatusCode;
+use axum::http::Uri;
+use axum::response::Response;
+use axum::Router;
+use clap::Args;
+use hyper_util::client::legacy::connect::HttpConnector;
+use hyper_util::client::legacy::Client;
+use hyper_util::rt::TokioExecutor;
+use tower::ServiceExt;
+
+use crate::app::app;
+use crate::app::ServerState;
+use crate::config::Config;
+use crate::file_cache::FileCache;
+
+/// Options of the load generator
+#[derive(Args, Clone, Debug)]
+pub struct BenchConfig {
+    /// Base URL of a running srvr, ie `http://127.0.0.1:12234`, the config is served in-process when omitted
+    #[arg(long)]
+    url: Option<Uri>,
+
+    /// Number of requests in flight at the same time
+    #[arg(long, short, default_value_t = 32)]
+    concurrency: usize,
+
+    /// Total number of requests
+    #[arg(long, short = 'n', default_value_t = 10_000)]
+    requests: usize,
+
+    /// Path to request, can be repeated to request the paths in turn
+    #[arg(long = "path", value_name = "PATH", default_values_t = [String::from("/")])]
+    paths: Vec<String>,
+
+    /// Value of the `accept-encoding` header of the requests
+    #[arg(long, value_name = "ENCODINGS")]
+    accept_encoding: Option<HeaderValue>,
+}
+
+/// Where the requests are sent to
+#[derive(Clone)]
+enum Target {
+    /// A running srvr
+    Remote {
+        client: Box<Client<HttpConnector, Body>>,
+        base: String,
+    },
+
+    /// The router of the config, in-process
+    InProcess(Router),
+}
+
+impl Target {
+    async fn send(
+        &self,
+        path: &str,
+        accept_encoding: Option<&HeaderValue>,
+    ) -> anyhow::Result<Response> {
+        let uri = match self {
+            Self::Remote { base, .. } => format!("{base}{path}"),
+            Self::InProcess(_) => path.to_string(),
+        };
+
+        let mut request = Request::get(uri).body(Body::empty())?;
+
+        if let Some(accept_encoding) = accept_encoding {
+            request
+                .headers_mut()
+                .insert(ACCEPT_ENCODING, accept_encoding.clone());
+        }
+
+        let response = match self {
+            Self::Remote { client, .. } => client.request(request).await?.map(Body::new),
+            Self::InProcess(router) => router.clone().oneshot(request).await?,
+        };
+
+        Ok(response)
+    }
+}
+
+/// Outcome of a single request
+struct Sample {
+    latency: Duration,
+    status: StatusCode,
+    bytes: usize,
+}
+
+/// Run the load generator and print a report
+pub async fn bench(config: Config, bench_config: BenchConfig) -> anyhow::Result<()> {
+    let mut file_cache = None;
+
+    let target = if let Some(url) = &bench_config.url {
+        Target::Remote {
+            client: Box::new(Client::builder(TokioExecutor::new()).build_http()),
+            base: url.to_string().trim_end_matches('/').to_string(),
+        }
+    } else {
+        let state = ServerState::from_config(config);
+        file_cache = Some(Arc::clone(&state.file_cache));
+
+        Target::InProcess(app(state))
+    };
+
+    let bench_config = Arc::new(bench_config);
+    let next_request = Arc::new(AtomicUsize::new(0));
+    let started_at = Instant::now();
+
+    let workers = (0..bench_config.concurrency.max(1))
+        .map(|_| {
+            let target = target.clone();
+            let bench_config = Arc::clone(&bench_config);
+            let next_request = Arc::clone(&next_request);
+
+            tokio::spawn(async move {
+                let mut samples = vec![];
+                let mut failures = 0;
+
+                loop {
+                    let index = next_request.fetch_add(1, Ordering::Relaxed);
+
+                    if index >= bench_config.requests {
+                        break;
+                    }
+
+                    let path = &bench_config.paths[index % bench_config.paths.len()];
+                    let request_started_at = Instant::now();
+
+                    let result = async {
+                        let response = target
+                            .send(path, bench_config.accept_encoding.as_ref())
+                            .await?;
+                        let status = response.status();
+                        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await?;
+
+                        anyhow::Ok((status, body.len()))
+                    };
+
+                    match result.await {
+                        Ok((status, bytes)) => samples.push(Sample {
+                            latency: request_started_at.elapsed(),
+                            status,
+                            bytes,
+                        }),
+                        Err(err) => {
+                            tracing::debug!("Request for {path} failed: {err}");
+                            failures += 1;
+                        }
+                    }
+                }
+
+                (samples, failures)
+            })
+        })
+        .collect::<Vec<_>>();
+
+    let mut samples = vec![];
+    let mut failures = 0;
+
+    for worker in workers {
+        let (worker_samples, worker_failures) = worker.await?;
+        samples.extend(worker_samples);
+        failures += worker_failures;
+    }
+
+    report(
+        &samples,
+        failures,
+        started_at.elapsed(),
+        file_cache.as_deref(),
+    );
+
+    Ok(())
+}
+
+/// Print the results of a run
+#[allow(clippy::cast_precision_loss)]
+fn report(samples: &[Sample], failures: usize, elapsed: Duration, file_cache: Option<&FileCache>) {
+    let mut latencies = samples
+        .iter()
+        .map(|sample| sample.latency)
+        .collect::<Vec<_>>();
+    latencies.sort();
+
+    let mut statuses = BTreeMap::<StatusCode, usize>::new();
+    for sample in samples {
+        *statuses.entry(sample.status).or_default() += 1;
+    }
+
+    let bytes = samples.iter().map(|sample| sample.bytes).sum::<usize>();
+    let requests = samples.len() + failures;
+
+    println!("Requests:      {requests} ({failures} failed)");
+    println!("Duration:      {}", format_latency(elapsed));
+    println!(
+        "Requests/sec:  {:.1}",
+        samples.len() as f64 / elapsed.as_secs_f64()
+    );
+    println!("Transferred:   {:.1} MiB", bytes as f64 / 1_048_576.0);
+    println!(
+        "Latency:       p50 {}, p90 {}, p99 {}, max {}",
+        format_latency(percentile(&latencies, 50)),
+        format_latency(percentile(&latencies, 90)),
+        format_latency(percentile(&latencies, 99)),
+        format_latency(latencies.last().copied().unwrap_or_default()),
+    );
+
+    let statuses = statuses
+        .iter()
+        .map(|(status, count)| format!("{} x {count}", status.as_u16()))
+        .collect::<Vec<_>>();
+    println!("Status codes:  {}", statuses.join(", "));
+
+    if let Some(file_cache) = file_cache {
+        let stats = file_cache.stats();
+        let lookups = stats.hits + stats.misses;
+        let ratio = if lookups == 0 {
+            0.0
+        } else {
+            stats.hits as f64 / lookups as f64 * 100.0
+        };
+
+        println!(
+            "File cache:    {} hits, {} misses ({ratio:.1}% hit rate)",
+            stats.hits, stats.misses
+        );
+    }
+}
+
+/// Latency below which the percentage of the sorted latencies fall
+fn percentile(latencies: &[Duration], percentage: usize) -> Duration {
+    if latencies.is_empty() {
+        return Duration::ZERO;
+    }
+
+    latencies[(latencies.len() - 1) * percentage / 100]
+}
+
+/// Format a latency in milliseconds
+fn format_latency(latency: Duration) -> String {
+    format!("{:.2}ms", latency.as_secs_f64() * 1000.0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_percentile() {
+        let latencies = (1..=100).map(Duration::from_millis).collect::<Vec<_>>();
+
+        assert_eq!(percentile(&latencies, 50), Duration::from_millis(50));
+        assert_eq!(percentile(&latencies, 99), Duration::from_millis(99));
+        assert_eq!(percentile(&latencies, 100), Duration::from_millis(100));
+        assert_eq!(percentile(&[], 50), Duration::ZERO);
+    }
+}
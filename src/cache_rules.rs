@@ -0,0 +1,303 @@
+//! `Cache-Control` rules per file pattern
+//!
+//! Rules are configured as `<patterns>=<cache-control>`, ie
+//! `*.css,*.js=public,max-age=31536000`. A pattern without a `/` matches the
+//! file name, one with a `/` the path within the base dir, ie `/assets/*`. The
+//! first matching rule wins, files without a matching rule get
+//! `public, max-age=<seconds>` with `--max-age`, or no `Cache-Control` header.
+//! The fallback file is always `no-cache`, it is served for every page of the
+//! site.
+//!
+//! Shared caches, like a CDN, can be controlled separately: `--s-maxage` and
+//! `--stale-while-revalidate` add their directive to every `public` value that
+//! does not have it already.
+
+use std::fmt;
+use std::path::Path;
+use std::str::FromStr;
+
+use axum::http::header::InvalidHeaderValue;
+use axum::http::HeaderValue;
+
+use crate::config::Config;
+use crate::utils::wildcard_match;
+
+/// Cache control header value for no-cache
+const CACHE_CONTROL_NO_CACHE: &str = "no-cache";
+
+#[derive(Debug, thiserror::Error)]
+pub enum CacheRuleError {
+    #[error("Expected a cache rule in the form of \"<patterns>=<cache-control>\", ie \"*.css,*.js=public,max-age=31536000\"")]
+    InvalidFormat,
+
+    #[error("Invalid cache control value: {0}")]
+    InvalidValue(#[from] InvalidHeaderValue),
+}
+
+/// The `Cache-Control` header for the files matching any of the patterns
+#[derive(Clone, Debug)]
+pub struct CacheRule {
+    patterns: Vec<String>,
+    value: HeaderValue,
+}
+
+impl CacheRule {
+    /// Check if the file, relative to the base dir, matches one of the patterns
+    fn matches(&self, file: &Path) -> bool {
+        let path = format!("/{}", file.display());
+        let name = file
+            .file_name()
+            .and_then(|name| name.to_str())
+            .unwrap_or_default();
+
+        self.patterns.iter().any(|pattern| {
+            if pattern.contains('/') {
+                wildcard_match(pattern, &path)
+            } else {
+                wildcard_match(pattern, name)
+            }
+        })
+    }
+}
+
+impl FromStr for CacheRule {
+    type Err = CacheRuleError;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let (patterns, value) = value.split_once('=').ok_or(CacheRuleError::InvalidFormat)?;
+
+        let patterns = patterns
+            .split(',')
+            .map(str::trim)
+            .filter(|pattern| !pattern.is_empty())
+            .map(String::from)
+            .collect::<Vec<_>>();
+
+        if patterns.is_empty() || value.trim().is_empty() {
+            return Err(CacheRuleError::InvalidFormat);
+        }
+
+        Ok(Self {
+            patterns,
+            value: HeaderValue::from_str(value.trim())?,
+        })
+    }
+}
+
+impl fmt::Display for CacheRule {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}={}",
+            self.patterns.join(","),
+            String::from_utf8_lossy(self.value.as_bytes())
+        )
+    }
+}
+
+/// Directives for shared caches, added to the `public` values
+#[derive(Clone, Copy, Debug, Default)]
+struct SharedCacheDirectives {
+    s_maxage: Option<u64>,
+    stale_while_revalidate: Option<u64>,
+}
+
+impl SharedCacheDirectives {
+    /// The value with the directives it is missing, only a `public` value can
+    /// be stored by a shared cache without further ado
+    fn apply(self, value: HeaderValue) -> HeaderValue {
+        let Ok(current) = value.to_str() else {
+            return value;
+        };
+
+        let names = current
+            .split(',')
+            .map(|directive| {
+                directive
+                    .split_once('=')
+                    .map_or(directive, |(name, _)| name)
+                    .trim()
+                    .to_ascii_lowercase()
+            })
+            .collect::<Vec<_>>();
+
+        if !names.iter().any(|name| name == "public") {
+            return value;
+        }
+
+        let missing = [
+            ("s-maxage", self.s_maxage),
+            ("stale-while-revalidate", self.stale_while_revalidate),
+        ]
+        .into_iter()
+        .filter(|(name, _)| !names.iter().any(|known| known == name))
+        .filter_map(|(name, seconds)| Some(format!("{name}={}", seconds?)))
+        .collect::<Vec<_>>();
+
+        if missing.is_empty() {
+            return value;
+        }
+
+        let extended = format!("{current}, {}", missing.join(", "));
+
+        HeaderValue::from_str(&extended).unwrap_or(value)
+    }
+}
+
+/// All configured cache rules, in order
+#[derive(Clone, Debug, Default)]
+pub struct CacheRules {
+    rules: Vec<CacheRule>,
+
+    /// For files without a matching rule
+    default: Option<HeaderValue>,
+}
+
+impl CacheRules {
+    pub fn new(rules: &[CacheRule], max_age: Option<u64>) -> Self {
+        Self {
+            rules: rules.to_vec(),
+            default: max_age.and_then(|max_age| {
+                HeaderValue::from_str(&format!("public, max-age={max_age}")).ok()
+            }),
+        }
+    }
+
+    /// The rules of the configuration, with the directives for shared caches
+    pub fn from_config(config: &Config) -> Self {
+        let directives = SharedCacheDirectives {
+            s_maxage: config.s_maxage,
+            stale_while_revalidate: config.stale_while_revalidate,
+        };
+
+        let Self { rules, default } = Self::new(&config.cache, config.max_age);
+
+        Self {
+            rules: rules
+                .into_iter()
+                .map(|rule| CacheRule {
+                    value: directives.apply(rule.value),
+                    ..rule
+                })
+                .collect(),
+            default: default.map(|default| directives.apply(default)),
+        }
+    }
+
+    /// The `Cache-Control` header for files without a matching rule, when the
+    /// response has none of its own, ie for media segments
+    pub fn default_cache_control(&self) -> Option<HeaderValue> {
+        self.default.clone()
+    }
+
+    /// The `Cache-Control` header of the rule for a file, relative to the base dir
+    pub fn cache_control(&self, file: &Path, is_fallback: bool) -> Option<HeaderValue> {
+        if is_fallback {
+            return Some(HeaderValue::from_static(CACHE_CONTROL_NO_CACHE));
+        }
+
+        self.rules
+            .iter()
+            .find(|rule| rule.matches(file))
+            .map(|rule| rule.value.clone())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rules(rules: &[&str]) -> CacheRules {
+        let rules = rules
+            .iter()
+            .map(|rule| rule.parse::<CacheRule>().expect("A valid rule"))
+            .collect::<Vec<_>>();
+
+        CacheRules::new(&rules, None)
+    }
+
+    #[test]
+    fn test_invalid_rules() {
+        assert!("*.css".parse::<CacheRule>().is_err());
+        assert!("=no-cache".parse::<CacheRule>().is_err());
+        assert!("*.css=".parse::<CacheRule>().is_err());
+        assert!("*.css=no\ncache".parse::<CacheRule>().is_err());
+    }
+
+    #[test]
+    fn test_cache_control() {
+        let rules = rules(&[
+            "/assets/*=public,max-age=31536000,immutable",
+            "*.css, *.js=public,max-age=3600",
+            "*.html=no-cache",
+        ]);
+
+        let cache_control = |file: &str, is_fallback| {
+            rules
+                .cache_control(Path::new(file), is_fallback)
+                .map(|value| value.to_str().expect("A valid value").to_string())
+        };
+
+        assert_eq!(
+            cache_control("assets/app.js", false).as_deref(),
+            Some("public,max-age=31536000,immutable")
+        );
+        assert_eq!(
+            cache_control("app.js", false).as_deref(),
+            Some("public,max-age=3600")
+        );
+        assert_eq!(
+            cache_control("docs/index.html", false).as_deref(),
+            Some("no-cache")
+        );
+        assert_eq!(cache_control("photo.png", false), None);
+        assert_eq!(cache_control("app.js", true).as_deref(), Some("no-cache"));
+    }
+
+    #[test]
+    fn test_max_age() {
+        let rule = "*.html=no-cache"
+            .parse::<CacheRule>()
+            .expect("A valid rule");
+        let rules = CacheRules::new(&[rule], Some(3600));
+
+        assert_eq!(rules.cache_control(Path::new("app.js"), false), None);
+        assert_eq!(
+            rules.default_cache_control(),
+            Some(HeaderValue::from_static("public, max-age=3600"))
+        );
+        assert_eq!(
+            rules.cache_control(Path::new("index.html"), false),
+            Some(HeaderValue::from_static("no-cache"))
+        );
+        assert_eq!(
+            rules.cache_control(Path::new("index.html"), true),
+            Some(HeaderValue::from_static("no-cache"))
+        );
+    }
+
+    #[test]
+    fn test_shared_cache_directives() {
+        let directives = SharedCacheDirectives {
+            s_maxage: Some(600),
+            stale_while_revalidate: Some(60),
+        };
+
+        assert_eq!(
+            directives.apply(HeaderValue::from_static("public, max-age=60")),
+            "public, max-age=60, s-maxage=600, stale-while-revalidate=60"
+        );
+        assert_eq!(
+            directives.apply(HeaderValue::from_static("Public,S-Maxage=10")),
+            "Public,S-Maxage=10, stale-while-revalidate=60"
+        );
+        assert_eq!(
+            directives.apply(HeaderValue::from_static("no-cache")),
+            "no-cache"
+        );
+        assert_eq!(
+            directives.apply(HeaderValue::from_static("private, max-age=60")),
+            "private, max-age=60"
+        );
+    }
+}
@@ -0,0 +1,202 @@
+//! A/B canary between two content directories
+//!
+//! A percentage of the clients is served from the canary dir instead of the
+//! base dir. Clients stick to their variant, either via a cookie or by hashing
+//! their IP address. The variant is part of the request span, so it shows up
+//! in the logs of every request.
+
+use std::net::IpAddr;
+use std::net::SocketAddr;
+
+use axum::extract::ConnectInfo;
+use axum::extract::Request;
+use axum::extract::State;
+use axum::http::header::COOKIE;
+use axum::http::header::SET_COOKIE;
+use axum::http::HeaderMap;
+use axum::http::HeaderValue;
+use axum::middleware::Next;
+use axum::response::Response;
+use clap::ValueEnum;
+use tracing::Span;
+
+use crate::app::ServerState;
+use crate::utils::chance;
+
+/// Name of the cookie that keeps clients on their variant
+const COOKIE_NAME: &str = "srvr_variant";
+
+/// How long a client keeps its variant, 30 days
+const COOKIE_MAX_AGE: u64 = 30 * 24 * 60 * 60;
+
+/// The content a client gets
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Variant {
+    #[default]
+    Stable,
+    Canary,
+}
+
+impl Variant {
+    const fn name(self) -> &'static str {
+        match self {
+            Self::Stable => "stable",
+            Self::Canary => "canary",
+        }
+    }
+
+    fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "stable" => Some(Self::Stable),
+            "canary" => Some(Self::Canary),
+            _ => None,
+        }
+    }
+}
+
+/// How clients stick to their variant
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, ValueEnum)]
+pub enum Stickiness {
+    /// Remember the variant in a cookie, new clients are assigned at random
+    #[default]
+    Cookie,
+
+    /// Derive the variant from a hash of the IP address of the client
+    Ip,
+}
+
+/// Variant of the client, from its cookie
+fn variant_from_cookie(headers: &HeaderMap) -> Option<Variant> {
+    headers
+        .get_all(COOKIE)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(';'))
+        .filter_map(|cookie| cookie.trim().split_once('='))
+        .find(|(name, _)| *name == COOKIE_NAME)
+        .and_then(|(_, value)| Variant::from_name(value))
+}
+
+/// Variant of the client, from a hash of its IP address
+///
+/// The hash (FNV-1a) is stable, so a client gets the same variant from every
+/// instance and across restarts
+fn variant_from_ip(ip: IpAddr, percent: u8) -> Variant {
+    let octets = match ip {
+        IpAddr::V4(ip) => ip.octets().to_vec(),
+        IpAddr::V6(ip) => ip
+            .to_ipv4_mapped()
+            .map_or_else(|| ip.octets().to_vec(), |ip| ip.octets().to_vec()),
+    };
+
+    let hash = octets
+        .iter()
+        .fold(0xcbf2_9ce4_8422_2325_u64, |hash, octet| {
+            (hash ^ u64::from(*octet)).wrapping_mul(0x0100_0000_01b3)
+        });
+
+    if hash % 100 < u64::from(percent) {
+        Variant::Canary
+    } else {
+        Variant::Stable
+    }
+}
+
+/// Variant of a new client, at random
+fn random_variant(percent: u8) -> Variant {
+    if chance(percent) {
+        Variant::Canary
+    } else {
+        Variant::Stable
+    }
+}
+
+/// Middleware that assigns a variant to every request, available as a request extension
+pub async fn canary(
+    State(state): State<ServerState>,
+    mut request: Request,
+    next: Next,
+) -> Response {
+    let percent = state.config.canary_percent;
+
+    let (variant, is_new) = match state.config.canary_sticky {
+        Stickiness::Cookie => variant_from_cookie(request.headers()).map_or_else(
+            || (random_variant(percent), true),
+            |variant| (variant, false),
+        ),
+
+        Stickiness::Ip => {
+            let variant = request
+                .extensions()
+                .get::<ConnectInfo<SocketAddr>>()
+                .map_or(Variant::Stable, |ConnectInfo(address)| {
+                    variant_from_ip(address.ip(), percent)
+                });
+
+            (variant, false)
+        }
+    };
+
+    Span::current().record("variant", variant.name());
+    request.extensions_mut().insert(variant);
+
+    let mut response = next.run(request).await;
+
+    if is_new {
+        let cookie = format!(
+            "{COOKIE_NAME}={}; Path=/; Max-Age={COOKIE_MAX_AGE}; SameSite=Lax",
+            variant.name()
+        );
+
+        if let Ok(cookie) = HeaderValue::from_str(&cookie) {
+            response.headers_mut().append(SET_COOKIE, cookie);
+        }
+    }
+
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_variant_from_cookie() {
+        let mut headers = HeaderMap::new();
+        assert_eq!(variant_from_cookie(&headers), None);
+
+        headers.insert(
+            COOKIE,
+            HeaderValue::from_static("theme=dark; srvr_variant=canary"),
+        );
+        assert_eq!(variant_from_cookie(&headers), Some(Variant::Canary));
+
+        headers.insert(COOKIE, HeaderValue::from_static("srvr_variant=other"));
+        assert_eq!(variant_from_cookie(&headers), None);
+    }
+
+    #[test]
+    fn test_variant_from_ip() {
+        let ip = IpAddr::from([192, 168, 1, 10]);
+
+        assert_eq!(variant_from_ip(ip, 0), Variant::Stable);
+        assert_eq!(variant_from_ip(ip, 100), Variant::Canary);
+        assert_eq!(variant_from_ip(ip, 50), variant_from_ip(ip, 50));
+
+        // IPv4 clients on a dual stack socket get the same variant
+        let mapped = IpAddr::from([0, 0, 0, 0, 0, 0xffff, 0xc0a8, 0x010a]);
+        for percent in 0..=100 {
+            assert_eq!(
+                variant_from_ip(ip, percent),
+                variant_from_ip(mapped, percent)
+            );
+        }
+
+        let canaries = (0..=255)
+            .filter(|octet| {
+                variant_from_ip(IpAddr::from([10, 0, 0, *octet]), 20) == Variant::Canary
+            })
+            .count();
+        assert!((20..=90).contains(&canaries));
+    }
+}
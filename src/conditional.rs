@@ -0,0 +1,237 @@
+//! Conditional requests
+//!
+//! All precondition headers are evaluated in one place, against the `ETag` and
+//! the modification date of the file that would be served. A client with the
+//! latest version gets a 304, a client whose precondition does not hold (ie an
+//! `If-Match` for an outdated version) gets a 412.
+
+use axum::http::HeaderMap;
+use axum::http::Method;
+use axum_extra::headers::ETag;
+use axum_extra::headers::HeaderMapExt;
+use axum_extra::headers::IfMatch;
+use axum_extra::headers::IfModifiedSince;
+use axum_extra::headers::IfNoneMatch;
+use axum_extra::headers::IfUnmodifiedSince;
+use httpdate::HttpDate;
+
+/// Outcome of the preconditions of a request
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Precondition {
+    /// Serve the file as usual
+    Passed,
+
+    /// The client has the latest version, respond with a 304
+    NotModified,
+
+    /// The precondition does not hold, respond with a 412
+    Failed,
+}
+
+/// Precondition headers of a request
+#[derive(Debug, Default)]
+pub struct Preconditions {
+    matches: Option<IfMatch>,
+    unmodified_since: Option<IfUnmodifiedSince>,
+    none_match: Option<IfNoneMatch>,
+    modified_since: Option<IfModifiedSince>,
+}
+
+impl Preconditions {
+    pub fn from_headers(headers: &HeaderMap) -> Self {
+        Self {
+            matches: headers.typed_get(),
+            unmodified_since: headers.typed_get(),
+            none_match: headers.typed_get(),
+            modified_since: headers.typed_get(),
+        }
+    }
+
+    /// Evaluate the preconditions for the file with the `ETag` and modification date
+    ///
+    /// The order is the one of RFC 9110 (section 13.2.2), a header is ignored
+    /// when a more precise one is present: the `ETag` conditions win over the
+    /// date conditions. Without a modification date, the date conditions are
+    /// ignored altogether.
+    pub fn evaluate(
+        &self,
+        method: &Method,
+        etag: &ETag,
+        last_modified: Option<HttpDate>,
+    ) -> Precondition {
+        let is_get_or_head = matches!(*method, Method::GET | Method::HEAD);
+
+        // 1. and 2., the client wants to act on a specific version
+        let has_version = match (&self.matches, &self.unmodified_since, last_modified) {
+            (Some(if_match), _, _) => if_match.precondition_passes(etag),
+            (None, Some(if_unmodified_since), Some(last_modified)) => {
+                if_unmodified_since.precondition_passes(last_modified.into())
+            }
+            _ => true,
+        };
+
+        if !has_version {
+            return Precondition::Failed;
+        }
+
+        // 3. and 4., the client has a version already
+        let has_latest = match (&self.none_match, &self.modified_since, last_modified) {
+            (Some(if_none_match), _, _) => !if_none_match.precondition_passes(etag),
+            (None, Some(if_modified_since), Some(last_modified)) if is_get_or_head => {
+                !if_modified_since.is_modified(last_modified.into())
+            }
+            _ => false,
+        };
+
+        if !has_latest {
+            Precondition::Passed
+        } else if is_get_or_head {
+            Precondition::NotModified
+        } else {
+            // other methods would change the resource, they can not use a cached version
+            Precondition::Failed
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+    use std::time::SystemTime;
+
+    use axum::http::HeaderValue;
+
+    use super::*;
+
+    fn etag() -> ETag {
+        "\"abc\"".parse().expect("A valid ETag")
+    }
+
+    fn preconditions(headers: &[(&'static str, &'static str)]) -> Preconditions {
+        let mut header_map = HeaderMap::new();
+
+        for (name, value) in headers {
+            header_map.insert(*name, HeaderValue::from_static(value));
+        }
+
+        Preconditions::from_headers(&header_map)
+    }
+
+    fn last_modified() -> HttpDate {
+        HttpDate::from(SystemTime::UNIX_EPOCH + Duration::from_secs(1_700_000_000))
+    }
+
+    #[test]
+    fn test_no_preconditions() {
+        assert_eq!(
+            Preconditions::default().evaluate(&Method::GET, &etag(), Some(last_modified())),
+            Precondition::Passed
+        );
+    }
+
+    #[test]
+    fn test_if_match() {
+        let current = preconditions(&[("if-match", "\"abc\"")]);
+        let any = preconditions(&[("if-match", "*")]);
+        let outdated = preconditions(&[("if-match", "\"def\"")]);
+
+        for method in [Method::GET, Method::PUT] {
+            assert_eq!(
+                current.evaluate(&method, &etag(), Some(last_modified())),
+                Precondition::Passed
+            );
+            assert_eq!(
+                any.evaluate(&method, &etag(), Some(last_modified())),
+                Precondition::Passed
+            );
+            assert_eq!(
+                outdated.evaluate(&method, &etag(), Some(last_modified())),
+                Precondition::Failed
+            );
+        }
+    }
+
+    #[test]
+    fn test_if_unmodified_since() {
+        let unmodified = preconditions(&[("if-unmodified-since", "Tue, 14 Nov 2023 22:13:20 GMT")]);
+        let modified = preconditions(&[("if-unmodified-since", "Tue, 14 Nov 2023 22:13:19 GMT")]);
+
+        assert_eq!(
+            unmodified.evaluate(&Method::GET, &etag(), Some(last_modified())),
+            Precondition::Passed
+        );
+        assert_eq!(
+            modified.evaluate(&Method::GET, &etag(), Some(last_modified())),
+            Precondition::Failed
+        );
+    }
+
+    #[test]
+    fn test_if_none_match() {
+        let current = preconditions(&[("if-none-match", "\"def\", \"abc\"")]);
+        let outdated = preconditions(&[("if-none-match", "\"def\"")]);
+
+        assert_eq!(
+            current.evaluate(&Method::GET, &etag(), Some(last_modified())),
+            Precondition::NotModified
+        );
+        assert_eq!(
+            current.evaluate(&Method::POST, &etag(), Some(last_modified())),
+            Precondition::Failed
+        );
+        assert_eq!(
+            outdated.evaluate(&Method::GET, &etag(), Some(last_modified())),
+            Precondition::Passed
+        );
+    }
+
+    #[test]
+    fn test_precedence() {
+        use Precondition::*;
+
+        let now = "Tue, 14 Nov 2023 22:13:20 GMT";
+        let before = "Tue, 14 Nov 2023 22:13:19 GMT";
+
+        #[rustfmt::skip]
+        let cases = [
+            // If-Match wins over If-Unmodified-Since, either way
+            (Method::GET, vec![("if-match", "\"abc\""), ("if-unmodified-since", before)], Passed),
+            (Method::GET, vec![("if-match", "\"def\""), ("if-unmodified-since", now)], Failed),
+
+            // If-None-Match wins over If-Modified-Since, either way
+            (Method::GET, vec![("if-none-match", "\"def\""), ("if-modified-since", now)], Passed),
+            (Method::GET, vec![("if-none-match", "\"abc\""), ("if-modified-since", before)], NotModified),
+
+            // a failed precondition wins over a client with the latest version
+            (Method::GET, vec![("if-match", "\"def\""), ("if-none-match", "\"abc\"")], Failed),
+            (Method::HEAD, vec![("if-unmodified-since", before), ("if-modified-since", now)], Failed),
+
+            // If-Modified-Since only applies to GET and HEAD
+            (Method::HEAD, vec![("if-modified-since", now)], NotModified),
+            (Method::POST, vec![("if-modified-since", now)], Passed),
+            (Method::POST, vec![("if-none-match", "*")], Failed),
+        ];
+
+        for (method, headers, expected) in cases {
+            assert_eq!(
+                preconditions(&headers).evaluate(&method, &etag(), Some(last_modified())),
+                expected,
+                "{method} with {headers:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn test_without_last_modified() {
+        let before = "Tue, 14 Nov 2023 22:13:19 GMT";
+
+        assert_eq!(
+            preconditions(&[("if-unmodified-since", before)]).evaluate(&Method::GET, &etag(), None),
+            Precondition::Passed
+        );
+        assert_eq!(
+            preconditions(&[("if-modified-since", before)]).evaluate(&Method::GET, &etag(), None),
+            Precondition::Passed
+        );
+    }
+}
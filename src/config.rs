@@ -1,17 +1,65 @@
+use std::ffi::OsString;
 use std::fs::metadata;
+use std::fs::read_to_string;
 use std::io;
+use std::num::NonZeroUsize;
 use std::path::PathBuf;
 use std::process::exit;
+use std::time::Duration;
 
+use axum::http::HeaderName;
+use axum::http::HeaderValue;
+use axum::http::Method;
+use axum::http::Uri;
+use clap::builder::BoolishValueParser;
+use clap::Arg;
+use clap::ArgAction;
 use clap::Args;
 use clap::Command;
 use clap::CommandFactory;
+use clap::FromArgMatches;
 use clap::Parser;
+use clap::Subcommand;
 use clap::ValueHint;
 use clap_complete::generate;
 use clap_complete::Generator;
 use clap_complete::Shell;
 
+use crate::audit::AuditLog;
+use crate::auth::rules::AuthRules;
+use crate::auth::rules::AuthRulesError;
+use crate::auth::signed_url::SignConfig;
+use crate::auth::Credentials;
+use crate::auth::Htpasswd;
+use crate::auth::HtpasswdError;
+use crate::auth::TokenHashes;
+use crate::auth::TokenHashesError;
+use crate::bench::BenchConfig;
+use crate::cache_rules::CacheRule;
+use crate::canary::Stickiness;
+use crate::encoding::is_compressible;
+use crate::encoding::ContentTypePattern;
+use crate::encoding::Encoding;
+use crate::explain::ExplainConfig;
+use crate::forwarded::TrustedProxy;
+use crate::header_rules::CustomHeader;
+use crate::header_rules::ScopedHeader;
+use crate::listing::check_template;
+use crate::listing::ListingTemplateError;
+use crate::precompress::PrecompressConfig;
+use crate::proxy::ProxyMount;
+use crate::rate_limit::RateLimit;
+use crate::redirects::Redirect;
+use crate::shadow::ShadowTarget;
+use crate::throttle::parse_byte_rate;
+use crate::tls::AlpnProtocol;
+use crate::tls::SniCertificate;
+use crate::trailing_slash::TrailingSlash;
+use crate::utils::parse_byte_size;
+
+/// Prefix of the environment variables of the options
+const ENV_PREFIX: &str = "SRVR_";
+
 #[derive(Debug, thiserror::Error)]
 pub enum ConfigError {
     #[error("Could not open base dir \"{0}\": {1}")]
@@ -19,31 +67,148 @@ pub enum ConfigError {
 
     #[error("Could not open fallback path \"{0}\": {1}")]
     InvalidFallbackPath(PathBuf, std::io::Error),
+
+    #[error("Could not open not found path \"{0}\": {1}")]
+    InvalidNotFoundPath(PathBuf, std::io::Error),
+
+    #[error(transparent)]
+    InvalidListingTemplate(#[from] ListingTemplateError),
+
+    #[error("Could not open canary dir \"{0}\": {1}")]
+    MissingCanaryDir(PathBuf, std::io::Error),
+
+    #[error("Client certificates need a certificate of our own, via --tls-cert or --tls-sni")]
+    ClientCaWithoutCertificate,
+
+    #[error("Redirecting to HTTPS needs TLS, via --tls-cert, --tls-sni or --acme-domain")]
+    RedirectWithoutTls,
+
+    #[error("Could not read auth file \"{0}\": {1}")]
+    MissingAuthFile(PathBuf, std::io::Error),
+
+    #[error("Invalid auth file \"{0}\": {1}")]
+    InvalidAuthFile(PathBuf, HtpasswdError),
+
+    #[error("Could not read auth rules \"{0}\": {1}")]
+    MissingAuthRules(PathBuf, std::io::Error),
+
+    #[error("Invalid auth rules \"{0}\": {1}")]
+    InvalidAuthRules(PathBuf, AuthRulesError),
+
+    #[error("Auth rules need a way to authenticate, ie --auth, --token or --auth-header")]
+    AuthRulesWithoutAuth,
+
+    #[error("Could not read token file \"{0}\": {1}")]
+    MissingTokenFile(PathBuf, std::io::Error),
+
+    #[error("Invalid token file \"{0}\": {1}")]
+    InvalidTokenFile(PathBuf, TokenHashesError),
+
+    #[error("Could not open audit log \"{0}\": {1}")]
+    InvalidAuditLog(PathBuf, std::io::Error),
+
+    #[error("Could not create an ACME cache dir, set one with --acme-cache: {0}")]
+    MissingAcmeCache(std::io::Error),
+
+    #[cfg(feature = "image-resize")]
+    #[error("Could not create an image cache dir, set one with --image-cache-dir: {0}")]
+    MissingImageCacheDir(std::io::Error),
 }
 
 #[derive(Parser, Clone, Debug)]
 #[command(author, version, about, long_about = None)]
-struct CliConfig {
+pub struct CliConfig {
     /// The actual config for srvr
     #[command(flatten)]
-    config: Config,
+    pub config: Config,
+
+    /// Run a tool instead of serving
+    #[command(subcommand)]
+    pub command: Option<CliCommand>,
 
     /// Generate shell completions
     #[arg(long, value_enum, hide = true)]
     generate_shell_completions: Option<Shell>,
+
+    /// Print the effective configuration as TOML, including the resolved
+    /// address and paths, and exit
+    #[arg(long)]
+    pub print_config: bool,
+}
+
+/// Tools working with the config of srvr
+#[derive(Subcommand, Clone, Debug)]
+pub enum CliCommand {
+    /// Load test a running srvr, or the config in-process
+    Bench(BenchConfig),
+
+    /// Request every servable file in-process and validate the responses
+    Selftest,
+
+    /// Explain how a request path is resolved to a file
+    Explain(ExplainConfig),
+
+    /// Install the mkcert CA and mint a locally trusted certificate
+    Trust,
+
+    /// Write Brotli, Zstandard, gzip and deflate variants of the compressible files
+    Precompress(PrecompressConfig),
+
+    /// Sign a path with the signing secret, for a link that expires
+    Sign(SignConfig),
 }
 
 /// Serve files in a directory on a HTTP endpoint
 #[derive(Args, Clone, Debug)]
+#[allow(clippy::struct_excessive_bools)]
 pub struct Config {
     /// The directory to serve to the world
     #[arg(default_value = ".", value_hint = ValueHint::DirPath)]
     pub base_dir: PathBuf,
 
-    /// The file to use as the fallback file, defaults to <base_dir>/index.html
+    /// Preset for development: nothing is cached (`no-store`), any origin can fetch files,
+    /// directories are listed, pages reload when files change and every request is logged in
+    /// detail
+    #[arg(long)]
+    pub dev: bool,
+
+    /// Reload pages in the browser when files in the base dir change
+    #[arg(long)]
+    pub live_reload: bool,
+
+    /// The file to use as the fallback file, defaults to `<base_dir>/index.html`
     #[arg(long, short)]
     pub fallback_path: Option<PathBuf>,
 
+    /// Use the fallback file for every missing file, not just for page navigations
+    #[arg(long)]
+    pub fallback_always: bool,
+
+    /// Do not use a fallback file, requests for missing files result in a 404
+    #[arg(long, conflicts_with = "fallback_path")]
+    pub no_fallback: bool,
+
+    /// The file to serve with a 404, instead of the nearest `404.html` in the base dir
+    #[arg(long, value_name = "FILE", value_hint = ValueHint::FilePath)]
+    pub not_found_path: Option<PathBuf>,
+
+    /// Serve the 404 page for missing pages, the fallback file only when there is
+    /// none, for sites that are not a single page app
+    #[arg(long, conflicts_with = "no_fallback")]
+    pub prefer_not_found: bool,
+
+    /// Serve `about.html` for `/about`, so URLs can leave out the extension
+    #[arg(long)]
+    pub clean_urls: bool,
+
+    /// Redirect the URLs of files to the form with or without a trailing slash
+    #[arg(long, value_enum, default_value_t)]
+    pub trailing_slash: TrailingSlash,
+
+    /// Redirect `/docs/index.html` to `/docs/`, so every page has a single URL
+    #[arg(long)]
+    pub canonical_index: bool,
+
     /// The address to run srvr on, defaults to 127.0.0.1:12234
     #[arg(long, short)]
     pub address: Option<String>,
@@ -51,6 +216,490 @@ pub struct Config {
     /// The port to run srvr on, defaults to 12234 (overrides `address`)
     #[arg(long, short)]
     pub port: Option<u16>,
+
+    /// Serve HTTPS with this certificate (chain), PEM encoded
+    #[arg(long, value_name = "FILE", value_hint = ValueHint::FilePath, requires = "tls_key")]
+    pub tls_cert: Option<PathBuf>,
+
+    /// Private key of the TLS certificate, PEM encoded
+    #[arg(long, value_name = "FILE", value_hint = ValueHint::FilePath, requires = "tls_cert")]
+    pub tls_key: Option<PathBuf>,
+
+    /// Serve HTTPS with a locally trusted certificate, minted with the CA of mkcert
+    #[arg(long, conflicts_with = "tls_cert")]
+    pub tls_mkcert: bool,
+
+    /// Serve the certificate (chain) and private key for a host, picked via SNI, ie
+    /// `example.com cert.pem key.pem`; the host can start with a wildcard, ie `*.example.com`
+    ///
+    /// The certificate of `--tls-cert` is served for other hosts
+    #[arg(long, value_name = "HOST CERT KEY", conflicts_with = "acme_domain")]
+    pub tls_sni: Vec<SniCertificate>,
+
+    /// Require clients to authenticate with a certificate signed by this CA, PEM encoded
+    ///
+    /// Not available with ACME, its validation requests do not have a client certificate
+    #[arg(long, value_name = "FILE", value_hint = ValueHint::FilePath)]
+    pub tls_client_ca: Option<PathBuf>,
+
+    /// Get a certificate for this domain via ACME (Let's Encrypt), can be repeated
+    ///
+    /// The TLS-ALPN-01 challenge is used, so srvr should be reachable on port 443
+    #[arg(long, value_name = "DOMAIN", conflicts_with = "tls_cert")]
+    pub acme_domain: Vec<String>,
+
+    /// Directory to store the ACME certificates and account in, defaults to
+    /// `srvr/acme` in the user cache dir (`XDG_CACHE_HOME` or `~/.cache`)
+    #[arg(long, value_name = "DIR", value_hint = ValueHint::DirPath, requires = "acme_domain")]
+    pub acme_cache: Option<PathBuf>,
+
+    /// Contact email for the ACME account, used for expiry notices
+    #[arg(long, value_name = "EMAIL", requires = "acme_domain")]
+    pub acme_email: Option<String>,
+
+    /// Use the staging environment of Let's Encrypt, for testing
+    #[arg(long, requires = "acme_domain")]
+    pub acme_staging: bool,
+
+    /// Also listen for plain HTTP on this port, only to redirect to HTTPS
+    #[arg(long, value_name = "PORT")]
+    pub redirect_http: Option<u16>,
+
+    /// Only speak HTTP/1.1, ie for debugging; HTTP/2 is negotiated over TLS by default
+    #[arg(long)]
+    pub http1_only: bool,
+
+    /// Protocols offered over TLS, in order of preference; ie `--alpn http/1.1` disables HTTP/2, `--alpn h2` forces it
+    #[arg(
+        long,
+        value_name = "PROTOCOL",
+        value_enum,
+        value_delimiter = ',',
+        conflicts_with = "http1_only"
+    )]
+    pub alpn: Vec<AlpnProtocol>,
+
+    /// List the contents of directories without an `index.html`, as HTML or as JSON
+    #[arg(long)]
+    pub autoindex: bool,
+
+    /// Template for the HTML directory listings (minijinja), instead of the built-in one
+    #[arg(long, value_name = "FILE", value_hint = ValueHint::FilePath, requires = "autoindex")]
+    pub listing_template: Option<PathBuf>,
+
+    /// Show thumbnails of images in the HTML directory listings
+    #[cfg(feature = "image-resize")]
+    #[arg(long, requires = "autoindex")]
+    pub listing_thumbnails: bool,
+
+    /// Only serve files with these extensions, ie `html,css,js,png`; everything else results in a 404
+    #[arg(long, value_name = "EXTENSIONS", value_delimiter = ',')]
+    pub only_ext: Vec<String>,
+
+    /// Do not serve hidden files and directories, ie paths with a segment starting with a `.`
+    #[arg(long)]
+    pub block_dotfiles: bool,
+
+    /// Path prefix that is exempt from `--block-dotfiles`, can be repeated; an empty value removes the default
+    #[arg(long, value_name = "PREFIX", default_value = "/.well-known")]
+    pub dotfile_exempt: Vec<String>,
+
+    /// Refuse to serve files larger than this size, ie `100MB`
+    #[arg(long, value_name = "SIZE", value_parser = parse_byte_size)]
+    pub max_file_size: Option<u64>,
+
+    /// Maximum size of request bodies, larger requests are rejected with a 413; proxied
+    /// requests are left to the proxied server
+    #[arg(long, value_name = "SIZE", value_parser = parse_byte_size, default_value = "1MB")]
+    pub max_body_size: u64,
+
+    /// Time a client gets to send the headers of a request
+    #[arg(long, value_name = "DURATION", value_parser = humantime::parse_duration, default_value = "10s")]
+    pub header_read_timeout: Duration,
+
+    /// Time a connection can be without any traffic before it is closed
+    #[arg(long, value_name = "DURATION", value_parser = humantime::parse_duration, default_value = "2m")]
+    pub idle_timeout: Duration,
+
+    /// Minimum transfer rate (per second) of clients that are not keeping up, `0` disables the check
+    #[arg(long, value_name = "SIZE", value_parser = parse_byte_size, default_value = "1K")]
+    pub min_rate: u64,
+
+    /// Maximum number of requests per client address, ie `100/10s`, more get a 429
+    ///
+    /// Short bursts up to the number of requests are allowed. Behind a trusted proxy the client
+    /// address is taken from `x-forwarded-for`
+    #[arg(long, value_name = "REQUESTS/PERIOD")]
+    pub rate_limit: Option<RateLimit>,
+
+    /// Maximum number of requests handled at the same time, more get a 503 instead of waiting
+    #[arg(long, value_name = "N")]
+    pub max_concurrent_requests: Option<NonZeroUsize>,
+
+    /// Ban client addresses with this number of 404 or 403 responses within the ban window,
+    /// their requests get a 403 for the ban duration
+    #[arg(long, value_name = "N", value_parser = clap::value_parser!(u32).range(1..))]
+    pub ban_after: Option<u32>,
+
+    /// Window in which the failed requests of a client are counted
+    #[arg(long, value_name = "DURATION", value_parser = humantime::parse_duration, default_value = "1m", requires = "ban_after")]
+    pub ban_window: Duration,
+
+    /// Time a client stays banned
+    #[arg(long, value_name = "DURATION", value_parser = humantime::parse_duration, default_value = "10m", requires = "ban_after")]
+    pub ban_duration: Duration,
+
+    /// Append a JSON line to this file for every request refused for a security reason, ie a
+    /// failed authentication or a rate limited client
+    #[arg(long, value_name = "FILE", value_hint = ValueHint::FilePath)]
+    pub audit_log: Option<PathBuf>,
+
+    /// Maximum number of simultaneous connections from a single address, more are closed right
+    /// away
+    ///
+    /// Behind a proxy every connection comes from the proxy, use `--rate-limit` instead
+    #[arg(long, value_name = "N")]
+    pub max_connections_per_ip: Option<NonZeroUsize>,
+
+    /// Maximum transfer rate of all responses together, ie `500KB/s`
+    #[arg(long, value_name = "RATE", value_parser = parse_byte_rate)]
+    pub throttle: Option<u64>,
+
+    /// Maximum transfer rate of every connection, ie `100KB/s`
+    #[arg(long, value_name = "RATE", value_parser = parse_byte_rate)]
+    pub throttle_connection: Option<u64>,
+
+    /// Redirect requests with duplicate slashes or dot segments to their normalized URL
+    #[arg(long)]
+    pub normalize_redirect: bool,
+
+    /// Redirect a path to another location, ie `/old /new 308`, or `/old 410` for removed content
+    ///
+    /// The status code defaults to 301
+    #[arg(long, value_name = "FROM TO [STATUS]")]
+    pub redirect: Vec<Redirect>,
+
+    /// Do not send `X-Content-Type-Options: nosniff`, letting browsers guess the type of files
+    #[arg(long)]
+    pub allow_sniffing: bool,
+
+    /// Send this `Server` header with every response, ie `srvr`, replacing the one of a
+    /// proxied server
+    #[arg(long, value_name = "VALUE")]
+    pub server_header: Option<HeaderValue>,
+
+    /// Never send a `Server` header, not even the one of a proxied server
+    #[arg(long, conflicts_with = "server_header")]
+    pub no_server_header: bool,
+
+    /// Allow requests from any origin (CORS), ie to load fonts or to `fetch()` files from
+    /// another site
+    #[arg(long)]
+    pub cors: bool,
+
+    /// Allow requests from this origin (CORS), ie `https://example.com`, instead of from any
+    #[arg(long, value_name = "ORIGIN")]
+    pub cors_origin: Vec<HeaderValue>,
+
+    /// Methods allowed for cross-origin requests, defaults to `GET,HEAD`
+    #[arg(long, value_name = "METHODS", value_delimiter = ',')]
+    pub cors_methods: Vec<Method>,
+
+    /// Request headers allowed for cross-origin requests, defaults to the ones a preflight asks for
+    #[arg(long, value_name = "HEADERS", value_delimiter = ',')]
+    pub cors_headers: Vec<HeaderName>,
+
+    /// How long browsers can cache the answer to a preflight request, in seconds
+    #[arg(long, value_name = "SECONDS")]
+    pub cors_max_age: Option<u64>,
+
+    /// Send hardening headers: `X-Content-Type-Options`, `X-Frame-Options`, `Referrer-Policy`
+    /// and `Permissions-Policy`
+    ///
+    /// Each can be overridden with `--header`, or removed with an empty value, ie
+    /// `--header "X-Frame-Options:"`
+    #[arg(long)]
+    pub secure: bool,
+
+    /// Set the `Cache-Control` header of matching files, ie `*.css,*.js=public,max-age=31536000`
+    ///
+    /// Patterns with a `/` match the path within the base dir, ie `/assets/*`, others the file
+    /// name. The first matching rule wins, the fallback file is always `no-cache`
+    #[arg(long, value_name = "PATTERNS=CACHE-CONTROL")]
+    pub cache: Vec<CacheRule>,
+
+    /// Cache files for this many seconds, `Cache-Control: public, max-age=<seconds>`, for files
+    /// without a matching `--cache` rule
+    #[arg(long, value_name = "SECONDS")]
+    pub max_age: Option<u64>,
+
+    /// Let shared caches, like a CDN, keep `public` files for this many seconds, via `s-maxage`
+    #[arg(long, value_name = "SECONDS")]
+    pub s_maxage: Option<u64>,
+
+    /// Let caches serve a stale `public` file for this many seconds while they revalidate it
+    #[arg(long, value_name = "SECONDS")]
+    pub stale_while_revalidate: Option<u64>,
+
+    /// Add a header to every response, ie `X-Frame-Options: DENY`, an empty value removes it
+    #[arg(long, value_name = "NAME: VALUE")]
+    pub header: Vec<CustomHeader>,
+
+    /// Add a header to the responses of matching paths, ie `/admin/*=X-Frame-Options: DENY`
+    ///
+    /// Paths can have `:name` placeholders and end with a `*`, like in a `_headers` file
+    #[arg(long, value_name = "PATH=NAME: VALUE")]
+    pub header_for: Vec<ScopedHeader>,
+
+    /// Forward requests matching a prefix to another server, ie `/api=http://localhost:3000/v1`
+    ///
+    /// When the target has a path, the prefix is replaced by that path. With authentication, the
+    /// `authorization` header of the client is not forwarded
+    #[arg(long, value_name = "PREFIX=URL")]
+    pub proxy: Vec<ProxyMount>,
+
+    /// Forward the original `host` header to proxied servers, instead of the host of the target
+    #[arg(long)]
+    pub proxy_preserve_host: bool,
+
+    /// Trust the `forwarded` and `x-forwarded-*` headers of requests from this proxy,
+    /// an IP address or a network, ie `10.0.0.0/8`
+    ///
+    /// The original scheme and host of requests are used for redirects and forwarded
+    /// to proxied servers
+    #[arg(long, value_name = "IP[/PREFIX]")]
+    pub trusted_proxy: Vec<TrustedProxy>,
+
+    /// Use absolute URLs for the location of redirects, with the original scheme and host
+    #[arg(long)]
+    pub absolute_redirects: bool,
+
+    /// Mirror `GET` and `HEAD` requests to this server, its responses are discarded
+    #[arg(long, value_name = "URL")]
+    pub shadow: Option<ShadowTarget>,
+
+    /// Percentage of the requests to mirror to the shadow server
+    #[arg(long, value_name = "PERCENT", default_value_t = 100, value_parser = clap::value_parser!(u8).range(0..=100))]
+    pub shadow_sample: u8,
+
+    /// Send a `Strict-Transport-Security` header, with a max age in seconds (one year by default)
+    #[arg(long, value_name = "SECONDS", num_args = 0..=1, require_equals = true, default_missing_value = "31536000")]
+    pub hsts: Option<u64>,
+
+    /// Let the HSTS header apply to all subdomains as well
+    #[arg(long, requires = "hsts")]
+    pub hsts_include_subdomains: bool,
+
+    /// Mark the HSTS header for inclusion in the browser preload lists
+    #[arg(long, requires = "hsts_include_subdomains")]
+    pub hsts_preload: bool,
+
+    /// Enable cross-origin isolation (COOP/COEP/CORP headers), needed for `SharedArrayBuffer`
+    #[arg(long)]
+    pub coi: bool,
+
+    /// Ask search engines not to index anything, via `x-robots-tag` and a deny-all `robots.txt`
+    #[arg(long)]
+    pub noindex: bool,
+
+    /// Respond with a JSON body for errors, not only for clients preferring JSON
+    #[arg(long)]
+    pub json_errors: bool,
+
+    /// Content-Security-Policy for HTML documents, `{nonce}` is replaced by a per-response nonce
+    /// that is added to the `<script>` and `<style>` tags of the document
+    #[arg(long, value_name = "POLICY")]
+    pub csp: Option<String>,
+
+    /// Only report violations of the Content-Security-Policy, via
+    /// `Content-Security-Policy-Report-Only`, instead of enforcing it
+    #[arg(long, requires = "csp")]
+    pub csp_report_only: bool,
+
+    /// Send the Content-Security-Policy with every response, not only with HTML documents
+    #[arg(long, requires = "csp")]
+    pub csp_all: bool,
+
+    /// Scan HTML documents for stylesheets and scripts, sent as `103 Early Hints` and `link` headers
+    #[arg(long)]
+    pub early_hints: bool,
+
+    /// URL to preload for every HTML document, sent as `103 Early Hints` and `link` headers
+    #[arg(long, value_name = "URL")]
+    pub preload: Vec<String>,
+
+    /// Directory with releases, the admin API can switch to serving one of its directories
+    #[arg(long, value_name = "DIR", value_hint = ValueHint::DirPath, requires = "admin_token")]
+    pub releases_dir: Option<PathBuf>,
+
+    /// Serve a percentage of the clients from this directory instead of the base dir
+    #[arg(long, value_name = "DIR", value_hint = ValueHint::DirPath)]
+    pub canary_dir: Option<PathBuf>,
+
+    /// Percentage of the clients that gets the canary dir
+    #[arg(long, value_name = "PERCENT", default_value_t = 10, value_parser = clap::value_parser!(u8).range(0..=100))]
+    pub canary_percent: u8,
+
+    /// How clients stick to the canary or the base dir
+    #[arg(long, value_enum, default_value_t)]
+    pub canary_sticky: Stickiness,
+
+    /// Minify HTML, CSS and JavaScript files, the minified files are cached
+    #[arg(long)]
+    pub minify: bool,
+
+    /// Compress text files on the fly when there is no precompressed variant,
+    /// the compressed files are cached
+    #[arg(long)]
+    pub compress: bool,
+
+    /// Encodings to serve, precompressed or compressed on the fly, ie `br,gzip`
+    /// to turn off the others
+    #[arg(
+        long,
+        value_enum,
+        value_name = "ENCODINGS",
+        value_delimiter = ',',
+        default_values_t = Encoding::ALL
+    )]
+    pub encodings: Vec<Encoding>,
+
+    /// Serve precompressed variants without their original file, ie when only
+    /// `app.js.gz` is deployed, by default they are skipped
+    #[arg(long)]
+    pub serve_orphan_sidecars: bool,
+
+    /// Content types to compress (on the fly or with `srvr precompress`), ie
+    /// `text/*,application/json`, defaults to text, JSON, XML, JavaScript, SVG and a few more
+    #[arg(long, value_name = "TYPES", value_delimiter = ',')]
+    pub compress_types: Vec<ContentTypePattern>,
+
+    /// Files smaller than this size are not compressed, it is not worth it
+    #[arg(long, value_name = "SIZE", value_parser = parse_byte_size, default_value = "1K")]
+    pub compress_min_size: u64,
+
+    /// Persist the file cache to this file on shutdown, and restore it on startup
+    #[arg(long, value_name = "FILE", value_hint = ValueHint::FilePath)]
+    pub cache_snapshot: Option<PathBuf>,
+
+    /// Enable the admin API under `/_srvr/`, requests should authenticate with this bearer token
+    #[arg(long, value_name = "TOKEN")]
+    pub admin_token: Option<String>,
+
+    /// Require HTTP Basic authentication for every request, with this user, ie `admin:secret`
+    #[arg(long, value_name = "USER:PASSWORD")]
+    pub auth: Vec<Credentials>,
+
+    /// Require HTTP Basic authentication for every request, with the users of this htpasswd
+    /// file, with bcrypt, MD5 or SHA-crypt passwords
+    #[arg(long, value_name = "FILE", value_hint = ValueHint::FilePath)]
+    pub auth_file: Option<PathBuf>,
+
+    /// Realm of the HTTP Basic authentication, shown by some browsers when asking for credentials
+    #[arg(long, value_name = "REALM", default_value = "srvr")]
+    pub auth_realm: String,
+
+    /// Require an `authorization: Bearer <TOKEN>` header for every request, with this token
+    #[arg(long, value_name = "TOKEN")]
+    pub token: Vec<String>,
+
+    /// Require an `authorization: Bearer <TOKEN>` header for every request, with a token
+    /// of which the SHA-256 (in hex) is in this file, one per line
+    #[arg(long, value_name = "FILE", value_hint = ValueHint::FilePath)]
+    pub token_file: Option<PathBuf>,
+
+    /// Accept a JSON Web Token signed with this HMAC secret as bearer token, for every request
+    #[arg(long, value_name = "SECRET")]
+    pub jwt_secret: Option<String>,
+
+    /// Accept a JSON Web Token signed with one of the keys at this JWKS URL as bearer token, for
+    /// every request
+    #[arg(long, value_name = "URL")]
+    pub jwks_url: Option<Uri>,
+
+    /// How often to fetch the keys at the JWKS URL again
+    #[arg(long, value_name = "DURATION", value_parser = humantime::parse_duration, default_value = "1h")]
+    pub jwks_refresh: Duration,
+
+    /// Only accept JSON Web Tokens for this audience, its `aud` claim
+    #[arg(long, value_name = "AUDIENCE")]
+    pub jwt_audience: Vec<String>,
+
+    /// Only accept JSON Web Tokens from this issuer, its `iss` claim
+    #[arg(long, value_name = "ISSUER")]
+    pub jwt_issuer: Vec<String>,
+
+    /// Require this header on every request, with the user as authenticated by a trusted proxy,
+    /// ie `X-Auth-Request-User` of oauth2-proxy or `Remote-User` of Authelia
+    #[arg(long, value_name = "HEADER", requires = "trusted_proxy")]
+    pub auth_header: Option<HeaderName>,
+
+    /// Only require authentication for the paths with a rule in this file, optionally for some
+    /// users only, ie `/admin/** alice bob`
+    #[arg(long, value_name = "FILE", value_hint = ValueHint::FilePath)]
+    pub auth_rules: Option<PathBuf>,
+
+    /// Accept URLs signed with this secret, via `srvr sign`, until they expire
+    #[arg(long, value_name = "SECRET")]
+    pub signing_secret: Option<String>,
+
+    /// Only serve the files matching these patterns to pages of this site, or of the allowed
+    /// referers, ie `*.jpg,*.png`
+    ///
+    /// Patterns with a `/` match the path, others the file name. Requests without a referer are
+    /// let through
+    #[arg(long, value_name = "PATTERNS", value_delimiter = ',')]
+    pub hotlink_protect: Vec<String>,
+
+    /// Hosts that can embed the protected files as well, ie `example.com,*.example.com`
+    #[arg(
+        long,
+        value_name = "HOSTS",
+        value_delimiter = ',',
+        requires = "hotlink_protect"
+    )]
+    pub allowed_referers: Vec<String>,
+
+    /// Serve this path instead of a 403 to other sites embedding a protected file, ie
+    /// `/hotlink.png`
+    #[arg(long, value_name = "PATH", requires = "hotlink_protect")]
+    pub hotlink_placeholder: Option<String>,
+
+    /// Validate HTTP Basic credentials with a bind to this LDAP server, ie
+    /// `ldaps://ldap.example.com`
+    #[cfg(feature = "ldap")]
+    #[arg(long, value_name = "URL", requires = "ldap_base_dn")]
+    pub ldap_url: Option<String>,
+
+    /// Base DN of the users on the LDAP server, ie `ou=people,dc=example,dc=com`
+    #[cfg(feature = "ldap")]
+    #[arg(long, value_name = "DN", requires = "ldap_url")]
+    pub ldap_base_dn: Option<String>,
+
+    /// Attribute of the user name in the DN to bind as, `<ATTRIBUTE>=<user>,<base DN>`
+    #[cfg(feature = "ldap")]
+    #[arg(long, value_name = "ATTRIBUTE", default_value = "uid")]
+    pub ldap_user_attribute: String,
+
+    /// How long a successful bind is remembered, before the credentials are checked again
+    #[cfg(feature = "ldap")]
+    #[arg(long, value_name = "DURATION", value_parser = humantime::parse_duration, default_value = "5m")]
+    pub ldap_cache_ttl: Duration,
+
+    /// Optimize for serving media, ie HLS/DASH playlists and segments
+    #[arg(long)]
+    pub media: bool,
+
+    /// Resize images on the fly via the `w`, `h` and `format` query parameters
+    #[cfg(feature = "image-resize")]
+    #[arg(long)]
+    pub image_resize: bool,
+
+    /// The directory to cache resized images in, defaults to `srvr/images` in the
+    /// user cache dir (`XDG_CACHE_HOME` or `~/.cache`)
+    #[cfg(feature = "image-resize")]
+    #[arg(long, value_hint = ValueHint::DirPath)]
+    pub image_cache_dir: Option<PathBuf>,
 }
 
 /// Print the completions for srvr and `exit(0)`
@@ -59,17 +708,135 @@ fn print_completions<G: Generator>(gen: G, cmd: &mut Command) -> ! {
     exit(0);
 }
 
-impl Config {
+impl CliConfig {
     /// Create a config from the environment
-    pub fn from_env() -> anyhow::Result<Self> {
-        let cli_config = CliConfig::parse();
+    pub fn from_env() -> Self {
+        Self::from_args(std::env::args_os())
+    }
+
+    /// Create a config from the arguments, with the presets applied, options
+    /// can be set via the environment as well
+    pub fn from_args<I, T>(args: I) -> Self
+    where
+        I: IntoIterator<Item = T>,
+        T: Into<OsString> + Clone,
+    {
+        let matches = Self::command_with_env().get_matches_from(args);
+        let mut cli_config = Self::from_arg_matches(&matches).unwrap_or_else(|err| err.exit());
+
+        if cli_config.config.dev {
+            cli_config.config.apply_dev_preset();
+        }
 
         if let Some(generate_shell_completions) = cli_config.generate_shell_completions {
-            let mut cli_command = CliConfig::command();
+            let mut cli_command = Self::command_with_env();
             print_completions(generate_shell_completions, &mut cli_command);
         }
 
-        let config = cli_config.config;
+        cli_config
+    }
+
+    /// Command where every option of the config can be set with an environment
+    /// variable as well, ie `SRVR_BASE_DIR` or `SRVR_FALLBACK_PATH`
+    fn command_with_env() -> Command {
+        let config_command = Config::augment_args(Command::new("srvr"));
+        let config_ids = config_command
+            .get_arguments()
+            .map(Arg::get_id)
+            .collect::<Vec<_>>();
+
+        Self::command().mut_args(|arg| {
+            if !config_ids.contains(&arg.get_id()) {
+                return arg;
+            }
+
+            let env = format!("{ENV_PREFIX}{}", arg.get_id().as_str().to_uppercase());
+
+            // flags from the environment can be `1`, `yes` or `on` as well
+            if matches!(arg.get_action(), ArgAction::SetTrue) {
+                arg.env(env).value_parser(BoolishValueParser::new())
+            } else {
+                arg.env(env)
+            }
+        })
+    }
+}
+
+impl Config {
+    /// Expand the `--dev` preset into the options it implies
+    fn apply_dev_preset(&mut self) {
+        self.autoindex = true;
+
+        // first, so it wins over the rules for production
+        if let Ok(no_store) = "*=no-store".parse() {
+            self.cache.insert(0, no_store);
+        }
+
+        self.cors = true;
+        self.live_reload = true;
+    }
+
+    /// Every request needs to be authenticated
+    pub fn has_auth(&self) -> bool {
+        self.has_basic_auth()
+            || !self.token.is_empty()
+            || self.token_file.is_some()
+            || self.jwt_secret.is_some()
+            || self.jwks_url.is_some()
+            || self.auth_header.is_some()
+            || self.signing_secret.is_some()
+    }
+
+    /// Requests can authenticate with HTTP Basic credentials
+    pub fn has_basic_auth(&self) -> bool {
+        #[cfg(feature = "ldap")]
+        if self.ldap_url.is_some() {
+            return true;
+        }
+
+        !self.auth.is_empty() || self.auth_file.is_some()
+    }
+
+    /// Connections are served over TLS
+    pub fn is_tls(&self) -> bool {
+        self.tls_cert.is_some()
+            || self.tls_mkcert
+            || !self.tls_sni.is_empty()
+            || !self.acme_domain.is_empty()
+    }
+
+    /// Protocols offered via ALPN, in order of preference
+    pub fn alpn_protocols(&self) -> Vec<AlpnProtocol> {
+        if !self.alpn.is_empty() {
+            self.alpn.clone()
+        } else if self.http1_only {
+            vec![AlpnProtocol::Http1]
+        } else {
+            vec![AlpnProtocol::Http2, AlpnProtocol::Http1]
+        }
+    }
+
+    /// Only HTTP/1.1 is spoken, HTTP/2 is disabled
+    pub fn is_http1_only(&self) -> bool {
+        !self.alpn_protocols().contains(&AlpnProtocol::Http2)
+    }
+
+    /// Check if a file should be compressed, by its type and size
+    pub fn is_compressible(&self, content_type: &HeaderValue, content_length: u64) -> bool {
+        let is_compressible_type = if self.compress_types.is_empty() {
+            is_compressible(content_type)
+        } else {
+            self.compress_types
+                .iter()
+                .any(|pattern| pattern.matches(content_type))
+        };
+
+        is_compressible_type && content_length >= self.compress_min_size
+    }
+
+    /// Check that the paths of the config exist
+    pub fn validate(self) -> anyhow::Result<Self> {
+        let config = self;
 
         // check for the existence of base dir
         metadata(&config.base_dir)
@@ -81,6 +848,85 @@ impl Config {
                 .map_err(|err| ConfigError::InvalidFallbackPath(fallback_path.clone(), err))?;
         }
 
+        if let Some(not_found_path) = &config.not_found_path {
+            metadata(not_found_path)
+                .map_err(|err| ConfigError::InvalidNotFoundPath(not_found_path.clone(), err))?;
+        }
+
+        if let Some(listing_template) = &config.listing_template {
+            check_template(listing_template).map_err(ConfigError::InvalidListingTemplate)?;
+        }
+
+        if let Some(canary_dir) = &config.canary_dir {
+            metadata(canary_dir)
+                .map_err(|err| ConfigError::MissingCanaryDir(canary_dir.clone(), err))?;
+        }
+
+        if config.tls_client_ca.is_some()
+            && (config.tls_cert.is_none() && !config.tls_mkcert && config.tls_sni.is_empty())
+        {
+            return Err(ConfigError::ClientCaWithoutCertificate.into());
+        }
+
+        if config.redirect_http.is_some() && !config.is_tls() {
+            return Err(ConfigError::RedirectWithoutTls.into());
+        }
+
+        if let Some(auth_file) = &config.auth_file {
+            read_to_string(auth_file)
+                .map_err(|err| ConfigError::MissingAuthFile(auth_file.clone(), err))?
+                .parse::<Htpasswd>()
+                .map_err(|err| ConfigError::InvalidAuthFile(auth_file.clone(), err))?;
+        }
+
+        if let Some(auth_rules) = &config.auth_rules {
+            if !config.has_auth() {
+                return Err(ConfigError::AuthRulesWithoutAuth.into());
+            }
+
+            read_to_string(auth_rules)
+                .map_err(|err| ConfigError::MissingAuthRules(auth_rules.clone(), err))?
+                .parse::<AuthRules>()
+                .map_err(|err| ConfigError::InvalidAuthRules(auth_rules.clone(), err))?;
+        }
+
+        if let Some(token_file) = &config.token_file {
+            read_to_string(token_file)
+                .map_err(|err| ConfigError::MissingTokenFile(token_file.clone(), err))?
+                .parse::<TokenHashes>()
+                .map_err(|err| ConfigError::InvalidTokenFile(token_file.clone(), err))?;
+        }
+
+        if let Some(audit_log) = &config.audit_log {
+            AuditLog::open(audit_log)
+                .map_err(|err| ConfigError::InvalidAuditLog(audit_log.clone(), err))?;
+        }
+
+        let config = if !config.acme_domain.is_empty() && config.acme_cache.is_none() {
+            let acme_cache =
+                crate::acme::default_cache_dir().map_err(ConfigError::MissingAcmeCache)?;
+
+            Self {
+                acme_cache: Some(acme_cache),
+                ..config
+            }
+        } else {
+            config
+        };
+
+        #[cfg(feature = "image-resize")]
+        let config = if config.image_resize && config.image_cache_dir.is_none() {
+            let image_cache_dir = crate::image_resize::default_cache_dir()
+                .map_err(ConfigError::MissingImageCacheDir)?;
+
+            Self {
+                image_cache_dir: Some(image_cache_dir),
+                ..config
+            }
+        } else {
+            config
+        };
+
         Ok(config)
     }
 }
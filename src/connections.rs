@@ -0,0 +1,263 @@
+//! Registry of active connections
+//!
+//! Every accepted connection is registered until it is closed, together with
+//! the most recent request on it and the number of bytes sent so far. The
+//! number of connections per client address is kept as well, to limit them.
+
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::net::SocketAddr;
+use std::sync::atomic::AtomicU64;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::sync::PoisonError;
+use std::time::Instant;
+
+use axum::http::Method;
+use axum::http::Uri;
+use serde::Serialize;
+
+/// Request that was most recently received on a connection
+#[derive(Clone, Debug)]
+struct ActiveRequest {
+    method: Method,
+    path: String,
+    started_at: Instant,
+}
+
+/// A single connection, shared between the registry and the connection itself
+#[derive(Debug)]
+pub struct Connection {
+    id: u64,
+    peer: SocketAddr,
+    connected_at: Instant,
+    bytes_sent: AtomicU64,
+    request: Mutex<Option<ActiveRequest>>,
+}
+
+impl Connection {
+    /// Account for bytes written to the client
+    pub fn add_bytes_sent(&self, bytes: u64) {
+        self.bytes_sent.fetch_add(bytes, Ordering::Relaxed);
+    }
+
+    /// Register a new request on the connection
+    pub fn start_request(&self, method: &Method, uri: &Uri) {
+        let request = ActiveRequest {
+            method: method.clone(),
+            path: uri.path().to_string(),
+            started_at: Instant::now(),
+        };
+
+        if let Ok(mut current) = self.request.lock() {
+            *current = Some(request);
+        }
+    }
+
+    /// Snapshot of the connection
+    fn info(&self) -> ConnectionInfo {
+        let request = self.request.lock().ok().and_then(|request| request.clone());
+
+        ConnectionInfo {
+            id: self.id,
+            peer: self.peer,
+            duration_ms: millis(self.connected_at),
+            bytes_sent: self.bytes_sent.load(Ordering::Relaxed),
+            request: request.map(|request| RequestInfo {
+                method: request.method.to_string(),
+                path: request.path,
+                duration_ms: millis(request.started_at),
+            }),
+        }
+    }
+}
+
+/// Milliseconds elapsed since the instant
+fn millis(since: Instant) -> u64 {
+    u64::try_from(since.elapsed().as_millis()).unwrap_or(u64::MAX)
+}
+
+/// Snapshot of a connection, as exposed via the admin API
+#[derive(Debug, Serialize)]
+pub struct ConnectionInfo {
+    id: u64,
+    peer: SocketAddr,
+    duration_ms: u64,
+    bytes_sent: u64,
+    request: Option<RequestInfo>,
+}
+
+/// Snapshot of the most recent request of a connection
+#[derive(Debug, Serialize)]
+pub struct RequestInfo {
+    method: String,
+    path: String,
+    duration_ms: u64,
+}
+
+/// All active connections
+#[derive(Debug, Default)]
+pub struct Connections {
+    next_id: AtomicU64,
+    active: Mutex<HashMap<u64, Arc<Connection>>>,
+    per_address: Mutex<HashMap<IpAddr, usize>>,
+}
+
+impl Connections {
+    /// Register a new connection, unless the address of the peer has the
+    /// maximum number of connections already, it is removed again when the
+    /// guard is dropped
+    pub fn try_register(
+        self: &Arc<Self>,
+        peer: SocketAddr,
+        max_per_address: Option<usize>,
+    ) -> Option<ConnectionGuard> {
+        {
+            let mut per_address = self
+                .per_address
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner);
+            let count = per_address.entry(peer.ip()).or_default();
+
+            if max_per_address.is_some_and(|max| *count >= max) {
+                return None;
+            }
+
+            *count += 1;
+        }
+
+        Some(self.add(peer))
+    }
+
+    /// Add the connection to the active ones
+    fn add(self: &Arc<Self>, peer: SocketAddr) -> ConnectionGuard {
+        let connection = Arc::new(Connection {
+            id: self.next_id.fetch_add(1, Ordering::Relaxed),
+            peer,
+            connected_at: Instant::now(),
+            bytes_sent: AtomicU64::new(0),
+            request: Mutex::new(None),
+        });
+
+        if let Ok(mut active) = self.active.lock() {
+            active.insert(connection.id, Arc::clone(&connection));
+        }
+
+        ConnectionGuard {
+            connections: Arc::clone(self),
+            connection,
+        }
+    }
+
+    /// Snapshot of all active connections, the longest running first
+    pub fn list(&self) -> Vec<ConnectionInfo> {
+        let Ok(active) = self.active.lock() else {
+            return vec![];
+        };
+
+        let mut connections = active
+            .values()
+            .map(|connection| connection.info())
+            .collect::<Vec<_>>();
+
+        connections.sort_by_key(|connection| connection.id);
+
+        connections
+    }
+}
+
+/// Keeps a connection registered for as long as it lives
+pub struct ConnectionGuard {
+    connections: Arc<Connections>,
+    connection: Arc<Connection>,
+}
+
+impl ConnectionGuard {
+    pub fn connection(&self) -> &Arc<Connection> {
+        &self.connection
+    }
+}
+
+impl Drop for ConnectionGuard {
+    fn drop(&mut self) {
+        if let Ok(mut active) = self.connections.active.lock() {
+            active.remove(&self.connection.id);
+        }
+
+        let mut per_address = self
+            .connections
+            .per_address
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+        let address = self.connection.peer.ip();
+
+        if let Some(count) = per_address.get_mut(&address) {
+            *count -= 1;
+
+            if *count == 0 {
+                per_address.remove(&address);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_register_and_drop() {
+        let connections = Arc::new(Connections::default());
+        let peer = "127.0.0.1:1234".parse().expect("A valid address");
+
+        let first = connections
+            .try_register(peer, None)
+            .expect("A registered connection");
+        let second = connections
+            .try_register(peer, None)
+            .expect("A registered connection");
+
+        first.connection().add_bytes_sent(42);
+        first
+            .connection()
+            .start_request(&Method::GET, &Uri::from_static("/video.mp4?t=1"));
+
+        let list = connections.list();
+        assert_eq!(list.len(), 2);
+        assert_eq!(list[0].bytes_sent, 42);
+        assert_eq!(
+            list[0]
+                .request
+                .as_ref()
+                .map(|request| request.path.as_str()),
+            Some("/video.mp4")
+        );
+        assert!(list[1].request.is_none());
+
+        drop(first);
+        assert_eq!(connections.list().len(), 1);
+
+        drop(second);
+        assert!(connections.list().is_empty());
+    }
+
+    #[test]
+    fn test_max_per_address() {
+        let connections = Arc::new(Connections::default());
+        let peer = "127.0.0.1:1234".parse().expect("A valid address");
+        let other_port = "127.0.0.1:1235".parse().expect("A valid address");
+        let other_peer = "127.0.0.2:1234".parse().expect("A valid address");
+
+        let first = connections.try_register(peer, Some(2));
+        let second = connections.try_register(other_port, Some(2));
+        assert!(first.is_some());
+        assert!(second.is_some());
+        assert!(connections.try_register(peer, Some(2)).is_none());
+        assert!(connections.try_register(other_peer, Some(2)).is_some());
+
+        drop(first);
+        assert!(connections.try_register(peer, Some(2)).is_some());
+        assert!(connections.try_register(peer, None).is_some());
+    }
+}
@@ -0,0 +1,117 @@
+//! Cross-Origin Resource Sharing
+//!
+//! With `--cors` every origin can fetch files, with `--cors-origin` only the
+//! given ones. Preflight requests are answered before anything else, they
+//! never reach the files.
+//!
+//! See <https://developer.mozilla.org/en-US/docs/Web/HTTP/CORS>
+
+use std::time::Duration;
+
+use axum::http::Method;
+use tower_http::cors::AllowHeaders;
+use tower_http::cors::AllowOrigin;
+use tower_http::cors::CorsLayer;
+
+use crate::config::Config;
+
+/// Methods allowed when none are configured, files are only read
+const DEFAULT_METHODS: [Method; 2] = [Method::GET, Method::HEAD];
+
+/// Layer that handles CORS, when enabled
+pub fn cors_layer(config: &Config) -> Option<CorsLayer> {
+    if !config.cors && config.cors_origin.is_empty() {
+        return None;
+    }
+
+    let allow_origin = if config.cors_origin.is_empty() {
+        AllowOrigin::any()
+    } else {
+        AllowOrigin::list(config.cors_origin.iter().cloned())
+    };
+
+    let allow_methods = if config.cors_methods.is_empty() {
+        DEFAULT_METHODS.to_vec()
+    } else {
+        config.cors_methods.clone()
+    };
+
+    // without a list, whatever the preflight asks for is allowed
+    let allow_headers = if config.cors_headers.is_empty() {
+        AllowHeaders::mirror_request()
+    } else {
+        AllowHeaders::list(config.cors_headers.iter().cloned())
+    };
+
+    let mut layer = CorsLayer::new()
+        .allow_origin(allow_origin)
+        .allow_methods(allow_methods)
+        .allow_headers(allow_headers);
+
+    if let Some(max_age) = config.cors_max_age {
+        layer = layer.max_age(Duration::from_secs(max_age));
+    }
+
+    Some(layer)
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::body::Body;
+    use axum::http::header::ACCESS_CONTROL_ALLOW_METHODS;
+    use axum::http::header::ACCESS_CONTROL_ALLOW_ORIGIN;
+    use axum::http::header::ACCESS_CONTROL_REQUEST_METHOD;
+    use axum::http::header::ORIGIN;
+    use axum::http::Request;
+    use axum::http::Response;
+    use axum::routing::get;
+    use axum::Router;
+    use tower::ServiceExt;
+
+    use super::*;
+    use crate::config::CliConfig;
+
+    async fn preflight(args: &[&str], origin: &str) -> Response<Body> {
+        let config = CliConfig::from_args(["srvr"].iter().chain(args)).config;
+        let layer = cors_layer(&config).expect("A CORS layer");
+        let router = Router::new()
+            .route("/", get(|| async { "file" }))
+            .layer(layer);
+
+        let request = Request::options("/")
+            .header(ORIGIN, origin)
+            .header(ACCESS_CONTROL_REQUEST_METHOD, "GET")
+            .body(Body::empty())
+            .expect("A valid request");
+
+        router.oneshot(request).await.expect("A response")
+    }
+
+    #[test]
+    fn test_disabled() {
+        let config = CliConfig::from_args(["srvr"]).config;
+
+        assert!(cors_layer(&config).is_none());
+    }
+
+    #[tokio::test]
+    async fn test_preflight_allowed_origin() {
+        let args = ["--cors-origin", "https://example.com"];
+        let response = preflight(&args, "https://example.com").await;
+        let headers = response.headers();
+
+        assert_eq!(headers[ACCESS_CONTROL_ALLOW_ORIGIN], "https://example.com");
+        assert_eq!(headers[ACCESS_CONTROL_ALLOW_METHODS], "GET,HEAD");
+
+        let response = preflight(&["--cors"], "https://example.org").await;
+        assert_eq!(response.headers()[ACCESS_CONTROL_ALLOW_ORIGIN], "*");
+    }
+
+    #[tokio::test]
+    async fn test_preflight_rejected_origin() {
+        let args = ["--cors-origin", "https://example.com"];
+        let response = preflight(&args, "https://example.org").await;
+
+        assert!(!response.headers().contains_key(ACCESS_CONTROL_ALLOW_ORIGIN));
+    }
+}
@@ -0,0 +1,243 @@
+//! Content-Security-Policy for HTML documents
+//!
+//! A policy can use a nonce via the `{nonce}` placeholder, ie
+//! `script-src 'nonce-{nonce}'`. Every response then gets a fresh nonce, which
+//! is added to the `<script>` and `<style>` tags of the document. Documents are
+//! parsed once into a template, only the nonce is substituted per response.
+//!
+//! A new policy can be tried without breaking anything with
+//! `--csp-report-only`, violations are then only reported.
+
+use std::collections::HashMap;
+use std::fmt::Write;
+use std::path::Path;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::time::SystemTime;
+
+use axum::http::header::CONTENT_SECURITY_POLICY;
+use axum::http::header::CONTENT_SECURITY_POLICY_REPORT_ONLY;
+use axum::http::HeaderName;
+use axum::http::HeaderValue;
+
+/// Placeholder in the policy for the nonce of the response
+const NONCE_PLACEHOLDER: &str = "{nonce}";
+
+/// The configured policy, with the templates of the documents served so far
+#[derive(Debug, Default)]
+pub struct Csp {
+    policy: Option<String>,
+
+    /// Only report violations, the policy is not enforced
+    report_only: bool,
+
+    templates: Mutex<HashMap<PathBuf, (SystemTime, Arc<Template>)>>,
+}
+
+impl Csp {
+    pub fn new(policy: Option<String>, report_only: bool) -> Self {
+        Self {
+            policy,
+            report_only,
+            templates: Mutex::default(),
+        }
+    }
+
+    /// Name of the header with the policy, depending on whether it is enforced
+    pub fn header_name(&self) -> HeaderName {
+        if self.report_only {
+            CONTENT_SECURITY_POLICY_REPORT_ONLY
+        } else {
+            CONTENT_SECURITY_POLICY
+        }
+    }
+
+    /// Check if the policy uses a nonce, which requires rewriting the documents
+    pub fn uses_nonce(&self) -> bool {
+        self.policy
+            .as_deref()
+            .is_some_and(|policy| policy.contains(NONCE_PLACEHOLDER))
+    }
+
+    /// Value of the header for a document, with a fresh nonce that is not in
+    /// the document itself
+    pub fn header(&self) -> Option<HeaderValue> {
+        let policy = self.policy.as_deref()?;
+        HeaderValue::from_str(&policy.replace(NONCE_PLACEHOLDER, &nonce()?)).ok()
+    }
+
+    /// The document at the path with a fresh nonce, and the matching header
+    ///
+    /// `None` when the policy has no nonce, or the document could not be read
+    pub async fn document(&self, document: &Path) -> Option<(HeaderValue, Vec<u8>)> {
+        let policy = self.policy.as_deref().filter(|_| self.uses_nonce())?;
+        let template = self.template(document).await?;
+
+        let nonce = nonce()?;
+        let header = HeaderValue::from_str(&policy.replace(NONCE_PLACEHOLDER, &nonce)).ok()?;
+
+        Some((header, template.render(&nonce)))
+    }
+
+    /// Template of the document, parsed again once the document changes
+    async fn template(&self, document: &Path) -> Option<Arc<Template>> {
+        let modified = tokio::fs::metadata(document)
+            .await
+            .and_then(|meta| meta.modified())
+            .ok()?;
+
+        if let Ok(templates) = self.templates.lock() {
+            if let Some((template_modified, template)) = templates.get(document) {
+                if *template_modified == modified {
+                    return Some(Arc::clone(template));
+                }
+            }
+        }
+
+        let html = match tokio::fs::read(document).await {
+            Ok(html) => html,
+            Err(err) => {
+                tracing::warn!("Could not read {document:?} to add nonces: {err}");
+                return None;
+            }
+        };
+
+        let template = Arc::new(Template::parse(&html));
+
+        if let Ok(mut templates) = self.templates.lock() {
+            templates.insert(document.to_path_buf(), (modified, Arc::clone(&template)));
+        }
+
+        Some(template)
+    }
+}
+
+/// A fresh nonce, 128 bits of randomness
+fn nonce() -> Option<String> {
+    let mut bytes = [0; 16];
+
+    if let Err(err) = getrandom::getrandom(&mut bytes) {
+        tracing::error!("Could not generate a nonce: {err}");
+        return None;
+    }
+
+    Some(bytes.iter().fold(String::new(), |mut nonce, byte| {
+        let _ = write!(nonce, "{byte:02x}");
+        nonce
+    }))
+}
+
+/// A document split at the places the nonce attribute goes
+#[derive(Debug, PartialEq, Eq)]
+struct Template {
+    parts: Vec<Vec<u8>>,
+}
+
+impl Template {
+    /// Split the document right after the name of every `<script>` and `<style>` tag
+    fn parse(html: &[u8]) -> Self {
+        let mut parts = vec![];
+        let mut part_start = 0;
+        let mut position = 0;
+
+        while let Some(offset) = html[position..].iter().position(|byte| *byte == b'<') {
+            position += offset + 1;
+            let rest = &html[position..];
+
+            if rest.starts_with(b"!--") {
+                let end = rest
+                    .windows(3)
+                    .position(|window| window == b"-->")
+                    .map_or(html.len(), |end| position + end + 3);
+
+                position = end;
+                continue;
+            }
+
+            for name in [&b"script"[..], b"style"] {
+                let is_tag = rest.len() > name.len()
+                    && rest[..name.len()].eq_ignore_ascii_case(name)
+                    && matches!(rest[name.len()], b' ' | b'\t' | b'\n' | b'\r' | b'/' | b'>');
+
+                if is_tag {
+                    position += name.len();
+                    parts.push(html[part_start..position].to_vec());
+                    part_start = position;
+                }
+            }
+        }
+
+        parts.push(html[part_start..].to_vec());
+
+        Self { parts }
+    }
+
+    /// The document with the nonce attribute added to the tags
+    fn render(&self, nonce: &str) -> Vec<u8> {
+        let attribute = format!(" nonce=\"{nonce}\"");
+
+        let mut html = Vec::with_capacity(
+            self.parts.iter().map(Vec::len).sum::<usize>() + attribute.len() * self.parts.len(),
+        );
+
+        for (index, part) in self.parts.iter().enumerate() {
+            if index > 0 {
+                html.extend_from_slice(attribute.as_bytes());
+            }
+            html.extend_from_slice(part);
+        }
+
+        html
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render() {
+        let template = Template::parse(
+            b"<head><!-- <script> --><SCRIPT src=\"/a.js\"></SCRIPT><style>p{}</style>\
+              <scripts></scripts><link rel=stylesheet></head>",
+        );
+
+        assert_eq!(template.parts.len(), 3);
+        assert_eq!(
+            String::from_utf8_lossy(&template.render("abc")),
+            "<head><!-- <script> --><SCRIPT nonce=\"abc\" src=\"/a.js\"></SCRIPT>\
+             <style nonce=\"abc\">p{}</style><scripts></scripts><link rel=stylesheet></head>"
+        );
+    }
+
+    #[test]
+    fn test_nonce() {
+        let first = nonce().expect("A nonce");
+        let second = nonce().expect("A nonce");
+
+        assert_eq!(first.len(), 32);
+        assert_ne!(first, second);
+    }
+
+    #[test]
+    fn test_uses_nonce() {
+        assert!(Csp::new(Some(String::from("script-src 'nonce-{nonce}'")), false).uses_nonce());
+        assert!(!Csp::new(Some(String::from("default-src 'self'")), false).uses_nonce());
+        assert!(!Csp::new(None, false).uses_nonce());
+    }
+
+    #[test]
+    fn test_header_name() {
+        let policy = Some(String::from("default-src 'self'"));
+
+        assert_eq!(
+            Csp::new(policy.clone(), false).header_name(),
+            CONTENT_SECURITY_POLICY
+        );
+        assert_eq!(
+            Csp::new(policy, true).header_name(),
+            CONTENT_SECURITY_POLICY_REPORT_ONLY
+        );
+    }
+}
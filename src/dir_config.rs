@@ -0,0 +1,175 @@
+//! Per-directory configuration
+//!
+//! A `.srvr` file in a directory sets response headers for the files in it and
+//! in its subdirectories, ie a long `Cache-Control` for `/assets`. It has a
+//! header per line, like `Cache-Control: no-cache`, and `#` comments. A header
+//! of a deeper `.srvr` file replaces the one of the directories above it, an
+//! empty value removes it.
+
+use std::collections::HashSet;
+use std::str::FromStr;
+
+use axum::http::HeaderMap;
+use axum::http::HeaderName;
+use axum::http::HeaderValue;
+
+/// Name of the file with the configuration of a directory, it is never served
+pub const DIR_CONFIG_FILE_NAME: &str = ".srvr";
+
+#[derive(Debug, thiserror::Error)]
+pub enum DirConfigError {
+    #[error("Line {0}: expected a header, ie `Cache-Control: no-cache`")]
+    MissingColon(usize),
+
+    #[error("Line {0}: invalid header name \"{1}\"")]
+    InvalidName(usize, String),
+
+    #[error("Line {0}: invalid value for header \"{1}\"")]
+    InvalidValue(usize, String),
+}
+
+/// Invalid header, without the line it is on
+#[derive(Debug, thiserror::Error)]
+pub enum HeaderError {
+    #[error("expected a header, ie `Cache-Control: no-cache`")]
+    MissingColon,
+
+    #[error("invalid header name \"{0}\"")]
+    InvalidName(String),
+
+    #[error("invalid value for header \"{0}\"")]
+    InvalidValue(String),
+}
+
+impl HeaderError {
+    /// The error for the header on a line of a file
+    pub fn at_line(self, line_number: usize) -> DirConfigError {
+        match self {
+            Self::MissingColon => DirConfigError::MissingColon(line_number),
+            Self::InvalidName(name) => DirConfigError::InvalidName(line_number, name),
+            Self::InvalidValue(name) => DirConfigError::InvalidValue(line_number, name),
+        }
+    }
+}
+
+/// Configuration of a directory, from its `.srvr` file
+#[derive(Debug, Default)]
+pub struct DirConfig {
+    /// Headers in the order of the file, `None` removes the header
+    headers: Vec<(HeaderName, Option<HeaderValue>)>,
+}
+
+impl DirConfig {
+    pub fn new(headers: Vec<(HeaderName, Option<HeaderValue>)>) -> Self {
+        Self { headers }
+    }
+
+    /// Apply the headers to a response, replacing the headers with the same
+    /// name, ie from a `.srvr` file of a parent directory
+    pub fn apply(&self, headers: &mut HeaderMap) {
+        let mut replaced = HashSet::new();
+
+        for (name, value) in &self.headers {
+            if replaced.insert(name) {
+                headers.remove(name);
+            }
+
+            if let Some(value) = value {
+                headers.append(name.clone(), value.clone());
+            }
+        }
+    }
+}
+
+impl FromStr for DirConfig {
+    type Err = DirConfigError;
+
+    fn from_str(content: &str) -> Result<Self, Self::Err> {
+        let mut headers = vec![];
+
+        for (index, line) in content.lines().enumerate() {
+            let line_number = index + 1;
+            let line = line.trim();
+
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            headers.push(parse_header(line).map_err(|err| err.at_line(line_number))?);
+        }
+
+        Ok(Self { headers })
+    }
+}
+
+/// Parse a header line, ie `Cache-Control: no-cache`, an empty value removes
+/// the header
+pub fn parse_header(line: &str) -> Result<(HeaderName, Option<HeaderValue>), HeaderError> {
+    let (name, value) = line.split_once(':').ok_or(HeaderError::MissingColon)?;
+
+    let name = name
+        .trim()
+        .parse::<HeaderName>()
+        .map_err(|_| HeaderError::InvalidName(name.trim().to_string()))?;
+
+    let value = match value.trim() {
+        "" => None,
+        value => Some(
+            value
+                .parse::<HeaderValue>()
+                .map_err(|_| HeaderError::InvalidValue(name.to_string()))?,
+        ),
+    };
+
+    Ok((name, value))
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::http::header::CACHE_CONTROL;
+
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        let config = "# assets never change\n\nCache-Control: max-age=31536000\nX-Frame-Options:\n"
+            .parse::<DirConfig>()
+            .expect("A valid config");
+
+        assert_eq!(config.headers.len(), 2);
+        assert_eq!(config.headers[0].0, CACHE_CONTROL);
+        assert_eq!(config.headers[1].1, None);
+
+        assert!(matches!(
+            "Cache-Control".parse::<DirConfig>(),
+            Err(DirConfigError::MissingColon(1))
+        ));
+        assert!(matches!(
+            "\nCache Control: no-cache".parse::<DirConfig>(),
+            Err(DirConfigError::InvalidName(2, _))
+        ));
+    }
+
+    #[test]
+    fn test_apply() {
+        let parent = "Cache-Control: no-cache\nX-Frame-Options: DENY\nLink: </a.css>"
+            .parse::<DirConfig>()
+            .expect("A valid config");
+        let child = "Cache-Control: max-age=60\nX-Frame-Options:\nLink: </b.css>\nLink: </c.css>"
+            .parse::<DirConfig>()
+            .expect("A valid config");
+
+        let mut headers = HeaderMap::new();
+        headers.insert(CACHE_CONTROL, HeaderValue::from_static("public"));
+
+        parent.apply(&mut headers);
+        child.apply(&mut headers);
+
+        assert_eq!(headers[CACHE_CONTROL], "max-age=60");
+        assert!(headers.get("x-frame-options").is_none());
+        assert_eq!(
+            headers.get_all("link").iter().collect::<Vec<_>>(),
+            ["</b.css>", "</c.css>"]
+        );
+    }
+}
@@ -0,0 +1,312 @@
+//! Preload links for HTML documents
+//!
+//! The stylesheets and scripts a document needs are announced before the
+//! document itself, in a `103 Early Hints` response and as `link` headers of
+//! the final response. They come from the configured preload list and, with
+//! `--early-hints`, from scanning the `<head>` of the document.
+
+use std::collections::HashMap;
+use std::path::Path;
+use std::path::PathBuf;
+use std::sync::Mutex;
+use std::time::SystemTime;
+
+use axum::http::HeaderValue;
+
+/// Preload links, the scanned links are remembered per document
+#[derive(Debug, Default)]
+pub struct Preloads {
+    configured: Vec<HeaderValue>,
+    scan: bool,
+    scanned: Mutex<HashMap<PathBuf, (SystemTime, Vec<HeaderValue>)>>,
+}
+
+impl Preloads {
+    pub fn new(preload: &[String], scan: bool) -> Self {
+        let configured = preload
+            .iter()
+            .filter_map(|url| link(url, Resource::from_url(url)))
+            .collect();
+
+        Self {
+            configured,
+            scan,
+            scanned: Mutex::default(),
+        }
+    }
+
+    /// Check if any links could be sent at all
+    pub fn is_enabled(&self) -> bool {
+        self.scan || !self.configured.is_empty()
+    }
+
+    /// Links to preload for the document at the path, which is the original
+    /// (not precompressed) file
+    pub async fn links(&self, document: &Path) -> Vec<HeaderValue> {
+        let mut links = self.configured.clone();
+
+        if self.scan {
+            for link in self.scanned_links(document).await {
+                if !links.contains(&link) {
+                    links.push(link);
+                }
+            }
+        }
+
+        links
+    }
+
+    /// Links found in the document, scanned again once the document changes
+    async fn scanned_links(&self, document: &Path) -> Vec<HeaderValue> {
+        let Ok(modified) = tokio::fs::metadata(document)
+            .await
+            .and_then(|meta| meta.modified())
+        else {
+            return vec![];
+        };
+
+        if let Ok(scanned) = self.scanned.lock() {
+            if let Some((scanned_modified, links)) = scanned.get(document) {
+                if *scanned_modified == modified {
+                    return links.clone();
+                }
+            }
+        }
+
+        let links = match tokio::fs::read(document).await {
+            Ok(html) => scan(&String::from_utf8_lossy(&html)),
+            Err(err) => {
+                tracing::debug!("Could not scan {document:?} for preload links: {err}");
+                vec![]
+            }
+        };
+
+        if let Ok(mut scanned) = self.scanned.lock() {
+            scanned.insert(document.to_path_buf(), (modified, links.clone()));
+        }
+
+        links
+    }
+}
+
+/// Kind of resource to preload, decides the `as` of the link
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Resource {
+    Style,
+    Script,
+    Module,
+    Font,
+    Image,
+    Fetch,
+}
+
+impl Resource {
+    /// Guess the kind of resource from the extension of the URL
+    fn from_url(url: &str) -> Self {
+        let path = url.split(['?', '#']).next().unwrap_or_default();
+        let extension = path.rsplit_once('.').map(|(_, extension)| extension);
+
+        match extension.map(str::to_ascii_lowercase).as_deref() {
+            Some("css") => Self::Style,
+            Some("js") => Self::Script,
+            Some("mjs") => Self::Module,
+            Some("woff" | "woff2" | "ttf" | "otf") => Self::Font,
+            Some("png" | "jpg" | "jpeg" | "gif" | "webp" | "avif" | "svg") => Self::Image,
+            _ => Self::Fetch,
+        }
+    }
+}
+
+/// Value of a `link` header preloading the URL
+fn link(url: &str, resource: Resource) -> Option<HeaderValue> {
+    let parameters = match resource {
+        Resource::Style => "rel=preload; as=style",
+        Resource::Script => "rel=preload; as=script",
+        Resource::Module => "rel=modulepreload",
+        // fonts are always fetched in CORS mode
+        Resource::Font => "rel=preload; as=font; crossorigin",
+        Resource::Image => "rel=preload; as=image",
+        Resource::Fetch => "rel=preload; as=fetch; crossorigin",
+    };
+
+    HeaderValue::from_str(&format!("<{url}>; {parameters}")).ok()
+}
+
+/// Find the stylesheets and scripts in the `<head>` of the document
+fn scan(html: &str) -> Vec<HeaderValue> {
+    let mut links = vec![];
+
+    for (name, attributes) in tags(html) {
+        if name.eq_ignore_ascii_case("/head") || name.eq_ignore_ascii_case("body") {
+            break;
+        }
+
+        let attribute = |wanted: &str| {
+            attributes
+                .iter()
+                .find(|(name, _)| name.eq_ignore_ascii_case(wanted))
+                .map(|(_, value)| *value)
+        };
+
+        let resource = if name.eq_ignore_ascii_case("link") {
+            let is_stylesheet = attribute("rel").is_some_and(|rel| {
+                rel.split_ascii_whitespace()
+                    .any(|rel| rel.eq_ignore_ascii_case("stylesheet"))
+            });
+
+            is_stylesheet
+                .then(|| attribute("href"))
+                .flatten()
+                .map(|href| (href, Resource::Style))
+        } else if name.eq_ignore_ascii_case("script") {
+            let is_module =
+                attribute("type").is_some_and(|kind| kind.eq_ignore_ascii_case("module"));
+
+            attribute("src").map(|src| {
+                (
+                    src,
+                    if is_module {
+                        Resource::Module
+                    } else {
+                        Resource::Script
+                    },
+                )
+            })
+        } else {
+            None
+        };
+
+        if let Some((url, resource)) = resource {
+            if url.is_empty() || url.starts_with("data:") {
+                continue;
+            }
+
+            if let Some(link) = link(url, resource) {
+                if !links.contains(&link) {
+                    links.push(link);
+                }
+            }
+        }
+    }
+
+    links
+}
+
+/// The start tags of the document with their attributes, comments are skipped
+fn tags(html: &str) -> impl Iterator<Item = (&str, Vec<(&str, &str)>)> {
+    let mut rest = html;
+
+    std::iter::from_fn(move || loop {
+        let start = rest.find('<')?;
+        rest = &rest[start + 1..];
+
+        if let Some(comment) = rest.strip_prefix("!--") {
+            rest = comment.find("-->").map_or("", |end| &comment[end + 3..]);
+            continue;
+        }
+
+        let end = rest.find('>')?;
+        let tag = &rest[..end];
+        rest = &rest[end + 1..];
+
+        let tag = tag.trim_end_matches('/');
+        let (name, attributes) = tag
+            .split_once(|c: char| c.is_ascii_whitespace())
+            .unwrap_or((tag, ""));
+
+        return Some((name, parse_attributes(attributes)));
+    })
+}
+
+/// Parse the attributes of a tag, quoted or not
+fn parse_attributes(mut attributes: &str) -> Vec<(&str, &str)> {
+    let mut parsed = vec![];
+
+    loop {
+        attributes = attributes.trim_start();
+        if attributes.is_empty() {
+            return parsed;
+        }
+
+        let name_end = attributes
+            .find(|c: char| c == '=' || c.is_ascii_whitespace())
+            .unwrap_or(attributes.len());
+        let name = &attributes[..name_end];
+        attributes = attributes[name_end..].trim_start();
+
+        let Some(value) = attributes.strip_prefix('=') else {
+            parsed.push((name, ""));
+            continue;
+        };
+        let value = value.trim_start();
+
+        let (value, rest) = if let Some(quote @ ('"' | '\'')) = value.chars().next() {
+            let value = &value[1..];
+            let end = value.find(quote).unwrap_or(value.len());
+            (&value[..end], value.get(end + 1..).unwrap_or_default())
+        } else {
+            let end = value
+                .find(|c: char| c.is_ascii_whitespace())
+                .unwrap_or(value.len());
+            (&value[..end], &value[end..])
+        };
+
+        parsed.push((name, value));
+        attributes = rest;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_scan() {
+        let html = r#"<!doctype html>
+            <html>
+            <head>
+                <!-- <link rel="stylesheet" href="/old.css"> -->
+                <link rel="icon" href="/favicon.ico">
+                <link rel=stylesheet href=/app.css>
+                <link href='/print.css' rel="Stylesheet" media="print" />
+                <script src="/app.js" defer></script>
+                <script type="module" src="/main.mjs"></script>
+                <script>inline()</script>
+            </head>
+            <body>
+                <script src="/late.js"></script>
+            </body>
+            </html>"#;
+
+        assert_eq!(
+            scan(html),
+            [
+                "</app.css>; rel=preload; as=style",
+                "</print.css>; rel=preload; as=style",
+                "</app.js>; rel=preload; as=script",
+                "</main.mjs>; rel=modulepreload",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_configured_links() {
+        let preloads = Preloads::new(
+            &[
+                String::from("/fonts/inter.woff2"),
+                String::from("/hero.webp?v=2"),
+                String::from("/data.json"),
+            ],
+            false,
+        );
+
+        assert_eq!(
+            preloads.configured,
+            [
+                "</fonts/inter.woff2>; rel=preload; as=font; crossorigin",
+                "</hero.webp?v=2>; rel=preload; as=image",
+                "</data.json>; rel=preload; as=fetch; crossorigin",
+            ]
+        );
+    }
+}
@@ -1,13 +1,17 @@
 //! Encoding (compression) support utilities
 
-use std::convert::Infallible;
+use std::cmp::Reverse;
+use std::fmt;
+use std::io::Write;
+use std::path::Path;
+use std::str::FromStr;
 
-use axum::async_trait;
-use axum::extract::FromRequestParts;
 use axum::http::header::ACCEPT_ENCODING;
-use axum::http::request::Parts;
+use axum::http::header::RANGE;
 use axum::http::HeaderMap;
 use axum::http::HeaderValue;
+use clap::ValueEnum;
+use mime::Mime;
 
 /// Brotli encoding in `accept-encoding` header
 ///
@@ -25,104 +29,411 @@ const ENCODING_GZIP: &str = "gzip";
 /// Extension for gzip encoded files
 const ENCODING_GZIP_EXTENSION: &str = ".gz";
 
+/// Zstandard encoding in `accept-encoding` header
+///
+/// See <https://developer.mozilla.org/en-US/docs/Web/HTTP/Headers/Accept-Encoding>
+const ENCODING_ZSTD: &str = "zstd";
+
+/// Extension for Zstandard encoded files
+const ENCODING_ZSTD_EXTENSION: &str = ".zst";
+
+/// Deflate encoding in `accept-encoding` header, the zlib format
+///
+/// See <https://developer.mozilla.org/en-US/docs/Web/HTTP/Headers/Accept-Encoding>
+const ENCODING_DEFLATE: &str = "deflate";
+
+/// Extension for deflate encoded files
+const ENCODING_DEFLATE_EXTENSION: &str = ".zz";
+
+/// Level of on-the-fly Zstandard compression, the default level
+const ZSTD_LEVEL: i32 = 3;
+
+/// Level of ahead-of-time Zstandard compression, the highest regular level
+const ZSTD_BEST_LEVEL: i32 = 19;
+
+/// Quality of on-the-fly Brotli compression, the highest qualities are too slow
+const BROTLI_QUALITY: i32 = 5;
+
+/// Quality of ahead-of-time Brotli compression, the highest quality
+const BROTLI_BEST_QUALITY: i32 = 11;
+
+/// Highest quality value in the `accept-encoding` header, `q=1`, in thousandths
+const MAX_QUALITY: u16 = 1000;
+
 /// Supported encodings
-#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, ValueEnum)]
 pub enum Encoding {
     /// Brotili compression
+    #[value(name = "br")]
     Brotli,
 
+    /// Zstandard compression
+    Zstd,
+
     /// Gzip compression
     Gzip,
+
+    /// Deflate compression, only for clients without support for anything better
+    Deflate,
 }
 
 impl Encoding {
-    /// Convert encoding to `HeaderValue`
+    /// All supported encodings, in order of preference
+    pub const ALL: [Encoding; 4] = [
+        Encoding::Brotli,
+        Encoding::Zstd,
+        Encoding::Gzip,
+        Encoding::Deflate,
+    ];
+
+    /// Name of the encoding in the `accept-encoding` and `content-encoding` headers
     #[inline]
-    pub const fn to_header_value(self) -> HeaderValue {
+    pub const fn name(self) -> &'static str {
         match self {
-            Encoding::Brotli => HeaderValue::from_static(ENCODING_BR),
-            Encoding::Gzip => HeaderValue::from_static(ENCODING_GZIP),
+            Encoding::Brotli => ENCODING_BR,
+            Encoding::Zstd => ENCODING_ZSTD,
+            Encoding::Gzip => ENCODING_GZIP,
+            Encoding::Deflate => ENCODING_DEFLATE,
         }
     }
 
+    /// Convert encoding to `HeaderValue`
+    #[inline]
+    pub const fn to_header_value(self) -> HeaderValue {
+        HeaderValue::from_static(self.name())
+    }
+
     /// Get extension for encoding
     #[inline]
     pub const fn get_extension(self) -> &'static str {
         match self {
             Encoding::Brotli => ENCODING_BR_EXTENSION,
+            Encoding::Zstd => ENCODING_ZSTD_EXTENSION,
             Encoding::Gzip => ENCODING_GZIP_EXTENSION,
+            Encoding::Deflate => ENCODING_DEFLATE_EXTENSION,
+        }
+    }
+
+    /// Content type of a file compressed with the encoding, when it is requested
+    /// directly instead of as a variant of another file
+    #[inline]
+    pub const fn content_type(self) -> &'static str {
+        match self {
+            Encoding::Brotli => "application/x-brotli",
+            Encoding::Zstd => "application/zstd",
+            Encoding::Gzip => "application/gzip",
+            Encoding::Deflate => "application/zlib",
+        }
+    }
+
+    /// Encoding of a compressed file by its extension, ie gzip for `backup.tar.gz`
+    pub fn from_path(path: &Path) -> Option<Self> {
+        let extension = path.extension()?;
+
+        Self::ALL
+            .into_iter()
+            .find(|encoding| extension == encoding.get_extension().trim_start_matches('.'))
+    }
+
+    /// Compress the content on the fly
+    pub fn compress(self, content: &[u8]) -> std::io::Result<Vec<u8>> {
+        self.compress_with(content, Level::Fast)
+    }
+
+    /// Compress the content as small as possible, it is slow
+    pub fn compress_best(self, content: &[u8]) -> std::io::Result<Vec<u8>> {
+        self.compress_with(content, Level::Best)
+    }
+
+    fn compress_with(self, content: &[u8], level: Level) -> std::io::Result<Vec<u8>> {
+        match self {
+            Encoding::Brotli => {
+                let params = brotli::enc::BrotliEncoderParams {
+                    quality: match level {
+                        Level::Fast => BROTLI_QUALITY,
+                        Level::Best => BROTLI_BEST_QUALITY,
+                    },
+                    ..Default::default()
+                };
+
+                let mut compressed = vec![];
+                brotli::BrotliCompress(&mut &content[..], &mut compressed, &params)?;
+
+                Ok(compressed)
+            }
+            Encoding::Zstd => zstd::encode_all(
+                content,
+                match level {
+                    Level::Fast => ZSTD_LEVEL,
+                    Level::Best => ZSTD_BEST_LEVEL,
+                },
+            ),
+            Encoding::Gzip => {
+                let mut encoder = flate2::write::GzEncoder::new(vec![], level.flate2());
+                encoder.write_all(content)?;
+
+                encoder.finish()
+            }
+            Encoding::Deflate => {
+                let mut encoder = flate2::write::ZlibEncoder::new(vec![], level.flate2());
+                encoder.write_all(content)?;
+
+                encoder.finish()
+            }
+        }
+    }
+}
+
+/// Compression level, a trade-off between speed and size
+#[derive(Clone, Copy)]
+enum Level {
+    Fast,
+    Best,
+}
+
+impl Level {
+    /// Level of gzip and deflate compression
+    fn flate2(self) -> flate2::Compression {
+        match self {
+            Level::Fast => flate2::Compression::default(),
+            Level::Best => flate2::Compression::best(),
         }
     }
 }
 
+/// Check if content of the type gets smaller when it is compressed, most
+/// images, videos and archives are compressed already
+pub fn is_compressible(content_type: &HeaderValue) -> bool {
+    let Some(mime) = content_type
+        .to_str()
+        .ok()
+        .and_then(|content_type| content_type.parse::<Mime>().ok())
+    else {
+        return false;
+    };
+
+    mime.type_() == mime::TEXT
+        || mime
+            .suffix()
+            .is_some_and(|suffix| suffix == mime::JSON || suffix == mime::XML)
+        || matches!(
+            mime.essence_str(),
+            "application/javascript"
+                | "application/json"
+                | "application/xml"
+                | "application/wasm"
+                | "image/svg+xml"
+                | "image/x-icon"
+                | "font/ttf"
+                | "font/otf"
+        )
+}
+
+#[derive(Debug, thiserror::Error)]
+#[error("Invalid content type \"{0}\", expected a type like text/html or text/*")]
+pub struct ContentTypePatternError(String);
+
+/// Content type to compress on the fly, the subtype can be a wildcard
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ContentTypePattern {
+    type_: String,
+
+    /// `None` for any subtype
+    subtype: Option<String>,
+}
+
+impl ContentTypePattern {
+    /// Check if the content type matches the pattern, parameters are ignored
+    pub fn matches(&self, content_type: &HeaderValue) -> bool {
+        let Some(mime) = content_type
+            .to_str()
+            .ok()
+            .and_then(|content_type| content_type.parse::<Mime>().ok())
+        else {
+            return false;
+        };
+
+        mime.type_() == self.type_.as_str()
+            && self
+                .subtype
+                .as_ref()
+                .map_or(true, |subtype| mime.subtype() == subtype.as_str())
+    }
+}
+
+impl FromStr for ContentTypePattern {
+    type Err = ContentTypePatternError;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let value = value.trim().to_ascii_lowercase();
+
+        let Some((type_, subtype)) = value.split_once('/') else {
+            return Err(ContentTypePatternError(value));
+        };
+
+        let is_valid = |part: &str| {
+            !part.is_empty()
+                && part
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || "!#$&-^_.+".contains(c))
+        };
+
+        if !is_valid(type_) || (subtype != "*" && !is_valid(subtype)) {
+            return Err(ContentTypePatternError(value));
+        }
+
+        Ok(Self {
+            type_: type_.to_string(),
+            subtype: (subtype != "*").then(|| subtype.to_string()),
+        })
+    }
+}
+
+impl fmt::Display for ContentTypePattern {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}/{}",
+            self.type_,
+            self.subtype.as_deref().unwrap_or("*")
+        )
+    }
+}
+
+/// Identity (no) encoding in `accept-encoding` header
+///
+/// See <https://developer.mozilla.org/en-US/docs/Web/HTTP/Headers/Accept-Encoding>
+const ENCODING_IDENTITY: &str = "identity";
+
+/// Wildcard in `accept-encoding` header, for any encoding not listed otherwise
+const ENCODING_WILDCARD: &str = "*";
+
 /// Client encoding support
-#[derive(Default)]
 pub struct ClientEncodingSupport {
-    /// Support for brotli encoding
-    has_brotli: bool,
+    /// Encodings the client accepts, the most preferred first
+    encodings: Vec<Encoding>,
 
-    /// Support for gzip encoding
-    has_gzip: bool,
+    /// Whether the client accepts content without any encoding
+    accepts_identity: bool,
+}
+
+impl Default for ClientEncodingSupport {
+    fn default() -> Self {
+        Self {
+            encodings: vec![],
+            accepts_identity: true,
+        }
+    }
 }
 
 impl ClientEncodingSupport {
     /// Create new `ClientEncodingSupport` from `HeaderMap`
     ///
     /// Will check for `accept-encoding` header and check if it contains
-    /// `br` or `gzip` encoding
-    fn from_header_map(incoming_headers: &HeaderMap) -> Self {
-        let mut support = Self::default();
+    /// `br`, `zstd`, `gzip` or `deflate` encoding, ordered by their quality
+    /// values. Equally preferred encodings keep the order of the server, an
+    /// encoding with `q=0` is not acceptable at all. The wildcard `*` applies
+    /// to all encodings that are not listed, including `identity`.
+    ///
+    /// Only the encodings enabled on the server are used, a client that
+    /// accepts nothing else is treated as one without any encoding support.
+    ///
+    /// Range requests get no encoding, their ranges are meant for the original
+    /// file and not for the bytes of a precompressed variant
+    pub fn from_header_map(incoming_headers: &HeaderMap, enabled: &[Encoding]) -> Self {
+        if incoming_headers.contains_key(RANGE) {
+            return Self::default();
+        }
 
-        let encodings = incoming_headers
+        let Some(entries) = incoming_headers
             .get(ACCEPT_ENCODING)
             .and_then(|encoding| encoding.to_str().ok())
-            .map(|encoding| encoding.split(',').map(str::trim).collect::<Vec<&str>>());
-
-        if let Some(encodings) = encodings {
-            support.has_brotli = Self::check_support(&encodings, ENCODING_BR);
-            support.has_gzip = Self::check_support(&encodings, ENCODING_GZIP);
+            .map(|encoding| encoding.split(',').map(str::trim).collect::<Vec<&str>>())
+        else {
+            return Self::default();
+        };
+
+        let wildcard = Self::quality(&entries, ENCODING_WILDCARD);
+
+        let mut encodings = Encoding::ALL
+            .into_iter()
+            .filter(|encoding| enabled.contains(encoding))
+            .filter_map(|encoding| {
+                let quality = Self::quality(&entries, encoding.name()).or(wildcard)?;
+                (quality > 0).then_some((encoding, quality))
+            })
+            .collect::<Vec<_>>();
+
+        // the sort is stable, ties are left in the order of the server
+        encodings.sort_by_key(|(_, quality)| Reverse(*quality));
+
+        // identity is always acceptable, unless it is excluded explicitly
+        let accepts_identity = Self::quality(&entries, ENCODING_IDENTITY)
+            .or(wildcard)
+            .map_or(true, |quality| quality > 0);
+
+        Self {
+            encodings: encodings
+                .into_iter()
+                .map(|(encoding, _)| encoding)
+                .collect(),
+            accepts_identity,
         }
-
-        support
     }
 
-    /// Check if the client supports the given encoding
+    /// Quality value of the given encoding, in thousandths, `None` when the
+    /// client does not list it
     ///
-    /// This also checks the quality value of the encoding:
-    /// - `Accept-Encoding: deflate, gzip;q=1.0, *;q=0.5` would match `"gzip"`
+    /// - `Accept-Encoding: deflate, gzip;q=0.5, *;q=0.1` gives `500` for `"gzip"`
     ///
     /// See <https://developer.mozilla.org/en-US/docs/Web/HTTP/Headers/Accept-Encoding>
-    fn check_support(encodings: &[&str], encoding_name: &str) -> bool {
-        encodings.iter().any(|encoding| {
-            match encoding.split_once(';') {
-                Some((encoding, _)) => encoding.trim(),
-                None => encoding,
+    fn quality(entries: &[&str], encoding_name: &str) -> Option<u16> {
+        entries.iter().find_map(|entry| {
+            let mut parts = entry.split(';').map(str::trim);
+
+            if !parts.next()?.eq_ignore_ascii_case(encoding_name) {
+                return None;
             }
-            .eq_ignore_ascii_case(encoding_name)
+
+            parts
+                .find_map(|param| {
+                    let (name, value) = param.split_once('=')?;
+                    name.trim()
+                        .eq_ignore_ascii_case("q")
+                        .then(|| parse_quality(value.trim()))
+                })
+                .unwrap_or(Some(MAX_QUALITY))
         })
     }
 
-    /// Get list of supported encodings
+    /// Get list of supported encodings, the most preferred first
     #[inline]
-    pub const fn supported_encodings(&self) -> &[Encoding] {
-        match (self.has_brotli, self.has_gzip) {
-            (true, true) => &[Encoding::Brotli, Encoding::Gzip],
-            (true, false) => &[Encoding::Brotli],
-            (false, true) => &[Encoding::Gzip],
-            (false, false) => &[],
-        }
+    pub fn supported_encodings(&self) -> &[Encoding] {
+        &self.encodings
+    }
+
+    /// Check if content without an encoding is acceptable, if not, the client
+    /// gets a 406 instead
+    #[inline]
+    pub const fn accepts_identity(&self) -> bool {
+        self.accepts_identity
     }
 }
 
-#[async_trait]
-impl<S> FromRequestParts<S> for ClientEncodingSupport
-where
-    S: Send + Sync,
-{
-    type Rejection = Infallible;
+/// Parse a quality value, ie `0.8` or `1.000`, into thousandths
+fn parse_quality(value: &str) -> Option<u16> {
+    let (whole, fraction) = value.split_once('.').unwrap_or((value, ""));
 
-    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
-        Ok(ClientEncodingSupport::from_header_map(&parts.headers))
+    if fraction.len() > 3 || !fraction.bytes().all(|byte| byte.is_ascii_digit()) {
+        return None;
+    }
+
+    let fraction = format!("{fraction:0<3}").parse::<u16>().ok()?;
+
+    match whole {
+        "0" => Some(fraction),
+        "1" if fraction == 0 => Some(MAX_QUALITY),
+        _ => None,
     }
 }
 
@@ -130,77 +441,155 @@ where
 mod tests {
     use super::*;
 
+    fn support(accept_encoding: &'static str) -> ClientEncodingSupport {
+        let mut headers = HeaderMap::new();
+        headers.insert(ACCEPT_ENCODING, HeaderValue::from_static(accept_encoding));
+
+        ClientEncodingSupport::from_header_map(&headers, &Encoding::ALL)
+    }
+
     #[test]
-    fn test_check_support() {
+    fn test_quality() {
         let encodings = &["gzip", "brotli"];
 
-        assert!(ClientEncodingSupport::check_support(encodings, "gzip"));
-        assert!(ClientEncodingSupport::check_support(encodings, "brotli"));
-        assert!(!ClientEncodingSupport::check_support(encodings, "deflate"),);
+        assert_eq!(
+            ClientEncodingSupport::quality(encodings, "gzip"),
+            Some(1000)
+        );
+        assert_eq!(
+            ClientEncodingSupport::quality(encodings, "brotli"),
+            Some(1000)
+        );
+        assert_eq!(ClientEncodingSupport::quality(encodings, "deflate"), None);
     }
 
     #[test]
-    fn test_check_support_with_quality() {
-        let encodings = &["gzip;q=1.0", "brotli"];
+    fn test_quality_with_quality() {
+        let encodings = &["gzip;q=1.0", "brotli; Q=0.25", "zstd;q=0", "deflate;q=2"];
 
-        assert!(ClientEncodingSupport::check_support(encodings, "gzip"));
-        assert!(ClientEncodingSupport::check_support(encodings, "brotli"));
-        assert!(!ClientEncodingSupport::check_support(encodings, "deflate"),);
+        assert_eq!(
+            ClientEncodingSupport::quality(encodings, "gzip"),
+            Some(1000)
+        );
+        assert_eq!(
+            ClientEncodingSupport::quality(encodings, "brotli"),
+            Some(250)
+        );
+        assert_eq!(ClientEncodingSupport::quality(encodings, "zstd"), Some(0));
+        assert_eq!(ClientEncodingSupport::quality(encodings, "deflate"), None);
     }
 
     #[test]
-    fn test_supported_encodings() {
-        let mut support = ClientEncodingSupport::default();
+    fn test_parse_quality() {
+        assert_eq!(parse_quality("1"), Some(1000));
+        assert_eq!(parse_quality("1.000"), Some(1000));
+        assert_eq!(parse_quality("0.5"), Some(500));
+        assert_eq!(parse_quality("0.125"), Some(125));
+        assert_eq!(parse_quality("0"), Some(0));
+
+        assert_eq!(parse_quality("1.5"), None);
+        assert_eq!(parse_quality("0.1234"), None);
+        assert_eq!(parse_quality("-1"), None);
+        assert_eq!(parse_quality(""), None);
+    }
 
-        support.has_gzip = true;
-        support.has_brotli = true;
+    #[test]
+    fn test_supported_encodings() {
         assert_eq!(
-            support.supported_encodings(),
+            support("br, gzip").supported_encodings(),
             &[Encoding::Brotli, Encoding::Gzip]
         );
+        assert_eq!(support("br").supported_encodings(), &[Encoding::Brotli]);
+        assert_eq!(support("identity").supported_encodings(), &[]);
+        assert_eq!(support("gzip").supported_encodings(), &[Encoding::Gzip]);
+        assert_eq!(
+            support("gzip, zstd").supported_encodings(),
+            &[Encoding::Zstd, Encoding::Gzip]
+        );
+        assert_eq!(
+            support("deflate, gzip, zstd").supported_encodings(),
+            &[Encoding::Zstd, Encoding::Gzip, Encoding::Deflate]
+        );
+    }
 
-        support.has_gzip = false;
-        assert_eq!(support.supported_encodings(), &[Encoding::Brotli]);
-
-        support.has_brotli = false;
-        assert_eq!(support.supported_encodings(), &[]);
+    #[test]
+    fn test_preference_order() {
+        assert_eq!(
+            support("br;q=0.1, gzip;q=1.0").supported_encodings(),
+            &[Encoding::Gzip, Encoding::Brotli]
+        );
+        assert_eq!(
+            support("gzip;q=0.5, deflate;q=0.8, br;q=0.5").supported_encodings(),
+            &[Encoding::Deflate, Encoding::Brotli, Encoding::Gzip]
+        );
+        assert_eq!(
+            support("br;q=0, gzip").supported_encodings(),
+            &[Encoding::Gzip]
+        );
+    }
 
-        support.has_gzip = true;
-        assert_eq!(support.supported_encodings(), &[Encoding::Gzip]);
+    #[test]
+    fn test_wildcard() {
+        assert_eq!(support("*").supported_encodings(), &Encoding::ALL);
+        assert_eq!(
+            support("gzip, *;q=0.5").supported_encodings(),
+            &[
+                Encoding::Gzip,
+                Encoding::Brotli,
+                Encoding::Zstd,
+                Encoding::Deflate
+            ]
+        );
+        assert_eq!(
+            support("br;q=0.2, *;q=0").supported_encodings(),
+            &[Encoding::Brotli]
+        );
     }
 
     #[test]
-    fn test_simple_header_map() {
+    fn test_enabled_encodings() {
         let mut headers = HeaderMap::new();
-        headers.insert(ACCEPT_ENCODING, HeaderValue::from_static("gzip, Br"));
+        headers.insert(ACCEPT_ENCODING, HeaderValue::from_static("zstd, br, gzip"));
 
-        let support = ClientEncodingSupport::from_header_map(&headers);
+        let support =
+            ClientEncodingSupport::from_header_map(&headers, &[Encoding::Gzip, Encoding::Brotli]);
+        assert_eq!(
+            support.supported_encodings(),
+            &[Encoding::Brotli, Encoding::Gzip]
+        );
 
-        assert!(support.has_gzip);
-        assert!(support.has_brotli);
+        let support = ClientEncodingSupport::from_header_map(&headers, &[]);
+        assert!(support.supported_encodings().is_empty());
+    }
 
+    #[test]
+    fn test_identity() {
+        assert!(ClientEncodingSupport::default().accepts_identity());
+        assert!(support("gzip").accepts_identity());
+        assert!(support("*").accepts_identity());
+        assert!(support("identity;q=0.5, *;q=0").accepts_identity());
+
+        let refused = support("identity;q=0, *;q=0");
+        assert!(!refused.accepts_identity());
+        assert!(refused.supported_encodings().is_empty());
+
+        assert!(!support("gzip, identity;q=0").accepts_identity());
+        assert!(!support("br, *;q=0").accepts_identity());
+    }
+
+    #[test]
+    fn test_simple_header_map() {
         assert_eq!(
             &[Encoding::Brotli, Encoding::Gzip],
-            support.supported_encodings(),
+            support("gzip, Br").supported_encodings(),
         );
     }
 
     #[test]
     fn test_header_map_with_quality() {
-        let mut headers = HeaderMap::new();
-        headers.insert(
-            ACCEPT_ENCODING,
-            HeaderValue::from_static("gzip;q=1.0, br ;  0.5"),
-        );
-
-        let support = ClientEncodingSupport::from_header_map(&headers);
-
-        assert!(support.has_gzip);
-        assert!(support.has_brotli);
-
         assert_eq!(
             &[Encoding::Brotli, Encoding::Gzip],
-            support.supported_encodings(),
+            support("gzip;q=1.0, br ;  0.5").supported_encodings(),
         );
     }
 
@@ -208,24 +597,125 @@ mod tests {
     fn test_empty_header_map() {
         let headers = HeaderMap::new();
 
-        let support = ClientEncodingSupport::from_header_map(&headers);
-
-        assert!(!support.has_gzip);
-        assert!(!support.has_brotli);
+        let support = ClientEncodingSupport::from_header_map(&headers, &Encoding::ALL);
 
-        assert!(support.supported_encodings().len() == 0);
+        assert!(support.supported_encodings().is_empty());
     }
 
     #[test]
     fn test_simple_header_map_with_deflate() {
+        assert_eq!(
+            &[Encoding::Deflate],
+            support("deflate").supported_encodings()
+        );
+    }
+
+    #[test]
+    fn test_compressible() {
+        for content_type in [
+            "text/html",
+            "text/css; charset=utf-8",
+            "application/javascript",
+            "application/manifest+json",
+            "image/svg+xml",
+        ] {
+            assert!(
+                is_compressible(&HeaderValue::from_static(content_type)),
+                "{content_type}"
+            );
+        }
+
+        for content_type in ["image/png", "video/mp4", "application/zip", "font/woff2"] {
+            assert!(
+                !is_compressible(&HeaderValue::from_static(content_type)),
+                "{content_type}"
+            );
+        }
+    }
+
+    #[test]
+    fn test_content_type_pattern() {
+        let text = "text/*"
+            .parse::<ContentTypePattern>()
+            .expect("A valid pattern");
+        let json = "Application/JSON"
+            .parse::<ContentTypePattern>()
+            .expect("A valid pattern");
+
+        assert!(text.matches(&HeaderValue::from_static("text/html; charset=utf-8")));
+        assert!(text.matches(&HeaderValue::from_static("text/css")));
+        assert!(!text.matches(&HeaderValue::from_static("application/json")));
+        assert!(json.matches(&HeaderValue::from_static("application/json")));
+        assert!(!json.matches(&HeaderValue::from_static("application/ld+json")));
+
+        assert!("text".parse::<ContentTypePattern>().is_err());
+        assert!("*/*".parse::<ContentTypePattern>().is_err());
+        assert!("text/".parse::<ContentTypePattern>().is_err());
+    }
+
+    #[test]
+    fn test_range_request() {
         let mut headers = HeaderMap::new();
-        headers.insert(ACCEPT_ENCODING, HeaderValue::from_static("deflate"));
+        headers.insert(ACCEPT_ENCODING, HeaderValue::from_static("br, gzip"));
+        headers.insert(RANGE, HeaderValue::from_static("bytes=0-99"));
+
+        let support = ClientEncodingSupport::from_header_map(&headers, &Encoding::ALL);
 
-        let support = ClientEncodingSupport::from_header_map(&headers);
+        assert!(support.supported_encodings().is_empty());
+    }
 
-        assert!(!support.has_gzip);
-        assert!(!support.has_brotli);
+    #[test]
+    fn test_from_path() {
+        assert_eq!(
+            Encoding::from_path(Path::new("backup.tar.gz")),
+            Some(Encoding::Gzip)
+        );
+        assert_eq!(
+            Encoding::from_path(Path::new("app.js.br")),
+            Some(Encoding::Brotli)
+        );
+        assert_eq!(Encoding::from_path(Path::new("app.js")), None);
+        assert_eq!(Encoding::from_path(Path::new("gz")), None);
+    }
 
-        assert!(support.supported_encodings().len() == 0);
+    #[test]
+    fn test_compress_roundtrip() {
+        use std::io::Read;
+
+        let content = "<ul><li>listing</li></ul>".repeat(100);
+
+        let compressed = Encoding::Brotli
+            .compress(content.as_bytes())
+            .expect("Compressible content");
+        let mut decoded = String::new();
+        brotli::Decompressor::new(&compressed[..], 4096)
+            .read_to_string(&mut decoded)
+            .expect("Decodable content");
+        assert_eq!(decoded, content);
+
+        let compressed = Encoding::Gzip
+            .compress(content.as_bytes())
+            .expect("Compressible content");
+        let mut decoded = String::new();
+        flate2::read::GzDecoder::new(&compressed[..])
+            .read_to_string(&mut decoded)
+            .expect("Decodable content");
+        assert_eq!(decoded, content);
+        assert!(compressed.len() < content.len());
+
+        let compressed = Encoding::Zstd
+            .compress(content.as_bytes())
+            .expect("Compressible content");
+        let decoded = zstd::decode_all(&compressed[..]).expect("Decodable content");
+        assert_eq!(decoded, content.as_bytes());
+
+        let compressed = Encoding::Deflate
+            .compress(content.as_bytes())
+            .expect("Compressible content");
+        let mut decoded = String::new();
+        flate2::read::ZlibDecoder::new(&compressed[..])
+            .read_to_string(&mut decoded)
+            .expect("Decodable content");
+        assert_eq!(decoded, content);
     }
 }
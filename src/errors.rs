@@ -0,0 +1,186 @@
+//! Error responses for API-style clients
+//!
+//! Clients preferring `application/json` (or every client, with `--json-errors`)
+//! get a JSON body for error responses, instead of an empty body or an HTML
+//! page. Every request gets an id, which is part of the JSON body.
+
+use std::sync::atomic::AtomicU64;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use std::time::SystemTime;
+use std::time::UNIX_EPOCH;
+
+use axum::extract::Request;
+use axum::extract::State;
+use axum::http::header::ACCEPT;
+use axum::http::header::CONTENT_ENCODING;
+use axum::http::header::CONTENT_LENGTH;
+use axum::http::header::CONTENT_TYPE;
+use axum::http::HeaderMap;
+use axum::http::HeaderValue;
+use axum::middleware::Next;
+use axum::response::IntoResponse;
+use axum::response::Response;
+use axum::Json;
+use serde::Serialize;
+use tower_http::request_id::MakeRequestId;
+use tower_http::request_id::RequestId;
+
+use crate::app::ServerState;
+
+/// Generates request ids, unique for this process and unlikely to repeat across restarts
+#[derive(Clone)]
+pub struct RequestIds {
+    prefix: u64,
+    next: Arc<AtomicU64>,
+}
+
+impl Default for RequestIds {
+    fn default() -> Self {
+        let prefix = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |since_epoch| since_epoch.as_secs());
+
+        Self {
+            prefix,
+            next: Arc::default(),
+        }
+    }
+}
+
+impl MakeRequestId for RequestIds {
+    fn make_request_id<B>(&mut self, _request: &axum::http::Request<B>) -> Option<RequestId> {
+        let id = self.next.fetch_add(1, Ordering::Relaxed);
+
+        HeaderValue::from_str(&format!("{:x}-{id:x}", self.prefix))
+            .ok()
+            .map(RequestId::new)
+    }
+}
+
+/// Body of a JSON error response
+#[derive(Debug, Serialize)]
+struct ErrorBody {
+    status: u16,
+    error: &'static str,
+    path: String,
+    request_id: Option<String>,
+}
+
+/// Check if the `accept` header prefers JSON over HTML
+///
+/// Wildcards are ignored, as browsers send `*/*` as well
+pub fn prefers_json(headers: &HeaderMap) -> bool {
+    let mut json = 0.0;
+    let mut html = 0.0;
+
+    let media_ranges = headers
+        .get_all(ACCEPT)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','));
+
+    for media_range in media_ranges {
+        let mut parts = media_range.split(';').map(str::trim);
+        let media_type = parts.next().unwrap_or_default();
+
+        let quality = parts
+            .find_map(|parameter| parameter.strip_prefix("q="))
+            .and_then(|quality| quality.parse::<f32>().ok())
+            .unwrap_or(1.0);
+
+        if media_type.eq_ignore_ascii_case("application/json")
+            || media_type.eq_ignore_ascii_case("application/problem+json")
+        {
+            json = f32::max(json, quality);
+        } else if media_type.eq_ignore_ascii_case("text/html") {
+            html = f32::max(html, quality);
+        }
+    }
+
+    json > 0.0 && json > html
+}
+
+/// Check if the response already has a JSON body
+fn is_json(headers: &HeaderMap) -> bool {
+    headers
+        .get(CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|content_type| {
+            let media_type = content_type.split(';').next().unwrap_or_default().trim();
+            media_type.ends_with("/json") || media_type.ends_with("+json")
+        })
+}
+
+/// Middleware that replaces the body of error responses with JSON
+pub async fn json_errors(
+    State(state): State<ServerState>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let wants_json = state.config.json_errors || prefers_json(request.headers());
+
+    if !wants_json {
+        return next.run(request).await;
+    }
+
+    let path = request.uri().path().to_string();
+    let request_id = request
+        .extensions()
+        .get::<RequestId>()
+        .and_then(|request_id| request_id.header_value().to_str().ok())
+        .map(ToString::to_string);
+
+    let response = next.run(request).await;
+    let status = response.status();
+
+    if !(status.is_client_error() || status.is_server_error()) || is_json(response.headers()) {
+        return response;
+    }
+
+    let (mut parts, _) = response.into_parts();
+
+    // `content-range` stays, it describes the full content for a 416
+    for name in [CONTENT_TYPE, CONTENT_LENGTH, CONTENT_ENCODING] {
+        parts.headers.remove(name);
+    }
+
+    let body = ErrorBody {
+        status: status.as_u16(),
+        error: status.canonical_reason().unwrap_or("Error"),
+        path,
+        request_id,
+    };
+
+    (parts, Json(body)).into_response()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn accept(value: &'static str) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert(ACCEPT, HeaderValue::from_static(value));
+        headers
+    }
+
+    #[test]
+    fn test_prefers_json() {
+        assert!(prefers_json(&accept("application/json")));
+        assert!(prefers_json(&accept("application/json, text/plain, */*")));
+        assert!(prefers_json(&accept("text/html;q=0.5, application/json")));
+        assert!(prefers_json(&accept("application/problem+json")));
+    }
+
+    #[test]
+    fn test_does_not_prefer_json() {
+        assert!(!prefers_json(&HeaderMap::new()));
+        assert!(!prefers_json(&accept("*/*")));
+        assert!(!prefers_json(&accept(
+            "text/html,application/xhtml+xml,application/xml;q=0.9,*/*;q=0.8"
+        )));
+        assert!(!prefers_json(&accept("text/html, application/json;q=0.9")));
+        assert!(!prefers_json(&accept("application/json;q=0")));
+    }
+}
@@ -0,0 +1,225 @@
+//! Resolution tracer
+//!
+//! Explains how a request path is resolved: the candidates that are tried in
+//! order, which of them wins and the response that would be sent. The request
+//! is handled in-process by the router, so the response is the real thing.
+
+use std::path::PathBuf;
+
+use axum::body::Body;
+use axum::http::header::ACCEPT;
+use axum::http::header::ACCEPT_ENCODING;
+use axum::http::HeaderMap;
+use axum::http::HeaderValue;
+use axum::http::Method;
+use axum::http::Request;
+use axum::http::Uri;
+use clap::Args;
+use percent_encoding::percent_decode_str;
+use tower::ServiceExt;
+
+use crate::app::app;
+use crate::app::paths_to_try;
+use crate::app::ServerState;
+use crate::config::Config;
+use crate::encoding::ClientEncodingSupport;
+use crate::file_cache::FILE_SYSTEM_THRESHOLD;
+use crate::normalize::normalize_path;
+use crate::paths::PathToTry;
+use crate::paths::SidecarProblem;
+
+/// Options of the resolution tracer
+#[derive(Args, Clone, Debug)]
+pub struct ExplainConfig {
+    /// The path to explain, ie `/docs/guide?page=2`
+    url_path: Uri,
+
+    /// Value of the `accept-encoding` header of the request
+    #[arg(long, value_name = "ENCODINGS")]
+    accept_encoding: Option<HeaderValue>,
+
+    /// Value of the `accept` header of the request, `text/html` for page navigations
+    #[arg(long, value_name = "TYPES")]
+    accept: Option<HeaderValue>,
+}
+
+/// Outcome of a single candidate
+enum Candidate {
+    /// Not allowed by `--only-ext`
+    NotServable,
+
+    /// Not on disk
+    Missing,
+
+    /// A precompressed variant that can not be used
+    Unusable(SidecarProblem),
+
+    /// Above `--max-file-size`, which stops the search
+    TooLarge(u64),
+
+    /// On disk and servable
+    Found(u64),
+}
+
+impl Candidate {
+    fn check(state: &ServerState, path_to_try: &PathToTry) -> Self {
+        if !state.is_servable(&path_to_try.path()) {
+            return Self::NotServable;
+        }
+
+        match std::fs::metadata(path_to_try.content_path()) {
+            Ok(meta) if meta.is_file() => {
+                let problem = path_to_try.encoding().and_then(|_| {
+                    let original_meta = std::fs::metadata(path_to_try.path()).ok();
+
+                    SidecarProblem::check(
+                        &meta,
+                        original_meta.as_ref(),
+                        state.config.serve_orphan_sidecars,
+                    )
+                });
+
+                if let Some(problem) = problem {
+                    Self::Unusable(problem)
+                } else if state
+                    .config
+                    .max_file_size
+                    .is_some_and(|max_file_size| meta.len() > max_file_size)
+                {
+                    Self::TooLarge(meta.len())
+                } else {
+                    Self::Found(meta.len())
+                }
+            }
+            _ => Self::Missing,
+        }
+    }
+
+    fn describe(&self) -> String {
+        match self {
+            Self::NotServable => String::from("skipped, not servable"),
+            Self::Missing => String::from("missing"),
+            Self::Unusable(problem) => format!("skipped, {}", problem.describe()),
+            Self::TooLarge(size) => format!("too large ({size} bytes), stops the search"),
+            Self::Found(size) if *size > FILE_SYSTEM_THRESHOLD => {
+                format!("found ({size} bytes, streamed from disk)")
+            }
+            Self::Found(size) => format!("found ({size} bytes, kept in memory)"),
+        }
+    }
+}
+
+/// The `Cache-Control` header the candidate gets, when it wins
+fn cache_control(state: &ServerState, path_to_try: &PathToTry) -> Option<String> {
+    let release = state.release();
+    let path = path_to_try.path();
+    let file = path.strip_prefix(&release.base_dir).unwrap_or(&path);
+
+    let cache_control = state
+        .cache_rules
+        .cache_control(file, path_to_try.is_fallback())
+        .or_else(|| state.cache_rules.default_cache_control())?;
+
+    Some(cache_control.to_str().ok()?.to_string())
+}
+
+/// Print how the path is resolved
+pub async fn explain(config: Config, explain_config: ExplainConfig) -> anyhow::Result<()> {
+    let state = ServerState::from_config(config);
+
+    let mut headers = HeaderMap::new();
+
+    if let Some(accept_encoding) = &explain_config.accept_encoding {
+        headers.insert(ACCEPT_ENCODING, accept_encoding.clone());
+    }
+
+    if let Some(accept) = &explain_config.accept {
+        headers.insert(ACCEPT, accept.clone());
+    }
+
+    let uri = explain_config.url_path;
+    println!("Request:    GET {uri}");
+
+    let normalized = normalize_path(uri.path()).unwrap_or_else(|| uri.path().to_string());
+    if normalized != uri.path() {
+        println!("Normalized: {normalized}");
+    }
+
+    let decoded = percent_decode_str(normalized.trim_start_matches('/')).decode_utf8()?;
+
+    if let Some(response) = state.redirects.response(&normalized, uri.query()) {
+        println!("Redirect:   {}", response.status());
+    } else if state.is_hidden(&format!("/{decoded}")) {
+        println!("Hidden:     blocked by --block-dotfiles");
+    } else {
+        let normalized_uri = normalized.parse::<Uri>()?;
+
+        let paths_to_try = paths_to_try(
+            &state,
+            &state.release(),
+            &Method::GET,
+            &headers,
+            &normalized_uri,
+            &ClientEncodingSupport::from_header_map(&headers, &state.config.encodings),
+            PathBuf::from(&*decoded),
+        )
+        .await;
+
+        println!("Candidates:");
+
+        let mut winner = None;
+
+        for (index, path_to_try) in paths_to_try.iter().enumerate() {
+            let candidate = Candidate::check(&state, path_to_try);
+
+            let mut details = vec![];
+            if let Some(encoding) = path_to_try.encoding() {
+                details.push(encoding.to_header_value().to_str()?.to_string());
+            }
+            if let Some(cache_control) = cache_control(&state, path_to_try) {
+                details.push(cache_control);
+            }
+
+            let details = if details.is_empty() {
+                String::new()
+            } else {
+                format!(" [{}]", details.join(", "))
+            };
+
+            let marker = if winner.is_none() && matches!(candidate, Candidate::Found(_)) {
+                winner = Some(index);
+                "  <- wins"
+            } else {
+                ""
+            };
+
+            println!(
+                "  {}. {}{details}: {}{marker}",
+                index + 1,
+                path_to_try.content_path().display(),
+                candidate.describe(),
+            );
+
+            if winner.is_none() && matches!(candidate, Candidate::TooLarge(_)) {
+                break;
+            }
+        }
+
+        if winner.is_none() {
+            println!("  No candidate wins, the not found response is used");
+        }
+    }
+
+    let mut request = Request::get(&uri).body(Body::empty())?;
+    *request.headers_mut() = headers;
+
+    let response = app(state).oneshot(request).await?;
+
+    println!("Response:   {}", response.status());
+
+    for (name, value) in response.headers() {
+        println!("  {name}: {}", value.to_str().unwrap_or("<binary>"));
+    }
+
+    Ok(())
+}
@@ -1,17 +1,58 @@
+use std::any::Any;
+use std::collections::hash_map::DefaultHasher;
 use std::collections::HashMap;
+use std::fmt::Display;
 use std::fs::Metadata;
+use std::hash::Hash;
+use std::hash::Hasher;
+use std::path::Path;
 use std::path::PathBuf;
+use std::str::FromStr;
+use std::sync::atomic::AtomicU64;
+use std::sync::atomic::Ordering;
 use std::sync::Arc;
+use std::time::Duration;
 use std::time::SystemTime;
+use std::time::UNIX_EPOCH;
 
+use axum::body::Body;
 use axum::http::HeaderValue;
+use axum_extra::body::AsyncReadBody;
+use axum_extra::headers::ETag;
 use httpdate::HttpDate;
 use tokio::fs::File;
 use tokio::io::AsyncReadExt;
 use tokio::sync::RwLock;
 
+use crate::encoding::Encoding;
+use crate::minify::Minifiable;
+use crate::minify::MAX_MINIFY_SIZE;
+use crate::snapshot::decode;
+use crate::snapshot::encode;
+use crate::snapshot::Snapshot;
+use crate::snapshot::SnapshotEntry;
+use crate::snapshot::SnapshotError;
+
 /// Threshold for which to start using the file system for serving files, ie _not_ to use the cache
-const FILE_SYSTEM_THRESHOLD: u64 = 65_536;
+pub const FILE_SYSTEM_THRESHOLD: u64 = 65_536;
+
+/// Maximum size of files that are compressed on the fly, their compressed
+/// variants are kept in memory
+const MAX_COMPRESS_SIZE: u64 = 8 * 1024 * 1024;
+
+/// Content type of a file, based on its extension
+pub fn content_type(path: &Path) -> HeaderValue {
+    crate::media::content_type(path)
+        .or_else(|| mime_guess::from_path(path).first_raw())
+        .or_else(|| Encoding::from_path(path).map(Encoding::content_type))
+        .map_or_else(
+            || {
+                HeaderValue::from_str(mime::APPLICATION_OCTET_STREAM.as_ref())
+                    .expect("A valid application/octet-stream header value")
+            },
+            HeaderValue::from_static,
+        )
+}
 
 #[derive(Clone)]
 pub enum FileCacheEntryContent {
@@ -19,6 +60,18 @@ pub enum FileCacheEntryContent {
     File,
 }
 
+impl FileCacheEntryContent {
+    /// Create a body with the full content
+    pub async fn into_body(self, content_path: &Path) -> std::io::Result<Body> {
+        match self {
+            Self::Cached(content) => Ok(Body::from(content.to_vec())),
+            Self::File => Ok(Body::new(AsyncReadBody::new(
+                File::open(content_path).await?,
+            ))),
+        }
+    }
+}
+
 #[derive(Clone)]
 pub enum FileCacheEntry {
     Found {
@@ -26,19 +79,211 @@ pub enum FileCacheEntry {
         content_type: HeaderValue,
         content_length: u64,
         last_modified: HttpDate,
+        etag: ETag,
     },
 
     NotFound,
 }
 
+/// Strong `ETag` of the content of a file
+///
+/// Content kept in memory is identified by its hash, so it stays the same when
+/// the file is touched without changing it. Files served from the file system
+/// are identified by their size and modification time, like most servers do.
+pub fn etag(content: &FileCacheEntryContent, content_length: u64, last_modified: HttpDate) -> ETag {
+    let etag = match content {
+        FileCacheEntryContent::Cached(content) => return content_etag(content),
+        FileCacheEntryContent::File => {
+            let last_modified = SystemTime::from(last_modified)
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default();
+            format!("\"{content_length:x}-{:x}\"", last_modified.as_secs())
+        }
+    };
+
+    etag.parse().expect("A valid ETag")
+}
+
+/// Strong `ETag` of content in memory, by its hash
+fn content_etag(content: &[u8]) -> ETag {
+    let mut hasher = DefaultHasher::new();
+    content.hash(&mut hasher);
+
+    format!("\"{:016x}\"", hasher.finish())
+        .parse()
+        .expect("A valid ETag")
+}
+
+/// Variant of a file compressed on the fly
+#[derive(Clone)]
+struct CompressedVariant {
+    /// `ETag` of the file it was compressed from
+    source_etag: ETag,
+
+    /// Compressed content, `None` when compressing did not make it smaller
+    content: Option<(Arc<Vec<u8>>, ETag)>,
+}
+
+/// A parsed configuration file, ie a `.srvr` file, kept until the file changes
+struct ParsedFile {
+    modified: SystemTime,
+
+    /// `None` when the file is invalid
+    parsed: Option<Arc<dyn Any + Send + Sync>>,
+}
+
 #[derive(Default)]
 pub struct FileCache {
     files: RwLock<HashMap<PathBuf, FileCacheEntry>>,
+    compressed: RwLock<HashMap<(PathBuf, Encoding), CompressedVariant>>,
+    parsed_files: RwLock<HashMap<PathBuf, ParsedFile>>,
+    minify: bool,
+    hits: AtomicU64,
+    misses: AtomicU64,
+}
+
+/// Number of cache lookups that were (not) found in the cache
+#[derive(Clone, Copy, Debug, Default)]
+pub struct FileCacheStats {
+    pub hits: u64,
+    pub misses: u64,
 }
 
 impl FileCache {
+    /// Create a cache, optionally minifying HTML, CSS and JavaScript files
+    pub fn new(minify: bool) -> Self {
+        Self {
+            minify,
+            ..Self::default()
+        }
+    }
+
     pub async fn get(&self, path: &PathBuf) -> Option<FileCacheEntry> {
-        self.files.read().await.get(path).map(Clone::clone)
+        let entry = self.files.read().await.get(path).cloned();
+
+        let counter = if entry.is_some() {
+            &self.hits
+        } else {
+            &self.misses
+        };
+        counter.fetch_add(1, Ordering::Relaxed);
+
+        entry
+    }
+
+    pub fn stats(&self) -> FileCacheStats {
+        FileCacheStats {
+            hits: self.hits.load(Ordering::Relaxed),
+            misses: self.misses.load(Ordering::Relaxed),
+        }
+    }
+
+    /// Forget all files, ie when they are no longer served
+    pub async fn clear(&self) {
+        self.files.write().await.clear();
+        self.compressed.write().await.clear();
+        self.parsed_files.write().await.clear();
+    }
+
+    /// Configuration file parsed as `T`, `None` when the file does not exist
+    ///
+    /// The file is parsed once until it changes, an invalid file is ignored
+    pub async fn parsed<T>(&self, path: &Path) -> Option<Arc<T>>
+    where
+        T: FromStr + Send + Sync + 'static,
+        T::Err: Display,
+    {
+        let modified = tokio::fs::metadata(path)
+            .await
+            .ok()?
+            .modified()
+            .unwrap_or(UNIX_EPOCH);
+
+        if let Some(parsed_file) = self.parsed_files.read().await.get(path) {
+            if parsed_file.modified == modified {
+                return Arc::clone(parsed_file.parsed.as_ref()?).downcast().ok();
+            }
+        }
+
+        let parsed = match tokio::fs::read_to_string(path).await {
+            Ok(content) => match content.parse::<T>() {
+                Ok(parsed) => Some(Arc::new(parsed)),
+                Err(err) => {
+                    tracing::warn!("Ignoring {path:?}: {err}");
+                    None
+                }
+            },
+            Err(err) => {
+                tracing::warn!("Could not read {path:?}: {err}");
+                None
+            }
+        };
+
+        self.parsed_files.write().await.insert(
+            path.to_path_buf(),
+            ParsedFile {
+                modified,
+                parsed: parsed
+                    .clone()
+                    .map(|parsed| parsed as Arc<dyn Any + Send + Sync>),
+            },
+        );
+
+        parsed
+    }
+
+    /// The file compressed with the encoding, compressed on the first request
+    ///
+    /// Variants are kept until the file changes, `None` when the file can not
+    /// be compressed or when compressing does not make it smaller
+    pub async fn compressed(
+        &self,
+        content_path: &Path,
+        entry: &FileCacheEntry,
+        encoding: Encoding,
+    ) -> Option<FileCacheEntry> {
+        let FileCacheEntry::Found {
+            content,
+            content_type,
+            content_length,
+            last_modified,
+            etag: source_etag,
+        } = entry
+        else {
+            return None;
+        };
+
+        let key = (content_path.to_path_buf(), encoding);
+        let variant = self.compressed.read().await.get(&key).cloned();
+
+        let variant = match variant {
+            Some(variant) if variant.source_etag == *source_etag => variant,
+            _ => {
+                if *content_length > MAX_COMPRESS_SIZE {
+                    return None;
+                }
+
+                let variant = CompressedVariant {
+                    source_etag: source_etag.clone(),
+                    content: compress(content, content_path, *content_length, encoding).await,
+                };
+
+                tracing::trace!("Compressed {content_path:?} with {encoding:?}");
+                self.compressed.write().await.insert(key, variant.clone());
+
+                variant
+            }
+        };
+
+        let (compressed, etag) = variant.content?;
+
+        Some(FileCacheEntry::Found {
+            content_length: compressed.len() as u64,
+            content: FileCacheEntryContent::Cached(compressed),
+            content_type: content_type.clone(),
+            last_modified: *last_modified,
+            etag,
+        })
     }
 
     async fn set(&self, path: PathBuf, entry: FileCacheEntry) -> FileCacheEntry {
@@ -52,19 +297,40 @@ impl FileCache {
         &self,
         meta: Metadata,
         content_path: PathBuf,
-        content_type_path: &PathBuf,
+        content_type_path: &Path,
     ) -> FileCacheEntry {
         match File::open(&content_path).await {
             Ok(mut file) => {
-                let mime = mime_guess::from_path(content_type_path)
-                    .first_raw()
-                    .map_or_else(
-                        || {
-                            HeaderValue::from_str(mime::APPLICATION_OCTET_STREAM.as_ref())
-                                .expect("A valid application/octet-stream header value")
-                        },
-                        HeaderValue::from_static,
-                    );
+                let mime = content_type(content_type_path);
+                let last_modified =
+                    HttpDate::from(meta.modified().unwrap_or_else(|_| SystemTime::now()));
+
+                // precompressed variants are served as they are
+                let minifiable = Minifiable::from_path(&content_path)
+                    .filter(|_| self.minify && meta.len() <= MAX_MINIFY_SIZE);
+
+                if let Some(minifiable) = minifiable {
+                    let mut bytes = vec![];
+
+                    if let Err(err) = file.read_to_end(&mut bytes).await {
+                        tracing::warn!("Could not read file into cache ({content_path:?}): {err}");
+                        return FileCacheEntry::NotFound;
+                    }
+
+                    let bytes = minify(minifiable, bytes).await;
+                    let content_length = bytes.len() as u64;
+                    let content = FileCacheEntryContent::Cached(bytes);
+
+                    let entry = FileCacheEntry::Found {
+                        etag: etag(&content, content_length, last_modified),
+                        content_length,
+                        content,
+                        content_type: mime,
+                        last_modified,
+                    };
+
+                    return self.set(content_path, entry).await;
+                }
 
                 let content = if meta.len() > FILE_SYSTEM_THRESHOLD {
                     tracing::trace!("Using file system to serve file");
@@ -87,12 +353,11 @@ impl FileCache {
                 };
 
                 let entry = FileCacheEntry::Found {
+                    etag: etag(&content, meta.len(), last_modified),
                     content,
                     content_type: mime,
                     content_length: meta.len(),
-                    last_modified: HttpDate::from(
-                        meta.modified().unwrap_or_else(|_| SystemTime::now()),
-                    ),
+                    last_modified,
                 };
 
                 self.set(content_path, entry).await
@@ -107,4 +372,169 @@ impl FileCache {
             }
         }
     }
+
+    /// Write the files in the cache to a snapshot, returns the number of files
+    pub async fn save_snapshot(&self, snapshot_path: &Path) -> Result<usize, SnapshotError> {
+        let entries = self
+            .files
+            .read()
+            .await
+            .iter()
+            .filter_map(|(path, entry)| {
+                let FileCacheEntry::Found {
+                    content,
+                    content_type,
+                    content_length,
+                    last_modified,
+                    ..
+                } = entry
+                else {
+                    return None;
+                };
+
+                let last_modified = SystemTime::from(*last_modified)
+                    .duration_since(UNIX_EPOCH)
+                    .ok()?;
+
+                Some(SnapshotEntry {
+                    path: path.clone(),
+                    content_type: content_type.to_str().ok()?.to_string(),
+                    content_length: *content_length,
+                    last_modified: last_modified.as_secs(),
+                    content: match content {
+                        FileCacheEntryContent::Cached(content) => Some(content.to_vec()),
+                        FileCacheEntryContent::File => None,
+                    },
+                })
+            })
+            .collect::<Vec<_>>();
+
+        // write next to the snapshot first, a crash should not leave half a snapshot behind
+        let mut temporary_path = snapshot_path.as_os_str().to_owned();
+        temporary_path.push(".tmp");
+
+        let saved = entries.len();
+        let snapshot = Snapshot {
+            minified: self.minify,
+            entries,
+        };
+
+        tokio::fs::write(&temporary_path, encode(&snapshot))
+            .await
+            .map_err(|err| SnapshotError::Io(snapshot_path.to_path_buf(), err))?;
+        tokio::fs::rename(&temporary_path, snapshot_path)
+            .await
+            .map_err(|err| SnapshotError::Io(snapshot_path.to_path_buf(), err))?;
+
+        Ok(saved)
+    }
+
+    /// Restore the files of a snapshot that did not change since, returns the
+    /// number of restored files
+    pub async fn load_snapshot(&self, snapshot_path: &Path) -> Result<usize, SnapshotError> {
+        let snapshot = tokio::fs::read(snapshot_path)
+            .await
+            .map_err(|err| SnapshotError::Io(snapshot_path.to_path_buf(), err))?;
+
+        let snapshot = decode(&snapshot)?;
+        if snapshot.minified != self.minify {
+            return Err(SnapshotError::Minify);
+        }
+
+        let mut restored = 0;
+
+        for entry in snapshot.entries {
+            let Ok(meta) = tokio::fs::metadata(&entry.path).await else {
+                continue;
+            };
+
+            let last_modified =
+                HttpDate::from(UNIX_EPOCH + Duration::from_secs(entry.last_modified));
+            // minified files are always kept in memory, at their minified length
+            let is_minified = self.minify
+                && meta.len() <= MAX_MINIFY_SIZE
+                && Minifiable::from_path(&entry.path).is_some();
+
+            let is_unchanged = meta.is_file()
+                && (is_minified || meta.len() == entry.content_length)
+                && meta.modified().ok().map(HttpDate::from) == Some(last_modified);
+
+            // files that cross the threshold are served differently, read them again
+            let is_cached = is_minified || meta.len() <= FILE_SYSTEM_THRESHOLD;
+
+            let (true, Ok(content_type)) = (
+                is_unchanged && is_cached == entry.content.is_some(),
+                HeaderValue::from_str(&entry.content_type),
+            ) else {
+                tracing::trace!("Not restoring changed file {:?}", entry.path);
+                continue;
+            };
+
+            let content = match entry.content {
+                Some(content) => FileCacheEntryContent::Cached(Arc::new(content)),
+                None => FileCacheEntryContent::File,
+            };
+
+            // the ETag is derived from what is restored, so it is the same as before
+            let found = FileCacheEntry::Found {
+                etag: etag(&content, entry.content_length, last_modified),
+                content,
+                content_type,
+                content_length: entry.content_length,
+                last_modified,
+            };
+
+            self.set(entry.path, found).await;
+            restored += 1;
+        }
+
+        Ok(restored)
+    }
+}
+
+/// Compress the content, `None` when it can not be read or does not get smaller
+async fn compress(
+    content: &FileCacheEntryContent,
+    content_path: &Path,
+    content_length: u64,
+    encoding: Encoding,
+) -> Option<(Arc<Vec<u8>>, ETag)> {
+    let source = match content {
+        FileCacheEntryContent::Cached(content) => Arc::clone(content),
+        FileCacheEntryContent::File => match tokio::fs::read(content_path).await {
+            Ok(content) => Arc::new(content),
+            Err(err) => {
+                tracing::warn!("Could not read file to compress ({content_path:?}): {err}");
+                return None;
+            }
+        },
+    };
+
+    let compressed = match tokio::task::spawn_blocking(move || encoding.compress(&source)).await {
+        Ok(Ok(compressed)) => compressed,
+        Ok(Err(err)) => {
+            tracing::warn!("Could not compress {content_path:?}: {err}");
+            return None;
+        }
+        Err(_) => return None,
+    };
+
+    if compressed.len() as u64 >= content_length {
+        return None;
+    }
+
+    let etag = content_etag(&compressed);
+
+    Some((Arc::new(compressed), etag))
+}
+
+/// Minify the content, falling back to the original content when it can not be minified
+async fn minify(minifiable: Minifiable, content: Vec<u8>) -> Arc<Vec<u8>> {
+    let content = Arc::new(content);
+    let source = Arc::clone(&content);
+
+    match tokio::task::spawn_blocking(move || minifiable.minify(&source)).await {
+        Ok(Some(minified)) => Arc::new(minified),
+        Ok(None) | Err(_) => content,
+    }
 }
@@ -0,0 +1,491 @@
+//! Origin of requests behind a reverse proxy
+//!
+//! A proxy that terminates TLS forwards requests over plain http, the original
+//! scheme and host are only known from the `forwarded` (RFC 7239) or the
+//! `x-forwarded-proto` and `x-forwarded-host` headers. Anyone can send these
+//! headers, so they are only used for requests from trusted proxies.
+//!
+//! The address of the client is taken from `x-forwarded-for` instead, as the
+//! last address that is not a trusted proxy: the ones before it could have
+//! been sent by the client itself. The same goes for the scheme and host, when
+//! a chain of proxies adds multiple values the one of the trusted proxy
+//! closest to the client wins, values before it could have been sent by the
+//! client.
+
+use std::fmt;
+use std::net::IpAddr;
+use std::net::SocketAddr;
+use std::str::FromStr;
+
+use axum::extract::ConnectInfo;
+use axum::extract::Request;
+use axum::extract::State;
+use axum::http::header::FORWARDED;
+use axum::http::header::HOST;
+use axum::http::header::LOCATION;
+use axum::http::uri::Authority;
+use axum::http::uri::Scheme;
+use axum::http::HeaderMap;
+use axum::http::HeaderName;
+use axum::http::HeaderValue;
+use axum::middleware::Next;
+use axum::response::Response;
+
+use crate::app::ServerState;
+use crate::proxy::X_FORWARDED_FOR;
+use crate::proxy::X_FORWARDED_HOST;
+use crate::proxy::X_FORWARDED_PROTO;
+
+#[derive(Debug, thiserror::Error)]
+pub enum TrustedProxyError {
+    #[error("Invalid proxy address \"{0}\", expected an IP address or a network, ie 10.0.0.0/8")]
+    InvalidAddress(String),
+
+    #[error("Invalid network prefix length \"{0}\"")]
+    InvalidPrefix(String),
+}
+
+/// Address or network of a proxy that is trusted to report the origin of requests
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct TrustedProxy {
+    network: IpAddr,
+    prefix: u8,
+}
+
+impl FromStr for TrustedProxy {
+    type Err = TrustedProxyError;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let (address, prefix) = match value.split_once('/') {
+            Some((address, prefix)) => (address, Some(prefix)),
+            None => (value, None),
+        };
+
+        let network = address
+            .parse::<IpAddr>()
+            .map_err(|_| TrustedProxyError::InvalidAddress(value.to_string()))?;
+
+        let max_prefix = if network.is_ipv4() { 32 } else { 128 };
+
+        let prefix = match prefix {
+            Some(prefix) => prefix
+                .parse::<u8>()
+                .ok()
+                .filter(|prefix| *prefix <= max_prefix)
+                .ok_or_else(|| TrustedProxyError::InvalidPrefix(prefix.to_string()))?,
+            None => max_prefix,
+        };
+
+        Ok(Self { network, prefix })
+    }
+}
+
+impl fmt::Display for TrustedProxy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/{}", self.network, self.prefix)
+    }
+}
+
+impl TrustedProxy {
+    /// The address is part of the network of the proxy
+    fn contains(self, address: IpAddr) -> bool {
+        let address = match address {
+            IpAddr::V6(address) => address
+                .to_ipv4_mapped()
+                .map_or(IpAddr::V6(address), IpAddr::V4),
+            IpAddr::V4(_) => address,
+        };
+
+        match (self.network, address) {
+            (IpAddr::V4(network), IpAddr::V4(address)) => {
+                let mask = u32::MAX
+                    .checked_shl(32 - u32::from(self.prefix))
+                    .unwrap_or(0);
+                u32::from(network) & mask == u32::from(address) & mask
+            }
+            (IpAddr::V6(network), IpAddr::V6(address)) => {
+                let mask = u128::MAX
+                    .checked_shl(128 - u32::from(self.prefix))
+                    .unwrap_or(0);
+                u128::from(network) & mask == u128::from(address) & mask
+            }
+            _ => false,
+        }
+    }
+}
+
+/// The peer is one of the trusted proxies
+pub fn is_trusted_proxy(trusted_proxies: &[TrustedProxy], peer: Option<IpAddr>) -> bool {
+    peer.is_some_and(|peer| {
+        trusted_proxies
+            .iter()
+            .any(|trusted_proxy| trusted_proxy.contains(peer))
+    })
+}
+
+/// Address of the client of the request, as reported by trusted proxies or
+/// else the peer
+pub fn client_address(state: &ServerState, request: &Request) -> Option<IpAddr> {
+    let peer = request
+        .extensions()
+        .get::<ConnectInfo<SocketAddr>>()
+        .map(|ConnectInfo(address)| address.ip());
+
+    forwarded_client_address(&state.config.trusted_proxy, request.headers(), peer)
+}
+
+/// The last address of `x-forwarded-for` that is not a trusted proxy, when the
+/// peer is one
+fn forwarded_client_address(
+    trusted_proxies: &[TrustedProxy],
+    headers: &HeaderMap,
+    peer: Option<IpAddr>,
+) -> Option<IpAddr> {
+    let mut client = peer;
+
+    if !is_trusted_proxy(trusted_proxies, peer) {
+        return client;
+    }
+
+    for address in header_values(headers, &X_FORWARDED_FOR).iter().rev() {
+        let Ok(address) = address.parse::<IpAddr>() else {
+            break;
+        };
+
+        client = Some(address);
+
+        if !is_trusted_proxy(trusted_proxies, client) {
+            break;
+        }
+    }
+
+    client
+}
+
+/// Number of trusted proxies the request passed, the peer and the trusted
+/// proxies at the end of `x-forwarded-for`
+fn trusted_hops(trusted_proxies: &[TrustedProxy], headers: &HeaderMap) -> usize {
+    let trusted_addresses = header_values(headers, &X_FORWARDED_FOR)
+        .iter()
+        .rev()
+        .take_while(|address| is_trusted_proxy(trusted_proxies, address.parse::<IpAddr>().ok()))
+        .count();
+
+    1 + trusted_addresses
+}
+
+/// Scheme and host the client used for the request
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Origin {
+    pub scheme: Scheme,
+    pub host: Option<Authority>,
+}
+
+impl Origin {
+    /// Origin of the request, as reported by a trusted proxy or else as received
+    pub fn from_request(state: &ServerState, request: &Request) -> Self {
+        let peer = request
+            .extensions()
+            .get::<ConnectInfo<SocketAddr>>()
+            .map(|ConnectInfo(address)| address.ip());
+
+        let scheme = if state.config.is_tls() {
+            Scheme::HTTPS
+        } else {
+            Scheme::HTTP
+        };
+
+        let mut origin = Self::new(&state.config.trusted_proxy, request.headers(), peer, scheme);
+
+        // HTTP/2 requests have the host in the URI
+        if origin.host.is_none() {
+            origin.host = request.uri().authority().cloned();
+        }
+
+        origin
+    }
+
+    fn new<'a>(
+        trusted_proxies: &[TrustedProxy],
+        headers: &'a HeaderMap,
+        peer: Option<IpAddr>,
+        scheme: Scheme,
+    ) -> Self {
+        let received = Self {
+            scheme,
+            host: header_value(headers, &HOST).and_then(|host| host.parse().ok()),
+        };
+
+        if !is_trusted_proxy(trusted_proxies, peer) {
+            return received;
+        }
+
+        let forwarded = header_values(headers, &FORWARDED);
+
+        let (forwarded_proto, forwarded_host) = if forwarded.is_empty() {
+            // every trusted proxy can add a value, the client can add its own before them
+            let hops = trusted_hops(trusted_proxies, headers);
+            let trusted_value =
+                |values: Vec<&'a str>| values.get(values.len().saturating_sub(hops)).copied();
+
+            (
+                trusted_value(header_values(headers, &X_FORWARDED_PROTO)),
+                trusted_value(header_values(headers, &X_FORWARDED_HOST)),
+            )
+        } else {
+            parse_forwarded(trusted_proxies, &forwarded)
+        };
+
+        let scheme = match forwarded_proto.map(str::to_ascii_lowercase).as_deref() {
+            Some("https") => Scheme::HTTPS,
+            Some("http") => Scheme::HTTP,
+            _ => received.scheme,
+        };
+
+        let host = forwarded_host
+            .and_then(|host| host.parse().ok())
+            .or(received.host);
+
+        Self { scheme, host }
+    }
+
+    /// Absolute URL for a path of this origin, other locations are kept as-is
+    pub fn absolute(&self, location: &str) -> Option<String> {
+        let host = self.host.as_ref()?;
+
+        (location.starts_with('/') && !location.starts_with("//"))
+            .then(|| format!("{}://{host}{location}", self.scheme))
+    }
+}
+
+/// Value of a header, when it is valid
+fn header_value<'a>(headers: &'a HeaderMap, name: &HeaderName) -> Option<&'a str> {
+    headers.get(name).and_then(|value| value.to_str().ok())
+}
+
+/// Values of a comma separated list, of every occurrence of the header
+fn header_values<'a>(headers: &'a HeaderMap, name: &HeaderName) -> Vec<&'a str> {
+    headers
+        .get_all(name)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .map(str::trim)
+        .filter(|value| !value.is_empty())
+        .collect()
+}
+
+/// The `proto` and `host` of the elements of a `forwarded` header that were
+/// added by trusted proxies, the element nearest to the client wins
+///
+/// The last element is added by the peer, an element before it only by a
+/// trusted proxy when the `for` of the element after it is one
+fn parse_forwarded<'a>(
+    trusted_proxies: &[TrustedProxy],
+    elements: &[&'a str],
+) -> (Option<&'a str>, Option<&'a str>) {
+    let mut proto = None;
+    let mut host = None;
+
+    for element in elements.iter().rev() {
+        let mut client = None;
+
+        for pair in element.split(';') {
+            let Some((name, value)) = pair.trim().split_once('=') else {
+                continue;
+            };
+
+            let value = value.trim_matches('"');
+
+            if name.eq_ignore_ascii_case("proto") {
+                proto = Some(value);
+            } else if name.eq_ignore_ascii_case("host") {
+                host = Some(value);
+            } else if name.eq_ignore_ascii_case("for") {
+                client = parse_node(value);
+            }
+        }
+
+        if !is_trusted_proxy(trusted_proxies, client) {
+            break;
+        }
+    }
+
+    (proto, host)
+}
+
+/// Address of a node of a `forwarded` header, ie `192.0.2.43:47011` or
+/// `[2001:db8::1]:4711`, `None` for obfuscated nodes
+fn parse_node(node: &str) -> Option<IpAddr> {
+    match node.strip_prefix('[') {
+        Some(node) => node.split_once(']')?.0.parse().ok(),
+        None => node.split(':').next()?.parse().ok(),
+    }
+}
+
+/// Middleware that makes the location of redirects absolute, using the origin of the request
+pub async fn absolute_redirects(
+    State(state): State<ServerState>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let origin = Origin::from_request(&state, &request);
+
+    let mut response = next.run(request).await;
+
+    let location = response
+        .headers()
+        .get(LOCATION)
+        .and_then(|location| location.to_str().ok())
+        .and_then(|location| origin.absolute(location))
+        .and_then(|location| HeaderValue::from_str(&location).ok());
+
+    if let Some(location) = location {
+        response.headers_mut().insert(LOCATION, location);
+    }
+
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn trusted_proxy(value: &str) -> TrustedProxy {
+        value.parse().expect("A valid trusted proxy")
+    }
+
+    #[test]
+    fn test_trusted_proxy() {
+        assert!("10.0.0.0/33".parse::<TrustedProxy>().is_err());
+        assert!("proxy.local".parse::<TrustedProxy>().is_err());
+
+        let network = trusted_proxy("10.1.0.0/16");
+        assert!(network.contains(IpAddr::from([10, 1, 2, 3])));
+        assert!(!network.contains(IpAddr::from([10, 2, 0, 1])));
+        assert!(network.contains(IpAddr::from([0, 0, 0, 0, 0, 0xffff, 0x0a01, 0x0203])));
+
+        let address = trusted_proxy("::1");
+        assert!(address.contains(IpAddr::from([0, 0, 0, 0, 0, 0, 0, 1])));
+        assert!(!address.contains(IpAddr::from([127, 0, 0, 1])));
+
+        assert!(trusted_proxy("0.0.0.0/0").contains(IpAddr::from([192, 168, 1, 1])));
+    }
+
+    #[test]
+    fn test_client_address() {
+        let trusted_proxies = [trusted_proxy("10.0.0.0/8")];
+        let proxy = Some(IpAddr::from([10, 0, 0, 1]));
+        let client = Some(IpAddr::from([192, 168, 1, 1]));
+
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            X_FORWARDED_FOR,
+            HeaderValue::from_static("1.1.1.1, 192.168.1.1, 10.0.0.2"),
+        );
+
+        assert_eq!(
+            forwarded_client_address(&trusted_proxies, &headers, proxy),
+            client
+        );
+        assert_eq!(
+            forwarded_client_address(&trusted_proxies, &headers, Some(IpAddr::from([8, 8, 8, 8]))),
+            Some(IpAddr::from([8, 8, 8, 8]))
+        );
+        assert_eq!(
+            forwarded_client_address(&trusted_proxies, &HeaderMap::new(), proxy),
+            proxy
+        );
+
+        // a spoofed value stops the search at the last valid address
+        headers.insert(
+            X_FORWARDED_FOR,
+            HeaderValue::from_static("garbage, 10.0.0.2"),
+        );
+        assert_eq!(
+            forwarded_client_address(&trusted_proxies, &headers, proxy),
+            Some(IpAddr::from([10, 0, 0, 2]))
+        );
+    }
+
+    #[test]
+    fn test_origin() {
+        let trusted_proxies = [trusted_proxy("10.0.0.0/8")];
+        let proxy = Some(IpAddr::from([10, 0, 0, 1]));
+        let client = Some(IpAddr::from([192, 168, 1, 1]));
+
+        let mut headers = HeaderMap::new();
+        headers.insert(HOST, HeaderValue::from_static("internal:8080"));
+        headers.insert(X_FORWARDED_PROTO, HeaderValue::from_static("https, http"));
+        headers.insert(X_FORWARDED_HOST, HeaderValue::from_static("example.com"));
+
+        // the edge proxy at 10.0.0.2 saw https, the one at 10.0.0.1 added http
+        headers.insert(
+            X_FORWARDED_FOR,
+            HeaderValue::from_static("192.168.1.1, 10.0.0.2"),
+        );
+
+        let origin = Origin::new(&trusted_proxies, &headers, client, Scheme::HTTP);
+        assert_eq!(origin.scheme, Scheme::HTTP);
+        assert_eq!(
+            origin.absolute("/docs/").as_deref(),
+            Some("http://internal:8080/docs/")
+        );
+
+        let origin = Origin::new(&trusted_proxies, &headers, proxy, Scheme::HTTP);
+        assert_eq!(
+            origin.absolute("/docs/").as_deref(),
+            Some("https://example.com/docs/")
+        );
+        assert_eq!(origin.absolute("//elsewhere.com/"), None);
+        assert_eq!(origin.absolute("https://elsewhere.com/"), None);
+
+        // the standard header wins
+        headers.insert(
+            FORWARDED,
+            HeaderValue::from_static(
+                "for=1.2.3.4;proto=https;host=\"www.example.com\", for=10.0.0.2",
+            ),
+        );
+        let origin = Origin::new(&trusted_proxies, &headers, proxy, Scheme::HTTP);
+        assert_eq!(
+            origin.absolute("/").as_deref(),
+            Some("https://www.example.com/")
+        );
+    }
+
+    #[test]
+    fn test_origin_spoofed() {
+        let trusted_proxies = [trusted_proxy("10.0.0.0/8")];
+        let proxy = Some(IpAddr::from([10, 0, 0, 1]));
+
+        // the proxy appends to the values the client sent
+        let mut headers = HeaderMap::new();
+        headers.insert(HOST, HeaderValue::from_static("internal:8080"));
+        headers.insert(
+            X_FORWARDED_FOR,
+            HeaderValue::from_static("10.0.0.2, 192.168.1.1"),
+        );
+        headers.insert(X_FORWARDED_PROTO, HeaderValue::from_static("http, https"));
+        headers.append(X_FORWARDED_HOST, HeaderValue::from_static("evil.com"));
+        headers.append(X_FORWARDED_HOST, HeaderValue::from_static("example.com"));
+
+        let origin = Origin::new(&trusted_proxies, &headers, proxy, Scheme::HTTP);
+        assert_eq!(
+            origin.absolute("/").as_deref(),
+            Some("https://example.com/")
+        );
+
+        headers.insert(
+            FORWARDED,
+            HeaderValue::from_static(
+                "for=10.0.0.2;proto=http;host=evil.com, for=\"192.168.1.1:4711\";proto=https;host=example.com",
+            ),
+        );
+        let origin = Origin::new(&trusted_proxies, &headers, proxy, Scheme::HTTP);
+        assert_eq!(
+            origin.absolute("/").as_deref(),
+            Some("https://example.com/")
+        );
+    }
+}
@@ -0,0 +1,349 @@
+//! Netlify-style `_headers` file
+//!
+//! A `_headers` file in the base dir has blocks of a path, with placeholders
+//! and a splat like the `_redirects` file, followed by indented headers:
+//!
+//! ```text
+//! /assets/*
+//!   Cache-Control: max-age=31536000
+//! ```
+//!
+//! Every block that matches the path of a request is applied to its response,
+//! in order. A header of a later block replaces the one of an earlier block,
+//! an empty value removes it, the same as with `.srvr` files.
+//!
+//! See <https://docs.netlify.com/routing/headers/>
+
+use std::fmt;
+use std::str::FromStr;
+
+use axum::http::HeaderMap;
+use axum::http::HeaderName;
+use axum::http::HeaderValue;
+
+use crate::dir_config::parse_header;
+use crate::dir_config::DirConfig;
+use crate::dir_config::DirConfigError;
+use crate::dir_config::HeaderError;
+use crate::path_pattern::PathPattern;
+use crate::path_pattern::PathPatternError;
+
+/// Name of the file with the rules, in the base dir, it is never served
+pub const HEADERS_FILE_NAME: &str = "_headers";
+
+#[derive(Debug, thiserror::Error)]
+pub enum HeaderRulesError {
+    #[error("Line {0}: expected a path before the headers, ie `/assets/*`")]
+    MissingPath(usize),
+
+    #[error("Line {0}: invalid path: {1}")]
+    InvalidPath(usize, PathPatternError),
+
+    #[error(transparent)]
+    InvalidHeader(#[from] DirConfigError),
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum ScopedHeaderError {
+    #[error("Expected a path and a header, ie `/admin/*=X-Frame-Options: DENY`")]
+    MissingPath,
+
+    #[error("Invalid path: {0}")]
+    InvalidPath(#[from] PathPatternError),
+
+    #[error("Invalid header: {0}")]
+    InvalidHeader(#[from] HeaderError),
+}
+
+/// A header for every response, from the command line, ie `X-Frame-Options: DENY`
+#[derive(Clone, Debug)]
+pub struct CustomHeader {
+    name: HeaderName,
+    value: Option<HeaderValue>,
+}
+
+impl FromStr for CustomHeader {
+    type Err = HeaderError;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let (name, value) = parse_header(value)?;
+
+        Ok(Self { name, value })
+    }
+}
+
+impl fmt::Display for CustomHeader {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.value {
+            Some(value) => write!(
+                f,
+                "{}: {}",
+                self.name,
+                String::from_utf8_lossy(value.as_bytes())
+            ),
+            None => write!(f, "{}:", self.name),
+        }
+    }
+}
+
+/// A header for the responses of matching paths, from the command line, ie
+/// `/admin/*=X-Frame-Options: DENY`
+#[derive(Clone, Debug)]
+pub struct ScopedHeader {
+    pattern: PathPattern,
+    header: CustomHeader,
+}
+
+impl FromStr for ScopedHeader {
+    type Err = ScopedHeaderError;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let (path, header) = value
+            .split_once('=')
+            .ok_or(ScopedHeaderError::MissingPath)?;
+
+        Ok(Self {
+            pattern: path.trim().parse()?,
+            header: header.parse()?,
+        })
+    }
+}
+
+impl fmt::Display for ScopedHeader {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}={}", self.pattern, self.header)
+    }
+}
+
+/// Hardening headers of the `--secure` preset
+const SECURE_HEADERS: &[(&str, &str)] = &[
+    ("x-content-type-options", "nosniff"),
+    ("x-frame-options", "DENY"),
+    ("referrer-policy", "strict-origin-when-cross-origin"),
+    (
+        "permissions-policy",
+        "camera=(), geolocation=(), microphone=(), payment=(), usb=()",
+    ),
+];
+
+/// Blocks of headers for the matching paths, in order, from the `_headers`
+/// file or the command line
+#[derive(Debug, Default)]
+pub struct HeaderRules {
+    rules: Vec<(PathPattern, DirConfig)>,
+}
+
+impl HeaderRules {
+    /// Rules for the headers of the command line, the headers for every
+    /// response first, followed by the scoped ones grouped by their path
+    pub fn from_config(headers: &[CustomHeader], scoped_headers: &[ScopedHeader]) -> Self {
+        let mut scoped_rules: Vec<(PathPattern, Vec<_>)> = vec![];
+
+        for scoped in scoped_headers {
+            match scoped_rules
+                .iter_mut()
+                .find(|(pattern, _)| *pattern == scoped.pattern)
+            {
+                Some((_, headers)) => headers.push(scoped.header.clone()),
+                None => scoped_rules.push((scoped.pattern.clone(), vec![scoped.header.clone()])),
+            }
+        }
+
+        let rules = (!headers.is_empty())
+            .then(|| (PathPattern::any(), headers.to_vec()))
+            .into_iter()
+            .chain(scoped_rules);
+
+        Self {
+            rules: rules
+                .into_iter()
+                .map(|(pattern, headers)| {
+                    let headers = headers
+                        .into_iter()
+                        .map(|header| (header.name, header.value))
+                        .collect();
+
+                    (pattern, DirConfig::new(headers))
+                })
+                .collect(),
+        }
+    }
+
+    /// Add the hardening headers of the `--secure` preset for every response,
+    /// before the other rules so those can override them
+    pub fn with_secure_headers(mut self) -> Self {
+        let headers = SECURE_HEADERS
+            .iter()
+            .map(|(name, value)| {
+                (
+                    HeaderName::from_static(name),
+                    Some(HeaderValue::from_static(value)),
+                )
+            })
+            .collect();
+
+        self.rules
+            .insert(0, (PathPattern::any(), DirConfig::new(headers)));
+        self
+    }
+
+    /// Apply the headers of every block that matches the path
+    pub fn apply(&self, path: &str, headers: &mut HeaderMap) {
+        for (pattern, config) in &self.rules {
+            if pattern.matches(path) {
+                config.apply(headers);
+            }
+        }
+    }
+}
+
+impl FromStr for HeaderRules {
+    type Err = HeaderRulesError;
+
+    fn from_str(content: &str) -> Result<Self, Self::Err> {
+        let mut rules = vec![];
+        let mut block: Option<(PathPattern, Vec<_>)> = None;
+
+        for (index, line) in content.lines().enumerate() {
+            let line_number = index + 1;
+            let line = line.trim();
+
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            // a header never starts with a slash, so this starts a new block
+            if line.starts_with('/') {
+                let pattern = line
+                    .parse::<PathPattern>()
+                    .map_err(|err| HeaderRulesError::InvalidPath(line_number, err))?;
+
+                if let Some((pattern, headers)) = block.replace((pattern, vec![])) {
+                    rules.push((pattern, DirConfig::new(headers)));
+                }
+
+                continue;
+            }
+
+            let Some((_, headers)) = &mut block else {
+                return Err(HeaderRulesError::MissingPath(line_number));
+            };
+
+            headers.push(parse_header(line).map_err(|err| err.at_line(line_number))?);
+        }
+
+        if let Some((pattern, headers)) = block {
+            rules.push((pattern, DirConfig::new(headers)));
+        }
+
+        Ok(Self { rules })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::http::header::CACHE_CONTROL;
+    use axum::http::HeaderValue;
+
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        assert!(matches!(
+            "Cache-Control: no-cache".parse::<HeaderRules>(),
+            Err(HeaderRulesError::MissingPath(1))
+        ));
+        assert!(matches!(
+            "/*/assets\n  Cache-Control: no-cache".parse::<HeaderRules>(),
+            Err(HeaderRulesError::InvalidPath(1, _))
+        ));
+        assert!(matches!(
+            "/assets/*\n  Cache Control: no-cache".parse::<HeaderRules>(),
+            Err(HeaderRulesError::InvalidHeader(
+                DirConfigError::InvalidName(2, _)
+            ))
+        ));
+    }
+
+    #[test]
+    fn test_apply() {
+        let rules = "# everything\n\
+                     /*\n  \
+                       X-Frame-Options: DENY\n  \
+                       Cache-Control: no-cache\n\
+                     \n\
+                     /assets/*\n  \
+                       Cache-Control: max-age=31536000\n\
+                     /embed/:name\n  \
+                       X-Frame-Options:\n"
+            .parse::<HeaderRules>()
+            .expect("Valid rules");
+
+        let mut headers = HeaderMap::new();
+        headers.insert(CACHE_CONTROL, HeaderValue::from_static("public"));
+        rules.apply("/assets/app.js", &mut headers);
+
+        assert_eq!(headers[CACHE_CONTROL], "max-age=31536000");
+        assert_eq!(headers["x-frame-options"], "DENY");
+
+        let mut headers = HeaderMap::new();
+        rules.apply("/embed/video", &mut headers);
+
+        assert_eq!(headers[CACHE_CONTROL], "no-cache");
+        assert!(headers.get("x-frame-options").is_none());
+    }
+
+    #[test]
+    fn test_from_config() {
+        let headers = ["X-Frame-Options: DENY", "Link: </a.css>", "Link: </b.css>"]
+            .map(|header| header.parse::<CustomHeader>().expect("A valid header"));
+        let scoped_headers = [
+            "/embed/*=X-Frame-Options:",
+            "/embed/*=Cache-Control: no-cache",
+        ]
+        .map(|header| header.parse::<ScopedHeader>().expect("A valid header"));
+
+        assert!(matches!(
+            "X-Frame-Options: DENY".parse::<ScopedHeader>(),
+            Err(ScopedHeaderError::MissingPath)
+        ));
+        assert!(matches!(
+            "admin=X-Frame-Options: DENY".parse::<ScopedHeader>(),
+            Err(ScopedHeaderError::InvalidPath(_))
+        ));
+
+        let rules = HeaderRules::from_config(&headers, &scoped_headers);
+        assert_eq!(rules.rules.len(), 2);
+
+        let mut headers = HeaderMap::new();
+        rules.apply("/index.html", &mut headers);
+
+        assert_eq!(headers["x-frame-options"], "DENY");
+        assert_eq!(headers.get_all("link").iter().count(), 2);
+
+        let mut headers = HeaderMap::new();
+        rules.apply("/embed/video", &mut headers);
+
+        assert!(headers.get("x-frame-options").is_none());
+        assert_eq!(headers[CACHE_CONTROL], "no-cache");
+    }
+
+    #[test]
+    fn test_secure_headers() {
+        let headers = ["X-Frame-Options: SAMEORIGIN", "Permissions-Policy:"]
+            .map(|header| header.parse::<CustomHeader>().expect("A valid header"));
+
+        let rules = HeaderRules::from_config(&headers, &[]).with_secure_headers();
+
+        let mut headers = HeaderMap::new();
+        rules.apply("/", &mut headers);
+
+        assert_eq!(headers["x-content-type-options"], "nosniff");
+        assert_eq!(headers["x-frame-options"], "SAMEORIGIN");
+        assert_eq!(
+            headers["referrer-policy"],
+            "strict-origin-when-cross-origin"
+        );
+        assert!(headers.get("permissions-policy").is_none());
+    }
+}
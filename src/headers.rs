@@ -0,0 +1,156 @@
+//! Response headers applied to every response
+//!
+//! Headers set by the handlers themselves win, the presets only fill in what
+//! is missing. Headers of the `_headers` file of the base dir replace those of
+//! the handlers.
+
+use axum::extract::Request;
+use axum::extract::State;
+use axum::http::header::CONTENT_TYPE;
+use axum::http::header::SERVER;
+use axum::http::HeaderMap;
+use axum::http::HeaderName;
+use axum::http::HeaderValue;
+use axum::middleware::Next;
+use axum::response::Response;
+use percent_encoding::percent_decode_str;
+
+use crate::app::ServerState;
+use crate::canary::Variant;
+use crate::header_rules::HeaderRules;
+use crate::header_rules::HEADERS_FILE_NAME;
+
+/// See <https://developer.mozilla.org/en-US/docs/Web/HTTP/Headers/Cross-Origin-Opener-Policy>
+const CROSS_ORIGIN_OPENER_POLICY: HeaderName =
+    HeaderName::from_static("cross-origin-opener-policy");
+
+/// See <https://developer.mozilla.org/en-US/docs/Web/HTTP/Headers/Cross-Origin-Embedder-Policy>
+const CROSS_ORIGIN_EMBEDDER_POLICY: HeaderName =
+    HeaderName::from_static("cross-origin-embedder-policy");
+
+/// See <https://developer.mozilla.org/en-US/docs/Web/HTTP/Headers/Cross-Origin-Resource-Policy>
+const CROSS_ORIGIN_RESOURCE_POLICY: HeaderName =
+    HeaderName::from_static("cross-origin-resource-policy");
+
+/// See <https://developers.google.com/search/docs/crawling-indexing/robots-meta-tag#xrobotstag>
+const X_ROBOTS_TAG: HeaderName = HeaderName::from_static("x-robots-tag");
+
+/// Set the header, unless the response already has it
+fn set_default(headers: &mut HeaderMap, name: HeaderName, value: &'static str) {
+    headers
+        .entry(name)
+        .or_insert_with(|| HeaderValue::from_static(value));
+}
+
+/// Check if the response is a (HTML) document, instead of a subresource
+pub fn is_document(headers: &HeaderMap) -> bool {
+    headers
+        .get(CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|content_type| content_type.starts_with("text/html"))
+}
+
+/// Headers needed for cross-origin isolation, ie to use `SharedArrayBuffer`
+fn apply_cross_origin_isolation(headers: &mut HeaderMap) {
+    set_default(headers, CROSS_ORIGIN_OPENER_POLICY, "same-origin");
+    set_default(headers, CROSS_ORIGIN_EMBEDDER_POLICY, "require-corp");
+
+    if !is_document(headers) {
+        set_default(headers, CROSS_ORIGIN_RESOURCE_POLICY, "same-origin");
+    }
+}
+
+/// Middleware that adds the configured headers to every response, and those
+/// of the `_headers` file of the release
+pub async fn response_headers(
+    State(state): State<ServerState>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let release = state.release_for(
+        request
+            .extensions()
+            .get::<Variant>()
+            .copied()
+            .unwrap_or_default(),
+    );
+    let path = percent_decode_str(request.uri().path())
+        .decode_utf8_lossy()
+        .into_owned();
+
+    let mut response = next.run(request).await;
+    let headers = response.headers_mut();
+
+    if let Some(rules) = state
+        .file_cache
+        .parsed::<HeaderRules>(&release.base_dir.join(HEADERS_FILE_NAME))
+        .await
+    {
+        rules.apply(&path, headers);
+    }
+
+    if state.config.coi {
+        apply_cross_origin_isolation(headers);
+    }
+
+    // documents with a nonce already have the header
+    let csp_header_name = state.csp.header_name();
+
+    if (is_document(headers) || state.config.csp_all) && !headers.contains_key(&csp_header_name) {
+        if let Some(csp) = state.csp.header() {
+            headers.insert(csp_header_name, csp);
+        }
+    }
+
+    if state.config.noindex {
+        headers.insert(X_ROBOTS_TAG, HeaderValue::from_static("noindex, nofollow"));
+    }
+
+    response
+}
+
+/// Middleware that sets the configured `Server` header, or removes it
+pub async fn server_header(
+    State(state): State<ServerState>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let mut response = next.run(request).await;
+
+    match &state.config.server_header {
+        Some(server_header) => {
+            response.headers_mut().insert(SERVER, server_header.clone());
+        }
+        None => {
+            response.headers_mut().remove(SERVER);
+        }
+    }
+
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_cross_origin_isolation() {
+        let mut document = HeaderMap::new();
+        document.insert(CONTENT_TYPE, HeaderValue::from_static("text/html"));
+        apply_cross_origin_isolation(&mut document);
+
+        assert_eq!(document[CROSS_ORIGIN_OPENER_POLICY], "same-origin");
+        assert_eq!(document[CROSS_ORIGIN_EMBEDDER_POLICY], "require-corp");
+        assert!(document.get(CROSS_ORIGIN_RESOURCE_POLICY).is_none());
+
+        let mut script = HeaderMap::new();
+        script.insert(CONTENT_TYPE, HeaderValue::from_static("text/javascript"));
+        script.insert(
+            CROSS_ORIGIN_RESOURCE_POLICY,
+            HeaderValue::from_static("cross-origin"),
+        );
+        apply_cross_origin_isolation(&mut script);
+
+        assert_eq!(script[CROSS_ORIGIN_RESOURCE_POLICY], "cross-origin");
+    }
+}
@@ -0,0 +1,142 @@
+//! Hotlink protection based on the `Referer` header
+//!
+//! With `--hotlink-protect '*.jpg,*.png'` the matching files are only served
+//! to requests without a referer, or from a page of this site or one of
+//! `--allowed-referers`, ie `example.com,*.example.com`. Other sites embedding
+//! them get a 403, or the `--hotlink-placeholder` file instead. A pattern
+//! without a `/` matches the file name, one with a `/` the path, like the cache
+//! rules.
+
+use axum::extract::Request;
+use axum::extract::State;
+use axum::http::header::CACHE_CONTROL;
+use axum::http::header::REFERER;
+use axum::http::uri::Authority;
+use axum::http::HeaderValue;
+use axum::http::StatusCode;
+use axum::http::Uri;
+use axum::middleware::Next;
+use axum::response::Response;
+use percent_encoding::percent_decode_str;
+
+use crate::app::ServerState;
+use crate::audit::Rejection;
+use crate::forwarded::Origin;
+use crate::normalize::with_path;
+use crate::utils::wildcard_match;
+
+/// Check if the path matches one of the patterns of protected files
+fn is_protected(patterns: &[String], path: &str) -> bool {
+    let name = path.rsplit('/').next().unwrap_or_default();
+
+    patterns.iter().any(|pattern| {
+        if pattern.contains('/') {
+            wildcard_match(pattern, path)
+        } else {
+            wildcard_match(pattern, name)
+        }
+    })
+}
+
+/// Check if a page of the host can embed the protected files, the host of the
+/// site itself always can
+fn is_allowed_referer(allowed_referers: &[String], host: &str, own_host: Option<&str>) -> bool {
+    let host = host.to_ascii_lowercase();
+
+    own_host.is_some_and(|own_host| own_host.eq_ignore_ascii_case(&host))
+        || allowed_referers
+            .iter()
+            .any(|allowed| wildcard_match(&allowed.to_ascii_lowercase(), &host))
+}
+
+/// Middleware that refuses protected files to pages of other sites, or serves
+/// them the placeholder
+pub async fn hotlink(
+    State(state): State<ServerState>,
+    mut request: Request,
+    next: Next,
+) -> Response {
+    let path = percent_decode_str(request.uri().path()).decode_utf8_lossy();
+
+    if !is_protected(&state.config.hotlink_protect, &path) {
+        return next.run(request).await;
+    }
+
+    // requests without a referer are let through, ie a link that was opened
+    // directly or a client that does not send it
+    let Some(referer) = request.headers().get(REFERER) else {
+        return next.run(request).await;
+    };
+
+    let referer_host = referer
+        .to_str()
+        .ok()
+        .and_then(|referer| referer.parse::<Uri>().ok())
+        .and_then(|referer| referer.host().map(String::from));
+
+    let origin = Origin::from_request(&state, &request);
+    let own_host = origin.host.as_ref().map(Authority::host);
+
+    if referer_host
+        .is_some_and(|host| is_allowed_referer(&state.config.allowed_referers, &host, own_host))
+    {
+        return next.run(request).await;
+    }
+
+    tracing::debug!("Refused a hotlink from {referer:?}");
+
+    let Some(placeholder) = &state.config.hotlink_placeholder else {
+        return Rejection::Hotlink.response(StatusCode::FORBIDDEN);
+    };
+
+    let Some(uri) = with_path(request.uri(), placeholder) else {
+        return Rejection::Hotlink.response(StatusCode::FORBIDDEN);
+    };
+
+    *request.uri_mut() = uri;
+
+    let mut response = next.run(request).await;
+
+    // the placeholder should not be cached as the protected file
+    response
+        .headers_mut()
+        .insert(CACHE_CONTROL, HeaderValue::from_static("no-store"));
+
+    Rejection::Hotlink.response(response)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_is_protected() {
+        let patterns = vec![String::from("*.jpg"), String::from("/media/*")];
+
+        assert!(is_protected(&patterns, "/photo.jpg"));
+        assert!(is_protected(&patterns, "/albums/photo.jpg"));
+        assert!(is_protected(&patterns, "/media/clip.mp4"));
+        assert!(!is_protected(&patterns, "/index.html"));
+        assert!(!is_protected(&patterns, "/photo.jpg.html"));
+    }
+
+    #[test]
+    fn test_is_allowed_referer() {
+        let allowed_referers = vec![String::from("example.com"), String::from("*.example.com")];
+
+        assert!(is_allowed_referer(&allowed_referers, "example.com", None));
+        assert!(is_allowed_referer(
+            &allowed_referers,
+            "WWW.example.com",
+            None
+        ));
+        assert!(is_allowed_referer(&[], "srvr.test", Some("srvr.test")));
+        assert!(!is_allowed_referer(&allowed_referers, "example.org", None));
+        assert!(!is_allowed_referer(
+            &allowed_referers,
+            "notexample.com",
+            None
+        ));
+        assert!(!is_allowed_referer(&[], "other.test", Some("srvr.test")));
+    }
+}
@@ -0,0 +1,131 @@
+//! Redirect plain HTTP to HTTPS
+//!
+//! With TLS, a second listener can be started that only redirects every
+//! request to the same URL over HTTPS. It shares the connection limits and the
+//! graceful shutdown with the HTTPS listener.
+
+use std::io;
+use std::net::SocketAddr;
+
+use axum::extract::Request;
+use axum::http::header::HOST;
+use axum::http::header::LOCATION;
+use axum::http::uri::Authority;
+use axum::http::HeaderValue;
+use axum::http::StatusCode;
+use axum::http::Uri;
+use axum::response::IntoResponse;
+use axum::response::Response;
+use axum::Router;
+use tokio::net::TcpListener;
+
+use crate::upgrade::inherited_listener;
+use crate::upgrade::REDIRECT_LISTEN_FD;
+
+/// Listen for plain HTTP requests, on the socket of the previous srvr when
+/// upgrading
+pub async fn redirect_listener(address: SocketAddr) -> io::Result<TcpListener> {
+    match inherited_listener(REDIRECT_LISTEN_FD)? {
+        Some(listener) => Ok(listener),
+        None => TcpListener::bind(address).await,
+    }
+}
+
+/// App that redirects every request to HTTPS, on the given port
+pub fn redirect_app(https_port: u16) -> Router {
+    Router::new().fallback(move |request: Request| async move { redirect(&request, https_port) })
+}
+
+/// Redirect the request to HTTPS
+fn redirect(request: &Request, https_port: u16) -> Response {
+    let host = request
+        .headers()
+        .get(HOST)
+        .and_then(|host| host.to_str().ok())
+        .and_then(|host| host.parse::<Authority>().ok())
+        .or_else(|| request.uri().authority().cloned());
+
+    let location = host
+        .map(|host| https_location(request.uri(), host.host(), https_port))
+        .and_then(|location| HeaderValue::from_str(&location).ok());
+
+    match location {
+        Some(location) => (StatusCode::MOVED_PERMANENTLY, [(LOCATION, location)]).into_response(),
+
+        // without a host there is no way to know where to go
+        None => StatusCode::BAD_REQUEST.into_response(),
+    }
+}
+
+/// The HTTPS URL for the request, the port is left out when it is the default
+fn https_location(uri: &Uri, host: &str, https_port: u16) -> String {
+    let path_and_query = uri.path_and_query().map_or("/", |path| path.as_str());
+
+    if https_port == 443 {
+        format!("https://{host}{path_and_query}")
+    } else {
+        format!("https://{host}:{https_port}{path_and_query}")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::body::Body;
+    use tower::ServiceExt;
+
+    use super::*;
+
+    async fn location(host: Option<&str>, path: &str) -> Option<String> {
+        let mut request = Request::get(path);
+        if let Some(host) = host {
+            request = request.header(HOST, host);
+        }
+        let request = request.body(Body::empty()).expect("A valid request");
+
+        let response = redirect_app(8443)
+            .oneshot(request)
+            .await
+            .expect("A response");
+        if response.status() == StatusCode::BAD_REQUEST {
+            return None;
+        }
+
+        assert_eq!(response.status(), StatusCode::MOVED_PERMANENTLY);
+        let location = response.headers().get(LOCATION)?.to_str().ok()?;
+        Some(location.to_string())
+    }
+
+    #[tokio::test]
+    async fn test_redirect() {
+        assert_eq!(
+            location(Some("example.com:8080"), "/docs/?page=2")
+                .await
+                .as_deref(),
+            Some("https://example.com:8443/docs/?page=2")
+        );
+        assert_eq!(
+            location(Some("[::1]:8080"), "/").await.as_deref(),
+            Some("https://[::1]:8443/")
+        );
+        assert_eq!(
+            location(None, "http://example.com/docs/").await.as_deref(),
+            Some("https://example.com:8443/docs/")
+        );
+        assert_eq!(location(None, "/docs/").await, None);
+        assert_eq!(location(Some("exa mple.com"), "/docs/").await, None);
+    }
+
+    #[test]
+    fn test_https_location() {
+        let uri = "/docs/?page=2".parse::<Uri>().expect("A valid uri");
+
+        assert_eq!(
+            https_location(&uri, "example.com", 443),
+            "https://example.com/docs/?page=2"
+        );
+        assert_eq!(
+            https_location(&uri, "localhost", 8443),
+            "https://localhost:8443/docs/?page=2"
+        );
+    }
+}
@@ -0,0 +1,387 @@
+//! On-the-fly image resizing
+//!
+//! Images can be requested in a different size and/or format via query
+//! parameters, ie `/photos/cat.jpg?w=320&format=webp`. Resized images are
+//! stored in a dedicated cache directory, so the (expensive) resizing only
+//! happens once per source file and set of parameters. By default that is a
+//! private directory of the current user, see [`default_cache_dir`].
+
+use std::collections::hash_map::DefaultHasher;
+use std::hash::Hash;
+use std::hash::Hasher;
+use std::io::Cursor;
+use std::path::Path;
+use std::path::PathBuf;
+use std::process;
+use std::sync::atomic::AtomicU64;
+use std::sync::atomic::Ordering;
+use std::time::SystemTime;
+
+use image::imageops::FilterType;
+use image::DynamicImage;
+use image::ImageFormat;
+
+use crate::utils::user_cache_dir;
+
+/// Name of the directory in the user cache dir used to cache resized images
+const DEFAULT_CACHE_DIR_NAME: &str = "images";
+
+/// Largest width or height an image can be resized to
+const MAX_DIMENSION: u32 = 4096;
+
+/// Largest width or height of a thumbnail
+const THUMBNAIL_DIMENSION: u32 = 160;
+
+/// Number of temporary files written, to give each a unique name
+static TEMP_FILES: AtomicU64 = AtomicU64::new(0);
+
+#[derive(Debug, thiserror::Error)]
+pub enum ResizeError {
+    #[error("Invalid resize parameter \"{0}\"")]
+    InvalidParameter(String),
+
+    #[error("Unsupported image format")]
+    UnsupportedFormat,
+
+    #[error("Could not access image: {0}")]
+    Io(#[from] std::io::Error),
+
+    #[error("Image exceeds the maximum file size ({0} bytes)")]
+    TooLarge(u64),
+
+    #[error("Could not process image: {0}")]
+    Image(#[from] image::ImageError),
+
+    #[error("Image processing was aborted: {0}")]
+    Aborted(#[from] tokio::task::JoinError),
+}
+
+/// Resize parameters as requested by the client
+#[derive(Debug, Default, Hash, PartialEq, Eq)]
+pub struct ResizeRequest {
+    /// Maximum width of the resulting image
+    width: Option<u32>,
+
+    /// Maximum height of the resulting image
+    height: Option<u32>,
+
+    /// Format of the resulting image, defaults to the format of the source
+    format: Option<ImageFormat>,
+}
+
+/// A resized image, ready to be served
+pub struct ResizedImage {
+    pub content: Vec<u8>,
+    pub content_type: &'static str,
+    pub last_modified: SystemTime,
+}
+
+impl ResizeRequest {
+    /// Parse the resize parameters from a query string
+    ///
+    /// Returns `Ok(None)` when the query string contains no resize parameters
+    /// at all, so the request can be handled as a regular file request
+    pub fn from_query(query: Option<&str>) -> Result<Option<Self>, ResizeError> {
+        let Some(query) = query else {
+            return Ok(None);
+        };
+
+        let mut request = Self::default();
+        let mut has_parameters = false;
+
+        for (key, value) in form_urlencoded::parse(query.as_bytes()) {
+            match &*key {
+                "w" => request.width = Some(Self::parse_dimension(&value)?),
+                "h" => request.height = Some(Self::parse_dimension(&value)?),
+                "format" => {
+                    request.format = Some(match &*value {
+                        "webp" => ImageFormat::WebP,
+                        "png" => ImageFormat::Png,
+                        "jpg" | "jpeg" => ImageFormat::Jpeg,
+                        "gif" => ImageFormat::Gif,
+                        _ => return Err(ResizeError::InvalidParameter(value.into_owned())),
+                    });
+                }
+                _ => continue,
+            }
+
+            has_parameters = true;
+        }
+
+        Ok(has_parameters.then_some(request))
+    }
+
+    /// Resize parameters of a thumbnail, ie for directory listings
+    pub fn thumbnail() -> Self {
+        Self {
+            width: Some(THUMBNAIL_DIMENSION),
+            height: Some(THUMBNAIL_DIMENSION),
+            format: Some(ImageFormat::WebP),
+        }
+    }
+
+    fn parse_dimension(value: &str) -> Result<u32, ResizeError> {
+        match value.parse::<u32>() {
+            Ok(dimension) if dimension > 0 && dimension <= MAX_DIMENSION => Ok(dimension),
+            _ => Err(ResizeError::InvalidParameter(value.to_string())),
+        }
+    }
+
+    /// Path of the cached result for this request in the cache dir
+    fn cache_path(
+        &self,
+        cache_dir: &Path,
+        source: &Path,
+        last_modified: SystemTime,
+        format: ImageFormat,
+    ) -> PathBuf {
+        let mut hasher = DefaultHasher::new();
+        source.hash(&mut hasher);
+        last_modified.hash(&mut hasher);
+        self.hash(&mut hasher);
+
+        let extension = format.extensions_str().first().copied().unwrap_or("img");
+
+        cache_dir.join(format!("{:016x}.{extension}", hasher.finish()))
+    }
+
+    /// Resize the given source image, using the cache dir when possible
+    ///
+    /// Source images above the max file size are refused, like they are when
+    /// serving them as they are. Without a cache dir every request resizes
+    pub async fn resize(
+        &self,
+        cache_dir: Option<&Path>,
+        source: &Path,
+        max_file_size: Option<u64>,
+    ) -> Result<ResizedImage, ResizeError> {
+        let source_format =
+            ImageFormat::from_path(source).map_err(|_| ResizeError::UnsupportedFormat)?;
+        let format = self.format.unwrap_or(source_format);
+
+        let meta = tokio::fs::metadata(source).await?;
+
+        if max_file_size.is_some_and(|max_file_size| meta.len() > max_file_size) {
+            return Err(ResizeError::TooLarge(meta.len()));
+        }
+
+        let last_modified = meta.modified().unwrap_or_else(|_| SystemTime::now());
+        let cache_path =
+            cache_dir.map(|cache_dir| self.cache_path(cache_dir, source, last_modified, format));
+
+        let cached = match &cache_path {
+            Some(cache_path) => tokio::fs::read(cache_path).await.ok(),
+            None => None,
+        };
+
+        let content = if let Some(content) = cached {
+            tracing::trace!("Resized image cache hit: {cache_path:?}");
+            content
+        } else {
+            tracing::trace!("Resized image cache miss, resizing {source:?}");
+
+            let source_content = tokio::fs::read(source).await?;
+            let width = self.width;
+            let height = self.height;
+
+            let content = tokio::task::spawn_blocking(move || {
+                let image = image::load_from_memory_with_format(&source_content, source_format)?;
+                encode(&fit(image, width, height), format)
+            })
+            .await??;
+
+            if let (Some(cache_dir), Some(cache_path)) = (cache_dir, &cache_path) {
+                store(cache_dir, cache_path, &content).await?;
+            }
+
+            content
+        };
+
+        Ok(ResizedImage {
+            content,
+            content_type: format.to_mime_type(),
+            last_modified,
+        })
+    }
+}
+
+/// Store a resized image in the cache dir
+async fn store(cache_dir: &Path, cache_path: &Path, content: &[u8]) -> std::io::Result<()> {
+    tokio::fs::create_dir_all(cache_dir).await?;
+
+    // write to a temporary file first, concurrent requests should never see a partial image,
+    // each write gets a file of its own as concurrent resizes share the cache path
+    let temp_path = cache_path.with_extension(format!(
+        "{}-{}.tmp",
+        process::id(),
+        TEMP_FILES.fetch_add(1, Ordering::Relaxed)
+    ));
+    tokio::fs::write(&temp_path, content).await?;
+    tokio::fs::rename(&temp_path, cache_path).await
+}
+
+/// Scale the image down to fit within the given dimensions, keeping its aspect ratio
+///
+/// Images are never scaled up
+fn fit(image: DynamicImage, width: Option<u32>, height: Option<u32>) -> DynamicImage {
+    let width = width.unwrap_or(u32::MAX);
+    let height = height.unwrap_or(u32::MAX);
+
+    if image.width() <= width && image.height() <= height {
+        image
+    } else {
+        image.resize(width, height, FilterType::Lanczos3)
+    }
+}
+
+/// Encode the image in the given format
+fn encode(image: &DynamicImage, format: ImageFormat) -> Result<Vec<u8>, ResizeError> {
+    let mut content = Cursor::new(Vec::new());
+
+    if format == ImageFormat::Jpeg {
+        // JPEG has no alpha channel
+        DynamicImage::ImageRgb8(image.to_rgb8()).write_to(&mut content, format)?;
+    } else {
+        image.write_to(&mut content, format)?;
+    }
+
+    Ok(content.into_inner())
+}
+
+/// Check if the file is an image that can be resized
+pub fn is_resizable(path: &Path) -> bool {
+    ImageFormat::from_path(path).is_ok_and(|format| format.reading_enabled())
+}
+
+/// Default directory to cache resized images in, ie `~/.cache/srvr/images`
+///
+/// Only the current user has access to it, a shared directory would let
+/// other users plant images to be served
+pub fn default_cache_dir() -> std::io::Result<PathBuf> {
+    user_cache_dir(DEFAULT_CACHE_DIR_NAME)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::create_dir_all;
+    use std::fs::read_dir;
+    use std::fs::remove_dir_all;
+    use std::time::Duration;
+
+    use image::RgbImage;
+
+    use super::*;
+
+    /// Dir with a 400x200 PNG image, `photo.png`
+    fn image_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("srvr-resize-{name}-{}", process::id()));
+        create_dir_all(&dir).expect("A writable dir");
+
+        DynamicImage::ImageRgb8(RgbImage::new(400, 200))
+            .save(dir.join("photo.png"))
+            .expect("A writable image");
+
+        dir
+    }
+
+    fn request(query: &str) -> ResizeRequest {
+        ResizeRequest::from_query(Some(query))
+            .expect("A valid query")
+            .expect("Resize parameters")
+    }
+
+    #[test]
+    fn test_from_query() {
+        assert!(matches!(ResizeRequest::from_query(None), Ok(None)));
+        assert!(matches!(ResizeRequest::from_query(Some("v=2")), Ok(None)));
+
+        let resize_request = request("w=320&h=200&format=webp&v=2");
+        assert_eq!(resize_request.width, Some(320));
+        assert_eq!(resize_request.height, Some(200));
+        assert_eq!(resize_request.format, Some(ImageFormat::WebP));
+    }
+
+    #[test]
+    fn test_from_query_limits() {
+        assert!(ResizeRequest::from_query(Some("w=4096")).is_ok());
+        assert!(ResizeRequest::from_query(Some("w=4097")).is_err());
+        assert!(ResizeRequest::from_query(Some("h=0")).is_err());
+        assert!(ResizeRequest::from_query(Some("w=-1")).is_err());
+        assert!(ResizeRequest::from_query(Some("format=tiff")).is_err());
+    }
+
+    #[test]
+    fn test_cache_path() {
+        let cache_dir = Path::new("/cache");
+        let source = Path::new("/base/photo.png");
+        let now = SystemTime::now();
+        let cache_path = |query, last_modified| {
+            request(query).cache_path(cache_dir, source, last_modified, ImageFormat::WebP)
+        };
+
+        let path = cache_path("w=100", now);
+        assert!(path.starts_with(cache_dir));
+        assert_eq!(path.extension().and_then(|ext| ext.to_str()), Some("webp"));
+
+        assert_eq!(path, cache_path("w=100", now));
+        assert_ne!(path, cache_path("w=200", now));
+        assert_ne!(path, cache_path("w=100", now + Duration::from_secs(1)));
+    }
+
+    #[tokio::test]
+    async fn test_resize() {
+        let dir = image_dir("resize");
+        let cache_dir = dir.join("cache");
+        let source = dir.join("photo.png");
+
+        let resized = request("w=100&format=jpg")
+            .resize(Some(&cache_dir), &source, None)
+            .await
+            .expect("A resized image");
+        assert_eq!(resized.content_type, "image/jpeg");
+
+        let image = image::load_from_memory(&resized.content).expect("A valid image");
+        assert_eq!((image.width(), image.height()), (100, 50));
+
+        // the second time it comes from the cache
+        let cached = read_dir(&cache_dir).expect("A cache dir").count();
+        assert_eq!(cached, 1);
+
+        let resized_again = request("w=100&format=jpg")
+            .resize(Some(&cache_dir), &source, None)
+            .await
+            .expect("A resized image");
+        assert_eq!(resized_again.content, resized.content);
+        assert_eq!(read_dir(&cache_dir).expect("A cache dir").count(), 1);
+
+        remove_dir_all(&dir).ok();
+    }
+
+    #[tokio::test]
+    async fn test_resize_never_upscales() {
+        let dir = image_dir("upscale");
+
+        let resized = request("w=800")
+            .resize(None, &dir.join("photo.png"), None)
+            .await
+            .expect("A resized image");
+        assert_eq!(resized.content_type, "image/png");
+
+        let image = image::load_from_memory(&resized.content).expect("A valid image");
+        assert_eq!((image.width(), image.height()), (400, 200));
+
+        remove_dir_all(&dir).ok();
+    }
+
+    #[tokio::test]
+    async fn test_resize_too_large() {
+        let dir = image_dir("too-large");
+
+        let resized = request("w=100")
+            .resize(None, &dir.join("photo.png"), Some(10))
+            .await;
+        assert!(matches!(resized, Err(ResizeError::TooLarge(_))));
+
+        remove_dir_all(&dir).ok();
+    }
+}
@@ -0,0 +1,809 @@
+//! Directory listings
+//!
+//! With `--autoindex`, directories without an `index.html` are listed when
+//! requested with a trailing slash. Browsers get an HTML page, clients
+//! preferring `application/json` get the entries as JSON, so scripts can crawl
+//! the server. Both are rendered from the same entries, so hidden and
+//! unservable files are left out of either one.
+//!
+//! The HTML page can be branded with a (minijinja) template of its own, via
+//! `--listing-template`; it gets the same entries as the built-in template.
+//! With `--listing-thumbnails` (and the `image-resize` feature), images get a
+//! thumbnail, served from an internal route and cached like resized images.
+//!
+//! Listings can be sorted and filtered via query parameters, ie
+//! `?sort=size&order=desc&filter=*.log`. Directories are always listed first
+//! and are never filtered out, so the listing stays navigable.
+//!
+//! Rendered listings get an `ETag` from their entries, are compressed for
+//! clients that support it and are kept in a small cache, so unchanged
+//! directories are not rendered (and compressed) again.
+
+use std::borrow::Cow;
+use std::cmp::Ordering;
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
+use std::collections::VecDeque;
+use std::hash::Hash;
+use std::hash::Hasher;
+#[cfg(feature = "image-resize")]
+use std::path::Component;
+use std::path::Path;
+use std::path::PathBuf;
+use std::sync::Mutex;
+use std::sync::PoisonError;
+use std::time::Duration;
+use std::time::UNIX_EPOCH;
+
+use axum::body::Bytes;
+#[cfg(feature = "image-resize")]
+use axum::extract::Path as UrlPath;
+#[cfg(feature = "image-resize")]
+use axum::extract::State;
+use axum::http::header::CONTENT_ENCODING;
+use axum::http::header::CONTENT_TYPE;
+use axum::http::header::VARY;
+use axum::http::HeaderMap;
+use axum::http::HeaderValue;
+use axum::http::Method;
+use axum::http::StatusCode;
+use axum::http::Uri;
+use axum::response::IntoResponse;
+use axum::response::Response;
+#[cfg(feature = "image-resize")]
+use axum::Extension;
+use axum_extra::headers::ETag;
+use axum_extra::headers::HeaderMapExt;
+use httpdate::HttpDate;
+use minijinja::value::Serde;
+use minijinja::Environment;
+use percent_encoding::percent_decode_str;
+use percent_encoding::utf8_percent_encode;
+use serde::Serialize;
+
+#[cfg(feature = "image-resize")]
+use crate::admin::ADMIN_PREFIX;
+#[cfg(feature = "image-resize")]
+use crate::app::resized_image_response;
+use crate::app::Release;
+use crate::app::ServerState;
+#[cfg(feature = "image-resize")]
+use crate::canary::Variant;
+use crate::conditional::Precondition;
+use crate::conditional::Preconditions;
+use crate::encoding::ClientEncodingSupport;
+use crate::encoding::Encoding;
+use crate::errors::prefers_json;
+#[cfg(feature = "image-resize")]
+use crate::image_resize::is_resizable;
+#[cfg(feature = "image-resize")]
+use crate::image_resize::ResizeRequest;
+use crate::paths::INDEX_FILE_NAME;
+use crate::utils::format_byte_size;
+use crate::utils::wildcard_match;
+use crate::utils::PATH_SEGMENT;
+
+/// Number of rendered listings that are cached
+const LISTING_CACHE_SIZE: usize = 64;
+
+/// Name of the template, its extension enables HTML auto-escaping
+const TEMPLATE_NAME: &str = "listing.html";
+
+/// Template of the HTML listing, without `--listing-template`
+const BUILTIN_TEMPLATE: &str = r#"<!DOCTYPE html>
+<html>
+<head>
+<meta charset="utf-8">
+<meta name="viewport" content="width=device-width">
+<title>Index of {{ path }}</title>
+</head>
+<body>
+<h1>Index of {{ path }}</h1>
+<table>
+<tr><th><a href="{{ sort.name }}">Name</a></th><th><a href="{{ sort.size }}">Size</a></th><th><a href="{{ sort.mtime }}">Modified</a></th></tr>
+{%- if parent %}
+<tr><td><a href="../">../</a></td><td></td><td></td></tr>
+{%- endif %}
+{%- for entry in entries %}
+<tr><td><a href="{{ entry.href }}">
+{%- if entry.thumbnail %}<img src="{{ entry.thumbnail }}" alt="" loading="lazy"><br>{% endif %}
+{{- entry.name }}{% if entry.is_dir %}/{% endif %}</a></td><td>{{ entry.display_size }}</td><td>{{ entry.modified }}</td></tr>
+{%- endfor %}
+</table>
+</body>
+</html>
+"#;
+
+/// Longest filter pattern that is accepted
+const MAX_FILTER_LENGTH: usize = 128;
+
+#[derive(Debug, thiserror::Error)]
+pub enum ListingQueryError {
+    #[error("Invalid listing parameter \"{0}\"")]
+    InvalidParameter(String),
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum ListingTemplateError {
+    #[error("Could not read listing template \"{0}\": {1}")]
+    Io(PathBuf, std::io::Error),
+
+    #[error("Invalid listing template: {0}")]
+    Template(#[from] minijinja::Error),
+}
+
+#[derive(Debug, thiserror::Error)]
+enum ListingError {
+    #[error(transparent)]
+    Template(#[from] ListingTemplateError),
+
+    #[error("Could not serialize listing: {0}")]
+    Json(#[from] serde_json::Error),
+
+    #[error("Could not compress listing: {0}")]
+    Compress(std::io::Error),
+}
+
+/// What the entries of a listing are sorted by
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+enum SortKey {
+    #[default]
+    Name,
+    Size,
+    Mtime,
+}
+
+impl SortKey {
+    fn as_str(self) -> &'static str {
+        match self {
+            Self::Name => "name",
+            Self::Size => "size",
+            Self::Mtime => "mtime",
+        }
+    }
+}
+
+/// Sorting and filtering of a listing, as requested by the client
+#[derive(Debug, Default, PartialEq, Eq, Hash)]
+pub struct ListingQuery {
+    sort: SortKey,
+    descending: bool,
+
+    /// Pattern the names of files should match, `*` and `?` are wildcards
+    filter: Option<String>,
+}
+
+impl ListingQuery {
+    /// Parse the listing parameters from a query string, other parameters are ignored
+    pub fn from_query(query: Option<&str>) -> Result<Self, ListingQueryError> {
+        let mut listing_query = Self::default();
+
+        for (key, value) in form_urlencoded::parse(query.unwrap_or_default().as_bytes()) {
+            match &*key {
+                "sort" => {
+                    listing_query.sort = match &*value {
+                        "name" => SortKey::Name,
+                        "size" => SortKey::Size,
+                        "mtime" => SortKey::Mtime,
+                        _ => return Err(ListingQueryError::InvalidParameter(value.into_owned())),
+                    };
+                }
+                "order" => {
+                    listing_query.descending = match &*value {
+                        "asc" => false,
+                        "desc" => true,
+                        _ => return Err(ListingQueryError::InvalidParameter(value.into_owned())),
+                    };
+                }
+                "filter" if value.is_empty() => listing_query.filter = None,
+                "filter" => {
+                    if value.chars().count() > MAX_FILTER_LENGTH || value.contains('/') {
+                        return Err(ListingQueryError::InvalidParameter(value.into_owned()));
+                    }
+
+                    listing_query.filter = Some(value.into_owned());
+                }
+                _ => {}
+            }
+        }
+
+        Ok(listing_query)
+    }
+
+    /// Filter and sort the entries
+    fn apply(&self, entries: &mut Vec<ListingEntry>) {
+        if let Some(filter) = &self.filter {
+            entries.retain(|entry| entry.is_dir || wildcard_match(filter, &entry.name));
+        }
+
+        entries.sort_by(|a, b| self.compare(a, b));
+    }
+
+    /// Directories first, then by the sort key; the name breaks ties
+    fn compare(&self, a: &ListingEntry, b: &ListingEntry) -> Ordering {
+        let ordering = match self.sort {
+            SortKey::Name => a.name.cmp(&b.name),
+            SortKey::Size => a.size.cmp(&b.size).then_with(|| a.name.cmp(&b.name)),
+            SortKey::Mtime => a.mtime.cmp(&b.mtime).then_with(|| a.name.cmp(&b.name)),
+        };
+
+        let ordering = if self.descending {
+            ordering.reverse()
+        } else {
+            ordering
+        };
+
+        b.is_dir.cmp(&a.is_dir).then(ordering)
+    }
+
+    /// Query string to sort by the key, the order flips when already sorted by it
+    fn sort_href(&self, sort: SortKey) -> String {
+        let descending = self.sort == sort && !self.descending;
+
+        let mut serializer = form_urlencoded::Serializer::new(String::new());
+        serializer.append_pair("sort", sort.as_str());
+        serializer.append_pair("order", if descending { "desc" } else { "asc" });
+
+        if let Some(filter) = &self.filter {
+            serializer.append_pair("filter", filter);
+        }
+
+        format!("?{}", serializer.finish())
+    }
+}
+
+/// A file or directory in a listing
+#[derive(Debug, Hash, Serialize)]
+pub struct ListingEntry {
+    pub name: String,
+
+    /// Size in bytes, `0` for directories
+    pub size: u64,
+
+    /// Last modification, in seconds since the Unix epoch
+    pub mtime: u64,
+
+    pub is_dir: bool,
+}
+
+/// Everything a listing template can use
+#[derive(Debug, Serialize)]
+struct TemplateContext<'a> {
+    /// Decoded path of the directory, ie `/docs/`
+    path: &'a str,
+
+    /// The directory has a parent to link to
+    parent: bool,
+
+    entries: Vec<TemplateEntry<'a>>,
+
+    /// Query strings to sort by each of the keys
+    sort: SortHrefs,
+
+    filter: Option<&'a str>,
+}
+
+/// An entry, with ready to use values for a template
+#[derive(Debug, Serialize)]
+struct TemplateEntry<'a> {
+    #[serde(flatten)]
+    entry: &'a ListingEntry,
+
+    /// Relative URL of the entry, directories end with a slash
+    href: String,
+
+    /// Human readable size, ie `1.5M`, or `-` for directories
+    display_size: String,
+
+    /// Last modification, as an HTTP date
+    modified: String,
+
+    /// URL of the thumbnail, for images with `--listing-thumbnails`
+    thumbnail: Option<String>,
+}
+
+impl<'a> TemplateEntry<'a> {
+    fn new(entry: &'a ListingEntry, thumbnails: Option<&str>) -> Self {
+        let href = utf8_percent_encode(&entry.name, PATH_SEGMENT).to_string();
+
+        let thumbnail = thumbnails
+            .filter(|_| !entry.is_dir && has_thumbnail(&entry.name))
+            .map(|thumbnails| format!("{thumbnails}{href}"));
+
+        Self {
+            entry,
+            thumbnail,
+            href: if entry.is_dir { href + "/" } else { href },
+            display_size: if entry.is_dir {
+                String::from("-")
+            } else {
+                format_byte_size(entry.size)
+            },
+            modified: HttpDate::from(UNIX_EPOCH + Duration::from_secs(entry.mtime)).to_string(),
+        }
+    }
+}
+
+/// Query strings to sort a listing by each of the keys
+#[derive(Debug, Serialize)]
+struct SortHrefs {
+    name: String,
+    size: String,
+    mtime: String,
+}
+
+/// Respond with a listing of the requested directory, `None` when the request
+/// is not for a listable directory
+pub async fn directory_listing(
+    state: &ServerState,
+    release: &Release,
+    method: &Method,
+    uri: &Uri,
+    path: &Path,
+    headers: &HeaderMap,
+) -> Option<Response> {
+    if !uri.path().ends_with('/') {
+        return None;
+    }
+
+    let dir = release.base_dir.join(path);
+
+    if !tokio::fs::metadata(&dir).await.ok()?.is_dir()
+        || tokio::fs::metadata(dir.join(INDEX_FILE_NAME)).await.is_ok()
+    {
+        return None;
+    }
+
+    let query = match ListingQuery::from_query(uri.query()) {
+        Ok(query) => query,
+        Err(err) => {
+            tracing::debug!("Refusing to list directory: {err}");
+            return Some(StatusCode::BAD_REQUEST.into_response());
+        }
+    };
+
+    // decoded, like the names of the entries
+    let url_path = percent_decode_str(uri.path()).decode_utf8_lossy();
+
+    let mut entries = match read_entries(state, &dir, &url_path).await {
+        Ok(entries) => entries,
+        Err(err) => {
+            tracing::warn!("Could not list directory {dir:?}: {err}");
+            return None;
+        }
+    };
+
+    query.apply(&mut entries);
+
+    tracing::trace!("Listing directory with {} entries", entries.len());
+
+    let listing = Listing {
+        url_path: &url_path,
+        query: &query,
+        entries: &entries,
+        json: prefers_json(headers),
+        thumbnails: thumbnails_url(state, uri),
+    };
+
+    let (hash, etag) = listing.etag(state).await;
+
+    // the same URL results in JSON or HTML, compressed or not, caches should know
+    let mut response_headers = HeaderMap::new();
+    response_headers.insert(VARY, HeaderValue::from_static("accept, accept-encoding"));
+    response_headers.typed_insert(etag.clone());
+
+    // listings have no modification date, only the ETag conditions apply
+    match Preconditions::from_headers(headers).evaluate(method, &etag, None) {
+        Precondition::Passed => {}
+        Precondition::NotModified => {
+            return Some((StatusCode::NOT_MODIFIED, response_headers).into_response());
+        }
+        Precondition::Failed => return Some(StatusCode::PRECONDITION_FAILED.into_response()),
+    }
+
+    let client_encoding_support =
+        ClientEncodingSupport::from_header_map(headers, &state.config.encodings);
+    let encoding = client_encoding_support
+        .supported_encodings()
+        .first()
+        .copied();
+
+    if encoding.is_none() && !client_encoding_support.accepts_identity() {
+        return Some(StatusCode::NOT_ACCEPTABLE.into_response());
+    }
+
+    let rendered = if let Some(rendered) = state.listing_cache.get(hash, encoding) {
+        tracing::trace!("Listing cache hit");
+        rendered
+    } else {
+        match listing.render(state, encoding).await {
+            Ok(rendered) => {
+                state.listing_cache.insert(hash, encoding, rendered.clone());
+                rendered
+            }
+            Err(err) => {
+                tracing::error!("Could not render directory listing: {err}");
+                return Some(StatusCode::INTERNAL_SERVER_ERROR.into_response());
+            }
+        }
+    };
+
+    response_headers.insert(CONTENT_TYPE, rendered.content_type);
+
+    if let Some(encoding) = encoding {
+        response_headers.insert(CONTENT_ENCODING, encoding.to_header_value());
+    }
+
+    Some((response_headers, rendered.body).into_response())
+}
+
+/// URL prefix of the thumbnails of the directory, when enabled
+#[cfg(feature = "image-resize")]
+fn thumbnails_url(state: &ServerState, uri: &Uri) -> Option<String> {
+    state
+        .config
+        .listing_thumbnails
+        .then(|| format!("{ADMIN_PREFIX}/thumbnails{}", uri.path()))
+}
+
+/// Thumbnails need the `image-resize` feature
+#[cfg(not(feature = "image-resize"))]
+fn thumbnails_url(_state: &ServerState, _uri: &Uri) -> Option<String> {
+    None
+}
+
+/// A listing that is about to be rendered
+struct Listing<'a> {
+    url_path: &'a str,
+    query: &'a ListingQuery,
+    entries: &'a [ListingEntry],
+
+    /// Rendered as JSON instead of HTML
+    json: bool,
+
+    /// URL prefix of the thumbnails
+    thumbnails: Option<String>,
+}
+
+impl Listing<'_> {
+    /// Tag that changes with everything that ends up in the rendered listing
+    ///
+    /// Returns the hash as well, rendered listings are cached by it
+    async fn etag(&self, state: &ServerState) -> (u64, ETag) {
+        let mut hasher = DefaultHasher::new();
+
+        // the built-in template can change between versions
+        env!("CARGO_PKG_VERSION").hash(&mut hasher);
+        self.url_path.hash(&mut hasher);
+        self.query.hash(&mut hasher);
+        self.entries.hash(&mut hasher);
+        self.json.hash(&mut hasher);
+        self.thumbnails.hash(&mut hasher);
+
+        if let Some(template) = &state.config.listing_template {
+            let modified = tokio::fs::metadata(template)
+                .await
+                .and_then(|meta| meta.modified())
+                .ok();
+            modified.hash(&mut hasher);
+        }
+
+        let hash = hasher.finish();
+        let etag = format!("\"{hash:016x}\"")
+            .parse::<ETag>()
+            .expect("A valid ETag");
+
+        (hash, etag)
+    }
+
+    /// Render the listing, compressed with the encoding
+    async fn render(
+        &self,
+        state: &ServerState,
+        encoding: Option<Encoding>,
+    ) -> Result<RenderedListing, ListingError> {
+        let (content_type, body) = if self.json {
+            (
+                HeaderValue::from_static("application/json"),
+                serde_json::to_vec(self.entries)?,
+            )
+        } else {
+            let html = render_html(
+                state.config.listing_template.as_deref(),
+                self.thumbnails.as_deref(),
+                self.url_path,
+                self.query,
+                self.entries,
+            )
+            .await?;
+
+            (
+                HeaderValue::from_static("text/html; charset=utf-8"),
+                html.into_bytes(),
+            )
+        };
+
+        let body = match encoding {
+            Some(encoding) => encoding.compress(&body).map_err(ListingError::Compress)?,
+            None => body,
+        };
+
+        Ok(RenderedListing {
+            content_type,
+            body: Bytes::from(body),
+        })
+    }
+}
+
+/// A rendered (and compressed) listing
+#[derive(Clone, Debug)]
+struct RenderedListing {
+    content_type: HeaderValue,
+    body: Bytes,
+}
+
+/// Cache of rendered listings, by their `ETag` and encoding
+///
+/// Listings are only rendered again when their directory changes, the cache is
+/// small and forgets the oldest listings first
+#[derive(Debug, Default)]
+pub struct ListingCache {
+    listings: Mutex<ListingCacheEntries>,
+}
+
+#[derive(Debug, Default)]
+struct ListingCacheEntries {
+    rendered: HashMap<(u64, Option<Encoding>), RenderedListing>,
+
+    /// Keys of the rendered listings, the oldest first
+    order: VecDeque<(u64, Option<Encoding>)>,
+}
+
+impl ListingCache {
+    fn get(&self, hash: u64, encoding: Option<Encoding>) -> Option<RenderedListing> {
+        self.listings
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .rendered
+            .get(&(hash, encoding))
+            .cloned()
+    }
+
+    fn insert(&self, hash: u64, encoding: Option<Encoding>, rendered: RenderedListing) {
+        let mut listings = self.listings.lock().unwrap_or_else(PoisonError::into_inner);
+
+        if listings
+            .rendered
+            .insert((hash, encoding), rendered)
+            .is_none()
+        {
+            listings.order.push_back((hash, encoding));
+        }
+
+        while listings.order.len() > LISTING_CACHE_SIZE {
+            if let Some(oldest) = listings.order.pop_front() {
+                listings.rendered.remove(&oldest);
+            }
+        }
+    }
+}
+
+/// Read the entries of a directory, without the hidden and unservable ones
+pub async fn read_entries(
+    state: &ServerState,
+    dir: &Path,
+    url_path: &str,
+) -> std::io::Result<Vec<ListingEntry>> {
+    let mut entries = vec![];
+    let mut read_dir = tokio::fs::read_dir(dir).await?;
+
+    while let Some(entry) = read_dir.next_entry().await? {
+        let Ok(name) = entry.file_name().into_string() else {
+            continue;
+        };
+
+        if state.is_hidden(&format!("{url_path}{name}")) {
+            continue;
+        }
+
+        // symlinks are followed, like they are when serving files
+        let Ok(meta) = tokio::fs::metadata(entry.path()).await else {
+            continue;
+        };
+
+        if !meta.is_dir() && !state.is_servable(&entry.path()) {
+            continue;
+        }
+
+        let mtime = meta
+            .modified()
+            .ok()
+            .and_then(|modified| modified.duration_since(UNIX_EPOCH).ok())
+            .map_or(0, |since_epoch| since_epoch.as_secs());
+
+        entries.push(ListingEntry {
+            name,
+            size: if meta.is_dir() { 0 } else { meta.len() },
+            mtime,
+            is_dir: meta.is_dir(),
+        });
+    }
+
+    Ok(entries)
+}
+
+/// Render the HTML page of a listing, with the configured template or the built-in one
+async fn render_html(
+    template: Option<&Path>,
+    thumbnails: Option<&str>,
+    url_path: &str,
+    query: &ListingQuery,
+    entries: &[ListingEntry],
+) -> Result<String, ListingTemplateError> {
+    // read on every listing, so changes to the template show up right away
+    let source = match template {
+        Some(template) => Cow::Owned(
+            tokio::fs::read_to_string(template)
+                .await
+                .map_err(|err| ListingTemplateError::Io(template.to_path_buf(), err))?,
+        ),
+        None => Cow::Borrowed(BUILTIN_TEMPLATE),
+    };
+
+    let context = TemplateContext {
+        path: url_path,
+        parent: url_path != "/",
+        entries: entries
+            .iter()
+            .map(|entry| TemplateEntry::new(entry, thumbnails))
+            .collect(),
+        sort: SortHrefs {
+            name: query.sort_href(SortKey::Name),
+            size: query.sort_href(SortKey::Size),
+            mtime: query.sort_href(SortKey::Mtime),
+        },
+        filter: query.filter.as_deref(),
+    };
+
+    Ok(template_environment()
+        .template_from_named_str(TEMPLATE_NAME, &source)?
+        .render(Serde(context))?)
+}
+
+/// Serve the thumbnail of an image, for the listing of its directory
+#[cfg(feature = "image-resize")]
+pub async fn thumbnail(
+    State(state): State<ServerState>,
+    variant: Option<Extension<Variant>>,
+    UrlPath(path): UrlPath<String>,
+) -> Response {
+    let release = state.release_for(
+        variant
+            .map(|Extension(variant)| variant)
+            .unwrap_or_default(),
+    );
+
+    let path = PathBuf::from(path);
+
+    // the same rules as for serving the image itself
+    let is_allowed = path
+        .components()
+        .all(|component| matches!(component, Component::Normal(_)))
+        && !state.is_hidden(&format!("/{}", path.display()))
+        && state.is_servable(&path);
+
+    if !is_allowed {
+        return StatusCode::NOT_FOUND.into_response();
+    }
+
+    let source = release.base_dir.join(path);
+
+    resized_image_response(&state, &ResizeRequest::thumbnail(), &source).await
+}
+
+/// Check if a thumbnail can be made of the file
+#[cfg(feature = "image-resize")]
+fn has_thumbnail(name: &str) -> bool {
+    is_resizable(Path::new(name))
+}
+
+/// Thumbnails need the `image-resize` feature
+#[cfg(not(feature = "image-resize"))]
+fn has_thumbnail(_name: &str) -> bool {
+    false
+}
+
+/// Check that the template can be read and parsed
+pub fn check_template(template: &Path) -> Result<(), ListingTemplateError> {
+    let source = std::fs::read_to_string(template)
+        .map_err(|err| ListingTemplateError::Io(template.to_path_buf(), err))?;
+
+    template_environment().template_from_named_str(TEMPLATE_NAME, &source)?;
+
+    Ok(())
+}
+
+/// Environment to render the templates in, the `.html` name enables auto-escaping
+fn template_environment() -> Environment<'static> {
+    Environment::new()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(name: &str, is_dir: bool) -> ListingEntry {
+        ListingEntry {
+            name: name.to_string(),
+            size: 0,
+            mtime: 0,
+            is_dir,
+        }
+    }
+
+    fn names(entries: &[ListingEntry]) -> Vec<&str> {
+        entries.iter().map(|entry| entry.name.as_str()).collect()
+    }
+
+    #[test]
+    fn test_dirs_first() {
+        let mut entries = vec![entry("b.txt", false), entry("z", true), entry("a", true)];
+        ListingQuery::default().apply(&mut entries);
+
+        assert_eq!(names(&entries), ["a", "z", "b.txt"]);
+    }
+
+    #[test]
+    fn test_sort_and_filter() {
+        let mut entries = vec![
+            ListingEntry {
+                size: 10,
+                ..entry("small.log", false)
+            },
+            ListingEntry {
+                size: 30,
+                ..entry("large.log", false)
+            },
+            ListingEntry {
+                size: 20,
+                ..entry("other.txt", false)
+            },
+            entry("logs", true),
+        ];
+
+        let query = ListingQuery::from_query(Some("sort=size&order=desc&filter=*.log"))
+            .expect("A valid query");
+        query.apply(&mut entries);
+
+        assert_eq!(names(&entries), ["logs", "large.log", "small.log"]);
+    }
+
+    #[test]
+    fn test_invalid_listing_query() {
+        assert!(ListingQuery::from_query(Some("sort=owner")).is_err());
+        assert!(ListingQuery::from_query(Some("order=up")).is_err());
+        assert!(ListingQuery::from_query(Some("filter=../*")).is_err());
+        assert!(ListingQuery::from_query(Some(&format!("filter={}", "*".repeat(200)))).is_err());
+        assert_eq!(
+            ListingQuery::from_query(Some("page=2&filter=")).ok(),
+            Some(ListingQuery::default())
+        );
+    }
+
+    #[tokio::test]
+    async fn test_render_html_escapes() {
+        let html = render_html(
+            None,
+            None,
+            "/docs/",
+            &ListingQuery::default(),
+            &[entry("<b> & \"c\".txt", false)],
+        )
+        .await
+        .expect("A valid built-in template");
+
+        assert!(html.contains("&lt;b&gt; &amp; &quot;c&quot;.txt"));
+        assert!(html.contains("href=\"%3Cb%3E%20%26%20%22c%22.txt\""));
+        assert!(html.contains("href=\"../\""));
+    }
+}
@@ -0,0 +1,238 @@
+//! Live reload of pages during development
+//!
+//! With `--live-reload` (part of `--dev`), HTML documents get a small script
+//! that listens to the server-sent events of `/_srvr/live-reload`. The base
+//! dir is watched for changes, every batch of changes sends an event and the
+//! open pages reload themselves. Documents with a `Content-Encoding` are served
+//! as they are, the script can not be added to them.
+
+use std::convert::Infallible;
+use std::future::ready;
+use std::path::Path;
+use std::sync::mpsc;
+use std::sync::Arc;
+use std::time::Duration;
+
+use axum::body::to_bytes;
+use axum::body::Body;
+use axum::extract::Request;
+use axum::extract::State;
+use axum::http::header::ACCEPT_RANGES;
+use axum::http::header::CONTENT_ENCODING;
+use axum::http::header::CONTENT_LENGTH;
+use axum::http::Method;
+use axum::http::StatusCode;
+use axum::middleware::Next;
+use axum::response::sse::Event;
+use axum::response::sse::KeepAlive;
+use axum::response::sse::Sse;
+use axum::response::IntoResponse;
+use axum::response::Response;
+use axum::routing::get;
+use axum::routing::MethodRouter;
+use futures_util::stream::unfold;
+use notify::event::ModifyKind;
+use notify::EventKind;
+use notify::RecursiveMode;
+use notify::Watcher;
+use tokio::sync::broadcast;
+use tokio::sync::broadcast::error::RecvError;
+
+use crate::admin::ADMIN_PREFIX;
+use crate::app::ServerState;
+use crate::headers::is_document;
+
+/// Path of the events, within the admin prefix
+pub const LIVE_RELOAD_PATH: &str = "/live-reload";
+
+/// Time to wait for more changes, before the pages are reloaded
+const WATCH_DEBOUNCE: Duration = Duration::from_millis(100);
+
+/// Largest document the script is added to
+const MAX_DOCUMENT_SIZE: usize = 10 * 1024 * 1024;
+
+/// Changes of the files, sent to every open page
+pub struct LiveReload {
+    changes: broadcast::Sender<()>,
+}
+
+impl Default for LiveReload {
+    fn default() -> Self {
+        let (changes, _) = broadcast::channel(16);
+
+        Self { changes }
+    }
+}
+
+/// Watch the base dir for changes in the background, if live reload is enabled
+pub fn spawn_live_reload(state: &ServerState) {
+    let Some(live_reload) = &state.live_reload else {
+        return;
+    };
+
+    let live_reload = Arc::clone(live_reload);
+    let base_dir = state.config.base_dir.clone();
+
+    std::thread::spawn(move || {
+        if let Err(err) = watch(&base_dir, &live_reload) {
+            tracing::warn!("Could not watch {base_dir:?} for live reload: {err}");
+        }
+    });
+}
+
+/// Send an event for every batch of changes in the base dir
+fn watch(base_dir: &Path, live_reload: &LiveReload) -> notify::Result<()> {
+    let (events_tx, events_rx) = mpsc::channel();
+
+    let mut watcher = notify::recommended_watcher(events_tx)?;
+    watcher.watch(base_dir, RecursiveMode::Recursive)?;
+
+    while let Ok(event) = events_rx.recv() {
+        let mut is_changed = is_change(&event);
+
+        // a bundler writes many files at once, the pages reload once for all of them
+        while let Ok(event) = events_rx.recv_timeout(WATCH_DEBOUNCE) {
+            is_changed |= is_change(&event);
+        }
+
+        if is_changed {
+            tracing::debug!("Files changed, reloading pages");
+
+            // fails when there are no open pages
+            live_reload.changes.send(()).ok();
+        }
+    }
+
+    Ok(())
+}
+
+/// Check if the event changed the content of the base dir, reading a file can
+/// change its metadata
+fn is_change(event: &notify::Result<notify::Event>) -> bool {
+    match event {
+        Ok(event) => match event.kind {
+            EventKind::Modify(ModifyKind::Metadata(_)) => false,
+            EventKind::Create(_) | EventKind::Modify(_) | EventKind::Remove(_) => true,
+            _ => false,
+        },
+        Err(err) => {
+            tracing::warn!("Could not watch for changes: {err}");
+            false
+        }
+    }
+}
+
+/// Route of the events, sent to the script of the pages
+pub fn events_route() -> MethodRouter<ServerState> {
+    get(|State(state): State<ServerState>| ready(events(&state)))
+}
+
+/// Stream of server-sent events, one for every batch of changes
+fn events(state: &ServerState) -> Response {
+    let Some(live_reload) = &state.live_reload else {
+        return StatusCode::NOT_FOUND.into_response();
+    };
+
+    let events = unfold(live_reload.changes.subscribe(), |mut changes| async move {
+        match changes.recv().await {
+            // a missed change is still a change
+            Ok(()) | Err(RecvError::Lagged(_)) => Some((
+                Ok::<_, Infallible>(Event::default().data("reload")),
+                changes,
+            )),
+            Err(RecvError::Closed) => None,
+        }
+    });
+
+    Sse::new(events)
+        .keep_alive(KeepAlive::default())
+        .into_response()
+}
+
+/// Script that reloads the page on every event
+fn script() -> String {
+    format!(
+        "<script>new EventSource(\"{ADMIN_PREFIX}{LIVE_RELOAD_PATH}\").onmessage = () => location.reload();</script>"
+    )
+}
+
+/// Add the script to the document, before the closing `</body>` or at the end
+fn with_script(document: &[u8], script: &str) -> Vec<u8> {
+    let position = document
+        .windows(b"</body>".len())
+        .rposition(|window| window.eq_ignore_ascii_case(b"</body>"))
+        .unwrap_or(document.len());
+
+    let mut content = Vec::with_capacity(document.len() + script.len());
+    content.extend_from_slice(&document[..position]);
+    content.extend_from_slice(script.as_bytes());
+    content.extend_from_slice(&document[position..]);
+
+    content
+}
+
+/// Middleware that adds the live reload script to HTML documents
+pub async fn live_reload(request: Request, next: Next) -> Response {
+    let is_head = request.method() == Method::HEAD;
+    let response = next.run(request).await;
+
+    let headers = response.headers();
+
+    if !is_document(headers)
+        || headers.contains_key(CONTENT_ENCODING)
+        || response.status() == StatusCode::PARTIAL_CONTENT
+    {
+        return response;
+    }
+
+    let Some(content_length) = headers
+        .get(CONTENT_LENGTH)
+        .and_then(|content_length| content_length.to_str().ok())
+        .and_then(|content_length| content_length.parse::<usize>().ok())
+        .filter(|content_length| *content_length <= MAX_DOCUMENT_SIZE)
+    else {
+        return response;
+    };
+
+    let script = script();
+    let (mut parts, body) = response.into_parts();
+
+    // ranges of the document on disk do not match the document with the script
+    parts.headers.remove(ACCEPT_RANGES);
+    parts
+        .headers
+        .insert(CONTENT_LENGTH, (content_length + script.len()).into());
+
+    if is_head {
+        return Response::from_parts(parts, body);
+    }
+
+    match to_bytes(body, MAX_DOCUMENT_SIZE).await {
+        Ok(document) => Response::from_parts(parts, Body::from(with_script(&document, &script))),
+        Err(err) => {
+            tracing::warn!("Could not add the live reload script: {err}");
+            StatusCode::INTERNAL_SERVER_ERROR.into_response()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_with_script() {
+        assert_eq!(
+            with_script(b"<html><body>Hi</body></html>", "<script></script>"),
+            b"<html><body>Hi<script></script></body></html>"
+        );
+        assert_eq!(
+            with_script(b"<HTML><BODY>Hi</BODY></HTML>", "<script></script>"),
+            b"<HTML><BODY>Hi<script></script></BODY></HTML>"
+        );
+        assert_eq!(
+            with_script(b"<p>Hi</p>", "<script></script>"),
+            b"<p>Hi</p><script></script>"
+        );
+    }
+}
@@ -3,29 +3,115 @@
 #![allow(clippy::module_name_repetitions)]
 #![doc = include_str!("../README.md")]
 
+use std::future::Future;
+use std::io;
+use std::net::SocketAddr;
+use std::path::Path;
 use std::process::exit;
+use std::sync::Arc;
 
 use tokio::net::TcpListener;
+use tokio::sync::watch;
+use tracing::metadata::LevelFilter;
 
 use crate::app::app;
 use crate::app::ServerState;
+use crate::auth::jwt::spawn_jwks_refresh;
+use crate::auth::signed_url::sign;
+use crate::ban::spawn_ban_cleanup;
+use crate::bench::bench;
+use crate::config::CliCommand;
+use crate::config::CliConfig;
 use crate::config::Config;
-use crate::utils::graceful_shutdown;
+use crate::explain::explain;
+use crate::file_cache::FileCache;
+use crate::http_redirect::redirect_app;
+use crate::http_redirect::redirect_listener;
+use crate::live_reload::spawn_live_reload;
+use crate::mkcert::trust;
+use crate::precompress::precompress;
+use crate::print_config::print_config;
+use crate::rate_limit::spawn_rate_limit_cleanup;
+use crate::selftest::selftest;
+use crate::server::serve;
+use crate::server::ConnectionLimits;
+use crate::snapshot::SnapshotError;
+use crate::tls::tls_acceptor;
+use crate::upgrade::inherited_listener;
+use crate::upgrade::notify_ready;
+use crate::upgrade::shutdown_or_upgrade;
+use crate::upgrade::SERVER_LISTEN_FD;
 use crate::utils::setup_address;
 use crate::utils::setup_tracing;
 
+mod acme;
+mod admin;
 mod app;
+mod audit;
+mod auth;
+mod ban;
+mod bench;
+mod cache_rules;
+mod canary;
+mod conditional;
 mod config;
+mod connections;
+mod cors;
+mod csp;
+mod dir_config;
+mod early_hints;
 mod encoding;
+mod errors;
+mod explain;
 mod file_cache;
+mod forwarded;
+mod header_rules;
+mod headers;
+mod hotlink;
+mod http_redirect;
+#[cfg(feature = "image-resize")]
+mod image_resize;
+mod listing;
+mod live_reload;
+mod media;
+mod metrics;
+mod minify;
+mod mkcert;
+mod normalize;
+mod partial;
+mod path_pattern;
 mod paths;
+mod precompress;
+mod print_config;
+mod proxy;
+mod rate_limit;
+mod redirect_rules;
+mod redirects;
+mod selftest;
+mod server;
+mod shadow;
+mod snapshot;
+mod throttle;
+mod tls;
+mod trailing_slash;
+mod upgrade;
 mod utils;
+mod virtual_files;
 
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
-    setup_tracing();
+    let cli_config = CliConfig::from_env();
 
-    let config = match Config::from_env() {
+    // tools only log warnings by default, their output should stand out
+    setup_tracing(if cli_config.command.is_some() {
+        LevelFilter::WARN
+    } else if cli_config.config.dev {
+        LevelFilter::DEBUG
+    } else {
+        LevelFilter::INFO
+    });
+
+    let config = match cli_config.config.validate() {
         Ok(config) => config,
         Err(err) => {
             tracing::error!("Could not handle arguments: {err}");
@@ -33,6 +119,14 @@ async fn main() -> anyhow::Result<()> {
         }
     };
 
+    if cli_config.print_config {
+        return print_config(&config);
+    }
+
+    if let Some(command) = cli_config.command {
+        return run_command(command, config).await;
+    }
+
     let address = match setup_address(&config) {
         Ok(address) => address,
         Err(err) => {
@@ -41,14 +135,21 @@ async fn main() -> anyhow::Result<()> {
         }
     };
 
-    let listener = match TcpListener::bind(address).await {
-        Ok(listener) => listener,
+    let tls = match tls_acceptor(&config) {
+        Ok(tls) => tls,
         Err(err) => {
-            tracing::error!("Could not listen on address {address}: {err}");
+            tracing::error!("Could not set up TLS: {err}");
             exit(1);
         }
     };
 
+    let listener = listener(address).await;
+
+    let address = listener.local_addr()?;
+    let redirect_http = redirect_http_listener(&config, address).await;
+
+    let shutdown = shutdown_or_upgrade(&listener, redirect_http.as_ref())?;
+
     tracing::info!("                                   ");
     tracing::info!(" ███████╗██████╗ ██╗   ██╗██████╗  ");
     tracing::info!(" ██╔════╝██╔══██╗██║   ██║██╔══██╗ ");
@@ -57,13 +158,139 @@ async fn main() -> anyhow::Result<()> {
     tracing::info!(" ███████║██║  ██║ ╚████╔╝ ██║  ██║ is starting");
     tracing::info!(" ╚══════╝╚═╝  ╚═╝  ╚═══╝  ╚═╝  ╚═╝ ");
     tracing::info!("                                   ");
-    tracing::info!("Serving {:?} on http://{address}", &config.base_dir);
+    let scheme = if tls.is_some() { "https" } else { "http" };
+    tracing::info!("Serving {:?} on {scheme}://{address}", &config.base_dir);
+
+    if let Some(listener) = &redirect_http {
+        tracing::info!("Redirecting http://{} to HTTPS", listener.local_addr()?);
+    }
 
+    let limits = ConnectionLimits::from_config(&config);
     let state = ServerState::from_config(config);
+    spawn_jwks_refresh(&state);
+    spawn_rate_limit_cleanup(&state);
+    spawn_ban_cleanup(&state);
+    spawn_live_reload(&state);
+
+    let connections = Arc::clone(&state.connections);
+    let file_cache = Arc::clone(&state.file_cache);
+    let cache_snapshot = state.config.cache_snapshot.clone();
+
+    if let Some(cache_snapshot) = &cache_snapshot {
+        restore_cache_snapshot(&file_cache, cache_snapshot).await;
+    }
 
-    axum::serve(listener, app(state).into_make_service())
-        .with_graceful_shutdown(graceful_shutdown())
-        .await?;
+    let http1_only = state.config.is_http1_only();
+
+    // both listeners shut down on the same signal
+    let shutdown_rx = share_shutdown(shutdown);
+
+    let redirecting = redirect_http.map(|redirect_listener| {
+        tokio::spawn(serve(
+            redirect_listener,
+            redirect_app(address.port()),
+            None,
+            http1_only,
+            limits,
+            Arc::clone(&connections),
+            shutdown_signal(shutdown_rx.clone()),
+        ))
+    });
+
+    notify_ready();
+
+    serve(
+        listener,
+        app(state),
+        tls,
+        http1_only,
+        limits,
+        connections,
+        shutdown_signal(shutdown_rx),
+    )
+    .await;
+
+    if let Some(redirecting) = redirecting {
+        redirecting.await.ok();
+    }
+
+    if let Some(cache_snapshot) = &cache_snapshot {
+        match file_cache.save_snapshot(cache_snapshot).await {
+            Ok(saved) => tracing::info!("Saved {saved} file(s) to the cache snapshot"),
+            Err(err) => tracing::error!("Could not save the cache snapshot: {err}"),
+        }
+    }
 
     Ok(())
 }
+
+/// Fill the file cache from the snapshot of a previous run, if there is one
+async fn restore_cache_snapshot(file_cache: &FileCache, cache_snapshot: &Path) {
+    match file_cache.load_snapshot(cache_snapshot).await {
+        Ok(restored) => tracing::info!("Restored {restored} file(s) from the cache snapshot"),
+        Err(SnapshotError::Io(_, err)) if err.kind() == io::ErrorKind::NotFound => {}
+        Err(err) => tracing::warn!("Starting with an empty cache: {err}"),
+    }
+}
+
+/// Run one of the tools instead of serving
+async fn run_command(command: CliCommand, config: Config) -> anyhow::Result<()> {
+    match command {
+        CliCommand::Bench(bench_config) => bench(config, bench_config).await,
+        CliCommand::Selftest => selftest(config).await,
+        CliCommand::Explain(explain_config) => explain(config, explain_config).await,
+        CliCommand::Trust => trust(),
+        CliCommand::Precompress(precompress_config) => {
+            precompress(config, precompress_config).await
+        }
+        CliCommand::Sign(sign_config) => sign(&config, &sign_config),
+    }
+}
+
+/// Listener for the server, inherited from a previous srvr when upgrading
+async fn listener(address: SocketAddr) -> TcpListener {
+    match inherited_listener(SERVER_LISTEN_FD) {
+        Ok(Some(listener)) => listener,
+        Ok(None) => match TcpListener::bind(address).await {
+            Ok(listener) => listener,
+            Err(err) => {
+                tracing::error!("Could not listen on address {address}: {err}");
+                exit(1);
+            }
+        },
+        Err(err) => {
+            tracing::error!("Could not take over inherited listener: {err}");
+            exit(1);
+        }
+    }
+}
+
+/// Listener for plain HTTP that redirects to HTTPS, on the same IP as the server
+async fn redirect_http_listener(config: &Config, address: SocketAddr) -> Option<TcpListener> {
+    let address = SocketAddr::new(address.ip(), config.redirect_http?);
+
+    match redirect_listener(address).await {
+        Ok(listener) => Some(listener),
+        Err(err) => {
+            tracing::error!("Could not listen on address {address}: {err}");
+            exit(1);
+        }
+    }
+}
+
+/// Share the shutdown signal, so multiple servers can wait for it
+fn share_shutdown(shutdown: impl Future<Output = ()> + Send + 'static) -> watch::Receiver<()> {
+    let (shutdown_tx, shutdown_rx) = watch::channel(());
+
+    tokio::spawn(async move {
+        shutdown.await;
+        drop(shutdown_tx);
+    });
+
+    shutdown_rx
+}
+
+/// Completes once the shutdown signal is sent, by dropping its sender
+async fn shutdown_signal(mut shutdown_rx: watch::Receiver<()>) {
+    shutdown_rx.changed().await.ok();
+}
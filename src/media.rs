@@ -0,0 +1,89 @@
+//! Media (HLS/DASH) serving support
+
+use std::path::Path;
+
+use axum::http::header::ACCESS_CONTROL_ALLOW_ORIGIN;
+use axum::http::header::ACCESS_CONTROL_EXPOSE_HEADERS;
+use axum::http::header::CACHE_CONTROL;
+use axum::http::HeaderMap;
+use axum::http::HeaderValue;
+
+/// Cache control header value for playlists, they change while streaming live
+const CACHE_CONTROL_PLAYLIST: &str = "no-cache";
+
+/// Cache control header value for segments, they never change once written
+const CACHE_CONTROL_SEGMENT: &str = "public, max-age=31536000, immutable";
+
+/// Headers media players need to read on cross-origin (range) requests
+const EXPOSE_HEADERS: &str = "Content-Length, Content-Range";
+
+/// Content types that `mime_guess` gets wrong (or not quite right) for media files
+const CONTENT_TYPES: &[(&str, &str)] = &[
+    ("m3u8", "application/vnd.apple.mpegurl"),
+    ("m3u", "audio/mpegurl"),
+    ("mpd", "application/dash+xml"),
+    ("ts", "video/mp2t"),
+    ("m4s", "video/iso.segment"),
+    ("m4v", "video/mp4"),
+];
+
+/// Kind of media file, used to determine the headers to send
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MediaKind {
+    /// HLS or DASH playlist/manifest
+    Playlist,
+
+    /// HLS or DASH segment
+    Segment,
+
+    /// Regular audio/video file
+    File,
+}
+
+impl MediaKind {
+    /// Determine the kind of media based on the extension of the path
+    pub fn from_path(path: &Path) -> Option<Self> {
+        let extension = path.extension()?.to_str()?;
+
+        match extension.to_ascii_lowercase().as_str() {
+            "m3u8" | "m3u" | "mpd" => Some(Self::Playlist),
+            "ts" | "m4s" | "aac" | "vtt" => Some(Self::Segment),
+            "mp4" | "m4v" | "m4a" | "webm" | "mp3" | "ogg" => Some(Self::File),
+            _ => None,
+        }
+    }
+
+    /// Cache control header value for this kind of media
+    #[inline]
+    pub const fn cache_control(self) -> Option<&'static str> {
+        match self {
+            Self::Playlist => Some(CACHE_CONTROL_PLAYLIST),
+            Self::Segment => Some(CACHE_CONTROL_SEGMENT),
+            Self::File => None,
+        }
+    }
+
+    /// Add the headers for this kind of media to the response headers
+    pub fn apply_headers(self, headers: &mut HeaderMap) {
+        // players are regularly hosted on another origin than the media itself
+        headers.insert(ACCESS_CONTROL_ALLOW_ORIGIN, HeaderValue::from_static("*"));
+        headers.insert(
+            ACCESS_CONTROL_EXPOSE_HEADERS,
+            HeaderValue::from_static(EXPOSE_HEADERS),
+        );
+
+        if let Some(cache_control) = self.cache_control() {
+            headers.insert(CACHE_CONTROL, HeaderValue::from_static(cache_control));
+        }
+    }
+}
+
+/// Get the content type of a media file, when `mime_guess` is known to get it wrong
+pub fn content_type(path: &Path) -> Option<&'static str> {
+    let extension = path.extension()?.to_str()?;
+
+    CONTENT_TYPES
+        .iter()
+        .find(|(known, _)| extension.eq_ignore_ascii_case(known))
+        .map(|(_, content_type)| *content_type)
+}
@@ -0,0 +1,33 @@
+//! Counters of the server, exposed via the admin API at `/_srvr/metrics`
+
+use std::sync::atomic::AtomicU64;
+use std::sync::atomic::Ordering;
+
+use serde::Serialize;
+
+/// Counters shared by every request
+#[derive(Debug, Default)]
+pub struct Metrics {
+    /// Requests refused with a 503 because of `--max-concurrent-requests`
+    overloaded_requests: AtomicU64,
+}
+
+impl Metrics {
+    /// Count a request that was refused because the server was overloaded
+    pub fn add_overloaded_request(&self) {
+        self.overloaded_requests.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Snapshot of the counters
+    pub fn snapshot(&self) -> MetricsSnapshot {
+        MetricsSnapshot {
+            overloaded_requests: self.overloaded_requests.load(Ordering::Relaxed),
+        }
+    }
+}
+
+/// Snapshot of the counters, as exposed via the admin API
+#[derive(Debug, Serialize)]
+pub struct MetricsSnapshot {
+    overloaded_requests: u64,
+}
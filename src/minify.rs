@@ -0,0 +1,212 @@
+//! Minification of HTML, CSS and JavaScript
+//!
+//! Files are minified when they are read into the file cache, so every
+//! request after the first one gets the minified bytes for free. Files that
+//! can not be minified are served as they are.
+//!
+//! HTML minification is conservative: comments are removed and whitespace
+//! between tags is collapsed, but tags themselves and the contents of
+//! `<pre>`, `<textarea>`, `<script>` and `<style>` are left alone.
+
+use std::path::Path;
+
+/// Largest file that is minified, larger files are streamed as they are
+pub const MAX_MINIFY_SIZE: u64 = 8 * 1024 * 1024;
+
+/// Elements with content that should be kept as-is
+const RAW_ELEMENTS: &[&str] = &["pre", "textarea", "script", "style"];
+
+/// Kind of file that can be minified
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Minifiable {
+    Html,
+    Css,
+    JavaScript,
+}
+
+impl Minifiable {
+    /// Kind of the file, based on its extension
+    pub fn from_path(path: &Path) -> Option<Self> {
+        let extension = path.extension()?.to_str()?.to_ascii_lowercase();
+
+        match extension.as_str() {
+            "html" | "htm" => Some(Self::Html),
+            "css" => Some(Self::Css),
+            "js" | "mjs" => Some(Self::JavaScript),
+            _ => None,
+        }
+    }
+
+    /// Minify the content, `None` when it can not be minified
+    pub fn minify(self, content: &[u8]) -> Option<Vec<u8>> {
+        let source = std::str::from_utf8(content).ok()?;
+
+        let minified = match self {
+            Self::Html => minify_html(source),
+            Self::Css => minifier::css::minify(source).ok()?.to_string(),
+            Self::JavaScript => minifier::js::minify(source).to_string(),
+        };
+
+        Some(minified.into_bytes())
+    }
+}
+
+/// Minify HTML, without changing how it renders
+fn minify_html(source: &str) -> String {
+    let mut minified = String::with_capacity(source.len());
+    let mut rest = source;
+
+    while !rest.is_empty() {
+        if let Some(comment) = rest.strip_prefix("<!--") {
+            // conditional comments are meant for (old) browsers
+            if !comment.starts_with("[if") {
+                rest = comment.find("-->").map_or("", |end| &comment[end + 3..]);
+                continue;
+            }
+        }
+
+        if rest.starts_with('<') {
+            let tag_length = tag_length(rest);
+            let (tag, after) = rest.split_at(tag_length);
+            minified.push_str(tag);
+            rest = after;
+
+            if let Some(name) = raw_element(tag) {
+                let end = find_closing_tag(rest, name).unwrap_or(rest.len());
+                minified.push_str(&rest[..end]);
+                rest = &rest[end..];
+            }
+
+            continue;
+        }
+
+        let text_length = rest.find('<').unwrap_or(rest.len());
+        let (text, after) = rest.split_at(text_length);
+        collapse_whitespace(text, &mut minified);
+        rest = after;
+    }
+
+    minified.trim().to_string()
+}
+
+/// Length of the tag at the start of the source, `>` in quoted attributes does not end it
+fn tag_length(source: &str) -> usize {
+    let mut quote = None;
+
+    for (index, c) in source.char_indices() {
+        match (quote, c) {
+            (None, '"' | '\'') => quote = Some(c),
+            (Some(open), _) if open == c => quote = None,
+            (None, '>') => return index + 1,
+            _ => {}
+        }
+    }
+
+    source.len()
+}
+
+/// Name of the raw element the tag opens, if any
+fn raw_element(tag: &str) -> Option<&'static str> {
+    let name = tag[1..]
+        .split(|c: char| c.is_ascii_whitespace() || c == '>' || c == '/')
+        .next()?;
+
+    RAW_ELEMENTS
+        .iter()
+        .copied()
+        .find(|raw| raw.eq_ignore_ascii_case(name))
+}
+
+/// Position of the closing tag of the element, case insensitive
+fn find_closing_tag(source: &str, name: &str) -> Option<usize> {
+    let closing = format!("</{name}");
+
+    source
+        .as_bytes()
+        .windows(closing.len())
+        .position(|window| window.eq_ignore_ascii_case(closing.as_bytes()))
+}
+
+/// Collapse every run of whitespace into a single space, or a newline when it had one
+fn collapse_whitespace(text: &str, minified: &mut String) {
+    let mut whitespace = None;
+
+    for c in text.chars() {
+        if c.is_ascii_whitespace() {
+            whitespace = match (whitespace, c) {
+                (_, '\n') | (Some('\n'), _) => Some('\n'),
+                _ => Some(' '),
+            };
+            continue;
+        }
+
+        if let Some(whitespace) = whitespace.take() {
+            push_whitespace(minified, whitespace);
+        }
+        minified.push(c);
+    }
+
+    if let Some(whitespace) = whitespace {
+        push_whitespace(minified, whitespace);
+    }
+}
+
+/// Push whitespace, merging it with whitespace before a removed comment
+fn push_whitespace(minified: &mut String, whitespace: char) {
+    match minified.pop() {
+        Some('\n') => minified.push('\n'),
+        Some(c) => {
+            if c != ' ' {
+                minified.push(c);
+            }
+            minified.push(whitespace);
+        }
+        None => minified.push(whitespace),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_minify_html() {
+        let html = "<!doctype html>\n<html>\n  <head>\n    <!-- a comment -->\n    \
+            <title>  A   title </title>\n    <script type=\"module\">\n  let a = 1;\n    \
+            </script>\n  </head>\n  <body>\n    <p title=\"a  >  b\">Some    text</p>\n    \
+            <PRE>  keep\n    this  </PRE>\n  </body>\n</html>\n";
+
+        assert_eq!(
+            minify_html(html),
+            "<!doctype html>\n<html>\n<head>\n<title> A title </title>\n\
+             <script type=\"module\">\n  let a = 1;\n    </script>\n</head>\n<body>\n\
+             <p title=\"a  >  b\">Some text</p>\n<PRE>  keep\n    this  </PRE>\n</body>\n</html>"
+        );
+    }
+
+    #[test]
+    fn test_conditional_comments() {
+        let html = "<!--[if IE]><p>Old</p><![endif]--><!-- gone --><p>New</p>";
+
+        assert_eq!(
+            minify_html(html),
+            "<!--[if IE]><p>Old</p><![endif]--><p>New</p>"
+        );
+    }
+
+    #[test]
+    fn test_minify() {
+        assert_eq!(
+            Minifiable::Css
+                .minify(b"p {\n  color: red;\n}\n")
+                .as_deref(),
+            Some(&b"p{color:red;}"[..])
+        );
+        assert_eq!(Minifiable::Html.minify(&[0xff, 0xfe]), None);
+        assert_eq!(
+            Minifiable::from_path(Path::new("app.MJS")),
+            Some(Minifiable::JavaScript)
+        );
+        assert_eq!(Minifiable::from_path(Path::new("image.png")), None);
+    }
+}
@@ -0,0 +1,147 @@
+//! Locally trusted certificates via mkcert
+//!
+//! mkcert manages a local CA that is trusted by the system and the browsers.
+//! `srvr trust` installs that CA when needed and mints a certificate, with
+//! `--tls-mkcert` a certificate is minted on start and served right away.
+//! Certificates are valid for `localhost`, the loopback addresses and the LAN
+//! address of this machine, so other devices on the network can use it too.
+
+use std::net::IpAddr;
+use std::net::Ipv4Addr;
+use std::net::UdpSocket;
+use std::path::PathBuf;
+use std::process::Command;
+
+/// Name of the mkcert executable, it should be in the `PATH`
+const MKCERT: &str = "mkcert";
+
+/// Name of the directory (in the system temp dir) the certificates are stored in
+const CERTIFICATE_DIR_NAME: &str = "srvr-mkcert";
+
+#[derive(Debug, thiserror::Error)]
+pub enum MkcertError {
+    #[error("Could not run mkcert, is it installed? {0}")]
+    NotInstalled(std::io::Error),
+
+    #[error("No mkcert CA found in \"{0}\", install one with `srvr trust`")]
+    NoCa(PathBuf),
+
+    #[error("mkcert failed: {0}")]
+    Failed(String),
+
+    #[error("Could not create \"{0}\": {1}")]
+    Io(PathBuf, std::io::Error),
+}
+
+/// Certificate minted by mkcert
+#[derive(Debug)]
+pub struct MkcertCertificate {
+    pub cert: PathBuf,
+    pub key: PathBuf,
+
+    /// Hosts the certificate is valid for
+    pub hosts: Vec<String>,
+}
+
+/// Mint a certificate with the existing mkcert CA
+pub fn mint_certificate() -> Result<MkcertCertificate, MkcertError> {
+    let ca_root = PathBuf::from(mkcert(&["-CAROOT"])?.trim());
+
+    if !ca_root.join("rootCA.pem").is_file() {
+        return Err(MkcertError::NoCa(ca_root));
+    }
+
+    let dir = std::env::temp_dir().join(CERTIFICATE_DIR_NAME);
+    std::fs::create_dir_all(&dir).map_err(|err| MkcertError::Io(dir.clone(), err))?;
+
+    let certificate = MkcertCertificate {
+        cert: dir.join("cert.pem"),
+        key: dir.join("key.pem"),
+        hosts: hosts(),
+    };
+
+    let mut args = vec![
+        "-cert-file".to_string(),
+        certificate.cert.to_string_lossy().into_owned(),
+        "-key-file".to_string(),
+        certificate.key.to_string_lossy().into_owned(),
+    ];
+    args.extend(certificate.hosts.iter().cloned());
+
+    mkcert(&args)?;
+
+    Ok(certificate)
+}
+
+/// Install the mkcert CA in the trust stores, and mint a certificate with it
+pub fn trust() -> anyhow::Result<()> {
+    // installing is a no-op when the CA is installed already
+    mkcert(&["-install"])?;
+
+    let certificate = mint_certificate()?;
+
+    println!("Certificate: {}", certificate.cert.display());
+    println!("Private key: {}", certificate.key.display());
+    println!("Valid for:   {}", certificate.hosts.join(", "));
+    println!("Serve with it using `srvr --tls-mkcert`");
+
+    Ok(())
+}
+
+/// Run mkcert, returns its output
+fn mkcert<S: AsRef<str>>(args: &[S]) -> Result<String, MkcertError> {
+    let output = Command::new(MKCERT)
+        .args(args.iter().map(AsRef::as_ref))
+        .output()
+        .map_err(MkcertError::NotInstalled)?;
+
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        return Err(MkcertError::Failed(stderr.trim().to_string()));
+    }
+
+    Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+/// Hosts to mint a certificate for
+fn hosts() -> Vec<String> {
+    let mut hosts = vec![
+        "localhost".to_string(),
+        "127.0.0.1".to_string(),
+        "::1".to_string(),
+    ];
+
+    if let Some(lan_ip) = lan_ip() {
+        hosts.push(lan_ip.to_string());
+    }
+
+    hosts
+}
+
+/// Address of this machine on the LAN, the one used for outgoing traffic
+fn lan_ip() -> Option<IpAddr> {
+    // connecting a UDP socket only picks a route, nothing is sent
+    let socket = UdpSocket::bind((Ipv4Addr::UNSPECIFIED, 0)).ok()?;
+    socket.connect((Ipv4Addr::new(192, 0, 2, 1), 80)).ok()?;
+
+    Some(socket.local_addr().ok()?.ip()).filter(|ip| !ip.is_loopback())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_hosts() {
+        let hosts = hosts();
+
+        assert_eq!(hosts[..3], ["localhost", "127.0.0.1", "::1"]);
+
+        // the LAN address, when there is one
+        for host in &hosts[3..] {
+            let ip = host.parse::<IpAddr>().expect("An IP address");
+            assert!(!ip.is_loopback());
+        }
+        assert!(hosts.len() <= 4);
+    }
+}
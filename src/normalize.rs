@@ -0,0 +1,146 @@
+//! URL normalization
+//!
+//! Duplicate slashes and dot segments are removed from the path of a
+//! request, so `/a//b/./c` and `/a/b/c` are handled the same way.
+
+use axum::extract::Request;
+use axum::extract::State;
+use axum::http::header::LOCATION;
+use axum::http::uri::PathAndQuery;
+use axum::http::HeaderValue;
+use axum::http::StatusCode;
+use axum::http::Uri;
+use axum::middleware::Next;
+use axum::response::IntoResponse;
+use axum::response::Response;
+
+use crate::app::ServerState;
+
+/// Normalize the path of a URL
+///
+/// Returns `None` when the path tries to go above the root via `..`
+pub fn normalize_path(path: &str) -> Option<String> {
+    let mut segments = vec![];
+
+    for segment in path.split('/') {
+        match segment {
+            "" | "." => {}
+            ".." => {
+                segments.pop()?;
+            }
+            segment => segments.push(segment),
+        }
+    }
+
+    let has_trailing_slash = !segments.is_empty()
+        && (path.ends_with('/') || path.ends_with("/.") || path.ends_with("/.."));
+
+    let mut normalized = format!("/{}", segments.join("/"));
+
+    if has_trailing_slash {
+        normalized.push('/');
+    }
+
+    Some(normalized)
+}
+
+/// Replace the path of the URI, keeping the query
+pub fn with_path(uri: &Uri, path: &str) -> Option<Uri> {
+    let path_and_query = match uri.query() {
+        Some(query) => format!("{path}?{query}"),
+        None => path.to_string(),
+    };
+
+    let mut parts = uri.clone().into_parts();
+    parts.path_and_query = Some(path_and_query.parse::<PathAndQuery>().ok()?);
+
+    Uri::from_parts(parts).ok()
+}
+
+/// Middleware that normalizes the path of incoming requests
+///
+/// Depending on the config the client is redirected to the normalized URL,
+/// otherwise the request is handled as if the normalized URL was requested
+pub async fn normalize(
+    State(state): State<ServerState>,
+    mut request: Request,
+    next: Next,
+) -> Response {
+    let uri = request.uri();
+
+    // paths going above the root are left alone, they are rejected later on
+    let Some(path) = normalize_path(uri.path()) else {
+        return next.run(request).await;
+    };
+
+    if path == uri.path() {
+        return next.run(request).await;
+    }
+
+    let Some(normalized) = with_path(uri, &path) else {
+        return next.run(request).await;
+    };
+
+    tracing::trace!("Normalized {uri} to {normalized}");
+
+    if state.config.normalize_redirect {
+        let location = normalized
+            .path_and_query()
+            .map_or("/", PathAndQuery::as_str)
+            .to_string();
+
+        if let Ok(location) = HeaderValue::from_str(&location) {
+            return (StatusCode::MOVED_PERMANENTLY, [(LOCATION, location)]).into_response();
+        }
+    }
+
+    *request.uri_mut() = normalized;
+
+    next.run(request).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_already_normalized() {
+        assert_eq!(normalize_path("/").as_deref(), Some("/"));
+        assert_eq!(normalize_path("/a/b/c").as_deref(), Some("/a/b/c"));
+        assert_eq!(normalize_path("/a/b/").as_deref(), Some("/a/b/"));
+    }
+
+    #[test]
+    fn test_duplicate_slashes() {
+        assert_eq!(normalize_path("//").as_deref(), Some("/"));
+        assert_eq!(normalize_path("/a//b///c").as_deref(), Some("/a/b/c"));
+        assert_eq!(normalize_path("/a/b//").as_deref(), Some("/a/b/"));
+    }
+
+    #[test]
+    fn test_dot_segments() {
+        assert_eq!(normalize_path("/a/./b/./c").as_deref(), Some("/a/b/c"));
+        assert_eq!(normalize_path("/a/b/../c").as_deref(), Some("/a/c"));
+        assert_eq!(normalize_path("/a/b/..").as_deref(), Some("/a/"));
+        assert_eq!(normalize_path("/a/.").as_deref(), Some("/a/"));
+        assert_eq!(normalize_path("/a/..").as_deref(), Some("/"));
+    }
+
+    #[test]
+    fn test_above_root() {
+        assert_eq!(normalize_path("/.."), None);
+        assert_eq!(normalize_path("/a/../../etc/passwd"), None);
+    }
+
+    #[test]
+    fn test_with_path_keeps_query() {
+        let uri = Uri::from_static("/a//b?c=d");
+
+        assert_eq!(
+            with_path(&uri, "/a/b")
+                .map(|uri| uri.to_string())
+                .as_deref(),
+            Some("/a/b?c=d")
+        );
+    }
+}
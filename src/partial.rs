@@ -0,0 +1,456 @@
+//! Partial content (range request) support
+//!
+//! See <https://developer.mozilla.org/en-US/docs/Web/HTTP/Range_requests>
+
+use std::io::Cursor;
+use std::io::SeekFrom;
+use std::ops::Bound;
+use std::ops::RangeInclusive;
+use std::path::Path;
+use std::pin::Pin;
+
+use axum::body::Body;
+use axum::http::HeaderValue;
+use axum_extra::body::AsyncReadBody;
+use axum_extra::headers::ETag;
+use axum_extra::headers::IfRange;
+use axum_extra::headers::LastModified;
+use axum_extra::headers::Range;
+use httpdate::HttpDate;
+use tokio::fs::File;
+use tokio::io::AsyncRead;
+use tokio::io::AsyncReadExt;
+use tokio::io::AsyncSeekExt;
+
+use crate::file_cache::FileCacheEntryContent;
+
+/// Maximum number of ranges in a request, more ranges get the full content
+const MAX_RANGES: usize = 16;
+
+/// Outcome of processing the `range` header of a request
+#[derive(Debug, PartialEq, Eq)]
+pub enum PartialContent {
+    /// No (usable) range requested, serve the full content
+    Full,
+
+    /// Serve only the given (inclusive) byte range
+    Partial(RangeInclusive<u64>),
+
+    /// Serve multiple byte ranges, as a `multipart/byteranges` body
+    Multipart(Vec<RangeInclusive<u64>>),
+
+    /// None of the requested ranges can be satisfied
+    Unsatisfiable,
+}
+
+/// Determine which part of the content should be served
+///
+/// Overlapping and adjacent ranges are merged, a request for too many ranges
+/// gets the full content. A range request is ignored when the `if-range`
+/// header indicates the client has an outdated version
+pub fn process_range(
+    range: Option<&Range>,
+    if_range: Option<&IfRange>,
+    last_modified: HttpDate,
+    etag: &ETag,
+    content_length: u64,
+) -> PartialContent {
+    let Some(range) = range else {
+        return PartialContent::Full;
+    };
+
+    if let Some(if_range) = if_range {
+        let last_modified = LastModified::from(std::time::SystemTime::from(last_modified));
+
+        if if_range.is_modified(Some(etag), Some(&last_modified)) {
+            tracing::trace!("Content changed since if-range, serving full content");
+            return PartialContent::Full;
+        }
+    }
+
+    let mut ranges = range
+        .satisfiable_ranges(content_length)
+        .filter_map(|bounds| to_inclusive(bounds, content_length))
+        .collect::<Vec<_>>();
+
+    if ranges.len() > MAX_RANGES {
+        tracing::trace!("Too many ranges requested, serving full content");
+        return PartialContent::Full;
+    }
+
+    ranges = merge_ranges(ranges);
+
+    match ranges.len() {
+        0 => PartialContent::Unsatisfiable,
+        1 => PartialContent::Partial(ranges.remove(0)),
+        _ => PartialContent::Multipart(ranges),
+    }
+}
+
+/// Merge overlapping and adjacent ranges, the result is sorted
+fn merge_ranges(mut ranges: Vec<RangeInclusive<u64>>) -> Vec<RangeInclusive<u64>> {
+    ranges.sort_by_key(|range| *range.start());
+
+    let mut merged: Vec<RangeInclusive<u64>> = Vec::with_capacity(ranges.len());
+
+    for range in ranges {
+        match merged.last_mut() {
+            Some(last) if *range.start() <= last.end().saturating_add(1) => {
+                *last = *last.start()..=*last.end().max(range.end());
+            }
+            _ => merged.push(range),
+        }
+    }
+
+    merged
+}
+
+/// Convert range bounds to an inclusive range within the content
+fn to_inclusive(
+    (start, end): (Bound<u64>, Bound<u64>),
+    content_length: u64,
+) -> Option<RangeInclusive<u64>> {
+    let last = content_length.checked_sub(1)?;
+
+    let start = match start {
+        Bound::Included(start) => start,
+        Bound::Excluded(start) => start.checked_add(1)?,
+        Bound::Unbounded => 0,
+    };
+
+    let end = match end {
+        Bound::Included(end) => end.min(last),
+        Bound::Excluded(end) => end.checked_sub(1)?.min(last),
+        Bound::Unbounded => last,
+    };
+
+    (start <= end).then_some(start..=end)
+}
+
+/// Length of an inclusive range
+#[inline]
+pub fn range_length(range: &RangeInclusive<u64>) -> u64 {
+    range.end() - range.start() + 1
+}
+
+/// Value for the `content-range` header of a partial response
+pub fn content_range(range: &RangeInclusive<u64>, content_length: u64) -> HeaderValue {
+    HeaderValue::from_str(&format!(
+        "bytes {}-{}/{content_length}",
+        range.start(),
+        range.end()
+    ))
+    .expect("A valid content-range header value")
+}
+
+/// Value for the `content-range` header of an unsatisfiable range response
+pub fn unsatisfiable_content_range(content_length: u64) -> HeaderValue {
+    HeaderValue::from_str(&format!("bytes */{content_length}"))
+        .expect("A valid content-range header value")
+}
+
+/// Create a body with only the requested range of the content
+pub async fn range_body(
+    content: FileCacheEntryContent,
+    content_path: &Path,
+    range: &RangeInclusive<u64>,
+) -> std::io::Result<Body> {
+    match content {
+        FileCacheEntryContent::Cached(content) => {
+            let start = usize::try_from(*range.start()).expect("Valid u64 -> usize conversion");
+            let end = usize::try_from(*range.end()).expect("Valid u64 -> usize conversion");
+
+            Ok(Body::from(content[start..=end].to_vec()))
+        }
+
+        FileCacheEntryContent::File => {
+            let mut file = File::open(content_path).await?;
+            file.seek(SeekFrom::Start(*range.start())).await?;
+
+            Ok(Body::new(AsyncReadBody::new(
+                file.take(range_length(range)),
+            )))
+        }
+    }
+}
+
+/// Body of a response with multiple ranges, each one in its own part with the
+/// content type and the range it covers
+pub struct MultipartRanges {
+    boundary: String,
+    parts: Vec<(String, RangeInclusive<u64>)>,
+}
+
+impl MultipartRanges {
+    pub fn new(
+        ranges: Vec<RangeInclusive<u64>>,
+        content_type: Option<&HeaderValue>,
+        content_length: u64,
+    ) -> Self {
+        let boundary = boundary();
+        let content_type = content_type
+            .and_then(|content_type| content_type.to_str().ok())
+            .map(|content_type| format!("Content-Type: {content_type}\r\n"))
+            .unwrap_or_default();
+
+        let parts = ranges
+            .into_iter()
+            .map(|range| {
+                let head = format!(
+                    "\r\n--{boundary}\r\n{content_type}Content-Range: bytes {}-{}/{content_length}\r\n\r\n",
+                    range.start(),
+                    range.end()
+                );
+
+                (head, range)
+            })
+            .collect();
+
+        Self { boundary, parts }
+    }
+
+    /// Value for the `content-type` header of the response
+    pub fn content_type(&self) -> HeaderValue {
+        HeaderValue::from_str(&format!("multipart/byteranges; boundary={}", self.boundary))
+            .expect("A valid content-type header value")
+    }
+
+    /// Length of the body, including the headers of the parts
+    pub fn content_length(&self) -> u64 {
+        let parts = self
+            .parts
+            .iter()
+            .map(|(head, range)| head.len() as u64 + range_length(range))
+            .sum::<u64>();
+
+        parts + self.tail().len() as u64
+    }
+
+    /// Create the body, files are streamed part by part
+    pub async fn into_body(
+        self,
+        content: FileCacheEntryContent,
+        content_path: &Path,
+    ) -> std::io::Result<Body> {
+        let tail = self.tail();
+
+        match content {
+            FileCacheEntryContent::Cached(content) => {
+                let mut body = vec![];
+
+                for (head, range) in self.parts {
+                    let start =
+                        usize::try_from(*range.start()).expect("Valid u64 -> usize conversion");
+                    let end = usize::try_from(*range.end()).expect("Valid u64 -> usize conversion");
+
+                    body.extend_from_slice(head.as_bytes());
+                    body.extend_from_slice(&content[start..=end]);
+                }
+
+                body.extend_from_slice(tail.as_bytes());
+
+                Ok(Body::from(body))
+            }
+
+            FileCacheEntryContent::File => {
+                let mut reader: Pin<Box<dyn AsyncRead + Send>> = Box::pin(tokio::io::empty());
+
+                for (head, range) in self.parts {
+                    let mut file = File::open(content_path).await?;
+                    file.seek(SeekFrom::Start(*range.start())).await?;
+
+                    let part = Cursor::new(head).chain(file.take(range_length(&range)));
+                    reader = Box::pin(reader.chain(part));
+                }
+
+                reader = Box::pin(reader.chain(Cursor::new(tail)));
+
+                Ok(Body::new(AsyncReadBody::new(reader)))
+            }
+        }
+    }
+
+    /// End of the body, after the last part
+    fn tail(&self) -> String {
+        format!("\r\n--{}--\r\n", self.boundary)
+    }
+}
+
+/// Random boundary between the parts, it is unlikely to be in the content
+fn boundary() -> String {
+    let mut bytes = [0; 8];
+
+    if let Err(err) = getrandom::getrandom(&mut bytes) {
+        tracing::warn!("Could not generate a random boundary: {err}");
+    }
+
+    format!("srvr-{:016x}", u64::from_le_bytes(bytes))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+    use std::time::Duration;
+    use std::time::SystemTime;
+
+    use super::*;
+
+    fn range(value: &'static str) -> Range {
+        use axum_extra::headers::Header;
+
+        let value = HeaderValue::from_static(value);
+        Range::decode(&mut std::iter::once(&value)).expect("A valid range header")
+    }
+
+    fn now() -> HttpDate {
+        HttpDate::from(SystemTime::now())
+    }
+
+    fn etag() -> ETag {
+        "\"abc\"".parse().expect("A valid ETag")
+    }
+
+    #[test]
+    fn test_no_range() {
+        assert_eq!(
+            process_range(None, None, now(), &etag(), 100),
+            PartialContent::Full
+        );
+    }
+
+    #[test]
+    fn test_simple_range() {
+        let range = range("bytes=0-9");
+
+        assert_eq!(
+            process_range(Some(&range), None, now(), &etag(), 100),
+            PartialContent::Partial(0..=9)
+        );
+    }
+
+    #[test]
+    fn test_open_ended_range() {
+        let range = range("bytes=90-");
+
+        assert_eq!(
+            process_range(Some(&range), None, now(), &etag(), 100),
+            PartialContent::Partial(90..=99)
+        );
+    }
+
+    #[test]
+    fn test_suffix_range() {
+        let range = range("bytes=-10");
+
+        assert_eq!(
+            process_range(Some(&range), None, now(), &etag(), 100),
+            PartialContent::Partial(90..=99)
+        );
+    }
+
+    #[test]
+    fn test_range_past_end_is_clamped() {
+        let range = range("bytes=50-500");
+
+        assert_eq!(
+            process_range(Some(&range), None, now(), &etag(), 100),
+            PartialContent::Partial(50..=99)
+        );
+    }
+
+    #[test]
+    fn test_unsatisfiable_range() {
+        let range = range("bytes=100-200");
+
+        assert_eq!(
+            process_range(Some(&range), None, now(), &etag(), 100),
+            PartialContent::Unsatisfiable
+        );
+    }
+
+    #[test]
+    fn test_outdated_if_range() {
+        let range = range("bytes=0-9");
+        let last_modified = now();
+        let if_range = IfRange::date(SystemTime::from(last_modified) - Duration::from_secs(60));
+
+        assert_eq!(
+            process_range(Some(&range), Some(&if_range), last_modified, &etag(), 100),
+            PartialContent::Full
+        );
+    }
+
+    #[test]
+    fn test_if_range_etag() {
+        let range = range("bytes=0-9");
+        let current = IfRange::etag(etag());
+        let outdated = IfRange::etag("\"def\"".parse().expect("A valid ETag"));
+
+        assert_eq!(
+            process_range(Some(&range), Some(&current), now(), &etag(), 100),
+            PartialContent::Partial(0..=9)
+        );
+        assert_eq!(
+            process_range(Some(&range), Some(&outdated), now(), &etag(), 100),
+            PartialContent::Full
+        );
+    }
+
+    #[test]
+    fn test_multiple_ranges() {
+        let range = range("bytes=50-59, 0-9");
+
+        assert_eq!(
+            process_range(Some(&range), None, now(), &etag(), 100),
+            PartialContent::Multipart(vec![0..=9, 50..=59])
+        );
+    }
+
+    #[test]
+    fn test_overlapping_ranges_are_merged() {
+        let overlapping = range("bytes=0-9, 5-19, 20-29, -10");
+
+        assert_eq!(
+            process_range(Some(&overlapping), None, now(), &etag(), 100),
+            PartialContent::Multipart(vec![0..=29, 90..=99])
+        );
+
+        let adjacent = range("bytes=0-49, 50-");
+
+        assert_eq!(
+            process_range(Some(&adjacent), None, now(), &etag(), 100),
+            PartialContent::Partial(0..=99)
+        );
+    }
+
+    #[tokio::test]
+    async fn test_multipart_body() {
+        let content = FileCacheEntryContent::Cached(Arc::new(b"0123456789".to_vec()));
+        let multipart = MultipartRanges::new(
+            vec![0..=1, 8..=9],
+            Some(&HeaderValue::from_static("text/plain")),
+            10,
+        );
+
+        let boundary = multipart.boundary.clone();
+        let content_length = multipart.content_length();
+
+        let body = multipart
+            .into_body(content, Path::new("unused"))
+            .await
+            .expect("A body from the cached content");
+        let body = axum::body::to_bytes(body, usize::MAX)
+            .await
+            .expect("A complete body");
+
+        assert_eq!(body.len() as u64, content_length);
+        assert_eq!(
+            body,
+            format!(
+                "\r\n--{boundary}\r\nContent-Type: text/plain\r\nContent-Range: bytes 0-1/10\r\n\r\n01\
+                 \r\n--{boundary}\r\nContent-Type: text/plain\r\nContent-Range: bytes 8-9/10\r\n\r\n89\
+                 \r\n--{boundary}--\r\n"
+            )
+        );
+    }
+}
@@ -0,0 +1,183 @@
+//! Path patterns of the Netlify-style `_redirects` and `_headers` files
+//!
+//! A pattern is a path with `:name` placeholders, which match a single
+//! segment, and an optional splat (`*`) as its last segment, which matches the
+//! rest of the path, ie `/news/:year/*`. A trailing slash makes no difference.
+
+use std::fmt;
+use std::str::FromStr;
+
+#[derive(Debug, thiserror::Error)]
+pub enum PathPatternError {
+    #[error("The path should start with a \"/\"")]
+    MissingSlash,
+
+    #[error("A splat is only supported as the last segment of the path")]
+    MisplacedSplat,
+}
+
+/// Segment of a pattern
+#[derive(Clone, Debug, PartialEq, Eq)]
+enum Segment {
+    Literal(String),
+
+    /// `:name`, matches a single segment
+    Placeholder(String),
+
+    /// `*`, matches the rest of the path
+    Splat,
+}
+
+/// Pattern to match request paths with
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PathPattern {
+    segments: Vec<Segment>,
+}
+
+impl PathPattern {
+    /// Pattern that matches every path, `/*`
+    pub fn any() -> Self {
+        Self {
+            segments: vec![Segment::Splat],
+        }
+    }
+
+    /// Values of the placeholders and the splat (as `splat`) when the path
+    /// matches
+    pub fn captures<'a>(&'a self, path: &'a str) -> Option<Vec<(&'a str, String)>> {
+        let segments = split_segments(path);
+        let mut captures = vec![];
+
+        for (index, segment) in self.segments.iter().enumerate() {
+            match segment {
+                Segment::Splat => {
+                    captures.push(("splat", segments.get(index..)?.join("/")));
+                    return Some(captures);
+                }
+                Segment::Literal(literal) => {
+                    if segments.get(index)? != literal {
+                        return None;
+                    }
+                }
+                Segment::Placeholder(name) => {
+                    captures.push((name, (*segments.get(index)?).to_string()));
+                }
+            }
+        }
+
+        (segments.len() == self.segments.len()).then_some(captures)
+    }
+
+    /// Check if the path matches the pattern
+    pub fn matches(&self, path: &str) -> bool {
+        self.captures(path).is_some()
+    }
+}
+
+impl FromStr for PathPattern {
+    type Err = PathPatternError;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let value = value
+            .strip_prefix('/')
+            .ok_or(PathPatternError::MissingSlash)?;
+
+        let segments = split_segments(value)
+            .into_iter()
+            .map(|segment| match segment {
+                "*" => Segment::Splat,
+                segment => segment.strip_prefix(':').map_or_else(
+                    || Segment::Literal(segment.to_string()),
+                    |name| Segment::Placeholder(name.to_string()),
+                ),
+            })
+            .collect::<Vec<_>>();
+
+        if segments
+            .iter()
+            .rev()
+            .skip(1)
+            .any(|segment| *segment == Segment::Splat)
+        {
+            return Err(PathPatternError::MisplacedSplat);
+        }
+
+        Ok(Self { segments })
+    }
+}
+
+impl fmt::Display for PathPattern {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.segments.is_empty() {
+            return f.write_str("/");
+        }
+
+        for segment in &self.segments {
+            match segment {
+                Segment::Literal(literal) => write!(f, "/{literal}")?,
+                Segment::Placeholder(name) => write!(f, "/:{name}")?,
+                Segment::Splat => f.write_str("/*")?,
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Segments of a path, a trailing slash makes no difference
+fn split_segments(path: &str) -> Vec<&str> {
+    let path = path.trim_matches('/');
+
+    if path.is_empty() {
+        vec![]
+    } else {
+        path.split('/').collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pattern(value: &str) -> PathPattern {
+        value.parse().expect("A valid pattern")
+    }
+
+    #[test]
+    fn test_parse() {
+        assert!(matches!(
+            "news".parse::<PathPattern>(),
+            Err(PathPatternError::MissingSlash)
+        ));
+        assert!(matches!(
+            "/*/news".parse::<PathPattern>(),
+            Err(PathPatternError::MisplacedSplat)
+        ));
+    }
+
+    #[test]
+    fn test_captures() {
+        let news = pattern("/news/:year/*");
+
+        assert_eq!(
+            news.captures("/news/2024/01/hello"),
+            Some(vec![
+                ("year", String::from("2024")),
+                ("splat", String::from("01/hello"))
+            ])
+        );
+        assert_eq!(
+            news.captures("/news/2024/"),
+            Some(vec![
+                ("year", String::from("2024")),
+                ("splat", String::new())
+            ])
+        );
+        assert!(!news.matches("/news"));
+        assert!(!news.matches("/blog/2024/01"));
+
+        assert!(pattern("/about/").matches("/about"));
+        assert!(!pattern("/about").matches("/about/team"));
+        assert!(pattern("/*").matches("/"));
+    }
+}
@@ -1,15 +1,60 @@
 use std::ffi::OsStr;
 use std::ffi::OsString;
+use std::fs::Metadata;
 use std::path::Path;
 use std::path::PathBuf;
 
+use axum::http::header::ACCEPT;
+use axum::http::HeaderMap;
+use axum::http::HeaderName;
+use axum::http::Method;
 use axum::http::Uri;
+use percent_encoding::percent_decode_str;
 
 use crate::encoding::ClientEncodingSupport;
 use crate::encoding::Encoding;
+use crate::trailing_slash::TrailingSlash;
 
-/// Cache control header value for no-cache
-const CACHE_CONTROL_NO_CACHE: &str = "no-cache";
+/// Fetch metadata header that indicates the mode of the request
+///
+/// See <https://developer.mozilla.org/en-US/docs/Web/HTTP/Headers/Sec-Fetch-Mode>
+const SEC_FETCH_MODE: HeaderName = HeaderName::from_static("sec-fetch-mode");
+
+/// Name of the file served for paths that could not be found
+const NOT_FOUND_FILE_NAME: &str = "404.html";
+
+/// Name of the file served for a directory
+pub const INDEX_FILE_NAME: &str = "index.html";
+
+/// Check if the request looks like a navigation to a page
+///
+/// Only navigations should get the fallback file, a missing script or
+/// stylesheet should result in a 404 instead of a HTML document
+pub fn is_navigation_request(method: &Method, headers: &HeaderMap, uri: &Uri) -> bool {
+    if method != Method::GET && method != Method::HEAD {
+        return false;
+    }
+
+    let has_extension = uri
+        .path()
+        .rsplit('/')
+        .next()
+        .is_some_and(|segment| segment.contains('.'));
+
+    if has_extension {
+        return false;
+    }
+
+    if let Some(mode) = headers.get(SEC_FETCH_MODE) {
+        return mode == "navigate";
+    }
+
+    headers.get(ACCEPT).map_or(true, |accept| {
+        accept
+            .to_str()
+            .is_ok_and(|accept| accept.contains("text/html"))
+    })
+}
 
 fn append_to_path(path: impl Into<OsString>, suffix: impl AsRef<OsStr>) -> PathBuf {
     let mut path = path.into();
@@ -21,7 +66,9 @@ fn append_to_path(path: impl Into<OsString>, suffix: impl AsRef<OsStr>) -> PathB
 pub struct PathToTry {
     path: PathBuf,
     encoding: Option<Encoding>,
-    cache_control: Option<&'static str>,
+
+    /// The fallback file, served for paths that could not be found
+    is_fallback: bool,
 }
 
 impl PathToTry {
@@ -44,52 +91,494 @@ impl PathToTry {
     }
 
     #[inline]
-    pub const fn cache_control(&self) -> Option<&'static str> {
-        self.cache_control
+    pub const fn is_fallback(&self) -> bool {
+        self.is_fallback
     }
 }
 
+/// Reason to skip a precompressed variant, in favor of the next path to try
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SidecarProblem {
+    /// Older than its original file, its content is outdated
+    Stale,
+
+    /// Its original file is gone, ie a leftover of a previous deploy
+    Orphan,
+}
+
+impl SidecarProblem {
+    /// Check a precompressed variant against its original file, which is
+    /// `None` when it does not exist
+    pub fn check(
+        sidecar: &Metadata,
+        original: Option<&Metadata>,
+        serve_orphans: bool,
+    ) -> Option<Self> {
+        let Some(original) = original.filter(|original| original.is_file()) else {
+            return (!serve_orphans).then_some(Self::Orphan);
+        };
+
+        match (sidecar.modified(), original.modified()) {
+            (Ok(modified), Ok(original_modified)) if modified < original_modified => {
+                Some(Self::Stale)
+            }
+            _ => None,
+        }
+    }
+
+    pub const fn describe(self) -> &'static str {
+        match self {
+            Self::Stale => "it is older than its original file",
+            Self::Orphan => "its original file is missing",
+        }
+    }
+}
+
+/// Extension tried for extensionless URLs, with clean URLs
+const CLEAN_URL_EXTENSION: &str = ".html";
+
+/// Check if the URL points at a directory, by its trailing slash
+pub fn is_dir_uri(uri: &Uri, trailing_slash: TrailingSlash) -> bool {
+    // with a trailing slash for files, the slash no longer means a directory
+    uri.path().ends_with('/') && trailing_slash != TrailingSlash::Add
+}
+
+/// All paths to try for the path in the base dir, `is_dir` when the URL or
+/// the path itself is a directory
 pub fn collect_paths_to_try(
     client_encoding_support: &ClientEncodingSupport,
     base_dir: &Path,
-    fallback_path: &Path,
-    uri: &Uri,
+    fallback_path: Option<&Path>,
     initial_path: PathBuf,
+    is_dir: bool,
+    clean_urls: bool,
 ) -> Vec<PathToTry> {
     let mut paths_to_try = vec![];
+    let mut path = base_dir
+        .join(initial_path)
+        .components()
+        .collect::<PathBuf>();
+    let mut candidates = vec![];
 
-    // empty request will never match anything, skip to fallback
-    if uri.path() != "/" {
-        let path = base_dir.join(initial_path);
+    // a directory is served by its index file, including the root
+    if is_dir {
+        path.push(INDEX_FILE_NAME);
+        candidates.push(path);
+    } else {
+        // with clean URLs, `/about` is served by `about.html`
+        let clean_path = (clean_urls
+            && path
+                .extension()
+                .map_or(true, |extension| extension != "html"))
+        .then(|| append_to_path(&path, CLEAN_URL_EXTENSION));
 
-        for encoding in client_encoding_support.supported_encodings() {
+        candidates.push(path);
+        candidates.extend(clean_path);
+    }
+
+    for path in candidates {
+        // a compressed file is served as is, ie `/backup.tar.gz` never gets a
+        // `backup.tar.gz.br` or a `Content-Encoding`
+        let encodings = if Encoding::from_path(&path).is_some() {
+            &[]
+        } else {
+            client_encoding_support.supported_encodings()
+        };
+
+        for encoding in encodings {
             paths_to_try.push(PathToTry {
                 path: path.clone(),
                 encoding: Some(*encoding),
-                cache_control: None,
+                is_fallback: false,
             });
         }
 
         paths_to_try.push(PathToTry {
             path,
             encoding: None,
-            cache_control: None,
+            is_fallback: false,
         });
     }
 
-    for encoding in client_encoding_support.supported_encodings() {
+    if let Some(fallback_path) = fallback_path {
+        for encoding in client_encoding_support.supported_encodings() {
+            paths_to_try.push(PathToTry {
+                path: fallback_path.to_path_buf(),
+                encoding: Some(*encoding),
+                is_fallback: true,
+            });
+        }
+
         paths_to_try.push(PathToTry {
             path: fallback_path.to_path_buf(),
-            encoding: Some(*encoding),
-            cache_control: Some(CACHE_CONTROL_NO_CACHE),
+            encoding: None,
+            is_fallback: true,
         });
     }
 
-    paths_to_try.push(PathToTry {
-        path: fallback_path.to_path_buf(),
-        encoding: None,
-        cache_control: None,
-    });
+    paths_to_try
+}
+
+/// URL of the directory, when the URL points at its index file explicitly
+///
+/// The last segment is compared decoded, so `/docs/index%2Ehtml` is an index
+/// as well, the query is kept as is
+pub fn index_location(uri: &Uri, clean_urls: bool) -> Option<String> {
+    let (directory, file_name) = uri.path().rsplit_once('/')?;
+    let file_name = percent_decode_str(file_name).decode_utf8().ok()?;
+
+    let is_index = file_name == INDEX_FILE_NAME
+        || (clean_urls && Some(&*file_name) == INDEX_FILE_NAME.strip_suffix(CLEAN_URL_EXTENSION));
+
+    if !is_index {
+        return None;
+    }
+
+    Some(match uri.query() {
+        Some(query) => format!("{directory}/?{query}"),
+        None => format!("{directory}/"),
+    })
+}
+
+/// Check if the URL path is hidden, ie one of its segments starts with a `.`
+///
+/// Paths within one of the exempt prefixes (ie `/.well-known`) are never hidden
+pub fn is_hidden_path(path: &str, exempt_prefixes: &[String]) -> bool {
+    let is_exempt = exempt_prefixes
+        .iter()
+        .map(|prefix| prefix.trim_matches('/'))
+        .filter(|prefix| !prefix.is_empty())
+        .any(|prefix| {
+            path.trim_start_matches('/')
+                .strip_prefix(prefix)
+                .is_some_and(|rest| rest.is_empty() || rest.starts_with('/'))
+        });
+
+    !is_exempt && path.split('/').any(|segment| segment.starts_with('.'))
+}
+
+/// Collect the `404.html` files to try, starting in the directory of the
+/// requested path and walking up to the base dir, or only the configured not
+/// found file
+pub fn collect_not_found_paths(
+    client_encoding_support: &ClientEncodingSupport,
+    base_dir: &Path,
+    not_found_path: Option<&Path>,
+    uri: &Uri,
+    initial_path: &Path,
+) -> Vec<PathToTry> {
+    let mut paths_to_try = vec![];
+
+    // a trailing slash means the requested path is a directory itself
+    let directory = if uri.path().ends_with('/') {
+        Some(initial_path)
+    } else {
+        initial_path.parent()
+    };
+
+    let paths = match not_found_path {
+        Some(not_found_path) => vec![not_found_path.to_path_buf()],
+        None => directory
+            .map_or_else(|| vec![Path::new("")], |d| d.ancestors().collect())
+            .into_iter()
+            .map(|directory| base_dir.join(directory).join(NOT_FOUND_FILE_NAME))
+            .collect(),
+    };
+
+    for path in paths {
+        for encoding in client_encoding_support.supported_encodings() {
+            paths_to_try.push(PathToTry {
+                path: path.clone(),
+                encoding: Some(*encoding),
+                is_fallback: false,
+            });
+        }
+
+        paths_to_try.push(PathToTry {
+            path,
+            encoding: None,
+            is_fallback: false,
+        });
+    }
 
     paths_to_try
 }
+
+#[cfg(test)]
+mod tests {
+    use axum::http::header::ACCEPT_ENCODING;
+    use axum::http::HeaderValue;
+
+    use super::*;
+
+    fn headers(accept: &'static str) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert(ACCEPT, HeaderValue::from_static(accept));
+        headers
+    }
+
+    #[test]
+    fn test_navigation_request() {
+        let html = headers("text/html,application/xhtml+xml,*/*;q=0.8");
+
+        assert!(is_navigation_request(
+            &Method::GET,
+            &html,
+            &Uri::from_static("/about")
+        ));
+        assert!(is_navigation_request(
+            &Method::GET,
+            &HeaderMap::new(),
+            &Uri::from_static("/about")
+        ));
+        assert!(!is_navigation_request(
+            &Method::POST,
+            &html,
+            &Uri::from_static("/about")
+        ));
+    }
+
+    #[test]
+    fn test_asset_request() {
+        assert!(!is_navigation_request(
+            &Method::GET,
+            &headers("*/*"),
+            &Uri::from_static("/assets/app.js")
+        ));
+        assert!(!is_navigation_request(
+            &Method::GET,
+            &headers("text/html"),
+            &Uri::from_static("/assets/app.js")
+        ));
+        assert!(!is_navigation_request(
+            &Method::GET,
+            &headers("image/avif,image/webp,*/*"),
+            &Uri::from_static("/about")
+        ));
+    }
+
+    #[test]
+    fn test_fetch_mode() {
+        let mut headers = headers("*/*");
+        headers.insert(SEC_FETCH_MODE, HeaderValue::from_static("navigate"));
+
+        assert!(is_navigation_request(
+            &Method::GET,
+            &headers,
+            &Uri::from_static("/about")
+        ));
+
+        headers.insert(SEC_FETCH_MODE, HeaderValue::from_static("cors"));
+
+        assert!(!is_navigation_request(
+            &Method::GET,
+            &headers,
+            &Uri::from_static("/about")
+        ));
+    }
+
+    #[test]
+    fn test_directory_index() {
+        let base_dir = Path::new("/srv");
+
+        let mut headers = HeaderMap::new();
+        headers.insert(ACCEPT_ENCODING, HeaderValue::from_static("gzip"));
+        let client_encoding_support =
+            ClientEncodingSupport::from_header_map(&headers, &Encoding::ALL);
+
+        let paths_to_try = collect_paths_to_try(
+            &client_encoding_support,
+            base_dir,
+            None,
+            PathBuf::from("docs"),
+            true,
+            false,
+        );
+
+        let content_paths = paths_to_try
+            .iter()
+            .map(PathToTry::content_path)
+            .collect::<Vec<_>>();
+        assert_eq!(
+            content_paths,
+            [
+                base_dir.join("docs/index.html.gz"),
+                base_dir.join("docs/index.html")
+            ]
+        );
+
+        let paths_to_try = collect_paths_to_try(
+            &ClientEncodingSupport::default(),
+            base_dir,
+            None,
+            PathBuf::new(),
+            true,
+            false,
+        );
+        assert_eq!(paths_to_try[0].path(), base_dir.join("index.html"));
+    }
+
+    #[test]
+    fn test_dir_uri() {
+        assert!(is_dir_uri(&Uri::from_static("/"), TrailingSlash::Ignore));
+        assert!(is_dir_uri(
+            &Uri::from_static("/docs/"),
+            TrailingSlash::Remove
+        ));
+        assert!(!is_dir_uri(
+            &Uri::from_static("/docs"),
+            TrailingSlash::Ignore
+        ));
+
+        // the trailing slash is canonical for files as well
+        assert!(!is_dir_uri(
+            &Uri::from_static("/about/"),
+            TrailingSlash::Add
+        ));
+    }
+
+    #[test]
+    fn test_compressed_file() {
+        let mut headers = HeaderMap::new();
+        headers.insert(ACCEPT_ENCODING, HeaderValue::from_static("gzip, br"));
+        let client_encoding_support =
+            ClientEncodingSupport::from_header_map(&headers, &Encoding::ALL);
+
+        let paths_to_try = collect_paths_to_try(
+            &client_encoding_support,
+            Path::new("/srv"),
+            None,
+            PathBuf::from("backup.tar.gz"),
+            false,
+            false,
+        );
+
+        let content_paths = paths_to_try
+            .iter()
+            .map(PathToTry::content_path)
+            .collect::<Vec<_>>();
+        assert_eq!(content_paths, [PathBuf::from("/srv/backup.tar.gz")]);
+    }
+
+    #[test]
+    fn test_not_found_paths() {
+        let paths_to_try = collect_not_found_paths(
+            &ClientEncodingSupport::default(),
+            Path::new("/srv"),
+            None,
+            &Uri::from_static("/docs/missing.html"),
+            Path::new("docs/missing.html"),
+        );
+
+        let paths = paths_to_try.iter().map(PathToTry::path).collect::<Vec<_>>();
+        assert_eq!(
+            paths,
+            [
+                PathBuf::from("/srv/docs/404.html"),
+                PathBuf::from("/srv/404.html")
+            ]
+        );
+
+        let paths_to_try = collect_not_found_paths(
+            &ClientEncodingSupport::default(),
+            Path::new("/srv"),
+            Some(Path::new("/errors/missing.html")),
+            &Uri::from_static("/docs/missing.html"),
+            Path::new("docs/missing.html"),
+        );
+
+        let paths = paths_to_try.iter().map(PathToTry::path).collect::<Vec<_>>();
+        assert_eq!(paths, [PathBuf::from("/errors/missing.html")]);
+    }
+
+    #[test]
+    fn test_clean_urls() {
+        let base_dir = Path::new("/srv");
+
+        let paths_to_try = collect_paths_to_try(
+            &ClientEncodingSupport::default(),
+            base_dir,
+            None,
+            PathBuf::from("about"),
+            false,
+            true,
+        );
+        let paths = paths_to_try.iter().map(PathToTry::path).collect::<Vec<_>>();
+        assert_eq!(paths, [base_dir.join("about"), base_dir.join("about.html")]);
+
+        let paths_to_try = collect_paths_to_try(
+            &ClientEncodingSupport::default(),
+            base_dir,
+            None,
+            PathBuf::from("about.html"),
+            false,
+            true,
+        );
+        assert_eq!(paths_to_try.len(), 1);
+
+        // the trailing slash is canonical for files as well
+        let paths_to_try = collect_paths_to_try(
+            &ClientEncodingSupport::default(),
+            base_dir,
+            None,
+            PathBuf::from("about/"),
+            false,
+            true,
+        );
+        let paths = paths_to_try.iter().map(PathToTry::path).collect::<Vec<_>>();
+        assert_eq!(paths, [base_dir.join("about"), base_dir.join("about.html")]);
+    }
+
+    #[test]
+    fn test_index_location() {
+        let location = |uri, clean_urls| index_location(&Uri::from_static(uri), clean_urls);
+
+        assert_eq!(location("/index.html", false).as_deref(), Some("/"));
+        assert_eq!(
+            location("/docs/index.html?page=2", false).as_deref(),
+            Some("/docs/?page=2")
+        );
+        assert_eq!(
+            location("/docs/index%2Ehtml", false).as_deref(),
+            Some("/docs/")
+        );
+        assert_eq!(location("/docs/index", true).as_deref(), Some("/docs/"));
+
+        assert_eq!(location("/docs/index", false), None);
+        assert_eq!(location("/docs/", false), None);
+        assert_eq!(location("/docs/index.html/", false), None);
+        assert_eq!(location("/docs/myindex.html", false), None);
+    }
+
+    #[test]
+    fn test_hidden_path() {
+        let no_exemptions = [];
+
+        assert!(is_hidden_path("/.env", &no_exemptions));
+        assert!(is_hidden_path("/.git/config", &no_exemptions));
+        assert!(is_hidden_path("/app/.secret/key", &no_exemptions));
+        assert!(is_hidden_path("/.well-known/security.txt", &no_exemptions));
+
+        assert!(!is_hidden_path("/", &no_exemptions));
+        assert!(!is_hidden_path("/index.html", &no_exemptions));
+        assert!(!is_hidden_path("/file.with.dots", &no_exemptions));
+    }
+
+    #[test]
+    fn test_hidden_path_exemptions() {
+        let well_known = [String::from("/.well-known")];
+
+        assert!(!is_hidden_path("/.well-known", &well_known));
+        assert!(!is_hidden_path(
+            "/.well-known/acme-challenge/token",
+            &well_known
+        ));
+        assert!(!is_hidden_path("/.well-known/.hidden", &well_known));
+        assert!(is_hidden_path("/.well-known-not/file", &well_known));
+        assert!(is_hidden_path("/.env", &well_known));
+        assert!(is_hidden_path("/app/.well-known/file", &well_known));
+
+        // an empty exemption disables the default one
+        assert!(is_hidden_path("/.well-known/file", &[String::new()]));
+    }
+}
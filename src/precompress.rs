@@ -0,0 +1,259 @@
+//! Ahead-of-time compression of the base dir
+//!
+//! `srvr precompress` writes a Brotli, Zstandard, gzip and deflate variant, or
+//! only the ones of `--encodings`, next to every compressible file, at the
+//! highest quality, so they are served without any compression on the fly.
+//! Variants that are newer than their file are kept, so running it again after
+//! a deploy only compresses the changed files. With `--watch` it keeps running,
+//! compressing files again as they change.
+
+use std::collections::BTreeSet;
+use std::num::NonZeroUsize;
+use std::path::Path;
+use std::path::PathBuf;
+use std::sync::atomic::AtomicUsize;
+use std::sync::atomic::Ordering;
+use std::sync::mpsc;
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::sync::PoisonError;
+use std::time::Duration;
+
+use clap::Args;
+use notify::EventKind;
+use notify::RecursiveMode;
+use notify::Watcher;
+
+use crate::app::ServerState;
+use crate::config::Config;
+use crate::encoding::Encoding;
+use crate::file_cache::content_type;
+use crate::selftest::collect_files;
+use crate::utils::format_byte_size;
+use crate::utils::wildcard_match;
+
+/// How long to wait for more changes, before compressing a batch of files
+const WATCH_DEBOUNCE: Duration = Duration::from_millis(100);
+
+/// Options of the precompressor
+#[derive(Args, Clone, Debug)]
+pub struct PrecompressConfig {
+    /// Only compress files with a name matching one of the patterns, ie `*.js`,
+    /// with `*` and `?` as wildcards
+    #[arg(long, value_name = "PATTERN")]
+    include: Vec<String>,
+
+    /// Number of files to compress at the same time, defaults to the number of CPUs
+    #[arg(long, short)]
+    jobs: Option<NonZeroUsize>,
+
+    /// Keep running, compressing files again as they change
+    #[arg(long)]
+    watch: bool,
+}
+
+/// Totals of a precompress run
+#[derive(Debug, Default)]
+struct Totals {
+    written: usize,
+    up_to_date: usize,
+    failed: usize,
+    saved: u64,
+}
+
+/// Write the compressed variants of all compressible files in the base dir
+pub async fn precompress(
+    config: Config,
+    precompress_config: PrecompressConfig,
+) -> anyhow::Result<()> {
+    let state = Arc::new(ServerState::from_config(config));
+
+    let mut files = vec![];
+    collect_files(&state.config.base_dir, &mut files)?;
+
+    let files = files
+        .into_iter()
+        .filter(|file| is_candidate(&state, &precompress_config.include, file))
+        .collect::<Vec<_>>();
+
+    let jobs = precompress_config
+        .jobs
+        .or_else(|| std::thread::available_parallelism().ok())
+        .map_or(1, NonZeroUsize::get);
+
+    let totals = {
+        let state = Arc::clone(&state);
+
+        // compressing is CPU bound, every job gets a thread of its own
+        tokio::task::spawn_blocking(move || compress_files(&state.config, &files, jobs)).await?
+    };
+
+    print_totals(&totals);
+
+    if precompress_config.watch {
+        return tokio::task::spawn_blocking(move || {
+            watch(&state, &precompress_config.include, jobs)
+        })
+        .await?;
+    }
+
+    if totals.failed > 0 {
+        anyhow::bail!("{} variant(s) could not be written", totals.failed);
+    }
+
+    Ok(())
+}
+
+/// Keep compressing the files in the base dir as they change, until the process
+/// is stopped
+fn watch(state: &ServerState, include: &[String], jobs: usize) -> anyhow::Result<()> {
+    let (events_tx, events_rx) = mpsc::channel();
+
+    let mut watcher = notify::recommended_watcher(events_tx)?;
+    watcher.watch(&state.config.base_dir, RecursiveMode::Recursive)?;
+
+    println!("Watching {} for changes", state.config.base_dir.display());
+
+    while let Ok(event) = events_rx.recv() {
+        let mut files = BTreeSet::new();
+        add_changed_files(event, &mut files);
+
+        // a bundler writes many files at once, compress them in a single batch
+        while let Ok(event) = events_rx.recv_timeout(WATCH_DEBOUNCE) {
+            add_changed_files(event, &mut files);
+        }
+
+        let files = files
+            .into_iter()
+            .filter(|file| file.is_file() && is_candidate(state, include, file))
+            .collect::<Vec<_>>();
+
+        let totals = compress_files(&state.config, &files, jobs);
+
+        if totals.written > 0 || totals.failed > 0 {
+            print_totals(&totals);
+        }
+    }
+
+    Ok(())
+}
+
+/// Add the files of a watch event that could have new content
+fn add_changed_files(event: notify::Result<notify::Event>, files: &mut BTreeSet<PathBuf>) {
+    match event {
+        Ok(event) if matches!(event.kind, EventKind::Create(_) | EventKind::Modify(_)) => {
+            files.extend(event.paths);
+        }
+        Ok(_) => {}
+        Err(err) => tracing::warn!("Could not watch for changes: {err}"),
+    }
+}
+
+/// Check if the file should get compressed variants, compressed files (sidecars
+/// or not) and files that are never served are skipped
+fn is_candidate(state: &ServerState, include: &[String], file: &Path) -> bool {
+    let relative_path = file.strip_prefix(&state.config.base_dir).unwrap_or(file);
+
+    Encoding::from_path(file).is_none()
+        && state.is_servable(file)
+        && !state.is_hidden(&format!("/{}", relative_path.display()))
+        && is_included(include, file)
+}
+
+/// Compress the files on `jobs` threads
+fn compress_files(config: &Config, files: &[PathBuf], jobs: usize) -> Totals {
+    let next = AtomicUsize::new(0);
+    let totals = Mutex::new(Totals::default());
+
+    std::thread::scope(|scope| {
+        for _ in 0..jobs.min(files.len()) {
+            scope.spawn(|| {
+                while let Some(file) = files.get(next.fetch_add(1, Ordering::Relaxed)) {
+                    compress_file(config, file, &totals);
+                }
+            });
+        }
+    });
+
+    totals.into_inner().unwrap_or_else(PoisonError::into_inner)
+}
+
+fn print_totals(totals: &Totals) {
+    println!(
+        "Wrote {} variant(s), {} up to date, saving {}",
+        totals.written,
+        totals.up_to_date,
+        format_byte_size(totals.saved)
+    );
+}
+
+/// Check if the name of the file matches one of the patterns, all files are
+/// included without patterns
+fn is_included(patterns: &[String], file: &Path) -> bool {
+    let Some(name) = file.file_name().and_then(|name| name.to_str()) else {
+        return false;
+    };
+
+    patterns.is_empty() || patterns.iter().any(|pattern| wildcard_match(pattern, name))
+}
+
+/// Write the variants of a single file, when it is compressible
+fn compress_file(config: &Config, file: &Path, totals: &Mutex<Totals>) {
+    let Ok(meta) = std::fs::metadata(file) else {
+        return;
+    };
+
+    if !config.is_compressible(&content_type(file), meta.len()) {
+        return;
+    }
+
+    let mut content = None;
+
+    for encoding in config.encodings.iter().copied() {
+        let mut sidecar = file.as_os_str().to_owned();
+        sidecar.push(encoding.get_extension());
+        let sidecar = PathBuf::from(sidecar);
+
+        let is_up_to_date = std::fs::metadata(&sidecar)
+            .and_then(|sidecar| Ok(sidecar.modified()? >= meta.modified()?))
+            .unwrap_or(false);
+
+        if is_up_to_date {
+            totals
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner)
+                .up_to_date += 1;
+            continue;
+        }
+
+        let result = match &content {
+            Some(content) => Ok(content),
+            None => std::fs::read(file).map(|read| &*content.insert(read)),
+        }
+        .and_then(|content| Ok((content.len(), encoding.compress_best(content)?)))
+        .and_then(|(length, compressed)| {
+            // a variant that is not smaller is of no use, it would not be written at all
+            if compressed.len() >= length {
+                return Ok(0);
+            }
+
+            std::fs::write(&sidecar, &compressed)?;
+            Ok((length - compressed.len()) as u64)
+        });
+
+        let mut totals = totals.lock().unwrap_or_else(PoisonError::into_inner);
+
+        match result {
+            Ok(0) => {}
+            Ok(saved) => {
+                tracing::debug!("Wrote {sidecar:?}");
+                totals.written += 1;
+                totals.saved += saved;
+            }
+            Err(err) => {
+                println!("FAIL {}: {err}", sidecar.display());
+                totals.failed += 1;
+            }
+        }
+    }
+}
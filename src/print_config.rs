@@ -0,0 +1,476 @@
+//! Print the effective configuration
+//!
+//! Every option is printed with the value it ended up with, whether it was
+//! given on the command line, is a default or comes from a preset like
+//! `--dev`, followed by the values srvr derives from them. The output is TOML,
+//! one key per option. Passwords, tokens and secrets are redacted, the output
+//! is safe to share.
+
+use std::num::NonZeroUsize;
+use std::path::Path;
+use std::path::PathBuf;
+use std::time::Duration;
+
+use axum::http::HeaderName;
+use axum::http::HeaderValue;
+use axum::http::Method;
+use axum::http::Uri;
+use clap::Args;
+use clap::Command;
+use clap::ValueEnum;
+
+use crate::app::Release;
+use crate::cache_rules::CacheRule;
+use crate::canary::Stickiness;
+use crate::config::Config;
+use crate::encoding::ContentTypePattern;
+use crate::encoding::Encoding;
+use crate::forwarded::TrustedProxy;
+use crate::header_rules::CustomHeader;
+use crate::header_rules::ScopedHeader;
+use crate::proxy::ProxyMount;
+use crate::rate_limit::RateLimit;
+use crate::redirects::Redirect;
+use crate::shadow::ShadowTarget;
+use crate::tls::AlpnProtocol;
+use crate::tls::SniCertificate;
+use crate::trailing_slash::TrailingSlash;
+use crate::utils::setup_address;
+
+/// Printed instead of the value of a secret option
+const REDACTED: &str = "<redacted>";
+
+/// Print the effective configuration as TOML
+pub fn print_config(config: &Config) -> anyhow::Result<()> {
+    let address = setup_address(config)?;
+
+    for line in option_lines(config) {
+        println!("{line}");
+    }
+
+    let scheme = if config.is_tls() { "https" } else { "http" };
+    let alpn_protocols = config
+        .alpn_protocols()
+        .iter()
+        .filter_map(ValueEnum::to_possible_value)
+        .map(|protocol| toml_string(protocol.get_name()))
+        .collect::<Vec<_>>();
+
+    let release = Release::new(config, config.base_dir.clone());
+
+    println!();
+    println!("[resolved]");
+    println!(
+        "address = {}",
+        toml_string(&format!("{scheme}://{address}"))
+    );
+    println!(
+        "base-dir = {}",
+        toml_string(&absolute(&release.base_dir).display().to_string())
+    );
+    println!(
+        "fallback-path = {}",
+        toml_string(&absolute(&release.fallback_path).display().to_string())
+    );
+    println!("alpn = [{}]", alpn_protocols.join(", "));
+
+    Ok(())
+}
+
+/// A `name = value` line for every option with a value, in the order of
+/// `--help`
+fn option_lines(config: &Config) -> Vec<String> {
+    let values = option_values(config);
+    let command = Config::augment_args(Command::new("srvr"));
+
+    command
+        .get_arguments()
+        .filter_map(|arg| {
+            let id = arg.get_id().as_str();
+            let (_, value) = values.iter().find(|(option, _)| *option == id)?;
+
+            // positional arguments have no long name, use the same style for them
+            let name = arg
+                .get_long()
+                .map_or_else(|| id.replace('_', "-"), String::from);
+
+            Some(format!("{name} = {}", value.as_ref()?))
+        })
+        .collect()
+}
+
+/// Value of every option of the config, by the id of its argument
+#[allow(clippy::too_many_lines)]
+fn option_values(config: &Config) -> Vec<(&'static str, Option<String>)> {
+    Vec::from([
+        ("base_dir", config.base_dir.toml_value()),
+        ("dev", config.dev.toml_value()),
+        ("live_reload", config.live_reload.toml_value()),
+        ("fallback_path", config.fallback_path.toml_value()),
+        ("fallback_always", config.fallback_always.toml_value()),
+        ("no_fallback", config.no_fallback.toml_value()),
+        ("not_found_path", config.not_found_path.toml_value()),
+        ("prefer_not_found", config.prefer_not_found.toml_value()),
+        ("clean_urls", config.clean_urls.toml_value()),
+        ("trailing_slash", config.trailing_slash.toml_value()),
+        ("canonical_index", config.canonical_index.toml_value()),
+        ("address", config.address.toml_value()),
+        ("port", config.port.toml_value()),
+        ("tls_cert", config.tls_cert.toml_value()),
+        ("tls_key", config.tls_key.toml_value()),
+        ("tls_mkcert", config.tls_mkcert.toml_value()),
+        ("tls_sni", config.tls_sni.toml_value()),
+        ("tls_client_ca", config.tls_client_ca.toml_value()),
+        ("acme_domain", config.acme_domain.toml_value()),
+        ("acme_cache", config.acme_cache.toml_value()),
+        ("acme_email", config.acme_email.toml_value()),
+        ("acme_staging", config.acme_staging.toml_value()),
+        ("redirect_http", config.redirect_http.toml_value()),
+        ("http1_only", config.http1_only.toml_value()),
+        ("alpn", config.alpn.toml_value()),
+        ("autoindex", config.autoindex.toml_value()),
+        ("listing_template", config.listing_template.toml_value()),
+        #[cfg(feature = "image-resize")]
+        ("listing_thumbnails", config.listing_thumbnails.toml_value()),
+        ("only_ext", config.only_ext.toml_value()),
+        ("block_dotfiles", config.block_dotfiles.toml_value()),
+        ("dotfile_exempt", config.dotfile_exempt.toml_value()),
+        ("max_file_size", config.max_file_size.toml_value()),
+        ("max_body_size", config.max_body_size.toml_value()),
+        (
+            "header_read_timeout",
+            config.header_read_timeout.toml_value(),
+        ),
+        ("idle_timeout", config.idle_timeout.toml_value()),
+        ("min_rate", config.min_rate.toml_value()),
+        ("rate_limit", config.rate_limit.toml_value()),
+        (
+            "max_concurrent_requests",
+            config.max_concurrent_requests.toml_value(),
+        ),
+        ("ban_after", config.ban_after.toml_value()),
+        ("ban_window", config.ban_window.toml_value()),
+        ("ban_duration", config.ban_duration.toml_value()),
+        ("audit_log", config.audit_log.toml_value()),
+        (
+            "max_connections_per_ip",
+            config.max_connections_per_ip.toml_value(),
+        ),
+        ("throttle", config.throttle.toml_value()),
+        (
+            "throttle_connection",
+            config.throttle_connection.toml_value(),
+        ),
+        ("normalize_redirect", config.normalize_redirect.toml_value()),
+        ("redirect", config.redirect.toml_value()),
+        ("allow_sniffing", config.allow_sniffing.toml_value()),
+        ("server_header", config.server_header.toml_value()),
+        ("no_server_header", config.no_server_header.toml_value()),
+        ("cors", config.cors.toml_value()),
+        ("cors_origin", config.cors_origin.toml_value()),
+        ("cors_methods", config.cors_methods.toml_value()),
+        ("cors_headers", config.cors_headers.toml_value()),
+        ("cors_max_age", config.cors_max_age.toml_value()),
+        ("secure", config.secure.toml_value()),
+        ("cache", config.cache.toml_value()),
+        ("max_age", config.max_age.toml_value()),
+        ("s_maxage", config.s_maxage.toml_value()),
+        (
+            "stale_while_revalidate",
+            config.stale_while_revalidate.toml_value(),
+        ),
+        ("header", config.header.toml_value()),
+        ("header_for", config.header_for.toml_value()),
+        ("proxy", config.proxy.toml_value()),
+        (
+            "proxy_preserve_host",
+            config.proxy_preserve_host.toml_value(),
+        ),
+        ("trusted_proxy", config.trusted_proxy.toml_value()),
+        ("absolute_redirects", config.absolute_redirects.toml_value()),
+        ("shadow", config.shadow.toml_value()),
+        ("shadow_sample", config.shadow_sample.toml_value()),
+        ("hsts", config.hsts.toml_value()),
+        (
+            "hsts_include_subdomains",
+            config.hsts_include_subdomains.toml_value(),
+        ),
+        ("hsts_preload", config.hsts_preload.toml_value()),
+        ("coi", config.coi.toml_value()),
+        ("noindex", config.noindex.toml_value()),
+        ("json_errors", config.json_errors.toml_value()),
+        ("csp", config.csp.toml_value()),
+        ("csp_report_only", config.csp_report_only.toml_value()),
+        ("csp_all", config.csp_all.toml_value()),
+        ("early_hints", config.early_hints.toml_value()),
+        ("preload", config.preload.toml_value()),
+        ("releases_dir", config.releases_dir.toml_value()),
+        ("canary_dir", config.canary_dir.toml_value()),
+        ("canary_percent", config.canary_percent.toml_value()),
+        ("canary_sticky", config.canary_sticky.toml_value()),
+        ("minify", config.minify.toml_value()),
+        ("compress", config.compress.toml_value()),
+        ("encodings", config.encodings.toml_value()),
+        (
+            "serve_orphan_sidecars",
+            config.serve_orphan_sidecars.toml_value(),
+        ),
+        ("compress_types", config.compress_types.toml_value()),
+        ("compress_min_size", config.compress_min_size.toml_value()),
+        ("cache_snapshot", config.cache_snapshot.toml_value()),
+        ("admin_token", Secret(&config.admin_token).toml_value()),
+        ("auth", Secret(&config.auth).toml_value()),
+        ("auth_file", config.auth_file.toml_value()),
+        ("auth_realm", config.auth_realm.toml_value()),
+        ("token", Secret(&config.token).toml_value()),
+        ("token_file", config.token_file.toml_value()),
+        ("jwt_secret", Secret(&config.jwt_secret).toml_value()),
+        ("jwks_url", config.jwks_url.toml_value()),
+        ("jwks_refresh", config.jwks_refresh.toml_value()),
+        ("jwt_audience", config.jwt_audience.toml_value()),
+        ("jwt_issuer", config.jwt_issuer.toml_value()),
+        ("auth_header", config.auth_header.toml_value()),
+        ("auth_rules", config.auth_rules.toml_value()),
+        (
+            "signing_secret",
+            Secret(&config.signing_secret).toml_value(),
+        ),
+        ("hotlink_protect", config.hotlink_protect.toml_value()),
+        ("allowed_referers", config.allowed_referers.toml_value()),
+        (
+            "hotlink_placeholder",
+            config.hotlink_placeholder.toml_value(),
+        ),
+        #[cfg(feature = "ldap")]
+        ("ldap_url", config.ldap_url.toml_value()),
+        #[cfg(feature = "ldap")]
+        ("ldap_base_dn", config.ldap_base_dn.toml_value()),
+        #[cfg(feature = "ldap")]
+        (
+            "ldap_user_attribute",
+            config.ldap_user_attribute.toml_value(),
+        ),
+        #[cfg(feature = "ldap")]
+        ("ldap_cache_ttl", config.ldap_cache_ttl.toml_value()),
+        ("media", config.media.toml_value()),
+        #[cfg(feature = "image-resize")]
+        ("image_resize", config.image_resize.toml_value()),
+        #[cfg(feature = "image-resize")]
+        ("image_cache_dir", config.image_cache_dir.toml_value()),
+    ])
+}
+
+/// Value of an option as TOML, `None` when the option has no value, like an
+/// empty list
+trait TomlValue {
+    fn toml_value(&self) -> Option<String>;
+}
+
+impl<T: TomlValue> TomlValue for Option<T> {
+    fn toml_value(&self) -> Option<String> {
+        self.as_ref()?.toml_value()
+    }
+}
+
+impl<T: TomlValue> TomlValue for Vec<T> {
+    fn toml_value(&self) -> Option<String> {
+        if self.is_empty() {
+            return None;
+        }
+
+        let values = self.iter().filter_map(T::toml_value).collect::<Vec<_>>();
+
+        Some(format!("[{}]", values.join(", ")))
+    }
+}
+
+/// Options that are printed as they are, ie flags and numbers
+macro_rules! toml_literal {
+    ($($ty:ty),*) => {
+        $(impl TomlValue for $ty {
+            fn toml_value(&self) -> Option<String> {
+                Some(self.to_string())
+            }
+        })*
+    };
+}
+
+toml_literal!(bool, u8, u16, u32, u64, NonZeroUsize);
+
+/// Options that are printed as a string, in the form they are given in
+macro_rules! toml_display {
+    ($($ty:ty),*) => {
+        $(impl TomlValue for $ty {
+            fn toml_value(&self) -> Option<String> {
+                Some(toml_string(&self.to_string()))
+            }
+        })*
+    };
+}
+
+toml_display!(
+    String,
+    Method,
+    Uri,
+    CacheRule,
+    ContentTypePattern,
+    CustomHeader,
+    ProxyMount,
+    RateLimit,
+    Redirect,
+    ScopedHeader,
+    ShadowTarget,
+    SniCertificate,
+    TrustedProxy
+);
+
+/// Options with a fixed set of values, printed by their name
+macro_rules! toml_value_enum {
+    ($($ty:ty),*) => {
+        $(impl TomlValue for $ty {
+            fn toml_value(&self) -> Option<String> {
+                Some(toml_string(self.to_possible_value()?.get_name()))
+            }
+        })*
+    };
+}
+
+toml_value_enum!(AlpnProtocol, Encoding, Stickiness, TrailingSlash);
+
+impl TomlValue for PathBuf {
+    fn toml_value(&self) -> Option<String> {
+        Some(toml_string(&self.display().to_string()))
+    }
+}
+
+impl TomlValue for Duration {
+    fn toml_value(&self) -> Option<String> {
+        Some(toml_string(&humantime::format_duration(*self).to_string()))
+    }
+}
+
+impl TomlValue for HeaderName {
+    fn toml_value(&self) -> Option<String> {
+        Some(toml_string(self.as_str()))
+    }
+}
+
+impl TomlValue for HeaderValue {
+    fn toml_value(&self) -> Option<String> {
+        Some(toml_string(&String::from_utf8_lossy(self.as_bytes())))
+    }
+}
+
+/// Value of a password, token or secret, only whether it is set is printed
+struct Secret<'a, T>(&'a T);
+
+impl<T> TomlValue for Secret<'_, Option<T>> {
+    fn toml_value(&self) -> Option<String> {
+        self.0.as_ref().map(|_| toml_string(REDACTED))
+    }
+}
+
+impl<T> TomlValue for Secret<'_, Vec<T>> {
+    fn toml_value(&self) -> Option<String> {
+        if self.0.is_empty() {
+            return None;
+        }
+
+        let values = vec![toml_string(REDACTED); self.0.len()];
+
+        Some(format!("[{}]", values.join(", ")))
+    }
+}
+
+/// Quoted TOML string, the escapes of JSON strings are valid in TOML as well
+fn toml_string(value: &str) -> String {
+    serde_json::Value::from(value).to_string()
+}
+
+/// Absolute version of the path, as is when it does not exist
+fn absolute(path: &Path) -> PathBuf {
+    path.canonicalize().unwrap_or_else(|_| path.to_path_buf())
+}
+
+#[cfg(test)]
+mod tests {
+    use clap::Parser;
+
+    use super::*;
+    use crate::config::CliConfig;
+
+    fn output(args: &[&str]) -> String {
+        let cli_config = CliConfig::from_args(["srvr"].iter().chain(args));
+
+        option_lines(&cli_config.config).join("\n")
+    }
+
+    #[test]
+    fn test_every_option() {
+        let config = CliConfig::parse_from(["srvr"]).config;
+        let values = option_values(&config);
+        let command = Config::augment_args(Command::new("srvr"));
+
+        for arg in command.get_arguments() {
+            let id = arg.get_id().as_str();
+            assert!(
+                values.iter().any(|(option, _)| *option == id),
+                "Missing option {id}"
+            );
+        }
+
+        assert_eq!(values.len(), command.get_arguments().count());
+    }
+
+    #[test]
+    fn test_secrets_redacted() {
+        let output = output(&[
+            "--admin-token",
+            "hunter1",
+            "--auth",
+            "alice:hunter2",
+            "--token",
+            "hunter3",
+            "--jwt-secret",
+            "hunter4",
+            "--signing-secret",
+            "hunter5",
+        ]);
+
+        assert!(!output.contains("hunter"));
+        assert!(output.contains("admin-token = \"<redacted>\""));
+        assert!(output.contains("auth = [\"<redacted>\"]"));
+    }
+
+    #[test]
+    fn test_dev_preset() {
+        let output = output(&["--dev"]);
+
+        assert!(output.contains("autoindex = true"));
+        assert!(output.contains("cors = true"));
+        assert!(output.contains("live-reload = true"));
+        assert!(output.contains("cache = [\"*=no-store\"]"));
+    }
+
+    #[test]
+    fn test_values() {
+        let output = output(&[
+            "--proxy",
+            "/api=http://localhost:3000",
+            "--proxy",
+            "/v1=http://localhost:3000/",
+            "--rate-limit",
+            "100/10s",
+            "--header-for",
+            "/admin/*=X-Frame-Options: DENY",
+            "--idle-timeout",
+            "1m",
+        ]);
+
+        assert!(output
+            .contains("proxy = [\"/api=http://localhost:3000\", \"/v1=http://localhost:3000/\"]"));
+        assert!(output.contains("rate-limit = \"100/10s\""));
+        assert!(output.contains("header-for = [\"/admin/*=x-frame-options: DENY\"]"));
+        assert!(output.contains("idle-timeout = \"1m\""));
+    }
+}
@@ -0,0 +1,432 @@
+//! Reverse proxy support
+//!
+//! Requests matching the prefix of a proxy mount are forwarded to another
+//! server, instead of being served from the base dir. Upgraded connections
+//! (ie websockets) are tunneled and response bodies are streamed as-is, so
+//! server-sent events work as expected.
+//!
+//! The path of the target of a mount determines how the request path is rewritten:
+//! - `/api=http://localhost:3000` forwards `/api/users` as `/api/users`
+//! - `/api=http://localhost:3000/` forwards `/api/users` as `/users`
+//! - `/api=http://localhost:3000/v1` forwards `/api/users` as `/v1/users`
+
+use std::fmt;
+use std::net::IpAddr;
+use std::net::SocketAddr;
+use std::str::FromStr;
+
+use axum::body::Body;
+use axum::extract::ConnectInfo;
+use axum::extract::Request;
+use axum::extract::State;
+use axum::http::header::AUTHORIZATION;
+use axum::http::header::CONNECTION;
+use axum::http::header::HOST;
+use axum::http::header::UPGRADE;
+use axum::http::uri::Scheme;
+use axum::http::HeaderMap;
+use axum::http::HeaderName;
+use axum::http::HeaderValue;
+use axum::http::StatusCode;
+use axum::http::Uri;
+use axum::middleware::Next;
+use axum::response::IntoResponse;
+use axum::response::Response;
+use hyper_util::client::legacy::connect::HttpConnector;
+use hyper_util::client::legacy::Client;
+use hyper_util::rt::TokioExecutor;
+use hyper_util::rt::TokioIo;
+
+use crate::app::ServerState;
+use crate::forwarded::Origin;
+
+/// Header with the addresses of the clients (and proxies) of a request
+pub const X_FORWARDED_FOR: HeaderName = HeaderName::from_static("x-forwarded-for");
+
+/// Header with the original host of a request
+pub const X_FORWARDED_HOST: HeaderName = HeaderName::from_static("x-forwarded-host");
+
+/// Header with the original scheme of a request
+pub const X_FORWARDED_PROTO: HeaderName = HeaderName::from_static("x-forwarded-proto");
+
+/// Hop-by-hop headers, these are meant for a single connection and should not be forwarded
+///
+/// See <https://www.rfc-editor.org/rfc/rfc9110#section-7.6.1>
+const HOP_BY_HOP_HEADERS: &[&str] = &[
+    "connection",
+    "keep-alive",
+    "proxy-authenticate",
+    "proxy-authorization",
+    "te",
+    "trailer",
+    "transfer-encoding",
+    "upgrade",
+];
+
+#[derive(Debug, thiserror::Error)]
+pub enum ProxyMountError {
+    #[error("Expected a proxy mount in the form of <prefix>=<url>")]
+    InvalidFormat,
+
+    #[error("The prefix of a proxy mount should start with a \"/\"")]
+    InvalidPrefix,
+
+    #[error("Invalid proxy target: {0}")]
+    InvalidTarget(#[from] axum::http::uri::InvalidUri),
+
+    #[error("Only http:// proxy targets are supported")]
+    UnsupportedScheme,
+
+    #[error("A proxy target can not have a query")]
+    UnsupportedQuery,
+}
+
+/// A path prefix that is forwarded to another server
+#[derive(Clone, Debug)]
+pub struct ProxyMount {
+    /// Prefix of the request path to match, without a trailing slash
+    prefix: String,
+
+    /// Server to forward the requests to
+    target: Uri,
+
+    /// Replacement for the prefix, when the target has a path
+    rewrite: Option<String>,
+}
+
+impl FromStr for ProxyMount {
+    type Err = ProxyMountError;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let (prefix, target) = value
+            .split_once('=')
+            .ok_or(ProxyMountError::InvalidFormat)?;
+
+        if !prefix.starts_with('/') {
+            return Err(ProxyMountError::InvalidPrefix);
+        }
+
+        // `Uri` does not distinguish between an empty path and "/"
+        let has_path = target
+            .split_once("://")
+            .is_some_and(|(_, rest)| rest.contains('/'));
+
+        let target = target.parse::<Uri>()?;
+
+        if target.scheme() != Some(&Scheme::HTTP) || target.authority().is_none() {
+            return Err(ProxyMountError::UnsupportedScheme);
+        }
+
+        if target.query().is_some() {
+            return Err(ProxyMountError::UnsupportedQuery);
+        }
+
+        let rewrite = has_path.then(|| target.path().trim_end_matches('/').to_string());
+
+        Ok(Self {
+            prefix: prefix.trim_end_matches('/').to_string(),
+            target,
+            rewrite,
+        })
+    }
+}
+
+impl fmt::Display for ProxyMount {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let prefix = if self.prefix.is_empty() {
+            "/"
+        } else {
+            &self.prefix
+        };
+        let authority = self
+            .target
+            .authority()
+            .map_or("", |authority| authority.as_str());
+
+        // without a path the target keeps the request path, see `upstream_uri`
+        let path = match self.rewrite.as_deref() {
+            Some("") => "/",
+            Some(rewrite) => rewrite,
+            None => "",
+        };
+
+        write!(f, "{prefix}=http://{authority}{path}")
+    }
+}
+
+impl ProxyMount {
+    /// Check if the mount matches the request path
+    fn matches(&self, path: &str) -> bool {
+        path.strip_prefix(&self.prefix)
+            .is_some_and(|rest| rest.is_empty() || rest.starts_with('/'))
+    }
+
+    /// The URI to forward the request to
+    fn upstream_uri(&self, uri: &Uri) -> Result<Uri, axum::http::Error> {
+        let path_and_query = match &self.rewrite {
+            Some(rewrite) => {
+                let rest = uri.path().strip_prefix(&self.prefix).unwrap_or_default();
+                let path = format!("{rewrite}{rest}");
+                let path = if path.is_empty() { "/" } else { &path };
+
+                match uri.query() {
+                    Some(query) => format!("{path}?{query}"),
+                    None => path.to_string(),
+                }
+            }
+
+            None => uri
+                .path_and_query()
+                .map_or_else(|| String::from("/"), ToString::to_string),
+        };
+
+        Uri::builder()
+            .scheme(Scheme::HTTP)
+            .authority(
+                self.target
+                    .authority()
+                    .expect("A proxy target with an authority")
+                    .clone(),
+            )
+            .path_and_query(path_and_query)
+            .build()
+    }
+}
+
+/// HTTP client used to forward requests
+#[derive(Clone)]
+pub struct ProxyClient {
+    client: Client<HttpConnector, Body>,
+}
+
+impl ProxyClient {
+    /// The underlying client, ie to send requests that are not proxied
+    pub const fn client(&self) -> &Client<HttpConnector, Body> {
+        &self.client
+    }
+}
+
+impl Default for ProxyClient {
+    fn default() -> Self {
+        Self {
+            client: Client::builder(TokioExecutor::new()).build_http(),
+        }
+    }
+}
+
+/// Remove the hop-by-hop headers, including the headers listed in `connection`
+pub fn remove_hop_by_hop_headers(headers: &mut HeaderMap) {
+    let connection_headers = headers
+        .get_all(CONNECTION)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .filter_map(|name| HeaderName::from_str(name.trim()).ok())
+        .collect::<Vec<_>>();
+
+    for name in connection_headers {
+        headers.remove(name);
+    }
+
+    for name in HOP_BY_HOP_HEADERS {
+        headers.remove(*name);
+    }
+}
+
+/// Add the `x-forwarded-*` headers, so the upstream knows about the original request
+pub fn add_forwarded_headers(
+    headers: &mut HeaderMap,
+    client_address: Option<IpAddr>,
+    origin: &Origin,
+) {
+    if let Some(client_address) = client_address {
+        let forwarded_for = match headers.get(X_FORWARDED_FOR).map(HeaderValue::to_str) {
+            Some(Ok(forwarded_for)) => format!("{forwarded_for}, {client_address}"),
+            _ => client_address.to_string(),
+        };
+
+        if let Ok(forwarded_for) = HeaderValue::from_str(&forwarded_for) {
+            headers.insert(X_FORWARDED_FOR, forwarded_for);
+        }
+    }
+
+    let host = origin
+        .host
+        .as_ref()
+        .and_then(|host| HeaderValue::from_str(host.as_str()).ok());
+
+    if let Some(host) = host {
+        headers.insert(X_FORWARDED_HOST, host);
+    }
+
+    if let Ok(proto) = HeaderValue::from_str(origin.scheme.as_str()) {
+        headers.insert(X_FORWARDED_PROTO, proto);
+    }
+}
+
+/// Middleware that forwards requests matching a proxy mount
+pub async fn proxy(State(state): State<ServerState>, mut request: Request, next: Next) -> Response {
+    let mount = state
+        .config
+        .proxy
+        .iter()
+        .find(|mount| mount.matches(request.uri().path()));
+
+    let Some(mount) = mount else {
+        return next.run(request).await;
+    };
+
+    if state.config.has_auth() {
+        // the credentials are meant for srvr, not for every proxied server
+        request.headers_mut().remove(AUTHORIZATION);
+    }
+
+    let preserve_host = state.config.proxy_preserve_host;
+    let origin = Origin::from_request(&state, &request);
+
+    match forward(&state.proxy_client, mount, preserve_host, &origin, request).await {
+        Ok(response) => response,
+        Err(err) => {
+            tracing::warn!("Could not proxy request to {}: {err}", mount.target);
+            StatusCode::BAD_GATEWAY.into_response()
+        }
+    }
+}
+
+/// Forward the request to the target of the mount
+async fn forward(
+    proxy_client: &ProxyClient,
+    mount: &ProxyMount,
+    preserve_host: bool,
+    origin: &Origin,
+    mut request: Request,
+) -> anyhow::Result<Response> {
+    let upgrade = request.headers().get(UPGRADE).cloned();
+    let client_upgrade = upgrade.is_some().then(|| hyper::upgrade::on(&mut request));
+    let client_address = request
+        .extensions()
+        .get::<ConnectInfo<SocketAddr>>()
+        .map(|ConnectInfo(address)| address.ip());
+
+    *request.uri_mut() = mount.upstream_uri(request.uri())?;
+
+    let headers = request.headers_mut();
+    remove_hop_by_hop_headers(headers);
+    add_forwarded_headers(headers, client_address, origin);
+
+    if !preserve_host {
+        // the client will use the authority of the upstream uri
+        headers.remove(HOST);
+    }
+
+    if let Some(upgrade) = &upgrade {
+        headers.insert(CONNECTION, "upgrade".parse()?);
+        headers.insert(UPGRADE, upgrade.clone());
+    }
+
+    tracing::trace!("Proxying request to {}", request.uri());
+
+    let mut response = proxy_client.client.request(request).await?;
+
+    if response.status() == StatusCode::SWITCHING_PROTOCOLS {
+        let Some(client_upgrade) = client_upgrade else {
+            anyhow::bail!("Upstream switched protocols without an upgrade request");
+        };
+
+        let upstream_upgrade = hyper::upgrade::on(&mut response);
+
+        tokio::spawn(async move {
+            let (client, upstream) = match tokio::try_join!(client_upgrade, upstream_upgrade) {
+                Ok(upgraded) => upgraded,
+                Err(err) => {
+                    tracing::warn!("Could not upgrade proxied connection: {err}");
+                    return;
+                }
+            };
+
+            let mut client = TokioIo::new(client);
+            let mut upstream = TokioIo::new(upstream);
+
+            if let Err(err) = tokio::io::copy_bidirectional(&mut client, &mut upstream).await {
+                tracing::debug!("Proxied connection closed: {err}");
+            }
+        });
+
+        // the upgrade headers are needed by the client to complete the upgrade
+        let (parts, _) = response.into_parts();
+        return Ok(Response::from_parts(parts, Body::empty()));
+    }
+
+    remove_hop_by_hop_headers(response.headers_mut());
+
+    // streamed as-is, so events of an event stream are not held back
+    Ok(response.map(Body::new))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn upstream(mount: &str, uri: &'static str) -> String {
+        let mount = mount.parse::<ProxyMount>().expect("A valid proxy mount");
+
+        mount
+            .upstream_uri(&Uri::from_static(uri))
+            .expect("A valid upstream uri")
+            .to_string()
+    }
+
+    #[test]
+    fn test_invalid_mounts() {
+        assert!("/api".parse::<ProxyMount>().is_err());
+        assert!("api=http://localhost:3000".parse::<ProxyMount>().is_err());
+        assert!("/api=https://localhost:3000".parse::<ProxyMount>().is_err());
+        assert!("/api=http://localhost:3000/?a=b"
+            .parse::<ProxyMount>()
+            .is_err());
+    }
+
+    #[test]
+    fn test_matches() {
+        let mount = "/api/=http://localhost:3000"
+            .parse::<ProxyMount>()
+            .expect("A valid proxy mount");
+
+        assert!(mount.matches("/api"));
+        assert!(mount.matches("/api/users"));
+        assert!(!mount.matches("/apis"));
+        assert!(!mount.matches("/"));
+    }
+
+    #[test]
+    fn test_keep_path() {
+        assert_eq!(
+            upstream("/api=http://localhost:3000", "/api/users?page=2"),
+            "http://localhost:3000/api/users?page=2"
+        );
+    }
+
+    #[test]
+    fn test_strip_prefix() {
+        assert_eq!(
+            upstream("/api=http://localhost:3000/", "/api/users?page=2"),
+            "http://localhost:3000/users?page=2"
+        );
+        assert_eq!(
+            upstream("/api=http://localhost:3000/", "/api"),
+            "http://localhost:3000/"
+        );
+    }
+
+    #[test]
+    fn test_replace_prefix() {
+        assert_eq!(
+            upstream("/api=http://localhost:3000/v1", "/api/users"),
+            "http://localhost:3000/v1/users"
+        );
+        assert_eq!(
+            upstream("/api=http://localhost:3000/v1/", "/api"),
+            "http://localhost:3000/v1"
+        );
+    }
+}
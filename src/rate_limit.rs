@@ -0,0 +1,285 @@
+//! Rate limiting per client address
+//!
+//! With `--rate-limit 100/10s` every client has a bucket of 100 requests that
+//! refills at 100 requests per 10 seconds, so short bursts are fine but a
+//! sustained rate above the limit is not. Requests over the limit get a 429
+//! with a `Retry-After` header. Behind a trusted proxy, the client address is
+//! taken from `x-forwarded-for`.
+//!
+//! The buckets are spread over shards, each with a lock of its own, so
+//! concurrent requests of different clients rarely wait on each other. Full
+//! buckets are dropped periodically, they are the same as a new bucket.
+
+use std::collections::hash_map::RandomState;
+use std::collections::HashMap;
+use std::fmt;
+use std::hash::BuildHasher;
+use std::hash::Hash;
+use std::hash::Hasher;
+use std::net::IpAddr;
+use std::str::FromStr;
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::sync::PoisonError;
+use std::time::Duration;
+use std::time::Instant;
+
+use axum::extract::Request;
+use axum::extract::State;
+use axum::http::header::RETRY_AFTER;
+use axum::http::StatusCode;
+use axum::middleware::Next;
+use axum::response::Response;
+
+use crate::app::ServerState;
+use crate::audit::Rejection;
+use crate::forwarded::client_address;
+
+/// Number of shards of the buckets
+const SHARDS: usize = 16;
+
+/// Time between removing the full buckets
+const CLEANUP_INTERVAL: Duration = Duration::from_secs(60);
+
+#[derive(Debug, thiserror::Error)]
+pub enum RateLimitError {
+    #[error("Expected a rate limit in the form of \"<requests>/<period>\", ie \"100/10s\"")]
+    InvalidFormat,
+
+    #[error("Invalid number of requests \"{0}\"")]
+    InvalidRequests(String),
+
+    #[error("Invalid period: {0}")]
+    Period(#[from] humantime::DurationError),
+}
+
+/// Number of requests allowed per period
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct RateLimit {
+    requests: u32,
+    period: Duration,
+}
+
+impl FromStr for RateLimit {
+    type Err = RateLimitError;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let (requests, period) = value.split_once('/').ok_or(RateLimitError::InvalidFormat)?;
+
+        let requests = requests
+            .trim()
+            .parse::<u32>()
+            .ok()
+            .filter(|requests| *requests > 0)
+            .ok_or_else(|| RateLimitError::InvalidRequests(requests.to_string()))?;
+
+        // `100/s` is a rate limit per second
+        let period = period.trim();
+        let period = if period.starts_with(|c: char| c.is_ascii_digit()) {
+            humantime::parse_duration(period)?
+        } else {
+            humantime::parse_duration(&format!("1{period}"))?
+        };
+
+        if period.is_zero() {
+            return Err(RateLimitError::InvalidFormat);
+        }
+
+        Ok(Self { requests, period })
+    }
+}
+
+impl fmt::Display for RateLimit {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}/{}",
+            self.requests,
+            humantime::format_duration(self.period)
+        )
+    }
+}
+
+impl RateLimit {
+    /// Requests a bucket gains per second
+    fn refill_rate(self) -> f64 {
+        f64::from(self.requests) / self.period.as_secs_f64()
+    }
+}
+
+/// Requests a client can still make, as of the last update
+#[derive(Clone, Copy, Debug)]
+struct Bucket {
+    tokens: f64,
+    updated_at: Instant,
+}
+
+/// Buckets of all clients, spread over shards
+pub struct RateLimiter {
+    limit: RateLimit,
+    hasher: RandomState,
+    shards: Vec<Mutex<HashMap<IpAddr, Bucket>>>,
+}
+
+impl RateLimiter {
+    pub fn new(limit: RateLimit) -> Self {
+        Self {
+            limit,
+            hasher: RandomState::new(),
+            shards: (0..SHARDS).map(|_| Mutex::default()).collect(),
+        }
+    }
+
+    /// Shard with the bucket of the client
+    fn shard(&self, client: IpAddr) -> &Mutex<HashMap<IpAddr, Bucket>> {
+        let mut hasher = self.hasher.build_hasher();
+        client.hash(&mut hasher);
+
+        // the remainder is below the number of shards
+        #[allow(clippy::cast_possible_truncation)]
+        let index = (hasher.finish() % SHARDS as u64) as usize;
+
+        &self.shards[index]
+    }
+
+    /// Take a request from the bucket of the client, or the time until one is
+    /// available
+    pub fn check(&self, client: IpAddr) -> Result<(), Duration> {
+        self.check_at(client, Instant::now())
+    }
+
+    fn check_at(&self, client: IpAddr, now: Instant) -> Result<(), Duration> {
+        let capacity = f64::from(self.limit.requests);
+        let refill_rate = self.limit.refill_rate();
+
+        let mut buckets = self
+            .shard(client)
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+
+        let bucket = buckets.entry(client).or_insert(Bucket {
+            tokens: capacity,
+            updated_at: now,
+        });
+
+        let elapsed = now.saturating_duration_since(bucket.updated_at);
+        bucket.tokens = elapsed
+            .as_secs_f64()
+            .mul_add(refill_rate, bucket.tokens)
+            .min(capacity);
+        bucket.updated_at = now;
+
+        if bucket.tokens < 1.0 {
+            return Err(Duration::from_secs_f64((1.0 - bucket.tokens) / refill_rate));
+        }
+
+        bucket.tokens -= 1.0;
+
+        Ok(())
+    }
+
+    /// Drop the buckets that refilled completely
+    fn cleanup(&self) {
+        for shard in &self.shards {
+            shard
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner)
+                .retain(|_, bucket| bucket.updated_at.elapsed() < self.limit.period);
+        }
+    }
+}
+
+/// Periodically drop the full buckets in the background, if rate limiting is
+/// enabled
+pub fn spawn_rate_limit_cleanup(state: &ServerState) {
+    let Some(rate_limiter) = &state.rate_limiter else {
+        return;
+    };
+
+    let rate_limiter = Arc::clone(rate_limiter);
+
+    tokio::spawn(async move {
+        loop {
+            tokio::time::sleep(CLEANUP_INTERVAL).await;
+            rate_limiter.cleanup();
+        }
+    });
+}
+
+/// Middleware that refuses requests of clients over the rate limit
+pub async fn rate_limit(
+    State(state): State<ServerState>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let (Some(rate_limiter), Some(client)) =
+        (&state.rate_limiter, client_address(&state, &request))
+    else {
+        return next.run(request).await;
+    };
+
+    let Err(retry_after) = rate_limiter.check(client) else {
+        return next.run(request).await;
+    };
+
+    tracing::debug!("Rate limited {client}");
+
+    // whole seconds, rounded up so the client does not retry too early
+    let retry_after = retry_after.as_secs() + u64::from(retry_after.subsec_nanos() > 0);
+
+    Rejection::RateLimited.response((
+        StatusCode::TOO_MANY_REQUESTS,
+        [(RETRY_AFTER, retry_after.to_string())],
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(
+            "100/10s".parse::<RateLimit>().expect("A valid rate limit"),
+            RateLimit {
+                requests: 100,
+                period: Duration::from_secs(10),
+            }
+        );
+        assert_eq!(
+            "5/min".parse::<RateLimit>().expect("A valid rate limit"),
+            RateLimit {
+                requests: 5,
+                period: Duration::from_secs(60),
+            }
+        );
+
+        assert!("100".parse::<RateLimit>().is_err());
+        assert!("0/10s".parse::<RateLimit>().is_err());
+        assert!("100/0s".parse::<RateLimit>().is_err());
+        assert!("100/soon".parse::<RateLimit>().is_err());
+    }
+
+    #[test]
+    fn test_check() {
+        let rate_limiter = RateLimiter::new("2/10s".parse().expect("A valid rate limit"));
+        let client = IpAddr::from([192, 168, 1, 1]);
+        let other = IpAddr::from([192, 168, 1, 2]);
+        let now = Instant::now();
+
+        assert!(rate_limiter.check_at(client, now).is_ok());
+        assert!(rate_limiter.check_at(client, now).is_ok());
+        assert_eq!(
+            rate_limiter.check_at(client, now),
+            Err(Duration::from_secs(5))
+        );
+        assert!(rate_limiter.check_at(other, now).is_ok());
+
+        assert!(rate_limiter
+            .check_at(client, now + Duration::from_secs(5))
+            .is_ok());
+        assert!(rate_limiter
+            .check_at(client, now + Duration::from_secs(5))
+            .is_err());
+    }
+}
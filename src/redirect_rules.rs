@@ -0,0 +1,287 @@
+//! Netlify-style `_redirects` file
+//!
+//! A `_redirects` file in the base dir has a rule per line, `<from> <to>
+//! [status]`, ie `/news/* /blog/:splat 301`. The source can have placeholders
+//! (`/:year/:month`) and end with a splat (`*`), their values are used in the
+//! target. A 3xx status redirects, any other status serves the target with that
+//! status instead, ie `/* /index.html 200` for a single-page app. The first
+//! matching rule wins, unless there is a file for the path: add a `!` to the
+//! status to apply the rule anyway.
+//!
+//! See <https://docs.netlify.com/routing/redirects/>
+
+use std::convert::Infallible;
+use std::str::FromStr;
+
+use axum::http::StatusCode;
+
+use crate::path_pattern::PathPattern;
+use crate::path_pattern::PathPatternError;
+
+/// Name of the file with the rules, in the base dir, it is never served
+pub const REDIRECTS_FILE_NAME: &str = "_redirects";
+
+/// Status code used when a rule has no explicit status code
+const DEFAULT_STATUS: StatusCode = StatusCode::MOVED_PERMANENTLY;
+
+#[derive(Debug, thiserror::Error)]
+pub enum RedirectRuleError {
+    #[error("Expected a rule in the form of \"<from> <to> [status]\"")]
+    InvalidFormat,
+
+    #[error("Conditions and query parameters are not supported")]
+    Unsupported,
+
+    #[error("Invalid source: {0}")]
+    InvalidSource(#[from] PathPatternError),
+
+    #[error("Invalid status code \"{0}\"")]
+    InvalidStatus(String),
+
+    #[error("Only redirects can have an external target, proxying is not supported")]
+    ExternalRewrite,
+}
+
+/// A single rule of the `_redirects` file
+#[derive(Clone, Debug)]
+pub struct RedirectRule {
+    from: PathPattern,
+    to: String,
+    status: StatusCode,
+
+    /// Applied even if there is a file for the path
+    pub force: bool,
+}
+
+/// What to do with a request that matches a rule
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum RuleAction {
+    /// Redirect to the location, the target with the values of the source
+    Redirect(String, StatusCode),
+
+    /// Serve the path instead, with the status
+    Rewrite(String, StatusCode),
+}
+
+impl RedirectRule {
+    /// Action for the path, when it matches the rule
+    pub fn action(&self, path: &str) -> Option<RuleAction> {
+        let captures = self.from.captures(path)?;
+        let target = substitute(&self.to, &captures);
+
+        Some(if self.status.is_redirection() {
+            RuleAction::Redirect(target, self.status)
+        } else {
+            RuleAction::Rewrite(target, self.status)
+        })
+    }
+}
+
+impl FromStr for RedirectRule {
+    type Err = RedirectRuleError;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let parts = value.split_whitespace().collect::<Vec<_>>();
+
+        // query parameters and conditions, not targets with a query
+        let is_unsupported =
+            |part: &&str| part.contains('=') && !part.starts_with('/') && !part.contains("://");
+
+        if parts.iter().skip(1).any(is_unsupported) {
+            return Err(RedirectRuleError::Unsupported);
+        }
+
+        let (from, to, status) = match parts.as_slice() {
+            [from, to] => (*from, *to, None),
+            [from, to, status] => (*from, *to, Some(*status)),
+            [_, _, _, ..] => return Err(RedirectRuleError::Unsupported),
+            _ => return Err(RedirectRuleError::InvalidFormat),
+        };
+
+        let (status, force) = match status {
+            Some(status) => {
+                let (status, force) = status
+                    .strip_suffix('!')
+                    .map_or((status, false), |status| (status, true));
+
+                let status = status
+                    .parse::<StatusCode>()
+                    .map_err(|_| RedirectRuleError::InvalidStatus(status.to_string()))?;
+
+                (status, force)
+            }
+            None => (DEFAULT_STATUS, false),
+        };
+
+        let from = from.parse::<PathPattern>()?;
+
+        if !status.is_redirection() && !to.starts_with('/') {
+            return Err(RedirectRuleError::ExternalRewrite);
+        }
+
+        Ok(Self {
+            from,
+            to: to.to_string(),
+            status,
+            force,
+        })
+    }
+}
+
+/// All rules of the `_redirects` file, in order
+#[derive(Clone, Debug, Default)]
+pub struct RedirectRules {
+    rules: Vec<RedirectRule>,
+}
+
+impl RedirectRules {
+    /// First rule that matches the path, with its action
+    pub fn find(&self, path: &str) -> Option<(&RedirectRule, RuleAction)> {
+        self.rules
+            .iter()
+            .find_map(|rule| Some((rule, rule.action(path)?)))
+    }
+}
+
+impl FromStr for RedirectRules {
+    type Err = Infallible;
+
+    /// Parse the rules, invalid or unsupported rules are skipped like Netlify
+    /// does, with a warning
+    fn from_str(content: &str) -> Result<Self, Self::Err> {
+        let mut rules = vec![];
+
+        for (index, line) in content.lines().enumerate() {
+            let line = line.trim();
+
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            match line.parse::<RedirectRule>() {
+                Ok(rule) => rules.push(rule),
+                Err(err) => {
+                    tracing::warn!(
+                        "Skipping line {} of {REDIRECTS_FILE_NAME}: {err}",
+                        index + 1
+                    );
+                }
+            }
+        }
+
+        Ok(Self { rules })
+    }
+}
+
+/// Replace the `:name` placeholders in the target with their values, unknown
+/// names are kept as is
+fn substitute(target: &str, captures: &[(&str, String)]) -> String {
+    let mut result = String::with_capacity(target.len());
+    let mut rest = target;
+
+    while let Some(start) = rest.find(':') {
+        result.push_str(&rest[..start]);
+
+        let name_length = rest[start + 1..]
+            .find(|c: char| !c.is_ascii_alphanumeric() && c != '_')
+            .unwrap_or(rest.len() - start - 1);
+        let name = &rest[start + 1..start + 1 + name_length];
+
+        match captures.iter().find(|(capture, _)| *capture == name) {
+            Some((_, value)) => result.push_str(value),
+            None => result.push_str(&rest[start..=start + name_length]),
+        }
+
+        rest = &rest[start + 1 + name_length..];
+    }
+
+    result.push_str(rest);
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rules(content: &str) -> RedirectRules {
+        content.parse().expect("Infallible")
+    }
+
+    #[test]
+    fn test_parse() {
+        assert!("/old".parse::<RedirectRule>().is_err());
+        assert!("old /new".parse::<RedirectRule>().is_err());
+        assert!("/old /new 3o1".parse::<RedirectRule>().is_err());
+        assert!("/store id=:id /blog/:id 301"
+            .parse::<RedirectRule>()
+            .is_err());
+        assert!("/* /index.html 200 Country=nl"
+            .parse::<RedirectRule>()
+            .is_err());
+        assert!("/*/old /new".parse::<RedirectRule>().is_err());
+        assert!("/u/:name /users?u=:name".parse::<RedirectRule>().is_ok());
+        assert!("/api/* https://api.example.com/:splat 200"
+            .parse::<RedirectRule>()
+            .is_err());
+
+        let rule = "/old /new 302!"
+            .parse::<RedirectRule>()
+            .expect("A valid rule");
+        assert_eq!(rule.status, StatusCode::FOUND);
+        assert!(rule.force);
+    }
+
+    #[test]
+    fn test_find() {
+        let rules = rules(
+            "# blog moved\n\
+             /news/:year/:month/* /blog/:year/:month/:splat\n\
+             /docs/* https://docs.example.com/:splat 302\n\
+             /gone /not-here 410\n\
+             /* /index.html 200\n",
+        );
+
+        let action = |path: &str| rules.find(path).map(|(_, action)| action);
+
+        assert_eq!(
+            action("/news/2024/01/hello/world"),
+            Some(RuleAction::Redirect(
+                String::from("/blog/2024/01/hello/world"),
+                StatusCode::MOVED_PERMANENTLY
+            ))
+        );
+        assert_eq!(
+            action("/docs/"),
+            Some(RuleAction::Redirect(
+                String::from("https://docs.example.com/"),
+                StatusCode::FOUND
+            ))
+        );
+        assert_eq!(
+            action("/gone"),
+            Some(RuleAction::Rewrite(
+                String::from("/not-here"),
+                StatusCode::GONE
+            ))
+        );
+        assert_eq!(
+            action("/news/2024"),
+            Some(RuleAction::Rewrite(
+                String::from("/index.html"),
+                StatusCode::OK
+            ))
+        );
+    }
+
+    #[test]
+    fn test_substitute() {
+        let captures = [("id", String::from("42")), ("splat", String::from("a/b"))];
+
+        assert_eq!(substitute("/items/:id/:splat", &captures), "/items/42/a/b");
+        assert_eq!(
+            substitute("https://example.com/:other", &captures),
+            "https://example.com/:other"
+        );
+        assert_eq!(substitute("/items/:", &captures), "/items/:");
+    }
+}
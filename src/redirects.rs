@@ -0,0 +1,214 @@
+//! Redirect map support
+//!
+//! Redirects are configured as `<from> <to> [status]`, ie `/old /new 301`,
+//! or as `<from> 410` for content that is gone for good. Redirects are
+//! evaluated before anything is looked up on the file system.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::str::FromStr;
+
+use axum::http::header::LOCATION;
+use axum::http::HeaderValue;
+use axum::http::StatusCode;
+use axum::response::IntoResponse;
+use axum::response::Response;
+
+/// Status code used when a redirect has no explicit status code
+const DEFAULT_STATUS: StatusCode = StatusCode::MOVED_PERMANENTLY;
+
+#[derive(Debug, thiserror::Error)]
+pub enum RedirectError {
+    #[error("Expected a redirect in the form of \"<from> <to> [status]\" or \"<from> 410\"")]
+    InvalidFormat,
+
+    #[error("The source of a redirect should start with a \"/\"")]
+    InvalidSource,
+
+    #[error("Invalid redirect target: {0}")]
+    InvalidTarget(#[from] axum::http::header::InvalidHeaderValue),
+
+    #[error("Unsupported redirect status code \"{0}\", expected 301, 302, 303, 307, 308 or 410")]
+    UnsupportedStatus(String),
+}
+
+/// What to do with a request for the source of a redirect
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum RedirectTarget {
+    /// Redirect to another location
+    Location {
+        location: HeaderValue,
+        status: StatusCode,
+    },
+
+    /// The content was removed
+    Gone,
+}
+
+/// A single redirect
+#[derive(Clone, Debug)]
+pub struct Redirect {
+    /// Path to redirect from
+    from: String,
+
+    /// Where to redirect to
+    target: RedirectTarget,
+}
+
+impl FromStr for Redirect {
+    type Err = RedirectError;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let parts = value.split_whitespace().collect::<Vec<_>>();
+
+        let (from, target) = match parts.as_slice() {
+            [from, "410"] => (*from, RedirectTarget::Gone),
+            [from, to] => (
+                *from,
+                RedirectTarget::Location {
+                    location: HeaderValue::from_str(to)?,
+                    status: DEFAULT_STATUS,
+                },
+            ),
+            [from, to, status] => (
+                *from,
+                RedirectTarget::Location {
+                    location: HeaderValue::from_str(to)?,
+                    status: parse_status(status)?,
+                },
+            ),
+            _ => return Err(RedirectError::InvalidFormat),
+        };
+
+        if !from.starts_with('/') {
+            return Err(RedirectError::InvalidSource);
+        }
+
+        Ok(Self {
+            from: from.to_string(),
+            target,
+        })
+    }
+}
+
+impl fmt::Display for Redirect {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.target {
+            RedirectTarget::Location { location, status } => write!(
+                f,
+                "{} {} {}",
+                self.from,
+                String::from_utf8_lossy(location.as_bytes()),
+                status.as_u16()
+            ),
+            RedirectTarget::Gone => write!(f, "{} 410", self.from),
+        }
+    }
+}
+
+/// Parse a redirect status code, only redirect codes are allowed
+fn parse_status(status: &str) -> Result<StatusCode, RedirectError> {
+    match status.parse::<StatusCode>() {
+        Ok(
+            status @ (StatusCode::MOVED_PERMANENTLY
+            | StatusCode::FOUND
+            | StatusCode::SEE_OTHER
+            | StatusCode::TEMPORARY_REDIRECT
+            | StatusCode::PERMANENT_REDIRECT),
+        ) => Ok(status),
+        _ => Err(RedirectError::UnsupportedStatus(status.to_string())),
+    }
+}
+
+/// All configured redirects, indexed by their source path
+#[derive(Clone, Debug, Default)]
+pub struct Redirects {
+    redirects: HashMap<String, RedirectTarget>,
+}
+
+impl Redirects {
+    pub fn new(redirects: &[Redirect]) -> Self {
+        Self {
+            redirects: redirects
+                .iter()
+                .map(|redirect| (redirect.from.clone(), redirect.target.clone()))
+                .collect(),
+        }
+    }
+
+    /// Create a response for the path, when it has a redirect
+    ///
+    /// The query of the request is kept, unless the target has its own query
+    pub fn response(&self, path: &str, query: Option<&str>) -> Option<Response> {
+        match self.redirects.get(path)? {
+            RedirectTarget::Location { location, status } => {
+                let location = match (query, location.to_str()) {
+                    (Some(query), Ok(target)) if !target.contains('?') => {
+                        HeaderValue::from_str(&format!("{target}?{query}"))
+                            .unwrap_or_else(|_| location.clone())
+                    }
+                    _ => location.clone(),
+                };
+
+                Some((*status, [(LOCATION, location)]).into_response())
+            }
+
+            RedirectTarget::Gone => Some(StatusCode::GONE.into_response()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn redirects(redirects: &[&str]) -> Redirects {
+        let redirects = redirects
+            .iter()
+            .map(|redirect| redirect.parse::<Redirect>().expect("A valid redirect"))
+            .collect::<Vec<_>>();
+
+        Redirects::new(&redirects)
+    }
+
+    #[test]
+    fn test_invalid_redirects() {
+        assert!("/old".parse::<Redirect>().is_err());
+        assert!("old /new".parse::<Redirect>().is_err());
+        assert!("/old /new 200".parse::<Redirect>().is_err());
+        assert!("/old /new 301 extra".parse::<Redirect>().is_err());
+    }
+
+    #[test]
+    fn test_redirect() {
+        let redirects = redirects(&["/old /new", "/temp /elsewhere 307", "/removed 410"]);
+
+        let response = redirects.response("/old", None).expect("A redirect");
+        assert_eq!(response.status(), StatusCode::MOVED_PERMANENTLY);
+        assert_eq!(response.headers()[LOCATION], "/new");
+
+        let response = redirects.response("/temp", None).expect("A redirect");
+        assert_eq!(response.status(), StatusCode::TEMPORARY_REDIRECT);
+        assert_eq!(response.headers()[LOCATION], "/elsewhere");
+
+        let response = redirects.response("/removed", None).expect("A redirect");
+        assert_eq!(response.status(), StatusCode::GONE);
+
+        assert!(redirects.response("/other", None).is_none());
+    }
+
+    #[test]
+    fn test_redirect_keeps_query() {
+        let redirects = redirects(&["/old /new", "/search /find?q=all"]);
+
+        let response = redirects
+            .response("/old", Some("page=2"))
+            .expect("A redirect");
+        assert_eq!(response.headers()[LOCATION], "/new?page=2");
+
+        let response = redirects
+            .response("/search", Some("page=2"))
+            .expect("A redirect");
+        assert_eq!(response.headers()[LOCATION], "/find?q=all");
+    }
+}
@@ -0,0 +1,311 @@
+//! Content validation of the base dir
+//!
+//! Every servable file is requested through the router (in-process), checking
+//! the status, content type and content length against the file on disk.
+//! Precompressed variants should decode to the content of the original file.
+
+use std::io::Read;
+use std::path::Path;
+use std::path::PathBuf;
+
+use axum::body::Body;
+use axum::http::header::ACCEPT_ENCODING;
+use axum::http::header::CONTENT_ENCODING;
+use axum::http::header::CONTENT_LENGTH;
+use axum::http::header::CONTENT_TYPE;
+use axum::http::HeaderValue;
+use axum::http::Request;
+use axum::http::StatusCode;
+use axum::Router;
+use percent_encoding::utf8_percent_encode;
+use tower::ServiceExt;
+
+use crate::app::app;
+use crate::app::ServerState;
+use crate::config::Config;
+use crate::encoding::Encoding;
+use crate::file_cache::content_type;
+use crate::minify::Minifiable;
+use crate::minify::MAX_MINIFY_SIZE;
+use crate::utils::PATH_SEGMENT;
+
+#[derive(Debug, thiserror::Error)]
+pub enum SelftestError {
+    #[error("Could not read \"{0}\": {1}")]
+    Io(PathBuf, std::io::Error),
+
+    #[error("The path is not valid UTF-8")]
+    InvalidPath,
+
+    #[error("Could not request the file: {0}")]
+    Request(String),
+
+    #[error("Expected status 200, got {0}")]
+    Status(StatusCode),
+
+    #[error("Expected content type {expected:?}, got {actual:?}")]
+    ContentType {
+        expected: HeaderValue,
+        actual: Option<HeaderValue>,
+    },
+
+    #[error("Expected content length {expected}, got {actual}")]
+    ContentLength { expected: u64, actual: u64 },
+
+    #[error("Expected content encoding {expected:?}, got {actual:?}")]
+    ContentEncoding {
+        expected: HeaderValue,
+        actual: Option<HeaderValue>,
+    },
+
+    #[error("The {0:?} variant does not decode to the original content")]
+    Decode(Encoding),
+}
+
+/// Validate all files in the base dir, failing when one of the files fails
+pub async fn selftest(config: Config) -> anyhow::Result<()> {
+    let state = ServerState::from_config(config);
+    let base_dir = state.config.base_dir.clone();
+    let router = app(state.clone());
+
+    let mut files = vec![];
+    collect_files(&base_dir, &mut files)?;
+
+    let files = files
+        .into_iter()
+        .filter(|file| !is_sidecar(file) && state.is_servable(file))
+        .collect::<Vec<_>>();
+
+    let mut failures = 0;
+
+    for file in &files {
+        let relative_path = file.strip_prefix(&base_dir).unwrap_or(file);
+
+        match check_file(&router, &state, relative_path, file).await {
+            Ok(()) => tracing::debug!("Checked {relative_path:?}"),
+            Err(err) => {
+                println!("FAIL {}: {err}", relative_path.display());
+                failures += 1;
+            }
+        }
+    }
+
+    println!("Checked {} file(s), {failures} failed", files.len());
+
+    if failures > 0 {
+        anyhow::bail!("{failures} file(s) failed the selftest");
+    }
+
+    Ok(())
+}
+
+/// Collect all files in the dir, recursively and in a stable order
+pub fn collect_files(dir: &Path, files: &mut Vec<PathBuf>) -> Result<(), SelftestError> {
+    let mut entries = std::fs::read_dir(dir)
+        .map_err(|err| SelftestError::Io(dir.to_path_buf(), err))?
+        .filter_map(Result::ok)
+        .map(|entry| entry.path())
+        .collect::<Vec<_>>();
+    entries.sort();
+
+    for path in entries {
+        if path.is_dir() {
+            collect_files(&path, files)?;
+        } else if path.is_file() {
+            files.push(path);
+        }
+    }
+
+    Ok(())
+}
+
+/// Check if the file is a precompressed variant of another file
+fn is_sidecar(path: &Path) -> bool {
+    Encoding::ALL.iter().any(|encoding| {
+        path.to_str()
+            .and_then(|path| path.strip_suffix(encoding.get_extension()))
+            .is_some_and(|original| Path::new(original).is_file())
+    })
+}
+
+/// URL path of a file, relative to the base dir
+fn url_path(relative_path: &Path) -> Result<String, SelftestError> {
+    let mut url_path = String::new();
+
+    for segment in relative_path {
+        let segment = segment.to_str().ok_or(SelftestError::InvalidPath)?;
+
+        url_path.push('/');
+        url_path.extend(utf8_percent_encode(segment, PATH_SEGMENT));
+    }
+
+    Ok(url_path)
+}
+
+/// Request a file, with an optional `accept-encoding` header
+async fn request(
+    router: &Router,
+    url_path: &str,
+    encoding: Option<Encoding>,
+) -> Result<(StatusCode, axum::http::HeaderMap, Vec<u8>), SelftestError> {
+    let mut request = Request::get(url_path)
+        .body(Body::empty())
+        .map_err(|err| SelftestError::Request(err.to_string()))?;
+
+    if let Some(encoding) = encoding {
+        request
+            .headers_mut()
+            .insert(ACCEPT_ENCODING, encoding.to_header_value());
+    }
+
+    let response = router
+        .clone()
+        .oneshot(request)
+        .await
+        .map_err(|err| SelftestError::Request(err.to_string()))?;
+
+    let (parts, body) = response.into_parts();
+    let body = axum::body::to_bytes(body, usize::MAX)
+        .await
+        .map_err(|err| SelftestError::Request(err.to_string()))?;
+
+    Ok((parts.status, parts.headers, body.to_vec()))
+}
+
+/// Check a single file, including its precompressed variants
+async fn check_file(
+    router: &Router,
+    state: &ServerState,
+    relative_path: &Path,
+    file: &Path,
+) -> Result<(), SelftestError> {
+    let url_path = url_path(relative_path)?;
+
+    if state.is_hidden(&url_path) {
+        tracing::debug!("Skipping {relative_path:?}, it is hidden");
+        return Ok(());
+    }
+    let content = std::fs::read(file).map_err(|err| SelftestError::Io(file.to_path_buf(), err))?;
+
+    if state
+        .config
+        .max_file_size
+        .is_some_and(|max_file_size| content.len() as u64 > max_file_size)
+    {
+        tracing::debug!("Skipping {relative_path:?}, it is above the max file size");
+        return Ok(());
+    }
+
+    let (status, headers, body) = request(router, &url_path, None).await?;
+
+    if status != StatusCode::OK {
+        return Err(SelftestError::Status(status));
+    }
+
+    let expected_content_type = content_type(file);
+    let actual_content_type = headers.get(CONTENT_TYPE);
+
+    if actual_content_type != Some(&expected_content_type) {
+        return Err(SelftestError::ContentType {
+            expected: expected_content_type,
+            actual: actual_content_type.cloned(),
+        });
+    }
+
+    // minified files are served at their minified length
+    let expected_length = Minifiable::from_path(file)
+        .filter(|_| state.config.minify && content.len() as u64 <= MAX_MINIFY_SIZE)
+        .and_then(|minifiable| minifiable.minify(&content))
+        .map_or(content.len(), |minified| minified.len()) as u64;
+
+    let content_length = headers
+        .get(CONTENT_LENGTH)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.parse::<u64>().ok())
+        .unwrap_or(body.len() as u64);
+
+    for actual in [content_length, body.len() as u64] {
+        if actual != expected_length {
+            return Err(SelftestError::ContentLength {
+                expected: expected_length,
+                actual,
+            });
+        }
+    }
+
+    for encoding in Encoding::ALL {
+        let mut sidecar = file.as_os_str().to_owned();
+        sidecar.push(encoding.get_extension());
+
+        if !Path::new(&sidecar).is_file() {
+            continue;
+        }
+
+        let (status, headers, body) = request(router, &url_path, Some(encoding)).await?;
+
+        if status != StatusCode::OK {
+            return Err(SelftestError::Status(status));
+        }
+
+        if headers.get(CONTENT_ENCODING) != Some(&encoding.to_header_value()) {
+            return Err(SelftestError::ContentEncoding {
+                expected: encoding.to_header_value(),
+                actual: headers.get(CONTENT_ENCODING).cloned(),
+            });
+        }
+
+        if decode(encoding, &body).as_deref() != Some(content.as_slice()) {
+            return Err(SelftestError::Decode(encoding));
+        }
+    }
+
+    Ok(())
+}
+
+/// Decode encoded content, `None` when the content is not validly encoded
+fn decode(encoding: Encoding, encoded: &[u8]) -> Option<Vec<u8>> {
+    let mut decoded = vec![];
+
+    let result = match encoding {
+        Encoding::Brotli => brotli::Decompressor::new(encoded, 4096).read_to_end(&mut decoded),
+        Encoding::Zstd => {
+            zstd::Decoder::new(encoded).and_then(|mut decoder| decoder.read_to_end(&mut decoded))
+        }
+        Encoding::Gzip => flate2::read::GzDecoder::new(encoded).read_to_end(&mut decoded),
+        Encoding::Deflate => flate2::read::ZlibDecoder::new(encoded).read_to_end(&mut decoded),
+    };
+
+    result.ok().map(|_| decoded)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_url_path() {
+        assert_eq!(
+            url_path(Path::new("docs/index.html")).ok().as_deref(),
+            Some("/docs/index.html")
+        );
+        assert_eq!(
+            url_path(Path::new("my files/a#b.txt")).ok().as_deref(),
+            Some("/my%20files/a%23b.txt")
+        );
+    }
+
+    #[test]
+    fn test_decode() {
+        use std::io::Write;
+
+        let mut gzip = flate2::write::GzEncoder::new(vec![], flate2::Compression::default());
+        gzip.write_all(b"hello").expect("Writable encoder");
+        let encoded = gzip.finish().expect("Finished encoder");
+
+        assert_eq!(
+            decode(Encoding::Gzip, &encoded).as_deref(),
+            Some(&b"hello"[..])
+        );
+        assert_eq!(decode(Encoding::Gzip, b"hello"), None);
+    }
+}
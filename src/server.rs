@@ -0,0 +1,904 @@
+//! HTTP server, accepting and serving connections
+//!
+//! Connections are guarded against slow (or stalled) clients:
+//! - Request headers should be received within the header read timeout
+//! - Connections without any progress for the idle timeout are closed
+//! - Clients that can not keep up with the minimum transfer rate are dropped
+//!
+//! Hyper has no support for informational responses on the server side, so
+//! `103 Early Hints` are written to the socket directly. That is limited to
+//! HTTP/1.1 over plain TCP, other protocols would break. Hyper only starts on a
+//! pipelined request once the previous response is completely in its buffer,
+//! early hints are skipped until that buffer is flushed, so they never end up
+//! in the middle of a previous response.
+
+use std::future::Future;
+use std::io;
+use std::net::SocketAddr;
+use std::num::NonZeroUsize;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::sync::MutexGuard;
+use std::sync::OnceLock;
+use std::sync::PoisonError;
+use std::task::ready;
+use std::task::Context;
+use std::task::Poll;
+use std::time::Duration;
+
+use axum::extract::ConnectInfo;
+use axum::http::HeaderValue;
+use axum::http::Version;
+use axum::Router;
+use hyper::body::Incoming;
+use hyper::server::conn::http1;
+use hyper::service::service_fn;
+use hyper_util::rt::TokioExecutor;
+use hyper_util::rt::TokioIo;
+use hyper_util::rt::TokioTimer;
+use hyper_util::server::conn::auto;
+use socket2::SockRef;
+use tokio::io::AsyncRead;
+use tokio::io::AsyncWrite;
+use tokio::io::ReadBuf;
+use tokio::net::TcpListener;
+use tokio::net::TcpStream;
+use tokio::sync::watch;
+use tokio::time::Instant;
+use tokio::time::Sleep;
+use tokio_rustls::TlsAcceptor;
+use tower::ServiceExt;
+
+use crate::config::Config;
+use crate::connections::Connection;
+use crate::connections::Connections;
+use crate::throttle::Bandwidth;
+use crate::throttle::ConnectionBandwidth;
+use crate::tls::client_certificate;
+use crate::tls::is_acme_challenge;
+use crate::tls::ClientCertificate;
+
+/// Window over which the transfer rate of a client is measured
+const RATE_WINDOW: Duration = Duration::from_secs(30);
+
+/// Limits applied to every connection
+#[derive(Clone, Copy, Debug)]
+pub struct ConnectionLimits {
+    /// Time a client gets to send the headers of a request
+    pub header_read_timeout: Duration,
+
+    /// Time a connection can be without any progress, in either direction
+    pub idle_timeout: Duration,
+
+    /// Minimum number of bytes per second a client should receive, when it
+    /// is not keeping up with the data being sent
+    pub min_rate: Option<u64>,
+
+    /// Window over which the transfer rate of a client is measured
+    pub rate_window: Duration,
+
+    /// Maximum number of simultaneous connections of a single address
+    pub max_connections_per_ip: Option<usize>,
+
+    /// Maximum number of bytes per second sent to a client, for the bodies of
+    /// its responses
+    pub throttle: Option<u64>,
+}
+
+impl ConnectionLimits {
+    pub fn from_config(config: &Config) -> Self {
+        Self {
+            header_read_timeout: config.header_read_timeout,
+            idle_timeout: config.idle_timeout,
+            min_rate: (config.min_rate > 0).then_some(config.min_rate),
+            rate_window: RATE_WINDOW,
+            max_connections_per_ip: config.max_connections_per_ip.map(NonZeroUsize::get),
+            throttle: config.throttle_connection,
+        }
+    }
+}
+
+/// Bytes written to a connection outside of hyper
+#[derive(Debug, Default)]
+struct Interim {
+    /// Bytes that still have to be written, ahead of anything hyper writes
+    pending: Vec<u8>,
+
+    /// Hyper is writing bytes that are not flushed yet, ie a previous
+    /// response is still on its way
+    unflushed: bool,
+}
+
+/// Handle to send `103 Early Hints` on a HTTP/1.1 connection, available as a
+/// request extension
+#[derive(Clone, Debug)]
+pub struct EarlyHints {
+    stream: Arc<TcpStream>,
+    interim: Arc<Mutex<Interim>>,
+}
+
+impl EarlyHints {
+    /// Send the links in a `103 Early Hints` response, skipped when the
+    /// connection is still busy with a previous response
+    pub fn send(&self, links: &[HeaderValue]) {
+        let mut interim = lock(&self.interim);
+
+        if interim.unflushed || !interim.pending.is_empty() {
+            tracing::trace!("Connection is busy, skipping early hints");
+            return;
+        }
+
+        let mut response = b"HTTP/1.1 103 Early Hints\r\n".to_vec();
+        for link in links {
+            response.extend_from_slice(b"link: ");
+            response.extend_from_slice(link.as_bytes());
+            response.extend_from_slice(b"\r\n");
+        }
+        response.extend_from_slice(b"\r\n");
+
+        match self.stream.try_write(&response) {
+            Ok(written) => interim.pending = response.split_off(written),
+            Err(err) if err.kind() == io::ErrorKind::WouldBlock => interim.pending = response,
+            Err(err) => tracing::debug!("Could not send early hints: {err}"),
+        }
+    }
+}
+
+/// Lock the interim bytes, they are always consistent so poisoning is ignored
+fn lock(interim: &Mutex<Interim>) -> MutexGuard<'_, Interim> {
+    interim.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+/// Stream that enforces the connection limits on the underlying stream
+struct GuardedStream {
+    stream: Arc<TcpStream>,
+    interim: Arc<Mutex<Interim>>,
+    limits: ConnectionLimits,
+    connection: Arc<Connection>,
+
+    /// Moment the connection is considered idle
+    idle: Pin<Box<Sleep>>,
+
+    /// Bytes written in the current rate window, only set once the client is
+    /// not keeping up
+    rate_window: Option<u64>,
+
+    /// End of the current rate window
+    rate_deadline: Pin<Box<Sleep>>,
+}
+
+impl GuardedStream {
+    fn new(early_hints: EarlyHints, limits: ConnectionLimits, connection: Arc<Connection>) -> Self {
+        Self {
+            stream: early_hints.stream,
+            interim: early_hints.interim,
+            limits,
+            connection,
+            idle: Box::pin(tokio::time::sleep(limits.idle_timeout)),
+            rate_window: None,
+            rate_deadline: Box::pin(tokio::time::sleep(limits.rate_window)),
+        }
+    }
+
+    /// Some progress was made, the connection is not idle
+    fn progress(&mut self) {
+        self.idle
+            .as_mut()
+            .reset(Instant::now() + self.limits.idle_timeout);
+    }
+
+    /// Read from the socket
+    fn poll_read_stream(
+        &self,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        loop {
+            ready!(self.stream.poll_read_ready(cx))?;
+
+            match self.stream.try_read(buf.initialize_unfilled()) {
+                Ok(read) => {
+                    buf.advance(read);
+                    return Poll::Ready(Ok(()));
+                }
+                Err(err) if err.kind() == io::ErrorKind::WouldBlock => {}
+                Err(err) => return Poll::Ready(Err(err)),
+            }
+        }
+    }
+
+    /// Write to the socket, after the pending interim bytes
+    fn poll_write_stream(&self, cx: &mut Context<'_>, buf: &[u8]) -> Poll<io::Result<usize>> {
+        let mut interim = lock(&self.interim);
+
+        // busy from the first attempt, a write that would block is still on its way
+        interim.unflushed = true;
+
+        while !interim.pending.is_empty() {
+            ready!(self.stream.poll_write_ready(cx))?;
+
+            match self.stream.try_write(&interim.pending) {
+                Ok(written) => drop(interim.pending.drain(..written)),
+                Err(err) if err.kind() == io::ErrorKind::WouldBlock => {}
+                Err(err) => return Poll::Ready(Err(err)),
+            }
+        }
+
+        loop {
+            ready!(self.stream.poll_write_ready(cx))?;
+
+            match self.stream.try_write(buf) {
+                Ok(written) => return Poll::Ready(Ok(written)),
+                Err(err) if err.kind() == io::ErrorKind::WouldBlock => {}
+                Err(err) => return Poll::Ready(Err(err)),
+            }
+        }
+    }
+
+    /// Check if the connection has been idle for too long
+    fn poll_idle(&mut self, cx: &mut Context<'_>) -> Poll<io::Error> {
+        self.idle.as_mut().poll(cx).map(|()| {
+            tracing::debug!("Closing idle connection");
+            io::Error::new(io::ErrorKind::TimedOut, "connection idle for too long")
+        })
+    }
+
+    /// Start measuring the transfer rate, the client is not keeping up
+    fn start_rate_window(&mut self) {
+        if self.limits.min_rate.is_some() && self.rate_window.is_none() {
+            self.rate_window = Some(0);
+            self.rate_deadline
+                .as_mut()
+                .reset(Instant::now() + self.limits.rate_window);
+        }
+    }
+
+    /// Check if the client is keeping up with the minimum transfer rate
+    ///
+    /// Every window is measured on its own, so filling the socket buffers at
+    /// the start of a transfer does not hide a client that stalls afterwards
+    fn poll_rate(&mut self, cx: &mut Context<'_>) -> Poll<io::Error> {
+        let (Some(min_rate), Some(written)) = (self.limits.min_rate, self.rate_window) else {
+            return Poll::Pending;
+        };
+
+        if self.rate_deadline.as_mut().poll(cx).is_pending() {
+            return Poll::Pending;
+        }
+
+        if u128::from(written) * 1000 < u128::from(min_rate) * self.limits.rate_window.as_millis() {
+            tracing::debug!("Closing connection below the minimum transfer rate");
+            return Poll::Ready(io::Error::new(
+                io::ErrorKind::TimedOut,
+                "connection below the minimum transfer rate",
+            ));
+        }
+
+        // the client kept up, measure again once it falls behind
+        self.rate_window = None;
+
+        Poll::Pending
+    }
+}
+
+impl AsyncRead for GuardedStream {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let filled = buf.filled().len();
+
+        match self.poll_read_stream(cx, buf) {
+            Poll::Ready(Ok(())) => {
+                if buf.filled().len() > filled {
+                    self.progress();
+                }
+
+                Poll::Ready(Ok(()))
+            }
+
+            Poll::Ready(Err(err)) => Poll::Ready(Err(err)),
+
+            Poll::Pending => self.poll_idle(cx).map(Err),
+        }
+    }
+}
+
+impl AsyncWrite for GuardedStream {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        match self.poll_write_stream(cx, buf) {
+            Poll::Ready(Ok(written)) => {
+                self.progress();
+                self.connection.add_bytes_sent(written as u64);
+
+                if let Some(window) = &mut self.rate_window {
+                    *window += written as u64;
+                }
+
+                match self.poll_rate(cx) {
+                    Poll::Ready(err) => Poll::Ready(Err(err)),
+                    Poll::Pending => Poll::Ready(Ok(written)),
+                }
+            }
+
+            Poll::Ready(Err(err)) => Poll::Ready(Err(err)),
+
+            Poll::Pending => {
+                self.start_rate_window();
+
+                if let Poll::Ready(err) = self.poll_rate(cx) {
+                    return Poll::Ready(Err(err));
+                }
+
+                self.poll_idle(cx).map(Err)
+            }
+        }
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        // hyper only flushes once its own buffer is written, so the response
+        // so far is on its way
+        lock(&self.interim).unflushed = false;
+
+        Poll::Ready(Ok(()))
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Poll::Ready(SockRef::from(&*self.stream).shutdown(std::net::Shutdown::Write))
+    }
+}
+
+/// Accept a connection, connection errors of a single client are ignored
+async fn accept(listener: &TcpListener) -> Option<(TcpStream, SocketAddr)> {
+    match listener.accept().await {
+        Ok(connection) => Some(connection),
+
+        Err(err) => {
+            if matches!(
+                err.kind(),
+                io::ErrorKind::ConnectionRefused
+                    | io::ErrorKind::ConnectionAborted
+                    | io::ErrorKind::ConnectionReset
+            ) {
+                return None;
+            }
+
+            // most likely out of file descriptors, give other connections some time to finish
+            tracing::error!("Could not accept connection: {err}");
+            tokio::time::sleep(Duration::from_secs(1)).await;
+            None
+        }
+    }
+}
+
+/// Serve the app on the listener until the shutdown signal completes
+///
+/// In-flight requests are given the chance to finish after the shutdown signal
+pub async fn serve(
+    listener: TcpListener,
+    app: Router,
+    tls: Option<TlsAcceptor>,
+    http1_only: bool,
+    limits: ConnectionLimits,
+    connections: Arc<Connections>,
+    shutdown: impl Future<Output = ()> + Send + 'static,
+) {
+    let (signal_tx, signal_rx) = watch::channel(());
+    let signal_tx = Arc::new(signal_tx);
+
+    tokio::spawn(async move {
+        shutdown.await;
+        drop(signal_rx);
+    });
+
+    let (close_tx, close_rx) = watch::channel(());
+
+    let builder = Arc::new(ConnectionBuilder::new(http1_only, limits));
+
+    loop {
+        let (stream, remote_address) = tokio::select! {
+            connection = accept(&listener) => match connection {
+                Some(connection) => connection,
+                None => continue,
+            },
+
+            () = signal_tx.closed() => break,
+        };
+
+        tracing::trace!("Connection {remote_address} accepted");
+
+        let Some(guard) = connections.try_register(remote_address, limits.max_connections_per_ip)
+        else {
+            // closed right away, before any work is done for it
+            tracing::debug!("Too many connections from {}", remote_address.ip());
+            continue;
+        };
+
+        let connection = Arc::clone(guard.connection());
+
+        let early_hints = EarlyHints {
+            stream: Arc::new(stream),
+            interim: Arc::default(),
+        };
+
+        let app = app.clone();
+        let service_early_hints = tls.is_none().then(|| early_hints.clone());
+        // only known after the TLS handshake
+        let certificate = Arc::new(OnceLock::<ClientCertificate>::new());
+        let service_certificate = Arc::clone(&certificate);
+        let bandwidth = limits
+            .throttle
+            .map(|rate| ConnectionBandwidth(Arc::new(Bandwidth::new(rate))));
+        let service = service_fn(move |mut request: hyper::Request<Incoming>| {
+            connection.start_request(request.method(), request.uri());
+            request.extensions_mut().insert(ConnectInfo(remote_address));
+
+            if let Some(bandwidth) = &bandwidth {
+                request.extensions_mut().insert(bandwidth.clone());
+            }
+
+            if let Some(certificate) = service_certificate.get() {
+                request.extensions_mut().insert(certificate.clone());
+            }
+
+            // written as plain HTTP/1.1, other versions would break
+            if let Some(early_hints) = &service_early_hints {
+                if request.version() == Version::HTTP_11 {
+                    request.extensions_mut().insert(early_hints.clone());
+                }
+            }
+
+            app.clone().oneshot(request)
+        });
+
+        let builder = Arc::clone(&builder);
+        let signal_tx = Arc::clone(&signal_tx);
+        let close_rx = close_rx.clone();
+        let tls = tls.clone();
+
+        tokio::spawn(async move {
+            let connection = Arc::clone(guard.connection());
+            let stream = GuardedStream::new(early_hints, limits, connection);
+
+            match tls {
+                None => {
+                    serve_connection(&builder, TokioIo::new(stream), service, &signal_tx).await;
+                }
+
+                Some(tls) => {
+                    // the handshake is bound by the same timeout as the request headers
+                    let handshake =
+                        tokio::time::timeout(limits.header_read_timeout, tls.accept(stream));
+
+                    match handshake.await {
+                        Ok(Ok(stream)) if is_acme_challenge(stream.get_ref().1) => {
+                            tracing::debug!("Answered ACME challenge from {remote_address}");
+                        }
+                        Ok(Ok(stream)) => {
+                            if let Some(client) = client_certificate(stream.get_ref().1) {
+                                tracing::trace!("Client {remote_address} is {}", client.subject);
+                                certificate.set(client).ok();
+                            }
+
+                            serve_connection(&builder, TokioIo::new(stream), service, &signal_tx)
+                                .await;
+                        }
+                        Ok(Err(err)) => {
+                            tracing::trace!("TLS handshake with {remote_address} failed: {err}");
+                        }
+                        Err(_) => tracing::trace!("TLS handshake with {remote_address} timed out"),
+                    }
+                }
+            }
+
+            tracing::trace!("Connection {remote_address} closed");
+
+            drop(guard);
+            drop(close_rx);
+        });
+    }
+
+    drop(close_rx);
+    drop(listener);
+
+    tracing::debug!(
+        "Waiting for {} connection(s) to finish",
+        close_tx.receiver_count()
+    );
+
+    close_tx.closed().await;
+}
+
+/// Builder for connections, the protocol is detected unless only HTTP/1.1 is allowed
+enum ConnectionBuilder {
+    Auto(auto::Builder<TokioExecutor>),
+    Http1(http1::Builder),
+}
+
+impl ConnectionBuilder {
+    fn new(http1_only: bool, limits: ConnectionLimits) -> Self {
+        if http1_only {
+            let mut builder = http1::Builder::new();
+            builder
+                .timer(TokioTimer::new())
+                .header_read_timeout(limits.header_read_timeout);
+
+            return Self::Http1(builder);
+        }
+
+        let mut builder = auto::Builder::new(TokioExecutor::new());
+        builder
+            .http1()
+            .timer(TokioTimer::new())
+            .header_read_timeout(limits.header_read_timeout);
+
+        Self::Auto(builder)
+    }
+}
+
+/// Serve a single connection, shutting down gracefully on the shutdown signal
+async fn serve_connection<I, S>(
+    builder: &ConnectionBuilder,
+    stream: I,
+    service: S,
+    signal_tx: &watch::Sender<()>,
+) where
+    I: hyper::rt::Read + hyper::rt::Write + Unpin + Send + 'static,
+    S: hyper::service::Service<hyper::Request<Incoming>, Response = axum::response::Response>
+        + Send
+        + 'static,
+    S::Future: Send + 'static,
+    S::Error: Into<Box<dyn std::error::Error + Send + Sync>>,
+{
+    match builder {
+        ConnectionBuilder::Auto(builder) => {
+            let connection = builder.serve_connection_with_upgrades(stream, service);
+            drive_connection(connection, signal_tx, |connection| {
+                connection.graceful_shutdown();
+            })
+            .await;
+        }
+
+        ConnectionBuilder::Http1(builder) => {
+            let connection = builder.serve_connection(stream, service).with_upgrades();
+            drive_connection(connection, signal_tx, |connection| {
+                connection.graceful_shutdown();
+            })
+            .await;
+        }
+    }
+}
+
+/// Run the connection to completion, it is shut down gracefully on the shutdown signal
+async fn drive_connection<C, E>(
+    connection: C,
+    signal_tx: &watch::Sender<()>,
+    graceful_shutdown: impl Fn(Pin<&mut C>),
+) where
+    C: Future<Output = Result<(), E>>,
+    E: std::fmt::Display,
+{
+    tokio::pin!(connection);
+
+    let signal_closed = signal_tx.closed();
+    tokio::pin!(signal_closed);
+
+    // the signal completes once, polling it again would panic
+    let mut shutting_down = false;
+
+    loop {
+        tokio::select! {
+            result = connection.as_mut() => {
+                if let Err(err) = result {
+                    tracing::trace!("Connection failed: {err}");
+                }
+
+                break;
+            }
+
+            () = &mut signal_closed, if !shutting_down => {
+                shutting_down = true;
+                graceful_shutdown(connection.as_mut());
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::body::Body;
+    use axum::body::Bytes;
+    use axum::routing::get;
+    use axum::Extension;
+    use futures_util::stream::unfold;
+    use tokio::io::AsyncReadExt;
+    use tokio::io::AsyncWriteExt;
+    use tokio::sync::oneshot;
+
+    use super::*;
+
+    /// Body of ten chunks, sent 20ms apart
+    fn slow_body() -> Body {
+        Body::from_stream(unfold(0, |sent| async move {
+            if sent == 10 {
+                return None;
+            }
+
+            tokio::time::sleep(Duration::from_millis(20)).await;
+            Some((
+                Ok::<_, io::Error>(Bytes::from_static(b"0123456789")),
+                sent + 1,
+            ))
+        }))
+    }
+
+    /// Limits that do not get in the way
+    fn limits() -> ConnectionLimits {
+        ConnectionLimits {
+            header_read_timeout: Duration::from_secs(10),
+            idle_timeout: Duration::from_secs(10),
+            min_rate: None,
+            rate_window: RATE_WINDOW,
+            max_connections_per_ip: None,
+            throttle: None,
+        }
+    }
+
+    /// Serve the app in the background, until the test is done
+    async fn start(app: Router, limits: ConnectionLimits) -> SocketAddr {
+        let listener = TcpListener::bind("127.0.0.1:0").await.expect("A free port");
+        let address = listener.local_addr().expect("A local address");
+
+        tokio::spawn(serve(
+            listener,
+            app,
+            None,
+            false,
+            limits,
+            Arc::default(),
+            std::future::pending(),
+        ));
+
+        address
+    }
+
+    /// Page sending a preload link in early hints
+    fn early_hints_app() -> Router {
+        let page = |Extension(early_hints): Extension<EarlyHints>| async move {
+            early_hints.send(&[HeaderValue::from_static(
+                "</app.css>; rel=preload; as=style",
+            )]);
+            "page"
+        };
+
+        Router::new()
+            .route("/", get(page))
+            .route("/large", get(|| async { vec![b'a'; 4 << 20] }))
+    }
+
+    #[tokio::test]
+    async fn test_shutdown_while_streaming() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.expect("A free port");
+        let address = listener.local_addr().expect("A local address");
+
+        let app = Router::new().route("/", get(|| async { slow_body() }));
+        let limits = limits();
+
+        let (shutdown_tx, shutdown_rx) = oneshot::channel::<()>();
+        let server = tokio::spawn(serve(
+            listener,
+            app,
+            None,
+            false,
+            limits,
+            Arc::default(),
+            async {
+                shutdown_rx.await.ok();
+            },
+        ));
+
+        let mut stream = TcpStream::connect(address).await.expect("A connection");
+        stream
+            .write_all(b"GET / HTTP/1.1\r\nhost: localhost\r\n\r\n")
+            .await
+            .expect("A written request");
+
+        // the response has started when the first data arrives
+        let mut response = vec![0; 1024];
+        let read = stream.read(&mut response).await.expect("A response");
+        response.truncate(read);
+
+        shutdown_tx.send(()).ok();
+
+        stream
+            .read_to_end(&mut response)
+            .await
+            .expect("The rest of the response");
+        server.await.expect("A stopped server");
+
+        let response = String::from_utf8_lossy(&response);
+        assert!(response.starts_with("HTTP/1.1 200 OK"));
+        assert_eq!(response.matches("0123456789").count(), 10);
+    }
+
+    #[tokio::test]
+    async fn test_early_hints() {
+        let address = start(early_hints_app(), limits()).await;
+
+        let mut stream = TcpStream::connect(address).await.expect("A connection");
+        stream
+            .write_all(b"GET / HTTP/1.1\r\nhost: localhost\r\nconnection: close\r\n\r\n")
+            .await
+            .expect("A written request");
+
+        let mut response = vec![];
+        stream.read_to_end(&mut response).await.expect("A response");
+
+        let response = String::from_utf8_lossy(&response);
+        assert!(response.starts_with(
+            "HTTP/1.1 103 Early Hints\r\nlink: </app.css>; rel=preload; as=style\r\n\r\nHTTP/1.1 200 OK\r\n"
+        ));
+        assert!(response.ends_with("\r\n\r\npage"));
+    }
+
+    #[tokio::test]
+    async fn test_early_hints_pipelined() {
+        let address = start(early_hints_app(), limits()).await;
+
+        // the large response fills the socket buffers, it is still on its way
+        // when the second request is handled
+        let mut stream = TcpStream::connect(address).await.expect("A connection");
+        stream
+            .write_all(
+                b"GET /large HTTP/1.1\r\nhost: localhost\r\n\r\n\
+                GET / HTTP/1.1\r\nhost: localhost\r\nconnection: close\r\n\r\n",
+            )
+            .await
+            .expect("Written requests");
+
+        tokio::time::sleep(Duration::from_millis(100)).await;
+
+        let mut response = vec![];
+        stream.read_to_end(&mut response).await.expect("A response");
+
+        let head = b"HTTP/1.1 200 OK\r\n";
+        assert!(response.starts_with(head));
+
+        let body_start = response
+            .windows(4)
+            .position(|window| window == b"\r\n\r\n")
+            .expect("The end of the headers")
+            + 4;
+        let (body, rest) = response[body_start..].split_at(4 << 20);
+        assert!(body.iter().all(|byte| *byte == b'a'));
+
+        let rest = String::from_utf8_lossy(rest);
+        assert!(rest.starts_with("HTTP/1.1 "));
+        assert!(rest.ends_with("\r\n\r\npage"));
+    }
+
+    #[tokio::test]
+    async fn test_early_hints_while_blocked() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.expect("A free port");
+        let address = listener.local_addr().expect("A local address");
+        let _client = TcpStream::connect(address).await.expect("A connection");
+        let (stream, peer) = listener.accept().await.expect("An accepted connection");
+
+        let connections = Arc::new(Connections::default());
+        let guard = connections
+            .try_register(peer, None)
+            .expect("A registered connection");
+        let early_hints = EarlyHints {
+            stream: Arc::new(stream),
+            interim: Arc::default(),
+        };
+        let mut stream = GuardedStream::new(
+            early_hints.clone(),
+            limits(),
+            Arc::clone(guard.connection()),
+        );
+
+        // the client does not read, so the socket buffers fill up
+        let chunk = vec![b'a'; 64 << 10];
+        let timeout = Duration::from_millis(50);
+        while tokio::time::timeout(timeout, stream.write(&chunk))
+            .await
+            .is_ok()
+        {}
+        stream.flush().await.expect("A flushed stream");
+
+        // the next response can not be written, it is still on its way
+        assert!(tokio::time::timeout(timeout, stream.write(&chunk))
+            .await
+            .is_err());
+
+        early_hints.send(&[HeaderValue::from_static(
+            "</app.css>; rel=preload; as=style",
+        )]);
+        assert!(lock(&early_hints.interim).pending.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_idle_connection_closed() {
+        let app = Router::new().route("/", get(|| async { "page" }));
+        let address = start(
+            app,
+            ConnectionLimits {
+                idle_timeout: Duration::from_millis(200),
+                ..limits()
+            },
+        )
+        .await;
+
+        let mut stream = TcpStream::connect(address).await.expect("A connection");
+        stream
+            .write_all(b"GET / HTTP/1.1\r\nhost: localhost\r\n\r\n")
+            .await
+            .expect("A written request");
+
+        // the connection is kept alive after the response, until it is idle for too long
+        let started = Instant::now();
+        let mut response = vec![];
+        let read = tokio::time::timeout(Duration::from_secs(5), stream.read_to_end(&mut response));
+        read.await.expect("A closed connection").ok();
+
+        assert!(started.elapsed() >= Duration::from_millis(200));
+        assert!(String::from_utf8_lossy(&response).ends_with("\r\n\r\npage"));
+    }
+
+    #[tokio::test]
+    async fn test_min_rate() {
+        let app = Router::new().route(
+            "/",
+            get(|| async {
+                Body::from_stream(unfold((), |()| async {
+                    Some((Ok::<_, io::Error>(Bytes::from(vec![b'a'; 64 << 10])), ()))
+                }))
+            }),
+        );
+        let address = start(
+            app,
+            ConnectionLimits {
+                min_rate: Some(1 << 20),
+                rate_window: Duration::from_millis(500),
+                ..limits()
+            },
+        )
+        .await;
+
+        let mut stream = TcpStream::connect(address).await.expect("A connection");
+        stream
+            .write_all(b"GET / HTTP/1.1\r\nhost: localhost\r\n\r\n")
+            .await
+            .expect("A written request");
+
+        // the client trickles along, far below the minimum rate
+        let mut received = 0;
+        let mut buf = vec![0; 1024];
+        for _ in 0..30 {
+            match stream.read(&mut buf).await {
+                Ok(0) | Err(_) => break,
+                Ok(read) => received += read,
+            }
+            tokio::time::sleep(Duration::from_millis(50)).await;
+        }
+
+        // what was already sent can be drained, but the body never ends
+        // unless the server gave up on the connection
+        tokio::time::timeout(
+            Duration::from_secs(5),
+            tokio::io::copy(&mut stream, &mut tokio::io::sink()),
+        )
+        .await
+        .expect("A closed connection")
+        .ok();
+
+        assert!(received > 0);
+    }
+}
@@ -0,0 +1,240 @@
+//! Request shadowing
+//!
+//! A sample of the requests is mirrored to another server, ie to load test or
+//! compare a new deployment on real traffic. Mirrored requests are fire and
+//! forget: the responses are discarded and never affect the client. Only
+//! `GET` and `HEAD` requests are mirrored, their bodies do not need to be
+//! buffered, and admin requests are never mirrored.
+//!
+//! Only authenticated requests are mirrored, without their credentials: the
+//! shadow server is reached over plain http and should not see passwords,
+//! tokens or cookies of the clients.
+
+use std::fmt;
+use std::net::SocketAddr;
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::Duration;
+
+use axum::body::Body;
+use axum::extract::ConnectInfo;
+use axum::extract::Request;
+use axum::extract::State;
+use axum::http::header::AUTHORIZATION;
+use axum::http::header::COOKIE;
+use axum::http::header::HOST;
+use axum::http::header::PROXY_AUTHORIZATION;
+use axum::http::uri::Scheme;
+use axum::http::HeaderName;
+use axum::http::HeaderValue;
+use axum::http::Method;
+use axum::http::Uri;
+use axum::middleware::Next;
+use axum::response::Response;
+use percent_encoding::percent_decode_str;
+use tokio::sync::Semaphore;
+
+use crate::admin::ADMIN_PREFIX;
+use crate::app::ServerState;
+use crate::forwarded::Origin;
+use crate::normalize::normalize_path;
+use crate::proxy::add_forwarded_headers;
+use crate::proxy::remove_hop_by_hop_headers;
+use crate::utils::chance;
+
+/// Headers with credentials of the client, they are not mirrored
+const CREDENTIAL_HEADERS: &[HeaderName] = &[AUTHORIZATION, COOKIE, PROXY_AUTHORIZATION];
+
+/// Header that marks a request as mirrored
+const X_SRVR_SHADOW: HeaderName = HeaderName::from_static("x-srvr-shadow");
+
+/// Maximum number of mirrored requests in flight, more are dropped
+const MAX_IN_FLIGHT: usize = 64;
+
+/// Time a mirrored request gets to complete
+const SHADOW_TIMEOUT: Duration = Duration::from_secs(10);
+
+#[derive(Debug, thiserror::Error)]
+pub enum ShadowTargetError {
+    #[error("Invalid shadow target: {0}")]
+    InvalidTarget(#[from] axum::http::uri::InvalidUri),
+
+    #[error("Only http:// shadow targets are supported")]
+    UnsupportedScheme,
+
+    #[error("A shadow target can not have a query")]
+    UnsupportedQuery,
+}
+
+/// Server to mirror requests to
+#[derive(Clone, Debug)]
+pub struct ShadowTarget(Uri);
+
+impl FromStr for ShadowTarget {
+    type Err = ShadowTargetError;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let target = value.parse::<Uri>()?;
+
+        if target.scheme() != Some(&Scheme::HTTP) || target.authority().is_none() {
+            return Err(ShadowTargetError::UnsupportedScheme);
+        }
+
+        if target.query().is_some() {
+            return Err(ShadowTargetError::UnsupportedQuery);
+        }
+
+        Ok(Self(target))
+    }
+}
+
+impl fmt::Display for ShadowTarget {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+impl ShadowTarget {
+    /// The URI to mirror the request to, the path of the target is a prefix
+    fn uri(&self, uri: &Uri) -> Result<Uri, axum::http::Error> {
+        let prefix = self.0.path().trim_end_matches('/');
+        let path_and_query = uri.path_and_query().map_or("/", |path| path.as_str());
+
+        Uri::builder()
+            .scheme(Scheme::HTTP)
+            .authority(
+                self.0
+                    .authority()
+                    .expect("A shadow target with an authority")
+                    .clone(),
+            )
+            .path_and_query(format!("{prefix}{path_and_query}"))
+            .build()
+    }
+}
+
+/// Limits the number of mirrored requests in flight
+#[derive(Debug)]
+pub struct ShadowSlots(Arc<Semaphore>);
+
+impl Default for ShadowSlots {
+    fn default() -> Self {
+        Self(Arc::new(Semaphore::new(MAX_IN_FLIGHT)))
+    }
+}
+
+/// Middleware that mirrors a sample of the requests to the shadow target
+pub async fn shadow(State(state): State<ServerState>, request: Request, next: Next) -> Response {
+    let Some(target) = &state.config.shadow else {
+        return next.run(request).await;
+    };
+
+    // the path as it is served, an encoded admin path is an admin path as well
+    let path = normalize_path(&percent_decode_str(request.uri().path()).decode_utf8_lossy());
+
+    let is_mirrorable = matches!(*request.method(), Method::GET | Method::HEAD)
+        && path.is_some_and(|path| !path.starts_with(ADMIN_PREFIX));
+
+    if is_mirrorable && chance(state.config.shadow_sample) {
+        mirror(&state, target, &request);
+    }
+
+    next.run(request).await
+}
+
+/// Send a copy of the request to the target, in the background
+fn mirror(state: &ServerState, target: &ShadowTarget, request: &Request) {
+    let Ok(permit) = Arc::clone(&state.shadow_slots.0).try_acquire_owned() else {
+        tracing::debug!("Too many mirrored requests in flight, not mirroring");
+        return;
+    };
+
+    let uri = match target.uri(request.uri()) {
+        Ok(uri) => uri,
+        Err(err) => {
+            tracing::debug!("Could not mirror request: {err}");
+            return;
+        }
+    };
+
+    let origin = Origin::from_request(state, request);
+
+    let mut mirrored = Request::new(Body::empty());
+    *mirrored.method_mut() = request.method().clone();
+    *mirrored.uri_mut() = uri;
+    *mirrored.headers_mut() = request.headers().clone();
+
+    let client_address = request
+        .extensions()
+        .get::<ConnectInfo<SocketAddr>>()
+        .map(|ConnectInfo(address)| address.ip());
+
+    let headers = mirrored.headers_mut();
+    remove_hop_by_hop_headers(headers);
+    add_forwarded_headers(headers, client_address, &origin);
+    headers.remove(HOST);
+
+    for name in CREDENTIAL_HEADERS {
+        headers.remove(name);
+    }
+
+    headers.insert(X_SRVR_SHADOW, HeaderValue::from_static("1"));
+
+    let client = state.proxy_client.client().clone();
+
+    tokio::spawn(async move {
+        let uri = mirrored.uri().clone();
+
+        match tokio::time::timeout(SHADOW_TIMEOUT, client.request(mirrored)).await {
+            Ok(Ok(response)) => {
+                tracing::trace!("Mirrored request to {uri}: {}", response.status());
+            }
+            Ok(Err(err)) => tracing::debug!("Could not mirror request to {uri}: {err}"),
+            Err(_) => tracing::debug!("Mirrored request to {uri} timed out"),
+        }
+
+        drop(permit);
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_invalid_targets() {
+        assert!(matches!(
+            "https://staging".parse::<ShadowTarget>(),
+            Err(ShadowTargetError::UnsupportedScheme)
+        ));
+        assert!(matches!(
+            "/staging".parse::<ShadowTarget>(),
+            Err(ShadowTargetError::UnsupportedScheme)
+        ));
+        assert!(matches!(
+            "http://staging:8080/?a=b".parse::<ShadowTarget>(),
+            Err(ShadowTargetError::UnsupportedQuery)
+        ));
+    }
+
+    #[test]
+    fn test_uri() {
+        let uri = "/docs/guide?page=2".parse::<Uri>().expect("A valid uri");
+
+        let target = "http://staging:8080"
+            .parse::<ShadowTarget>()
+            .expect("A valid target");
+        assert_eq!(
+            target.uri(&uri).expect("A valid uri"),
+            "http://staging:8080/docs/guide?page=2"
+        );
+
+        let target = "http://staging:8080/mirror/"
+            .parse::<ShadowTarget>()
+            .expect("A valid target");
+        assert_eq!(
+            target.uri(&uri).expect("A valid uri"),
+            "http://staging:8080/mirror/docs/guide?page=2"
+        );
+    }
+}
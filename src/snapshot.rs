@@ -0,0 +1,239 @@
+//! Snapshot of the file cache, to start warm after a restart
+//!
+//! The snapshot is written on shutdown and read at startup. Entries are only
+//! restored when the file on disk still has the same size and modification
+//! time, a snapshot of a different format version (or made with a different
+//! `--minify` setting) is ignored as a whole.
+//!
+//! Format (all integers are little endian):
+//! - magic `SRVRSNAP` and the format version (`u32`)
+//! - whether the cached content is minified (`u8`)
+//! - the number of entries (`u64`), followed by the entries:
+//!   - path, content type (`u64` length + UTF-8 bytes)
+//!   - content length, last modified in seconds since the epoch (`u64`)
+//!   - whether the content is kept in memory (`u8`), followed by the content
+//!     (`u64` length + bytes) when it is
+
+use std::path::PathBuf;
+
+/// Start of every snapshot file
+const MAGIC: &[u8; 8] = b"SRVRSNAP";
+
+/// Version of the format, bump it on every change to the format
+const VERSION: u32 = 2;
+
+#[derive(Debug, thiserror::Error)]
+pub enum SnapshotError {
+    #[error("Could not access the snapshot \"{0}\": {1}")]
+    Io(PathBuf, std::io::Error),
+
+    #[error("The file is not a snapshot")]
+    NotASnapshot,
+
+    #[error("The snapshot has format version {0}, expected {VERSION}")]
+    Version(u32),
+
+    #[error("The snapshot was made with a different --minify setting")]
+    Minify,
+
+    #[error("The snapshot is truncated or corrupt")]
+    Corrupt,
+}
+
+/// The cached files
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Snapshot {
+    /// The content of HTML, CSS and JavaScript files is minified
+    pub minified: bool,
+    pub entries: Vec<SnapshotEntry>,
+}
+
+/// A single cached file
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SnapshotEntry {
+    pub path: PathBuf,
+    pub content_type: String,
+    pub content_length: u64,
+    pub last_modified: u64,
+
+    /// Content of the file, when it is kept in memory
+    pub content: Option<Vec<u8>>,
+}
+
+/// Encode the snapshot, entries without a UTF-8 path are skipped
+pub fn encode(snapshot: &Snapshot) -> Vec<u8> {
+    let entries = snapshot
+        .entries
+        .iter()
+        .filter_map(|entry| Some((entry.path.to_str()?, entry)))
+        .collect::<Vec<_>>();
+
+    let minified = snapshot.minified;
+
+    let mut snapshot = MAGIC.to_vec();
+    snapshot.extend_from_slice(&VERSION.to_le_bytes());
+    snapshot.push(u8::from(minified));
+    snapshot.extend_from_slice(&(entries.len() as u64).to_le_bytes());
+
+    for (path, entry) in entries {
+        write_bytes(&mut snapshot, path.as_bytes());
+        write_bytes(&mut snapshot, entry.content_type.as_bytes());
+        snapshot.extend_from_slice(&entry.content_length.to_le_bytes());
+        snapshot.extend_from_slice(&entry.last_modified.to_le_bytes());
+
+        if let Some(content) = &entry.content {
+            snapshot.push(1);
+            write_bytes(&mut snapshot, content);
+        } else {
+            snapshot.push(0);
+        }
+    }
+
+    snapshot
+}
+
+/// Decode a snapshot
+pub fn decode(snapshot: &[u8]) -> Result<Snapshot, SnapshotError> {
+    let mut reader = Reader(snapshot);
+
+    if reader.take(MAGIC.len()).ok() != Some(&MAGIC[..]) {
+        return Err(SnapshotError::NotASnapshot);
+    }
+
+    let version = u32::from_le_bytes(
+        reader
+            .take(4)?
+            .try_into()
+            .map_err(|_| SnapshotError::Corrupt)?,
+    );
+    if version != VERSION {
+        return Err(SnapshotError::Version(version));
+    }
+
+    let minified = match reader.take(1)? {
+        [0] => false,
+        [1] => true,
+        _ => return Err(SnapshotError::Corrupt),
+    };
+
+    let count = reader.u64()?;
+    let mut entries = vec![];
+
+    for _ in 0..count {
+        let path = PathBuf::from(reader.string()?);
+        let content_type = reader.string()?;
+        let content_length = reader.u64()?;
+        let last_modified = reader.u64()?;
+
+        let content = match reader.take(1)? {
+            [0] => None,
+            [1] => Some(reader.bytes()?.to_vec()),
+            _ => return Err(SnapshotError::Corrupt),
+        };
+
+        entries.push(SnapshotEntry {
+            path,
+            content_type,
+            content_length,
+            last_modified,
+            content,
+        });
+    }
+
+    Ok(Snapshot { minified, entries })
+}
+
+/// Write bytes, prefixed with their length
+fn write_bytes(snapshot: &mut Vec<u8>, bytes: &[u8]) {
+    snapshot.extend_from_slice(&(bytes.len() as u64).to_le_bytes());
+    snapshot.extend_from_slice(bytes);
+}
+
+/// Reads the parts of a snapshot, failing when it is too short
+struct Reader<'a>(&'a [u8]);
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, length: usize) -> Result<&'a [u8], SnapshotError> {
+        if self.0.len() < length {
+            return Err(SnapshotError::Corrupt);
+        }
+
+        let (taken, rest) = self.0.split_at(length);
+        self.0 = rest;
+
+        Ok(taken)
+    }
+
+    fn u64(&mut self) -> Result<u64, SnapshotError> {
+        self.take(8)?
+            .try_into()
+            .map(u64::from_le_bytes)
+            .map_err(|_| SnapshotError::Corrupt)
+    }
+
+    fn bytes(&mut self) -> Result<&'a [u8], SnapshotError> {
+        let length = usize::try_from(self.u64()?).map_err(|_| SnapshotError::Corrupt)?;
+        self.take(length)
+    }
+
+    fn string(&mut self) -> Result<String, SnapshotError> {
+        String::from_utf8(self.bytes()?.to_vec()).map_err(|_| SnapshotError::Corrupt)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn snapshot() -> Snapshot {
+        let entries = vec![
+            SnapshotEntry {
+                path: PathBuf::from("/site/index.html"),
+                content_type: String::from("text/html"),
+                content_length: 5,
+                last_modified: 1_700_000_000,
+                content: Some(b"hello".to_vec()),
+            },
+            SnapshotEntry {
+                path: PathBuf::from("/site/video.mp4"),
+                content_type: String::from("video/mp4"),
+                content_length: 1_000_000,
+                last_modified: 1_700_000_001,
+                content: None,
+            },
+        ];
+
+        Snapshot {
+            minified: true,
+            entries,
+        }
+    }
+
+    #[test]
+    fn test_roundtrip() {
+        let snapshot = snapshot();
+
+        assert_eq!(decode(&encode(&snapshot)).ok(), Some(snapshot));
+    }
+
+    #[test]
+    fn test_invalid_snapshots() {
+        let snapshot = encode(&snapshot());
+
+        assert!(matches!(
+            decode(b"something else"),
+            Err(SnapshotError::NotASnapshot)
+        ));
+        assert!(matches!(
+            decode(&snapshot[..snapshot.len() - 1]),
+            Err(SnapshotError::Corrupt)
+        ));
+
+        let mut other_version = snapshot;
+        other_version[MAGIC.len()] = 1;
+        assert!(matches!(
+            decode(&other_version),
+            Err(SnapshotError::Version(1))
+        ));
+    }
+}
@@ -0,0 +1,177 @@
+//! Bandwidth throttling of responses
+//!
+//! With `--throttle 500KB/s` all response bodies together are sent at no more
+//! than that rate, with `--throttle-connection 100KB/s` every connection on
+//! its own. Bodies are sent in small chunks, each waits for its share of the
+//! bandwidth, so a few large downloads can not saturate the uplink and smaller
+//! responses still get their turn.
+
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::sync::PoisonError;
+use std::time::Duration;
+
+use axum::body::Body;
+use axum::body::Bytes;
+use axum::extract::Request;
+use axum::extract::State;
+use axum::middleware::Next;
+use axum::response::Response;
+use futures_util::stream::unfold;
+use futures_util::Stream;
+use futures_util::StreamExt;
+use tokio::time::Instant;
+
+use crate::app::ServerState;
+use crate::utils::parse_byte_size;
+use crate::utils::ByteSizeError;
+
+/// Largest part of a body that is sent at once
+const CHUNK_SIZE: usize = 16 * 1024;
+
+/// Parse a transfer rate, ie `500KB/s`, in bytes per second
+pub fn parse_byte_rate(value: &str) -> Result<u64, ByteSizeError> {
+    let size = value.trim().strip_suffix("/s").unwrap_or(value);
+    let rate = parse_byte_size(size)?;
+
+    if rate == 0 {
+        return Err(ByteSizeError::Invalid(value.to_string()));
+    }
+
+    Ok(rate)
+}
+
+/// Bandwidth shared by the bodies sent through it
+#[derive(Debug)]
+pub struct Bandwidth {
+    /// Bytes per second
+    rate: u64,
+
+    /// Moment the bandwidth is available again, after everything that was
+    /// sent so far
+    available_at: Mutex<Instant>,
+}
+
+impl Bandwidth {
+    pub fn new(rate: u64) -> Self {
+        Self {
+            rate,
+            available_at: Mutex::new(Instant::now()),
+        }
+    }
+
+    /// Reserve the bandwidth for a number of bytes, the moment they can be sent
+    fn reserve(&self, bytes: usize) -> Instant {
+        let mut available_at = self
+            .available_at
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+
+        // unused bandwidth is not saved up for later
+        let start = (*available_at).max(Instant::now());
+
+        #[allow(clippy::cast_precision_loss)]
+        let duration = Duration::from_secs_f64(bytes as f64 / self.rate as f64);
+        *available_at = start + duration;
+
+        start
+    }
+}
+
+/// Bandwidth of a single connection, added to its requests by the server
+#[derive(Clone, Debug)]
+pub struct ConnectionBandwidth(pub Arc<Bandwidth>);
+
+/// The data of the body in chunks, sent when all bandwidths have room for them
+fn throttled(
+    body: Body,
+    bandwidths: Vec<Arc<Bandwidth>>,
+) -> impl Stream<Item = Result<Bytes, axum::Error>> {
+    let chunks = body.into_data_stream().flat_map(|data| {
+        let chunks = match data {
+            Ok(mut data) => {
+                let mut chunks = vec![];
+
+                while data.len() > CHUNK_SIZE {
+                    chunks.push(Ok(data.split_to(CHUNK_SIZE)));
+                }
+
+                chunks.push(Ok(data));
+                chunks
+            }
+            Err(err) => vec![Err(err)],
+        };
+
+        futures_util::stream::iter(chunks)
+    });
+
+    unfold(
+        (Box::pin(chunks), bandwidths),
+        |(mut chunks, bandwidths)| async move {
+            let chunk = chunks.next().await?;
+
+            if let Ok(data) = &chunk {
+                let send_at = bandwidths
+                    .iter()
+                    .map(|bandwidth| bandwidth.reserve(data.len()))
+                    .max();
+
+                if let Some(send_at) = send_at {
+                    tokio::time::sleep_until(send_at).await;
+                }
+            }
+
+            Some((chunk, (chunks, bandwidths)))
+        },
+    )
+}
+
+/// Middleware that sends the response bodies within the configured bandwidths
+pub async fn throttle(State(state): State<ServerState>, request: Request, next: Next) -> Response {
+    let mut bandwidths = vec![];
+
+    if let Some(bandwidth) = &state.bandwidth {
+        bandwidths.push(Arc::clone(bandwidth));
+    }
+
+    if let Some(ConnectionBandwidth(bandwidth)) = request.extensions().get() {
+        bandwidths.push(Arc::clone(bandwidth));
+    }
+
+    let response = next.run(request).await;
+
+    if bandwidths.is_empty() {
+        return response;
+    }
+
+    response.map(|body| Body::from_stream(throttled(body, bandwidths)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_byte_rate() {
+        assert_eq!(parse_byte_rate("500KB/s").ok(), Some(500 * 1024));
+        assert_eq!(parse_byte_rate("1M").ok(), Some(1024 * 1024));
+        assert!(parse_byte_rate("0/s").is_err());
+        assert!(parse_byte_rate("fast").is_err());
+    }
+
+    #[tokio::test]
+    async fn test_throttled() {
+        let bandwidth = Arc::new(Bandwidth::new(CHUNK_SIZE as u64 * 20));
+        let body = Body::from(vec![0; CHUNK_SIZE * 3]);
+        let start = Instant::now();
+
+        let chunks = throttled(body, vec![bandwidth])
+            .map(|chunk| chunk.expect("A valid chunk").len())
+            .collect::<Vec<_>>()
+            .await;
+
+        assert_eq!(chunks, vec![CHUNK_SIZE; 3]);
+        // the first chunk is sent right away, the others 50ms apart
+        assert!(start.elapsed() >= Duration::from_millis(100));
+    }
+}
@@ -0,0 +1,723 @@
+//! TLS support
+//!
+//! With a certificate (chain) and a private key, both PEM encoded, or with
+//! certificates via ACME, connections are served over HTTPS. The TLS handshake
+//! happens on the guarded stream, so the connection limits apply to it as well.
+//!
+//! Multiple certificates can be served via SNI, picked by the host the client
+//! asks for; the certificate of `--tls-cert` (or `--tls-mkcert`) is used for
+//! other hosts.
+//!
+//! With a client CA, clients should authenticate with a certificate signed by
+//! that CA, connections without one are rejected during the handshake.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::fs::File;
+use std::io::BufReader;
+use std::path::Path;
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::sync::Arc;
+
+use clap::ValueEnum;
+use rustls_acme::acme::ACME_TLS_ALPN_NAME;
+use tokio_rustls::rustls::crypto::CryptoProvider;
+use tokio_rustls::rustls::pki_types::CertificateDer;
+use tokio_rustls::rustls::pki_types::PrivateKeyDer;
+use tokio_rustls::rustls::server::danger::ClientCertVerifier;
+use tokio_rustls::rustls::server::ClientHello;
+use tokio_rustls::rustls::server::ResolvesServerCert;
+use tokio_rustls::rustls::server::VerifierBuilderError;
+use tokio_rustls::rustls::server::WebPkiClientVerifier;
+use tokio_rustls::rustls::sign::CertifiedKey;
+use tokio_rustls::rustls::RootCertStore;
+use tokio_rustls::rustls::ServerConfig;
+use tokio_rustls::rustls::ServerConnection;
+use tokio_rustls::TlsAcceptor;
+use x509_parser::certificate::X509Certificate;
+use x509_parser::prelude::FromDer;
+
+use crate::acme::acme_resolver;
+use crate::config::Config;
+use crate::mkcert::mint_certificate;
+use crate::mkcert::MkcertError;
+
+#[derive(Debug, thiserror::Error)]
+pub enum TlsError {
+    #[error("Could not read \"{0}\": {1}")]
+    Io(PathBuf, std::io::Error),
+
+    #[error("No certificates found in \"{0}\"")]
+    NoCertificates(PathBuf),
+
+    #[error("No private key found in \"{0}\"")]
+    NoPrivateKey(PathBuf),
+
+    #[error("Invalid certificate or private key: {0}")]
+    InvalidConfig(#[from] tokio_rustls::rustls::Error),
+
+    #[error("Invalid client CA: {0}")]
+    InvalidClientCa(#[from] VerifierBuilderError),
+
+    #[error("Could not get a certificate from mkcert: {0}")]
+    Mkcert(#[from] MkcertError),
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum SniCertificateError {
+    #[error("Expected a certificate in the form of \"<host> <cert> <key>\"")]
+    InvalidFormat,
+
+    #[error("Invalid host \"{0}\", expected a hostname like example.com or *.example.com")]
+    InvalidHost(String),
+}
+
+/// Application protocol, negotiated during the TLS handshake
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum AlpnProtocol {
+    /// HTTP/2
+    #[value(name = "h2")]
+    Http2,
+
+    /// HTTP/1.1
+    #[value(name = "http/1.1")]
+    Http1,
+}
+
+impl AlpnProtocol {
+    /// Identification of the protocol, as registered with IANA
+    fn id(self) -> &'static [u8] {
+        match self {
+            Self::Http2 => b"h2",
+            Self::Http1 => b"http/1.1",
+        }
+    }
+}
+
+/// Certificate for a single host, picked via SNI
+#[derive(Clone, Debug)]
+pub struct SniCertificate {
+    /// Lowercase hostname, the first label can be a wildcard
+    host: String,
+    cert: PathBuf,
+    key: PathBuf,
+}
+
+impl FromStr for SniCertificate {
+    type Err = SniCertificateError;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let [host, cert, key] = value.split_whitespace().collect::<Vec<_>>()[..] else {
+            return Err(SniCertificateError::InvalidFormat);
+        };
+
+        let name = host.strip_prefix("*.").unwrap_or(host);
+        let is_valid = !name.is_empty()
+            && name.split('.').all(|label| {
+                !label.is_empty() && label.chars().all(|c| c.is_ascii_alphanumeric() || c == '-')
+            });
+
+        if !is_valid {
+            return Err(SniCertificateError::InvalidHost(host.to_string()));
+        }
+
+        Ok(Self {
+            host: host.to_ascii_lowercase(),
+            cert: PathBuf::from(cert),
+            key: PathBuf::from(key),
+        })
+    }
+}
+
+impl fmt::Display for SniCertificate {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} {} {}",
+            self.host,
+            self.cert.display(),
+            self.key.display()
+        )
+    }
+}
+
+/// Picks the certificate for the host the client asks for, via SNI
+#[derive(Debug)]
+struct SniResolver {
+    certificates: HashMap<String, Arc<CertifiedKey>>,
+
+    /// Certificate for clients without SNI, or for unknown hosts
+    default: Option<Arc<CertifiedKey>>,
+}
+
+impl SniResolver {
+    fn certificate(&self, host: Option<&str>) -> Option<Arc<CertifiedKey>> {
+        let Some(host) = host.map(str::to_ascii_lowercase) else {
+            return self.default.clone();
+        };
+
+        let wildcard = host
+            .split_once('.')
+            .map(|(_, parent)| format!("*.{parent}"));
+
+        self.certificates
+            .get(&host)
+            .or_else(|| self.certificates.get(&wildcard?))
+            .or(self.default.as_ref())
+            .cloned()
+    }
+}
+
+impl ResolvesServerCert for SniResolver {
+    fn resolve(&self, client_hello: ClientHello<'_>) -> Option<Arc<CertifiedKey>> {
+        self.certificate(client_hello.server_name())
+    }
+}
+
+/// Certificate a client authenticated with, available as a request extension
+#[derive(Clone, Debug)]
+pub struct ClientCertificate {
+    /// Subject of the certificate, ie `CN=dashboard,O=Example`
+    pub subject: String,
+}
+
+/// Acceptor for TLS connections, `None` when TLS is not configured
+pub fn tls_acceptor(config: &Config) -> Result<Option<TlsAcceptor>, TlsError> {
+    let provider = Arc::new(tokio_rustls::rustls::crypto::ring::default_provider());
+
+    let builder = ServerConfig::builder_with_provider(Arc::clone(&provider))
+        .with_safe_default_protocol_versions()?;
+
+    let builder = match &config.tls_client_ca {
+        Some(client_ca) => {
+            builder.with_client_cert_verifier(client_verifier(client_ca, Arc::clone(&provider))?)
+        }
+        None => builder.with_no_client_auth(),
+    };
+
+    let mut server_config = if let Some(resolver) = acme_resolver(config) {
+        let mut server_config = builder.with_cert_resolver(resolver);
+        server_config.alpn_protocols = vec![ACME_TLS_ALPN_NAME.to_vec()];
+        server_config
+    } else {
+        let default = if config.tls_mkcert {
+            let certificate = mint_certificate()?;
+            tracing::info!("Minted a certificate for {}", certificate.hosts.join(", "));
+
+            Some(certified_key(
+                &certificate.cert,
+                &certificate.key,
+                &provider,
+            )?)
+        } else if let (Some(cert_path), Some(key_path)) = (&config.tls_cert, &config.tls_key) {
+            Some(certified_key(cert_path, key_path, &provider)?)
+        } else {
+            None
+        };
+
+        if default.is_none() && config.tls_sni.is_empty() {
+            return Ok(None);
+        }
+
+        let certificates = config
+            .tls_sni
+            .iter()
+            .map(|sni| {
+                let certificate = certified_key(&sni.cert, &sni.key, &provider)?;
+                Ok((sni.host.clone(), certificate))
+            })
+            .collect::<Result<HashMap<_, _>, TlsError>>()?;
+
+        builder.with_cert_resolver(Arc::new(SniResolver {
+            certificates,
+            default,
+        }))
+    };
+
+    // the order is the preference, the first one the client supports is picked
+    let mut protocols = config
+        .alpn_protocols()
+        .into_iter()
+        .map(|protocol| protocol.id().to_vec())
+        .collect::<Vec<_>>();
+    protocols.append(&mut server_config.alpn_protocols);
+    server_config.alpn_protocols = protocols;
+
+    Ok(Some(TlsAcceptor::from(Arc::new(server_config))))
+}
+
+/// The connection only validates a certificate for the ACME server, it
+/// should be closed after the handshake
+pub fn is_acme_challenge(connection: &ServerConnection) -> bool {
+    connection.alpn_protocol() == Some(ACME_TLS_ALPN_NAME)
+}
+
+/// Verifier that requires clients to have a certificate signed by the CA
+fn client_verifier(
+    client_ca: &Path,
+    provider: Arc<CryptoProvider>,
+) -> Result<Arc<dyn ClientCertVerifier>, TlsError> {
+    let mut roots = RootCertStore::empty();
+
+    for certificate in read_certificates(client_ca)? {
+        roots.add(certificate)?;
+    }
+
+    Ok(WebPkiClientVerifier::builder_with_provider(Arc::new(roots), provider).build()?)
+}
+
+/// The certificate of the client, when it authenticated with one
+pub fn client_certificate(connection: &ServerConnection) -> Option<ClientCertificate> {
+    let certificate = connection.peer_certificates()?.first()?;
+    let (_, certificate) = X509Certificate::from_der(certificate).ok()?;
+
+    Some(ClientCertificate {
+        subject: certificate.subject().to_string(),
+    })
+}
+
+/// Read a certificate chain and its private key
+fn certified_key(
+    cert_path: &Path,
+    key_path: &Path,
+    provider: &CryptoProvider,
+) -> Result<Arc<CertifiedKey>, TlsError> {
+    let certificates = read_certificates(cert_path)?;
+    let private_key = provider
+        .key_provider
+        .load_private_key(read_private_key(key_path)?)?;
+
+    Ok(Arc::new(CertifiedKey::new(certificates, private_key)))
+}
+
+/// Read the certificate chain from a PEM file
+fn read_certificates(path: &Path) -> Result<Vec<CertificateDer<'static>>, TlsError> {
+    let file = File::open(path).map_err(|err| TlsError::Io(path.to_path_buf(), err))?;
+
+    let certificates = rustls_pemfile::certs(&mut BufReader::new(file))
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|err| TlsError::Io(path.to_path_buf(), err))?;
+
+    if certificates.is_empty() {
+        return Err(TlsError::NoCertificates(path.to_path_buf()));
+    }
+
+    Ok(certificates)
+}
+
+/// Read the first private key from a PEM file
+fn read_private_key(path: &Path) -> Result<PrivateKeyDer<'static>, TlsError> {
+    let file = File::open(path).map_err(|err| TlsError::Io(path.to_path_buf(), err))?;
+
+    rustls_pemfile::private_key(&mut BufReader::new(file))
+        .map_err(|err| TlsError::Io(path.to_path_buf(), err))?
+        .ok_or_else(|| TlsError::NoPrivateKey(path.to_path_buf()))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::create_dir_all;
+    use std::fs::remove_dir_all;
+    use std::fs::remove_file;
+    use std::fs::write;
+    use std::io;
+    use std::process;
+
+    use rcgen::BasicConstraints;
+    use rcgen::CertificateParams;
+    use rcgen::DistinguishedName;
+    use rcgen::DnType;
+    use rcgen::ExtendedKeyUsagePurpose;
+    use rcgen::IsCa;
+    use rcgen::KeyPair;
+    use tokio_rustls::rustls::pki_types::ServerName;
+    use tokio_rustls::rustls::sign::Signer;
+    use tokio_rustls::rustls::sign::SigningKey;
+    use tokio_rustls::rustls::ClientConfig;
+    use tokio_rustls::rustls::ClientConnection;
+    use tokio_rustls::rustls::SignatureAlgorithm;
+    use tokio_rustls::rustls::SignatureScheme;
+    use tokio_rustls::TlsConnector;
+
+    use super::*;
+    use crate::config::CliConfig;
+
+    /// Certificates signed by a test CA, in a temp dir that is removed when dropped
+    struct TestCertificates {
+        dir: PathBuf,
+        ca: rcgen::Certificate,
+        ca_key: KeyPair,
+    }
+
+    impl TestCertificates {
+        fn new(name: &str) -> Self {
+            let dir = std::env::temp_dir().join(format!("srvr-tls-{name}-{}", process::id()));
+            create_dir_all(&dir).expect("A writable dir");
+
+            let ca_key = KeyPair::generate().expect("A key pair");
+            let mut params = CertificateParams::default();
+            params.is_ca = IsCa::Ca(BasicConstraints::Unconstrained);
+            params.distinguished_name = DistinguishedName::new();
+            params
+                .distinguished_name
+                .push(DnType::CommonName, "srvr test CA");
+            let ca = params.self_signed(&ca_key).expect("A CA certificate");
+            write(dir.join("ca.pem"), ca.pem()).expect("A writable file");
+
+            Self { dir, ca, ca_key }
+        }
+
+        /// Path of a file in the dir, as an argument
+        fn path(&self, file_name: &str) -> String {
+            self.dir.join(file_name).to_string_lossy().into_owned()
+        }
+
+        /// Mint a certificate signed by the CA, returns the paths of the
+        /// certificate and its private key
+        fn mint(
+            &self,
+            name: &str,
+            hosts: &[&str],
+            usage: ExtendedKeyUsagePurpose,
+        ) -> (String, String) {
+            let key = KeyPair::generate().expect("A key pair");
+            let hosts = hosts.iter().map(ToString::to_string).collect::<Vec<_>>();
+            let mut params = CertificateParams::new(hosts).expect("Valid hosts");
+            params.distinguished_name = DistinguishedName::new();
+            params.distinguished_name.push(DnType::CommonName, name);
+            params.extended_key_usages = vec![usage];
+            let certificate = params
+                .signed_by(&key, &self.ca, &self.ca_key)
+                .expect("A certificate");
+
+            let (cert_path, key_path) = (format!("{name}.pem"), format!("{name}.key"));
+            write(self.dir.join(&cert_path), certificate.pem()).expect("A writable file");
+            write(self.dir.join(&key_path), key.serialize_pem()).expect("A writable file");
+
+            (self.path(&cert_path), self.path(&key_path))
+        }
+
+        /// Client trusting the CA, authenticating with a certificate when given
+        fn client_config(&self, certificate: Option<&(String, String)>) -> ClientConfig {
+            let provider = Arc::new(tokio_rustls::rustls::crypto::ring::default_provider());
+            let mut roots = RootCertStore::empty();
+            roots.add(self.ca.der().clone()).expect("A valid CA");
+
+            let builder = ClientConfig::builder_with_provider(provider)
+                .with_safe_default_protocol_versions()
+                .expect("Valid protocol versions")
+                .with_root_certificates(roots);
+
+            match certificate {
+                Some((cert, key)) => builder
+                    .with_client_auth_cert(
+                        read_certificates(Path::new(cert)).expect("A certificate"),
+                        read_private_key(Path::new(key)).expect("A private key"),
+                    )
+                    .expect("A valid client certificate"),
+                None => builder.with_no_client_auth(),
+            }
+        }
+    }
+
+    impl Drop for TestCertificates {
+        fn drop(&mut self) {
+            remove_dir_all(&self.dir).ok();
+        }
+    }
+
+    /// Acceptor for the arguments, TLS should be configured by them
+    fn acceptor(args: &[&str]) -> TlsAcceptor {
+        let config = CliConfig::from_args(["srvr"].iter().chain(args)).config;
+
+        tls_acceptor(&config)
+            .expect("A valid TLS config")
+            .expect("An acceptor")
+    }
+
+    /// Handshake between the client and the acceptor, over an in-memory stream
+    async fn handshake(
+        acceptor: &TlsAcceptor,
+        client: ClientConfig,
+        host: &str,
+    ) -> io::Result<(ServerConnection, ClientConnection)> {
+        let (client_stream, server_stream) = tokio::io::duplex(1 << 16);
+        let connector = TlsConnector::from(Arc::new(client));
+        let server_name = ServerName::try_from(host.to_string()).expect("A valid host");
+
+        let (server, client) = tokio::join!(
+            acceptor.accept(server_stream),
+            connector.connect(server_name, client_stream),
+        );
+
+        let (_, server) = server?.into_inner();
+        let (_, client) = client?.into_inner();
+        Ok((server, client))
+    }
+
+    /// Key that never signs, the resolver only picks certificates
+    #[derive(Debug)]
+    struct TestKey;
+
+    impl SigningKey for TestKey {
+        fn choose_scheme(&self, _offered: &[SignatureScheme]) -> Option<Box<dyn Signer>> {
+            None
+        }
+
+        fn algorithm(&self) -> SignatureAlgorithm {
+            SignatureAlgorithm::ED25519
+        }
+    }
+
+    /// Certificate recognizable by its name
+    fn certificate(name: &str) -> Arc<CertifiedKey> {
+        let certificate = CertificateDer::from(name.as_bytes().to_vec());
+
+        Arc::new(CertifiedKey::new(vec![certificate], Arc::new(TestKey)))
+    }
+
+    fn resolve(resolver: &SniResolver, host: Option<&str>) -> Option<String> {
+        let certificate = resolver.certificate(host)?;
+
+        Some(String::from_utf8_lossy(&certificate.cert[0]).into_owned())
+    }
+
+    #[test]
+    fn test_sni_certificate() {
+        let sni = "*.Example.com certs/example.pem certs/example.key"
+            .parse::<SniCertificate>()
+            .expect("A valid SNI certificate");
+
+        assert_eq!(sni.host, "*.example.com");
+        assert_eq!(sni.cert, PathBuf::from("certs/example.pem"));
+        assert_eq!(sni.key, PathBuf::from("certs/example.key"));
+        assert_eq!(
+            sni.to_string(),
+            "*.example.com certs/example.pem certs/example.key"
+        );
+    }
+
+    #[test]
+    fn test_sni_resolver() {
+        let resolver = SniResolver {
+            certificates: HashMap::from([
+                ("example.com".to_string(), certificate("exact")),
+                ("*.example.com".to_string(), certificate("wildcard")),
+                ("api.example.com".to_string(), certificate("api")),
+            ]),
+            default: Some(certificate("default")),
+        };
+
+        assert_eq!(
+            resolve(&resolver, Some("example.com")).as_deref(),
+            Some("exact")
+        );
+        assert_eq!(
+            resolve(&resolver, Some("API.example.com")).as_deref(),
+            Some("api")
+        );
+        assert_eq!(
+            resolve(&resolver, Some("www.example.com")).as_deref(),
+            Some("wildcard")
+        );
+        assert_eq!(
+            resolve(&resolver, Some("a.b.example.com")).as_deref(),
+            Some("default")
+        );
+        assert_eq!(
+            resolve(&resolver, Some("example.org")).as_deref(),
+            Some("default")
+        );
+        assert_eq!(
+            resolve(&resolver, Some("localhost")).as_deref(),
+            Some("default")
+        );
+        assert_eq!(resolve(&resolver, None).as_deref(), Some("default"));
+    }
+
+    #[test]
+    fn test_sni_resolver_without_default() {
+        let resolver = SniResolver {
+            certificates: HashMap::from([("*.example.com".to_string(), certificate("wildcard"))]),
+            default: None,
+        };
+
+        assert_eq!(
+            resolve(&resolver, Some("www.example.com")).as_deref(),
+            Some("wildcard")
+        );
+        assert_eq!(resolve(&resolver, Some("example.com")), None);
+        assert_eq!(resolve(&resolver, None), None);
+    }
+
+    /// Subject of the certificate the acceptor serves for the host
+    async fn served_certificate(
+        acceptor: &TlsAcceptor,
+        client: ClientConfig,
+        host: &str,
+    ) -> Option<String> {
+        let (_, connection) = handshake(acceptor, client, host).await.ok()?;
+        let certificate = connection.peer_certificates()?.first()?.clone();
+        let (_, certificate) = X509Certificate::from_der(&certificate).ok()?;
+
+        Some(certificate.subject().to_string())
+    }
+
+    #[test]
+    fn test_invalid_sni_certificates() {
+        assert!("example.com cert.pem".parse::<SniCertificate>().is_err());
+        assert!("exa mple.com cert.pem key.pem"
+            .parse::<SniCertificate>()
+            .is_err());
+        assert!("example..com cert.pem key.pem"
+            .parse::<SniCertificate>()
+            .is_err());
+        assert!("*. cert.pem key.pem".parse::<SniCertificate>().is_err());
+        assert!("*.Example.com cert.pem key.pem"
+            .parse::<SniCertificate>()
+            .is_ok());
+    }
+
+    #[test]
+    fn test_read_pem_files() {
+        let path = std::env::temp_dir().join(format!("srvr-tls-{}.pem", process::id()));
+        let missing = path.with_extension("missing");
+
+        write(&path, "not a PEM file").expect("A writable file");
+        let certificates = read_certificates(&path);
+        let private_key = read_private_key(&path);
+        remove_file(&path).ok();
+
+        assert!(matches!(certificates, Err(TlsError::NoCertificates(_))));
+        assert!(matches!(private_key, Err(TlsError::NoPrivateKey(_))));
+        assert!(matches!(read_certificates(&missing), Err(TlsError::Io(..))));
+        assert!(matches!(read_private_key(&missing), Err(TlsError::Io(..))));
+    }
+
+    #[tokio::test]
+    async fn test_client_ca() {
+        let certificates = TestCertificates::new("client-ca");
+        let (cert, key) = certificates.mint(
+            "server",
+            &["localhost"],
+            ExtendedKeyUsagePurpose::ServerAuth,
+        );
+        let client = certificates.mint("dashboard", &[], ExtendedKeyUsagePurpose::ClientAuth);
+        let client_ca = certificates.path("ca.pem");
+        let acceptor = acceptor(&[
+            "--tls-cert",
+            &cert,
+            "--tls-key",
+            &key,
+            "--tls-client-ca",
+            &client_ca,
+        ]);
+
+        let client_config = certificates.client_config(Some(&client));
+        let (connection, _) = handshake(&acceptor, client_config, "localhost")
+            .await
+            .expect("A connection");
+        let certificate = client_certificate(&connection).expect("A client certificate");
+        assert_eq!(certificate.subject, "CN=dashboard");
+
+        let client_config = certificates.client_config(None);
+        assert!(handshake(&acceptor, client_config, "localhost")
+            .await
+            .is_err());
+
+        // signed by another CA
+        let other = TestCertificates::new("client-ca-other");
+        let client = other.mint("dashboard", &[], ExtendedKeyUsagePurpose::ClientAuth);
+        let client_config = certificates.client_config(Some(&client));
+        assert!(handshake(&acceptor, client_config, "localhost")
+            .await
+            .is_err());
+    }
+
+    #[tokio::test]
+    async fn test_sni() {
+        let certificates = TestCertificates::new("sni");
+        let usage = || ExtendedKeyUsagePurpose::ServerAuth;
+        let (cert, key) = certificates.mint("default", &["localhost"], usage());
+        let (exact_cert, exact_key) = certificates.mint("exact", &["example.com"], usage());
+        let (wildcard_cert, wildcard_key) =
+            certificates.mint("wildcard", &["*.example.com"], usage());
+        let exact = format!("example.com {exact_cert} {exact_key}");
+        let wildcard = format!("*.example.com {wildcard_cert} {wildcard_key}");
+
+        let sni = acceptor(&[
+            "--tls-cert",
+            &cert,
+            "--tls-key",
+            &key,
+            "--tls-sni",
+            &exact,
+            "--tls-sni",
+            &wildcard,
+        ]);
+        let served = |host| served_certificate(&sni, certificates.client_config(None), host);
+        assert_eq!(served("example.com").await.as_deref(), Some("CN=exact"));
+        assert_eq!(
+            served("www.example.com").await.as_deref(),
+            Some("CN=wildcard")
+        );
+        assert_eq!(served("localhost").await.as_deref(), Some("CN=default"));
+
+        // without a default certificate, unknown hosts fail the handshake
+        let sni = acceptor(&["--tls-sni", &wildcard]);
+        let served = |host| served_certificate(&sni, certificates.client_config(None), host);
+        assert_eq!(
+            served("www.example.com").await.as_deref(),
+            Some("CN=wildcard")
+        );
+        assert_eq!(served("localhost").await, None);
+    }
+
+    #[tokio::test]
+    async fn test_alpn() {
+        let certificates = TestCertificates::new("alpn");
+        let usage = ExtendedKeyUsagePurpose::ServerAuth;
+        let (cert, key) = certificates.mint("server", &["localhost"], usage);
+
+        let negotiated = |args: &'static [&'static str], offered: &'static [&'static [u8]]| {
+            let acceptor = acceptor(&[&["--tls-cert", &cert, "--tls-key", &key], args].concat());
+            let mut client_config = certificates.client_config(None);
+            client_config.alpn_protocols = offered.iter().map(|id| id.to_vec()).collect();
+
+            async move {
+                let (connection, _) = handshake(&acceptor, client_config, "localhost")
+                    .await
+                    .ok()?;
+                let protocol = connection.alpn_protocol()?;
+
+                Some(String::from_utf8_lossy(protocol).into_owned())
+            }
+        };
+
+        let both: &[&[u8]] = &[b"http/1.1", b"h2"];
+        assert_eq!(negotiated(&[], both).await.as_deref(), Some("h2"));
+        assert_eq!(
+            negotiated(&[], &[b"http/1.1"]).await.as_deref(),
+            Some("http/1.1")
+        );
+        assert_eq!(
+            negotiated(&["--http1-only"], both).await.as_deref(),
+            Some("http/1.1")
+        );
+        assert_eq!(
+            negotiated(&["--alpn", "http/1.1,h2"], both)
+                .await
+                .as_deref(),
+            Some("http/1.1")
+        );
+
+        assert_eq!(
+            negotiated(&["--alpn", "h2"], both).await.as_deref(),
+            Some("h2")
+        );
+
+        // a client without a common protocol is refused
+        assert_eq!(negotiated(&["--alpn", "h2"], &[b"http/1.1"]).await, None);
+    }
+}
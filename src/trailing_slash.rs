@@ -0,0 +1,81 @@
+//! Trailing slash policy for files
+//!
+//! Either form of the URL of a file can be made canonical, the other one is
+//! redirected to it. Directories always get a trailing slash, their relative
+//! links depend on it.
+
+use axum::http::Uri;
+use clap::ValueEnum;
+
+/// What to do with a trailing slash in the URL of a file
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, ValueEnum)]
+pub enum TrailingSlash {
+    /// Redirect `/page` to `/page/`
+    Add,
+
+    /// Redirect `/page/` to `/page`
+    Remove,
+
+    /// Serve both, without redirecting
+    #[default]
+    Ignore,
+}
+
+impl TrailingSlash {
+    /// Where to redirect the URL of a file to, if anywhere
+    pub fn location(self, uri: &Uri) -> Option<String> {
+        let path = uri.path();
+
+        let canonical = match self {
+            Self::Add if !path.ends_with('/') => format!("{path}/"),
+            Self::Remove if path.ends_with('/') && path != "/" => {
+                path.trim_end_matches('/').to_string()
+            }
+            _ => return None,
+        };
+
+        // the root has no file, but keep it a valid path anyway
+        let canonical = if canonical.is_empty() {
+            String::from("/")
+        } else {
+            canonical
+        };
+
+        Some(match uri.query() {
+            Some(query) => format!("{canonical}?{query}"),
+            None => canonical,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_location() {
+        let page = Uri::from_static("/page");
+        let page_slash = Uri::from_static("/page/?lang=nl");
+
+        assert_eq!(
+            TrailingSlash::Add.location(&page).as_deref(),
+            Some("/page/")
+        );
+        assert_eq!(TrailingSlash::Add.location(&page_slash), None);
+
+        assert_eq!(TrailingSlash::Remove.location(&page), None);
+        assert_eq!(
+            TrailingSlash::Remove.location(&page_slash).as_deref(),
+            Some("/page?lang=nl")
+        );
+        assert_eq!(
+            TrailingSlash::Remove
+                .location(&Uri::from_static("/page//"))
+                .as_deref(),
+            Some("/page")
+        );
+
+        assert_eq!(TrailingSlash::Ignore.location(&page), None);
+        assert_eq!(TrailingSlash::Ignore.location(&page_slash), None);
+    }
+}
@@ -0,0 +1,368 @@
+//! Zero-downtime upgrades via socket handoff
+//!
+//! A listening socket can be inherited via the systemd socket activation
+//! protocol (`LISTEN_FDS`), so a socket unit keeps accepting connections while
+//! srvr restarts. Without a service manager, sending `SIGUSR2` to srvr starts
+//! the (possibly upgraded) binary with the listening sockets, after which the
+//! old process stops accepting and drains its in-flight requests. The socket
+//! of `--redirect-http` is passed as the second one.
+//!
+//! The old process only starts draining once the successor reports that it is
+//! serving, with `READY=1` on a `NOTIFY_SOCKET` as in the systemd notification
+//! protocol. That socket lives in a fresh directory only this user has access
+//! to. A successor that exits, or is not ready in time, is stopped and the old
+//! process keeps serving.
+//!
+//! The successor is a child of the old process, a service manager stops it
+//! together with the old process unless it is told about the new main process.
+//! When srvr runs under systemd with `Type=notify`, the ready successor is
+//! reported with `MAINPID=`. Other setups should prefer socket activation and
+//! a restart over `SIGUSR2`.
+
+use std::ffi::OsString;
+use std::future::Future;
+use std::io;
+use std::path::Path;
+use std::path::PathBuf;
+use std::sync::Mutex;
+use std::sync::OnceLock;
+use std::sync::PoisonError;
+use std::time::Duration;
+
+use listenfd::ListenFd;
+use tokio::net::TcpListener;
+
+use crate::utils::graceful_shutdown;
+
+/// Position of the socket of the server among the inherited sockets
+pub const SERVER_LISTEN_FD: usize = 0;
+
+/// Position of the socket of `--redirect-http` among the inherited sockets
+pub const REDIRECT_LISTEN_FD: usize = 1;
+
+/// Time a successor gets to report that it is serving
+const READY_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Time between checking if the successor exited
+const EXIT_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Environment variable with the notify socket of the service manager, passed
+/// to a successor as its `NOTIFY_SOCKET` is the one of the old process, empty
+/// without a service manager
+const MANAGER_NOTIFY_SOCKET: &str = "SRVR_MANAGER_NOTIFY_SOCKET";
+
+/// Sockets passed by a service manager or a previous srvr, reading them clears
+/// the environment so it is only done once
+static LISTEN_FD: OnceLock<Mutex<ListenFd>> = OnceLock::new();
+
+/// Take over a listening socket passed by a service manager or a previous srvr
+pub fn inherited_listener(index: usize) -> io::Result<Option<TcpListener>> {
+    let listener = LISTEN_FD
+        .get_or_init(|| Mutex::new(ListenFd::from_env()))
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner)
+        .take_tcp_listener(index)?;
+
+    let Some(listener) = listener else {
+        return Ok(None);
+    };
+
+    listener.set_nonblocking(true)?;
+
+    TcpListener::from_std(listener).map(Some)
+}
+
+/// Shutdown signal of the server, completes on a regular shutdown or once a
+/// successor took over the listeners
+pub fn shutdown_or_upgrade(
+    listener: &TcpListener,
+    redirect_listener: Option<&TcpListener>,
+) -> io::Result<impl Future<Output = ()>> {
+    #[cfg(unix)]
+    let upgrade = {
+        let listeners = std::iter::once(listener)
+            .chain(redirect_listener)
+            .map(|listener| std::os::fd::AsFd::as_fd(listener).try_clone_to_owned())
+            .collect::<io::Result<Vec<_>>>()?;
+
+        upgrade(listeners)
+    };
+
+    #[cfg(not(unix))]
+    let upgrade = {
+        let _ = (listener, redirect_listener);
+        std::future::pending::<()>()
+    };
+
+    Ok(async move {
+        tokio::select! {
+            () = graceful_shutdown() => {},
+            () = upgrade => {},
+        }
+    })
+}
+
+/// Report that this process is serving, to the previous srvr or a service
+/// manager that asked for it
+pub fn notify_ready() {
+    #[cfg(unix)]
+    if let Some(notify_socket) = std::env::var_os("NOTIFY_SOCKET") {
+        if let Err(err) = notify(Path::new(&notify_socket), "READY=1") {
+            tracing::warn!("Could not report readiness: {err}");
+        }
+    }
+}
+
+/// Notify socket of the service manager, if srvr runs under one
+#[cfg(unix)]
+fn manager_notify_socket() -> Option<OsString> {
+    match std::env::var_os(MANAGER_NOTIFY_SOCKET) {
+        Some(notify_socket) => (!notify_socket.is_empty()).then_some(notify_socket),
+        None => std::env::var_os("NOTIFY_SOCKET"),
+    }
+}
+
+/// Send a message to a notify socket
+#[cfg(unix)]
+fn notify(notify_socket: &Path, message: &str) -> io::Result<()> {
+    std::os::unix::net::UnixDatagram::unbound()?
+        .send_to(message.as_bytes(), notify_socket)
+        .map(drop)
+}
+
+/// Wait for an upgrade request and start the successor, completes once the
+/// successor is serving and this process should drain
+#[cfg(unix)]
+async fn upgrade(listeners: Vec<std::os::fd::OwnedFd>) {
+    use tokio::signal::unix::signal;
+    use tokio::signal::unix::SignalKind;
+
+    let Ok(mut upgrade_signal) = signal(SignalKind::user_defined2()) else {
+        tracing::warn!("Could not listen for upgrade signals");
+        return std::future::pending().await;
+    };
+
+    loop {
+        upgrade_signal.recv().await;
+
+        tracing::info!("Upgrade signal received, starting successor");
+
+        match start_successor(&listeners).await {
+            Ok(pid) => {
+                tracing::info!("Successor {pid} is ready, draining in-flight requests");
+
+                // the service manager would stop the successor along with this process
+                if let Some(notify_socket) = manager_notify_socket() {
+                    if let Err(err) = notify(Path::new(&notify_socket), &format!("MAINPID={pid}")) {
+                        tracing::warn!("Could not report the successor as main process: {err}");
+                    }
+                }
+
+                return;
+            }
+            Err(err) => tracing::error!("Could not start successor, continuing to serve: {err}"),
+        }
+    }
+}
+
+/// Start the successor and wait until it is ready, it is stopped when it is not
+#[cfg(unix)]
+async fn start_successor(listeners: &[std::os::fd::OwnedFd]) -> io::Result<u32> {
+    use tokio::net::UnixDatagram;
+
+    let notify_dir = private_temp_dir()?;
+    let notify_path = notify_dir.join("notify.sock");
+
+    let result = async {
+        let notify_socket = UnixDatagram::bind(&notify_path)?;
+
+        let mut child = spawn_successor(listeners, &notify_path)?;
+
+        if let Err(err) = wait_until_ready(&mut child, &notify_socket).await {
+            child.kill().ok();
+            child.wait().ok();
+
+            return Err(err);
+        }
+
+        Ok(child.id())
+    }
+    .await;
+
+    std::fs::remove_dir_all(&notify_dir).ok();
+
+    result
+}
+
+/// Create a new directory in the system temp dir only this user has access to
+///
+/// The name is random and creating it fails when it exists, so no other user
+/// can have a say in what is in it
+#[cfg(unix)]
+fn private_temp_dir() -> io::Result<PathBuf> {
+    use std::os::unix::fs::DirBuilderExt;
+
+    let mut bytes = [0; 8];
+    getrandom::getrandom(&mut bytes)
+        .map_err(|err| io::Error::new(io::ErrorKind::Other, err.to_string()))?;
+
+    let dir = std::env::temp_dir().join(format!("srvr-upgrade-{:016x}", u64::from_le_bytes(bytes)));
+    std::fs::DirBuilder::new().mode(0o700).create(&dir)?;
+
+    Ok(dir)
+}
+
+/// Wait for the child to report `READY=1`, fails when it exits or takes too long
+#[cfg(unix)]
+async fn wait_until_ready(
+    child: &mut std::process::Child,
+    notify_socket: &tokio::net::UnixDatagram,
+) -> io::Result<()> {
+    let ready = async {
+        let mut message = [0; 256];
+
+        loop {
+            let length = notify_socket.recv(&mut message).await?;
+
+            if message[..length]
+                .split(|byte| *byte == b'\n')
+                .any(|line| line == b"READY=1")
+            {
+                return Ok(());
+            }
+        }
+    };
+
+    let exited = async {
+        loop {
+            if let Some(status) = child.try_wait()? {
+                return io::Result::Ok(status);
+            }
+
+            tokio::time::sleep(EXIT_INTERVAL).await;
+        }
+    };
+
+    tokio::select! {
+        result = ready => result,
+        status = exited => Err(io::Error::new(
+            io::ErrorKind::Other,
+            format!("it exited with {}", status?),
+        )),
+        () = tokio::time::sleep(READY_TIMEOUT) => Err(io::Error::new(
+            io::ErrorKind::TimedOut,
+            "it was not ready in time",
+        )),
+    }
+}
+
+/// Start the current binary with the same arguments, passing the listening
+/// sockets and the socket to report readiness on
+#[cfg(unix)]
+fn spawn_successor(
+    listeners: &[std::os::fd::OwnedFd],
+    notify_path: &Path,
+) -> io::Result<std::process::Child> {
+    use std::process::Command;
+
+    use command_fds::CommandFdExt;
+    use command_fds::FdMapping;
+
+    /// First file descriptor of the socket activation protocol
+    const LISTEN_FDS_START: i32 = 3;
+
+    let mut mappings = vec![];
+
+    for (child_fd, listener) in (LISTEN_FDS_START..).zip(listeners) {
+        mappings.push(FdMapping {
+            parent_fd: listener.try_clone()?,
+            child_fd,
+        });
+    }
+
+    let mut command = Command::new(std::env::current_exe()?);
+
+    command
+        .args(std::env::args_os().skip(1))
+        .env("LISTEN_FDS", listeners.len().to_string())
+        .env_remove("LISTEN_PID")
+        .env("NOTIFY_SOCKET", notify_path)
+        .env(
+            MANAGER_NOTIFY_SOCKET,
+            manager_notify_socket().unwrap_or_default(),
+        )
+        .fd_mappings(mappings)
+        .map_err(|_| io::Error::new(io::ErrorKind::Other, "colliding file descriptors"))?;
+
+    command.spawn()
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use std::process::Command;
+
+    use tokio::net::UnixDatagram;
+
+    use super::*;
+
+    #[test]
+    fn test_private_temp_dir() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = private_temp_dir().expect("A private dir");
+        let other_dir = private_temp_dir().expect("A private dir");
+        assert_ne!(dir, other_dir);
+
+        let mode = std::fs::metadata(&dir)
+            .expect("An existing dir")
+            .permissions()
+            .mode();
+        assert_eq!(mode & 0o777, 0o700);
+
+        std::fs::remove_dir(&dir).ok();
+        std::fs::remove_dir(&other_dir).ok();
+    }
+
+    #[tokio::test]
+    async fn test_notify() {
+        let notify_dir = private_temp_dir().expect("A private dir");
+        let notify_path = notify_dir.join("notify.sock");
+        let notify_socket = UnixDatagram::bind(&notify_path).expect("A notify socket");
+
+        notify(&notify_path, "MAINPID=1234").expect("A sent message");
+
+        let mut message = [0; 256];
+        let length = notify_socket.recv(&mut message).await.expect("A message");
+        std::fs::remove_dir_all(&notify_dir).ok();
+        assert_eq!(&message[..length], b"MAINPID=1234");
+    }
+
+    #[tokio::test]
+    async fn test_wait_until_ready() {
+        let notify_dir = private_temp_dir().expect("A private dir");
+        let notify_path = notify_dir.join("notify.sock");
+        let notify_socket = UnixDatagram::bind(&notify_path).expect("A notify socket");
+
+        let mut child = Command::new("sleep")
+            .arg("10")
+            .spawn()
+            .expect("A running child");
+
+        UnixDatagram::unbound()
+            .expect("A socket")
+            .send_to(b"STATUS=starting\nREADY=1", &notify_path)
+            .await
+            .expect("A sent message");
+
+        let result = wait_until_ready(&mut child, &notify_socket).await;
+        child.kill().ok();
+        child.wait().ok();
+        assert!(result.is_ok());
+
+        let mut child = Command::new("false").spawn().expect("A running child");
+
+        let result = wait_until_ready(&mut child, &notify_socket).await;
+        std::fs::remove_dir_all(&notify_dir).ok();
+        assert!(result.is_err());
+    }
+}
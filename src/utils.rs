@@ -1,12 +1,26 @@
 //! Miscellaneous utilities
 
+use std::io;
 use std::net::SocketAddr;
+use std::path::Path;
+use std::path::PathBuf;
 
-use crate::Config;
+use percent_encoding::AsciiSet;
+use percent_encoding::NON_ALPHANUMERIC;
+use tracing::metadata::LevelFilter;
+
+use crate::config::Config;
 
 /// Default address srvr binds to
 const DEFAULT_ADDRESS: &str = "127.0.0.1:12234";
 
+/// Characters that are kept as-is when percent-encoding a path segment
+pub const PATH_SEGMENT: &AsciiSet = &NON_ALPHANUMERIC
+    .remove(b'-')
+    .remove(b'.')
+    .remove(b'_')
+    .remove(b'~');
+
 /// Get the value of ENV var, or a default
 ///
 /// Only when:
@@ -24,16 +38,122 @@ pub fn env_var_or_else(var_name: &'static str, or_else: fn() -> String) -> Strin
     or_else()
 }
 
-/// Setup tracing based on the environment
-pub fn setup_tracing() {
-    use tracing::metadata::LevelFilter;
+/// Pick at random, `true` for the percentage of the calls
+pub fn chance(percent: u8) -> bool {
+    let mut bytes = [0; 8];
+
+    if let Err(err) = getrandom::getrandom(&mut bytes) {
+        tracing::warn!("Could not generate a random number: {err}");
+        return false;
+    }
+
+    u64::from_le_bytes(bytes) % 100 < u64::from(percent)
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum ByteSizeError {
+    #[error("Invalid size \"{0}\", expected a number with an optional unit (B, K, M, G)")]
+    Invalid(String),
+
+    #[error("Size \"{0}\" is too large")]
+    TooLarge(String),
+}
+
+/// Parse a human readable size, ie `512`, `64K`, `10MB` or `1GiB`
+///
+/// Units are binary, so `1K` is 1024 bytes
+pub fn parse_byte_size(value: &str) -> Result<u64, ByteSizeError> {
+    let value = value.trim();
+    let split_at = value
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(value.len());
+    let (number, unit) = value.split_at(split_at);
+
+    let number = number
+        .parse::<u64>()
+        .map_err(|_| ByteSizeError::Invalid(value.to_string()))?;
+
+    let multiplier: u64 = match unit.trim().to_ascii_uppercase().as_str() {
+        "" | "B" => 1,
+        "K" | "KB" | "KIB" => 1 << 10,
+        "M" | "MB" | "MIB" => 1 << 20,
+        "G" | "GB" | "GIB" => 1 << 30,
+        _ => return Err(ByteSizeError::Invalid(value.to_string())),
+    };
+
+    number
+        .checked_mul(multiplier)
+        .ok_or_else(|| ByteSizeError::TooLarge(value.to_string()))
+}
+
+/// Format a size in a human readable way, ie `512B` or `1.5M`, the inverse of
+/// [`parse_byte_size`] with one decimal
+pub fn format_byte_size(size: u64) -> String {
+    let (unit, divisor) = match size {
+        0..=1023 => return format!("{size}B"),
+        1024..=1_048_575 => ("K", 1 << 10),
+        1_048_576..=1_073_741_823 => ("M", 1 << 20),
+        _ => ("G", 1 << 30),
+    };
+
+    let tenths = size.saturating_mul(10) / divisor;
+    format!("{}.{}{unit}", tenths / 10, tenths % 10)
+}
+
+/// Per-user cache directory of srvr, ie `~/.cache/srvr/<name>`
+///
+/// Follows `XDG_CACHE_HOME` when it is set. The directory is private to the
+/// current user, see [`create_private_dir`]
+pub fn user_cache_dir(name: &str) -> io::Result<PathBuf> {
+    use std::env::var_os;
+
+    let cache_home = var_os("XDG_CACHE_HOME")
+        .map(PathBuf::from)
+        .filter(|path| path.is_absolute())
+        .or_else(|| {
+            var_os("HOME")
+                .filter(|home| !home.is_empty())
+                .map(|home| PathBuf::from(home).join(".cache"))
+        })
+        .or_else(|| var_os("LOCALAPPDATA").map(PathBuf::from))
+        .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "no home directory found"))?;
+
+    let dir = cache_home.join("srvr").join(name);
+    create_private_dir(&dir)?;
+
+    Ok(dir)
+}
+
+/// Create a directory (and its parents) only the current user has access to
+///
+/// An existing directory has its permissions tightened, which fails when it
+/// belongs to another user
+pub fn create_private_dir(path: &Path) -> io::Result<()> {
+    let mut builder = std::fs::DirBuilder::new();
+    builder.recursive(true);
+
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::DirBuilderExt;
+        use std::os::unix::fs::PermissionsExt;
+
+        builder.mode(0o700).create(path)?;
+        std::fs::set_permissions(path, std::fs::Permissions::from_mode(0o700))
+    }
+
+    #[cfg(not(unix))]
+    builder.create(path)
+}
+
+/// Setup tracing based on the environment, using the level when none is configured
+pub fn setup_tracing(default_level: LevelFilter) {
     use tracing_subscriber::fmt::SubscriberBuilder;
     use tracing_subscriber::EnvFilter;
 
     SubscriberBuilder::default()
         .with_env_filter(
             EnvFilter::builder()
-                .with_default_directive(LevelFilter::INFO.into())
+                .with_default_directive(default_level.into())
                 .from_env_lossy(),
         )
         .try_init()
@@ -94,3 +214,108 @@ pub async fn graceful_shutdown() {
 
     tracing::info!("Terminate signal received, starting graceful shutdown");
 }
+
+/// Match a name against a pattern, `*` matches any run of characters and `?`
+/// a single one
+///
+/// Backtracks to the last `*` only, so matching stays linear-ish for any pattern
+pub fn wildcard_match(pattern: &str, name: &str) -> bool {
+    let pattern = pattern.chars().collect::<Vec<_>>();
+    let name = name.chars().collect::<Vec<_>>();
+
+    let (mut p, mut n) = (0, 0);
+    let mut backtrack = None;
+
+    while n < name.len() {
+        match pattern.get(p) {
+            Some('*') => {
+                backtrack = Some((p, n));
+                p += 1;
+            }
+            Some(&c) if c == '?' || c == name[n] => {
+                p += 1;
+                n += 1;
+            }
+            _ => match backtrack {
+                // let the last `*` match one more character
+                Some((star, matched)) => {
+                    backtrack = Some((star, matched + 1));
+                    p = star + 1;
+                    n = matched + 1;
+                }
+                None => return false,
+            },
+        }
+    }
+
+    pattern[p..].iter().all(|&c| c == '*')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_byte_size() {
+        assert_eq!(parse_byte_size("512").ok(), Some(512));
+        assert_eq!(parse_byte_size("512B").ok(), Some(512));
+        assert_eq!(parse_byte_size("64K").ok(), Some(65_536));
+        assert_eq!(parse_byte_size("10 MB").ok(), Some(10_485_760));
+        assert_eq!(parse_byte_size("1GiB").ok(), Some(1_073_741_824));
+        assert_eq!(parse_byte_size("1g").ok(), Some(1_073_741_824));
+    }
+
+    #[test]
+    fn test_format_byte_size() {
+        assert_eq!(format_byte_size(512), "512B");
+        assert_eq!(format_byte_size(1536), "1.5K");
+        assert_eq!(format_byte_size(10_485_760), "10.0M");
+        assert_eq!(format_byte_size(1_073_741_824), "1.0G");
+    }
+
+    #[test]
+    fn test_parse_invalid_byte_size() {
+        assert!(parse_byte_size("").is_err());
+        assert!(parse_byte_size("MB").is_err());
+        assert!(parse_byte_size("1.5MB").is_err());
+        assert!(parse_byte_size("10TB").is_err());
+        assert!(parse_byte_size("99999999999999999999G").is_err());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_create_private_dir() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = std::env::temp_dir().join(format!("srvr-private-{}", std::process::id()));
+        let mode = |path: &Path| {
+            std::fs::metadata(path)
+                .expect("An existing dir")
+                .permissions()
+                .mode()
+                & 0o777
+        };
+
+        create_private_dir(&dir.join("nested")).expect("A created dir");
+        assert_eq!(mode(&dir.join("nested")), 0o700);
+
+        // an existing dir is tightened
+        std::fs::set_permissions(&dir, std::fs::Permissions::from_mode(0o755))
+            .expect("A dir of our own");
+        create_private_dir(&dir).expect("An existing dir");
+        assert_eq!(mode(&dir), 0o700);
+
+        std::fs::remove_dir_all(&dir).ok();
+    }
+
+    #[test]
+    fn test_wildcard_match() {
+        assert!(wildcard_match("*.log", "app.log"));
+        assert!(wildcard_match("app-?.log", "app-1.log"));
+        assert!(wildcard_match("*a*b*", "xxaxxbxx"));
+        assert!(wildcard_match("*", ""));
+        assert!(!wildcard_match("*.log", "app.log.gz"));
+        assert!(!wildcard_match("app-?.log", "app-10.log"));
+        assert!(!wildcard_match("*a*b", "xxaxxbxxc"));
+    }
+}
@@ -0,0 +1,169 @@
+//! Virtual files, in-memory content that overrides paths
+//!
+//! Virtual files are managed via the admin API and are served ahead of the
+//! file system, ie for temporary notices, feature flags or an emergency fix
+//! without touching the deployed files. They only live in memory, a restart
+//! clears them.
+
+use std::collections::BTreeMap;
+use std::sync::PoisonError;
+use std::sync::RwLock;
+use std::time::SystemTime;
+
+use axum::body::Bytes;
+use axum::extract::Request;
+use axum::extract::State;
+use axum::http::header::CACHE_CONTROL;
+use axum::http::header::CONTENT_LENGTH;
+use axum::http::header::CONTENT_TYPE;
+use axum::http::header::LAST_MODIFIED;
+use axum::http::HeaderValue;
+use axum::http::Method;
+use axum::http::StatusCode;
+use axum::middleware::Next;
+use axum::response::IntoResponse;
+use axum::response::Response;
+use httpdate::HttpDate;
+use serde::Serialize;
+
+use crate::app::ServerState;
+
+/// Content of a virtual file
+#[derive(Clone, Debug)]
+struct VirtualFile {
+    content: Bytes,
+    content_type: HeaderValue,
+    last_modified: HttpDate,
+}
+
+/// Description of a virtual file, for the admin API
+#[derive(Debug, Serialize)]
+pub struct VirtualFileInfo {
+    pub path: String,
+    pub content_type: String,
+    pub content_length: usize,
+    pub last_modified: String,
+}
+
+/// All virtual files, indexed by their (normalized) path
+#[derive(Debug, Default)]
+pub struct VirtualFiles {
+    files: RwLock<BTreeMap<String, VirtualFile>>,
+}
+
+impl VirtualFiles {
+    /// Add a virtual file, or replace the one with the same path
+    pub fn insert(&self, path: String, content_type: HeaderValue, content: Bytes) {
+        let file = VirtualFile {
+            content,
+            content_type,
+            last_modified: HttpDate::from(SystemTime::now()),
+        };
+
+        self.files
+            .write()
+            .unwrap_or_else(PoisonError::into_inner)
+            .insert(path, file);
+    }
+
+    /// Remove a virtual file, `false` when there was no such file
+    pub fn remove(&self, path: &str) -> bool {
+        self.files
+            .write()
+            .unwrap_or_else(PoisonError::into_inner)
+            .remove(path)
+            .is_some()
+    }
+
+    /// Remove all virtual files, returns how many there were
+    pub fn clear(&self) -> usize {
+        let mut files = self.files.write().unwrap_or_else(PoisonError::into_inner);
+        let count = files.len();
+        files.clear();
+
+        count
+    }
+
+    /// All virtual files, sorted by path
+    pub fn list(&self) -> Vec<VirtualFileInfo> {
+        self.files
+            .read()
+            .unwrap_or_else(PoisonError::into_inner)
+            .iter()
+            .map(|(path, file)| VirtualFileInfo {
+                path: path.clone(),
+                content_type: file.content_type.to_str().unwrap_or_default().to_string(),
+                content_length: file.content.len(),
+                last_modified: file.last_modified.to_string(),
+            })
+            .collect()
+    }
+
+    fn get(&self, path: &str) -> Option<VirtualFile> {
+        self.files
+            .read()
+            .unwrap_or_else(PoisonError::into_inner)
+            .get(path)
+            .cloned()
+    }
+}
+
+/// Middleware that serves virtual files, other requests go to the file system
+pub async fn virtual_files(
+    State(state): State<ServerState>,
+    request: Request,
+    next: Next,
+) -> Response {
+    if !matches!(*request.method(), Method::GET | Method::HEAD) {
+        return next.run(request).await;
+    }
+
+    let Some(file) = state.virtual_files.get(request.uri().path()) else {
+        return next.run(request).await;
+    };
+
+    tracing::trace!("Serving virtual file");
+
+    let last_modified = HeaderValue::from_str(&file.last_modified.to_string())
+        .expect("A valid last modified header value");
+
+    (
+        StatusCode::OK,
+        [
+            (CONTENT_TYPE, file.content_type),
+            (CONTENT_LENGTH, HeaderValue::from(file.content.len())),
+            (LAST_MODIFIED, last_modified),
+            // virtual files are temporary, clients should not hold on to them
+            (CACHE_CONTROL, HeaderValue::from_static("no-cache")),
+        ],
+        file.content,
+    )
+        .into_response()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_virtual_files() {
+        let virtual_files = VirtualFiles::default();
+        let json = HeaderValue::from_static("application/json");
+
+        virtual_files.insert("/b.json".into(), json.clone(), Bytes::from_static(b"{}"));
+        virtual_files.insert("/a.json".into(), json.clone(), Bytes::from_static(b"[]"));
+        virtual_files.insert("/b.json".into(), json, Bytes::from_static(b"{\"a\":1}"));
+
+        let list = virtual_files.list();
+        assert_eq!(list.len(), 2);
+        assert_eq!(list[0].path, "/a.json");
+        assert_eq!(list[1].content_length, 7);
+
+        assert!(virtual_files.remove("/a.json"));
+        assert!(!virtual_files.remove("/a.json"));
+        assert!(virtual_files.get("/b.json").is_some());
+
+        assert_eq!(virtual_files.clear(), 1);
+        assert!(virtual_files.get("/b.json").is_none());
+    }
+}